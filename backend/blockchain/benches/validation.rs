@@ -0,0 +1,60 @@
+//! Block validation on synthetic blocks of single-key payments.
+//!
+//! Run with `cargo bench -p blockchain`.
+
+use blockchain::sigcheck::{verify_parallel, SignatureCheck};
+use blockchain::template::coinbase;
+use blockchain::{Block, Blockchain, TxOutput, Wallet};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const BLOCK_SIZES: [usize; 2] = [1_000, 4_000];
+
+/// A chain with one funded wallet per transaction and a sealed block spending them all.
+fn synthetic_block(transactions: usize) -> (Blockchain, Block) {
+    let mut chain = Blockchain::new();
+    let receiver = Wallet::new().get_wallet_id();
+    let mut txs = Vec::with_capacity(transactions + 1);
+    txs.push(coinbase(&receiver, 100, chain.chain.len() as u64, chain.get_latest_block().timestamp));
+    for i in 0..transactions {
+        let sender = Wallet::new();
+        chain.utxos.insert((format!("funding-{}", i), 0), TxOutput::new(100, sender.get_wallet_id()));
+        txs.push(chain.create_transaction(&sender, receiver.clone(), 10, None).expect("funded sender"));
+    }
+    let mut block = Block::unsealed(chain.chain.len() as u64, txs, chain.get_latest_block().hash.clone());
+    chain.consensus.seal(&mut block).expect("proof of work always seals");
+    (chain, block)
+}
+
+fn bench_validate_block(c: &mut Criterion) {
+    let mut group = c.benchmark_group("validate_block");
+    group.sample_size(10);
+    for size in BLOCK_SIZES {
+        let (chain, block) = synthetic_block(size);
+        chain.validate_block(&block).expect("synthetic block is valid");
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &block, |b, block| {
+            b.iter(|| chain.validate_block(block).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_signatures(c: &mut Criterion) {
+    let mut group = c.benchmark_group("signatures");
+    group.sample_size(10);
+    for size in BLOCK_SIZES {
+        let (_, block) = synthetic_block(size);
+        let checks: Vec<SignatureCheck> = block.transactions.iter().flat_map(|tx| tx.signature_checks()).collect();
+        group.throughput(Throughput::Elements(checks.len() as u64));
+        group.bench_with_input(BenchmarkId::new("one_by_one", size), &checks, |b, checks| {
+            b.iter(|| assert!(checks.iter().all(SignatureCheck::verify)))
+        });
+        group.bench_with_input(BenchmarkId::new("batched_parallel", size), &checks, |b, checks| {
+            b.iter(|| assert_eq!(verify_parallel(checks).len(), checks.len()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_validate_block, bench_signatures);
+criterion_main!(benches);
@@ -0,0 +1,91 @@
+//! Issued assets (tokens) carried by outputs next to the native coin.
+//!
+//! An issuance transaction defines an asset and creates its first supply. The
+//! asset id is derived from the first input the issuance spends, so it is unique
+//! and cannot be replayed. Assets with a mintable supply policy can later be
+//! minted by their admin, again anchored on a spent input. Apart from issuance
+//! and minting, every transaction must carry each asset's inputs over to its
+//! outputs unchanged.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::transaction::TxInput;
+
+/// Longest asset name accepted.
+pub const MAX_ASSET_NAME_LENGTH: usize = 32;
+/// Most decimal places an asset may declare.
+pub const MAX_ASSET_DECIMALS: u8 = 18;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "policy", rename_all = "snake_case")]
+pub enum SupplyPolicy {
+    /// Everything is created by the issuance transaction.
+    Fixed,
+    /// `admin_wallet_id` may create more at any time.
+    Mintable { admin_wallet_id: String },
+}
+
+/// What a transaction does to the asset registry besides moving coins.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Issuance {
+    /// Defines a new asset. Outputs carrying its id are the initial supply.
+    Issue { name: String, decimals: u8, policy: SupplyPolicy },
+    /// Creates more of a mintable asset. Outputs carrying it beyond the inputs are new supply.
+    Mint { asset_id: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AssetDefinition {
+    pub asset_id: String,
+    pub name: String,
+    pub decimals: u8,
+    pub policy: SupplyPolicy,
+    pub issuer_wallet_id: String,
+    /// Transaction that issued the asset.
+    pub issuance_tx_id: String,
+    /// Units created so far, by the issuance and any mints.
+    pub supply: u64,
+}
+
+impl Issuance {
+    /// Id of the asset the transaction issues or mints, given its first input.
+    pub fn asset_id(&self, first_input: &TxInput) -> String {
+        match self {
+            Issuance::Issue { .. } => asset_id_for(first_input),
+            Issuance::Mint { asset_id } => asset_id.clone(),
+        }
+    }
+
+    /// Checks the terms of a new asset.
+    pub fn validate_terms(&self) -> Result<(), String> {
+        if let Issuance::Issue { name, decimals, policy } = self {
+            if name.trim().is_empty() || name.len() > MAX_ASSET_NAME_LENGTH {
+                return Err(format!("Asset name must be 1 to {} bytes", MAX_ASSET_NAME_LENGTH));
+            }
+            if *decimals > MAX_ASSET_DECIMALS {
+                return Err(format!("Assets have at most {} decimals", MAX_ASSET_DECIMALS));
+            }
+            if let SupplyPolicy::Mintable { admin_wallet_id } = policy {
+                if admin_wallet_id.is_empty() {
+                    return Err("Mintable assets need an admin".to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AssetDefinition {
+    pub fn is_mintable_by(&self, wallet_id: &str) -> bool {
+        matches!(&self.policy, SupplyPolicy::Mintable { admin_wallet_id } if admin_wallet_id == wallet_id)
+    }
+}
+
+/// Asset id of an issuance whose first input is `input`.
+pub fn asset_id_for(input: &TxInput) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("asset:{}:{}", input.tx_id, input.output_index));
+    hex::encode(hasher.finalize())
+}
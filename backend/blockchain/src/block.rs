@@ -1,47 +1,116 @@
 use serde::{Serialize, Deserialize};
 use sha2::{Sha256, Digest};
 use chrono::Utc;
+use crate::encoding::block_hash_preimage;
+use crate::merkle::merkle_root;
 use crate::transaction::Transaction;
 use hex;
 
+/// Version of the blocks made before header versions existed.
+pub const INITIAL_BLOCK_VERSION: u32 = 1;
+/// First block version whose hash commits to the version.
+pub const VERSIONED_HASH_FROM: u32 = 2;
+
+fn initial_block_version() -> u32 {
+    INITIAL_BLOCK_VERSION
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Block {
+    /// Rule set the block was made under (see [`crate::params`]).
+    #[serde(default = "initial_block_version")]
+    pub version: u32,
     pub index: u64,
     pub timestamp: i64,
     pub transactions: Vec<Transaction>,
     pub previous_hash: String,
+    /// Root of the Merkle tree over `transactions` (see [`crate::merkle`]).
+    #[serde(default)]
+    pub merkle_root: String,
     pub nonce: u64,
     pub hash: String,
+    /// Proof-of-authority seal: the sealing validator's public key and its signature over `hash`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validator: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// The fields of a block covered by its hash. Together with a Merkle proof it is
+/// enough to show a transaction is in the block without the other transactions.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlockHeader {
+    #[serde(default = "initial_block_version")]
+    pub version: u32,
+    pub index: u64,
+    pub timestamp: i64,
+    pub previous_hash: String,
+    pub merkle_root: String,
+    pub nonce: u64,
+    pub hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validator: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
 }
 
 impl Block {
     pub fn new(index: u64, transactions: Vec<Transaction>, previous_hash: String) -> Self {
-        let timestamp = Utc::now().timestamp();
+        let mut block = Block::unsealed(index, transactions, previous_hash);
+        block.mine_block(2); // Default difficulty, can be adjusted
+        block
+    }
+
+    /// A block with its hash computed but not yet sealed by a consensus engine.
+    pub fn unsealed(index: u64, transactions: Vec<Transaction>, previous_hash: String) -> Self {
+        Block::unsealed_at(index, transactions, previous_hash, Utc::now().timestamp())
+    }
+
+    /// [`Block::unsealed`] with the given timestamp.
+    pub fn unsealed_at(index: u64, transactions: Vec<Transaction>, previous_hash: String, timestamp: i64) -> Self {
+        Block::unsealed_versioned(INITIAL_BLOCK_VERSION, index, transactions, previous_hash, timestamp)
+    }
+
+    /// [`Block::unsealed_at`] with the given block version.
+    pub fn unsealed_versioned(version: u32, index: u64, transactions: Vec<Transaction>, previous_hash: String, timestamp: i64) -> Self {
+        let merkle_root = merkle_root(&transactions);
         let mut block = Block {
+            version,
             index,
             timestamp,
             transactions,
             previous_hash,
+            merkle_root,
             nonce: 0,
             hash: String::new(),
+            validator: None,
+            signature: None,
         };
-        block.mine_block(2); // Default difficulty, can be adjusted
+        block.hash = block.calculate_hash();
         block
     }
 
     pub fn calculate_hash(&self) -> String {
-        let tx_data = serde_json::to_string(&self.transactions)
-            .expect("Failed to serialize transactions for block hash");
-        let input = format!("{}{}{}{}{}", 
-            self.index, 
-            self.timestamp, 
-            tx_data, 
-            self.previous_hash, 
-            self.nonce
-        );
-        let mut hasher = Sha256::new();
-        hasher.update(input);
-        hex::encode(hasher.finalize())
+        header_hash(self.version, self.index, self.timestamp, &self.merkle_root, &self.previous_hash, self.nonce)
+    }
+
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            version: self.version,
+            index: self.index,
+            timestamp: self.timestamp,
+            previous_hash: self.previous_hash.clone(),
+            merkle_root: self.merkle_root.clone(),
+            nonce: self.nonce,
+            hash: self.hash.clone(),
+            validator: self.validator.clone(),
+            signature: self.signature.clone(),
+        }
+    }
+
+    /// Whether `merkle_root` matches the block's transactions.
+    pub fn has_valid_merkle_root(&self) -> bool {
+        self.merkle_root == merkle_root(&self.transactions)
     }
 
     pub fn mine_block(&mut self, difficulty: usize) {
@@ -53,6 +122,18 @@ impl Block {
             }
             self.nonce += 1;
         }
-        println!("Block mined: {}", self.hash);
+        log::debug!("Block mined: {}", self.hash);
     }
 }
+
+impl BlockHeader {
+    pub fn calculate_hash(&self) -> String {
+        header_hash(self.version, self.index, self.timestamp, &self.merkle_root, &self.previous_hash, self.nonce)
+    }
+}
+
+fn header_hash(version: u32, index: u64, timestamp: i64, merkle_root: &str, previous_hash: &str, nonce: u64) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(block_hash_preimage(version, index, timestamp, merkle_root, previous_hash, nonce));
+    hex::encode(hasher.finalize())
+}
@@ -1,30 +1,145 @@
-use crate::block::Block;
-use crate::transaction::{Transaction, TxOutput};
-use std::collections::HashMap;
+use crate::archive::InvalidBlock;
+use crate::asset::{AssetDefinition, Issuance, SupplyPolicy};
+use crate::block::{Block, BlockHeader};
+use crate::consensus::{Consensus, ProofOfWork};
+use crate::environment::Environment;
+use crate::filter::BlockFilter;
+use crate::htlc::{Htlc, HtlcState};
+use crate::light_client::TransactionProof;
+use crate::memo::{EncryptedMemo, MEMO_NONCE_SIZE};
+use crate::merkle::merkle_proof;
+use crate::notary::NotaryReceipt;
+use crate::params::ChainParams;
+use crate::multisig::{MultisigPolicy, MultisigWitness};
+use crate::psbt::PartiallySignedTransaction;
+use crate::script::{verify_script, Script, ScriptContext, ScriptTemplate};
+use crate::script::ScriptError;
+use crate::sigcheck::{verify_block_signatures, SignatureCache};
+use crate::template::{check_coinbase, coinbase, BlockTemplate, MAX_TEMPLATES};
+use crate::transaction::{lock_time_reached, Transaction, TxInput, TxOutput, BATCH_RECEIVER};
+use crate::wallet::{wallet_id_from_public_key_hex, Wallet};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Most outputs a transaction may have to be relayed.
+pub const MAX_TX_OUTPUTS: usize = 1_000;
+/// Longest output memo, in bytes, relayed by the mempool.
+pub const MAX_MEMO_LENGTH: usize = 256;
+/// Furthest, in seconds, a block's timestamp may run ahead of this node's clock.
+pub const MAX_FUTURE_BLOCK_TIME: i64 = 2 * 60 * 60;
+/// Side blocks further than this many heights below or above the tip are forgotten.
+pub const MAX_SIDE_BLOCK_DEPTH: u64 = 100;
+/// Most side blocks kept; past it the ones furthest from the tip are forgotten first.
+pub const MAX_SIDE_BLOCKS: usize = 1_000;
 
 pub struct Blockchain {
     pub chain: Vec<Block>,
     pub pending_transactions: Vec<Transaction>,
-    pub difficulty: usize,
-    pub mining_reward: u64,
+    pub consensus: Box<dyn Consensus>,
+    pub params: ChainParams, // Consensus rules by activation height
     pub utxos: HashMap<(String, usize), TxOutput>, // (TxID, OutputIndex) -> Output
+    pub utxo_heights: HashMap<(String, usize), u64>, // (TxID, OutputIndex) -> Height of the creating block
+    pub assets: HashMap<String, AssetDefinition>, // Asset id -> Definition and supply so far
+    pub filters: Vec<BlockFilter>, // Compact filter of each block, by height
+    pub side_blocks: HashMap<String, Block>, // Hash -> Block off the main chain, on forks or awaiting a parent
+    pub templates: HashMap<String, Vec<BlockTemplate>>, // Miner -> Templates handed to it, newest last
+    pub env: Environment, // Clock and id source
+}
+
+impl Default for Blockchain {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Blockchain {
     pub fn new() -> Self {
+        Self::with_consensus(Box::new(ProofOfWork::new(2)))
+    }
+
+    pub fn with_consensus(consensus: Box<dyn Consensus>) -> Self {
+        Self::with_environment(consensus, Environment::system())
+    }
+
+    /// A chain that takes the time and fresh ids from `env`, e.g.
+    /// [`Environment::deterministic`] for reproducible chains.
+    pub fn with_environment(consensus: Box<dyn Consensus>, env: Environment) -> Self {
+        Self::with_params(consensus, env, ChainParams::default())
+    }
+
+    /// A chain that validates each block under the rules `params` sets for its height.
+    pub fn with_params(consensus: Box<dyn Consensus>, env: Environment, params: ChainParams) -> Self {
         let mut chain = Blockchain {
             chain: Vec::new(),
             pending_transactions: Vec::new(),
-            difficulty: 2,
-            mining_reward: 100,
+            consensus,
+            params,
             utxos: HashMap::new(),
+            utxo_heights: HashMap::new(),
+            assets: HashMap::new(),
+            filters: Vec::new(),
+            side_blocks: HashMap::new(),
+            templates: HashMap::new(),
+            env,
         };
         chain.create_genesis_block();
         chain
     }
 
+    /// A node holding `blocks`, read from a chain file or another node. The first
+    /// block must be a genesis block; every later one is validated in turn as if
+    /// it came from a peer.
+    pub fn import(consensus: Box<dyn Consensus>, env: Environment, params: ChainParams, blocks: Vec<Block>) -> Result<Self, InvalidBlock> {
+        let mut chain = Self::with_params(consensus, env, params);
+        chain.load(blocks)?;
+        Ok(chain)
+    }
+
+    /// Replaces the chain with `blocks`, validated as in [`Blockchain::import`].
+    /// On failure the chain is left as it was.
+    pub fn load(&mut self, blocks: Vec<Block>) -> Result<(), InvalidBlock> {
+        let mut blocks = blocks.into_iter();
+        let genesis = blocks.next().ok_or_else(|| InvalidBlock { index: 0, reason: "No genesis block".to_string() })?;
+        let genesis_error = if genesis.index != 0 || genesis.previous_hash != "0" {
+            Some("The first block is not a genesis block")
+        } else if !genesis.transactions.is_empty() {
+            Some("The genesis block has transactions")
+        } else if genesis.hash != genesis.calculate_hash() || !genesis.has_valid_merkle_root() {
+            Some("Block hash mismatch")
+        } else if genesis.version != self.params.rules_at(0).block_version {
+            Some("Genesis block version does not match the rules")
+        } else {
+            None
+        };
+        if let Some(reason) = genesis_error {
+            return Err(InvalidBlock { index: genesis.index, reason: reason.to_string() });
+        }
+
+        let chain = std::mem::replace(&mut self.chain, vec![genesis]);
+        let filters = std::mem::replace(&mut self.filters, vec![BlockFilter::build(&self.chain[0])]);
+        let pending = std::mem::take(&mut self.pending_transactions);
+        self.rebuild_state();
+        for block in blocks {
+            let index = self.chain.len() as u64;
+            if let Err(reason) = self.add_block(block) {
+                self.chain = chain;
+                self.filters = filters;
+                self.pending_transactions = pending;
+                self.rebuild_state();
+                return Err(InvalidBlock { index, reason });
+            }
+        }
+        // Nothing built on the old chain carries over
+        self.side_blocks.clear();
+        self.templates.clear();
+        Ok(())
+    }
+
     fn create_genesis_block(&mut self) {
-        let genesis_block = Block::new(0, Vec::new(), "0".to_string());
+        // The genesis block is not sealed, so every node can create it
+        let version = self.params.rules_at(0).block_version;
+        let genesis_block = Block::unsealed_versioned(version, 0, Vec::new(), "0".to_string(), self.env.now());
+        self.filters.push(BlockFilter::build(&genesis_block));
         self.chain.push(genesis_block);
     }
 
@@ -33,121 +148,773 @@ impl Blockchain {
     }
 
     pub fn mine_pending_transactions(&mut self, mining_reward_address: &str) {
-        // Create reward transaction
-        let reward_tx = Transaction {
-            id: uuid::Uuid::new_v4().to_string(), // Simple ID for now
-            sender_wallet_id: "SYSTEM_REWARD".to_string(),
-            receiver_wallet_id: mining_reward_address.to_string(),
-            amount: self.mining_reward,
-            note: Some("Mining Reward".to_string()),
-            timestamp: chrono::Utc::now().timestamp(),
-            sender_public_key: String::new(),
-            signature: String::new(),
-            inputs: Vec::new(),
-            outputs: vec![TxOutput {
-                amount: self.mining_reward,
-                receiver_wallet_id: mining_reward_address.to_string(),
-            }],
-        };
+        if let Err(e) = self.produce_block(mining_reward_address) {
+            log::warn!("Block not produced: {}", e);
+        }
+    }
 
-        let mut transactions = self.pending_transactions.clone();
-        transactions.push(reward_tx);
+    /// Builds a block from the reward plus the pending transactions, has the
+    /// consensus engine seal it and appends it. The block follows the rules in
+    /// force at its height.
+    pub fn produce_block(&mut self, mining_reward_address: &str) -> Result<(), String> {
+        let height = self.chain.len() as u64;
+        let rules = self.params.rules_at(height).clone();
+        let room = rules.max_block_transactions.map_or(usize::MAX, |max| max.saturating_sub(1));
+        let mut transactions = vec![coinbase(mining_reward_address, rules.mining_reward, height, self.env.now())];
+        transactions.extend(self.pending_transactions.iter().take(room).cloned());
 
         let previous_hash = self.get_latest_block().hash.clone();
-        let new_block = Block::new(
-            self.chain.len() as u64,
+        let mut new_block = Block::unsealed_versioned(
+            rules.block_version,
+            height,
             transactions,
             previous_hash,
+            self.env.now(),
         );
+        self.consensus.seal(&mut new_block).map_err(|e| e.to_string())?;
+
+        self.connect_block(new_block);
+        Ok(())
+    }
+
+    /// A template for `miner`, outside the node, to build the next block from. The
+    /// template is remembered for that miner only, so it has to send back just its
+    /// coinbase and nonce, and no other miner can crowd it out.
+    pub fn block_template(&mut self, miner: &str) -> Result<BlockTemplate, String> {
+        let difficulty = self.consensus.work_target().ok_or("Blocks of this chain are not sealed by work")?;
+        let height = self.chain.len() as u64;
+        let rules = self.params.rules_at(height);
+        let room = rules.max_block_transactions.map_or(usize::MAX, |max| max.saturating_sub(1));
+        let template = BlockTemplate {
+            template_id: self.env.ids.next_id(),
+            version: rules.block_version,
+            height,
+            previous_hash: self.get_latest_block().hash.clone(),
+            timestamp: self.env.now().max(self.get_latest_block().timestamp),
+            transactions: self.pending_transactions.iter().take(room).cloned().collect(),
+            coinbase_value: rules.mining_reward,
+            difficulty,
+            target: format!("{}{}", "0".repeat(difficulty), "f".repeat(64usize.saturating_sub(difficulty))),
+        };
+        // Templates on an older tip can no longer be submitted
+        self.templates.retain(|_, templates| {
+            templates.retain(|stale| stale.previous_hash == template.previous_hash);
+            !templates.is_empty()
+        });
+        let templates = self.templates.entry(miner.to_string()).or_default();
+        if templates.len() == MAX_TEMPLATES {
+            templates.remove(0);
+        }
+        templates.push(template.clone());
+        Ok(template)
+    }
+
+    /// Completes a template `miner` was handed with its coinbase and nonce and
+    /// appends the block if it is valid. The coinbase's receiver gets the reward.
+    pub fn submit_template_block(&mut self, miner: &str, template_id: &str, coinbase: Transaction, nonce: u64) -> Result<&Block, String> {
+        let template = self
+            .templates
+            .get(miner)
+            .and_then(|templates| templates.iter().find(|template| template.template_id == template_id))
+            .ok_or("Unknown or expired template")?;
+        if template.previous_hash != self.get_latest_block().hash {
+            return Err("Template is stale: the chain has moved on".to_string());
+        }
+        check_coinbase(&coinbase)?;
+        if self.locate_transaction(&coinbase.id).is_some() {
+            return Err("Coinbase id is already on the chain".to_string());
+        }
+        let block = template.block(coinbase, nonce);
+        self.add_block(block)?;
+        if let Some(templates) = self.templates.get_mut(miner) {
+            templates.retain(|template| template.template_id != template_id);
+        }
+        Ok(self.get_latest_block())
+    }
+
+    /// Appends a block produced elsewhere after checking it extends the tip and that
+    /// every transaction in it is valid at the block's height and time.
+    pub fn add_block(&mut self, block: Block) -> Result<(), String> {
+        self.validate_block(&block)?;
+        self.connect_block(block);
+        Ok(())
+    }
+
+    /// Accepts a block from a peer wherever it attaches. A block extending the tip is
+    /// appended; any other block is kept as a side block, and when a side branch
+    /// becomes longer than the main chain the chain reorganizes onto it. Ties keep
+    /// the branch seen first.
+    pub fn submit_block(&mut self, block: Block) -> Result<BlockStatus, String> {
+        if self.block_by_hash(&block.hash).is_some() {
+            return Ok(BlockStatus::Duplicate);
+        }
+        if block.hash != block.calculate_hash() {
+            return Err("Block hash mismatch".to_string());
+        }
+        if !block.has_valid_merkle_root() {
+            return Err("Merkle root does not match the block's transactions".to_string());
+        }
+        self.consensus.verify(&block.header()).map_err(|e| e.to_string())?;
+
+        let status = if block.previous_hash == self.get_latest_block().hash {
+            self.add_block(block)?;
+            // Blocks that arrived before this one may now extend it
+            if let Err(e) = self.adopt_best_branch() {
+                log::warn!("Side branch rejected: {}", e);
+            }
+            Ok(BlockStatus::Extended)
+        } else {
+            let hash = block.hash.clone();
+            self.side_blocks.insert(hash.clone(), block);
+            if self.branch_to(&hash).is_none() {
+                Ok(BlockStatus::Orphan)
+            } else {
+                self.adopt_best_branch().map(|reorganized| match reorganized {
+                    Some((disconnected, connected)) => BlockStatus::Reorganized { disconnected, connected },
+                    None => BlockStatus::SideChain,
+                })
+            }
+        };
+        self.prune_side_blocks();
+        status
+    }
+
+    /// Forgets side blocks too far from the tip to matter, then the furthest ones
+    /// while there are more than [`MAX_SIDE_BLOCKS`]. Ties go by hash so every
+    /// node keeps the same blocks.
+    fn prune_side_blocks(&mut self) {
+        let tip = self.get_latest_block().index;
+        self.side_blocks.retain(|_, block| block.index.abs_diff(tip) <= MAX_SIDE_BLOCK_DEPTH);
+        if self.side_blocks.len() > MAX_SIDE_BLOCKS {
+            let mut by_distance: Vec<(u64, String)> = self.side_blocks.values().map(|block| (block.index.abs_diff(tip), block.hash.clone())).collect();
+            by_distance.sort();
+            for (_, hash) in by_distance.split_off(MAX_SIDE_BLOCKS) {
+                self.side_blocks.remove(&hash);
+            }
+        }
+    }
+
+    /// A block on the main chain or a side branch.
+    pub fn block_by_hash(&self, hash: &str) -> Option<&Block> {
+        self.side_blocks.get(hash).or_else(|| self.chain.iter().rev().find(|block| block.hash == hash))
+    }
+
+    /// The height of the main-chain block a side block's branch forks from, and
+    /// the branch's hashes from the fork up to and including `hash`. `None` while
+    /// some ancestor is missing.
+    fn branch_to(&self, hash: &str) -> Option<(u64, Vec<String>)> {
+        let mut branch = Vec::new();
+        let mut block = self.side_blocks.get(hash)?;
+        loop {
+            branch.push(block.hash.clone());
+            if let Some(parent) = self.side_blocks.get(&block.previous_hash) {
+                block = parent;
+                continue;
+            }
+            let fork = block.index.checked_sub(1)?;
+            if self.chain.get(fork as usize)?.hash != block.previous_hash {
+                return None;
+            }
+            branch.reverse();
+            return Some((fork, branch));
+        }
+    }
+
+    /// Reorganizes onto the longest side branch if it is longer than the main
+    /// chain. Returns the number of blocks disconnected and connected.
+    fn adopt_best_branch(&mut self) -> Result<Option<(usize, usize)>, String> {
+        let tip_height = self.get_latest_block().index;
+        // Equal candidates are ordered by hash so every node picks the same one
+        let best = self
+            .side_blocks
+            .values()
+            .filter(|block| block.index > tip_height)
+            .max_by(|a, b| a.index.cmp(&b.index).then_with(|| b.hash.cmp(&a.hash)))
+            .map(|block| block.hash.clone());
+        match best.and_then(|hash| self.branch_to(&hash)) {
+            Some((fork, branch)) => self.reorganize(fork, branch).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Replaces the main chain above `fork` with `branch`. The state is rebuilt
+    /// from genesis, so nothing has to be undone block by block. If a block of
+    /// the branch is invalid the chain is left as it was and that block and its
+    /// descendants are forgotten; the blocks before it stay side blocks. Transactions of the disconnected blocks go
+    /// back to the mempool if they are still valid.
+    fn reorganize(&mut self, fork: u64, branch: Vec<String>) -> Result<(usize, usize), String> {
+        let chain = self.chain.clone();
+        let filters = self.filters.clone();
+        let pending = std::mem::take(&mut self.pending_transactions);
 
-        self.update_utxos(&new_block);
-        self.chain.push(new_block);
+        let disconnected = self.chain.split_off(fork as usize + 1);
+        self.filters.truncate(fork as usize + 1);
+        self.rebuild_state();
+        for hash in &branch {
+            let block = self.side_blocks[hash].clone();
+            if let Err(e) = self.add_block(block) {
+                self.chain = chain;
+                self.filters = filters;
+                self.pending_transactions = pending;
+                self.rebuild_state();
+                self.discard_side_branch(hash);
+                return Err(format!("Block {} rejected: {}", hash, e));
+            }
+        }
+        for hash in &branch {
+            self.side_blocks.remove(hash);
+        }
+
+        let disconnected_count = disconnected.len();
+        let mut candidates = Vec::new();
+        for block in disconnected {
+            candidates.extend(block.transactions.iter().filter(|tx| !tx.inputs.is_empty()).cloned());
+            self.side_blocks.insert(block.hash.clone(), block);
+        }
+        candidates.extend(pending);
         self.pending_transactions.clear();
+        for tx in candidates {
+            if self.validate_transaction(&tx).and_then(|_| Self::check_standard(&tx)).is_ok() {
+                self.pending_transactions.push(tx);
+            }
+        }
+        Ok((disconnected_count, branch.len()))
+    }
+
+    /// Forgets a side block and every side block built on it.
+    fn discard_side_branch(&mut self, hash: &str) {
+        let mut discarded = vec![hash.to_string()];
+        while let Some(hash) = discarded.pop() {
+            self.side_blocks.remove(&hash);
+            discarded.extend(self.side_blocks.values().filter(|block| block.previous_hash == hash).map(|block| block.hash.clone()));
+        }
+    }
+
+    /// Rebuilds everything derived from the blocks (the UTXO set, the asset
+    /// registry and the block filters) and drops pending transactions that no
+    /// longer apply. Returns how many pending transactions were dropped.
+    pub fn reindex(&mut self) -> usize {
+        self.rebuild_state();
+        self.filters = self.chain.iter().map(BlockFilter::build).collect();
+        let pending = std::mem::take(&mut self.pending_transactions);
+        let before = pending.len();
+        for tx in pending {
+            if self.validate_transaction(&tx).is_ok() {
+                self.pending_transactions.push(tx);
+            }
+        }
+        before - self.pending_transactions.len()
+    }
+
+    /// Recomputes the UTXO set and asset registry by replaying the main chain.
+    fn rebuild_state(&mut self) {
+        self.utxos.clear();
+        self.utxo_heights.clear();
+        self.assets.clear();
+        let chain = std::mem::take(&mut self.chain);
+        for block in &chain {
+            self.update_utxos(block);
+        }
+        self.chain = chain;
+    }
+
+    pub fn validate_block(&self, block: &Block) -> Result<(), String> {
+        let tip = self.get_latest_block();
+        if block.index != self.chain.len() as u64 {
+            return Err(format!("Expected block {}, got {}", self.chain.len(), block.index));
+        }
+        if block.previous_hash != tip.hash {
+            return Err("Block does not extend the current tip".to_string());
+        }
+        if block.hash != block.calculate_hash() {
+            return Err("Block hash mismatch".to_string());
+        }
+        if !block.has_valid_merkle_root() {
+            return Err("Merkle root does not match the block's transactions".to_string());
+        }
+        self.consensus.verify(&block.header()).map_err(|e| e.to_string())?;
+        if block.timestamp < tip.timestamp {
+            return Err("Block timestamp is before its parent".to_string());
+        }
+        // Otherwise one far-future block would drag every later block's timestamp with it
+        if block.timestamp > self.env.now() + MAX_FUTURE_BLOCK_TIME {
+            return Err("Block timestamp is too far in the future".to_string());
+        }
+        self.check_block_rules(block)?;
+
+        // Signatures are verified in parallel first; the UTXO checks then run in block order
+        let signatures = verify_block_signatures(block);
+        let mut spent = HashSet::new();
+        let mut ids = HashSet::new();
+        for tx in &block.transactions {
+            if !ids.insert(tx.id.as_str()) {
+                return Err(format!("Transaction {} appears twice in block", tx.id));
+            }
+        }
+        // The coinbase was checked with the block rules; everything else is a signed spend
+        for tx in &block.transactions[1..] {
+            for input in &tx.inputs {
+                if !spent.insert((input.tx_id.as_str(), input.output_index)) {
+                    return Err(format!("Output {}:{} spent twice in block", input.tx_id, input.output_index));
+                }
+            }
+            self.check_transaction(tx, block.index, block.timestamp, &signatures)
+                .map_err(|e| format!("Invalid transaction {}: {}", tx.id, e))?;
+        }
+        Ok(())
+    }
+
+    /// Checks the rules that change with the block's height (its version, size and
+    /// reward) and that the block opens with exactly one coinbase.
+    fn check_block_rules(&self, block: &Block) -> Result<(), String> {
+        let rules = self.params.rules_at(block.index);
+        if block.version != rules.block_version {
+            return Err(format!("Block version {} where height {} requires {}", block.version, block.index, rules.block_version));
+        }
+        if let Some(max) = rules.max_block_transactions {
+            if block.transactions.len() > max {
+                return Err(format!("Block has {} transactions, more than {}", block.transactions.len(), max));
+            }
+        }
+        let (coinbase, rest) = block.transactions.split_first().ok_or("Block has no coinbase")?;
+        check_coinbase(coinbase)?;
+        if let Some(tx) = rest.iter().find(|tx| tx.has_reserved_sender()) {
+            return Err(format!("Transaction {} claims reserved sender {} outside the coinbase", tx.id, tx.sender_wallet_id));
+        }
+        let paid = coinbase.outputs.iter().fold(0u64, |sum, output| sum.saturating_add(output.amount));
+        if paid > rules.mining_reward {
+            return Err(format!("Reward of {} exceeds {}", paid, rules.mining_reward));
+        }
+        if self.locate_transaction(&coinbase.id).is_some() {
+            return Err("Coinbase id is already on the chain".to_string());
+        }
+        Ok(())
+    }
+
+    fn connect_block(&mut self, block: Block) {
+        self.update_utxos(&block);
+        // Drop pending transactions that were confirmed or conflict with the block
+        let confirmed: HashSet<&str> = block.transactions.iter().map(|tx| tx.id.as_str()).collect();
+        let utxos = &self.utxos;
+        self.pending_transactions.retain(|tx| {
+            !confirmed.contains(tx.id.as_str())
+                && tx.inputs.iter().all(|input| utxos.contains_key(&(input.tx_id.clone(), input.output_index)))
+        });
+        self.filters.push(BlockFilter::build(&block));
+        self.chain.push(block);
     }
 
     pub fn add_transaction(&mut self, transaction: Transaction) -> bool {
-        if transaction.sender_wallet_id.is_empty() || transaction.receiver_wallet_id.is_empty() {
+        if let Err(e) = self.validate_transaction(&transaction).and_then(|_| Self::check_standard(&transaction)) {
+            log::warn!("Transaction {} rejected: {}", transaction.id, e);
             return false;
         }
+        self.pending_transactions.push(transaction);
+        true
+    }
+
+    /// Mempool policy on top of validity: only standard output scripts are relayed.
+    pub fn check_standard(transaction: &Transaction) -> Result<(), String> {
+        if transaction.outputs.len() > MAX_TX_OUTPUTS {
+            return Err(format!("Too many outputs: {} > {}", transaction.outputs.len(), MAX_TX_OUTPUTS));
+        }
+        if transaction.outputs.iter().filter(|output| output.is_unspendable()).count() > 1 {
+            return Err("More than one data output".to_string());
+        }
+        for (i, output) in transaction.outputs.iter().enumerate() {
+            if output.is_unspendable() && (output.amount > 0 || output.asset.is_some()) {
+                return Err(format!("Data output {} burns {} coins", i, output.amount));
+            }
+            if output.memo.as_ref().is_some_and(|memo| memo.len() > MAX_MEMO_LENGTH) {
+                return Err(format!("Memo of output {} is longer than {} bytes", i, MAX_MEMO_LENGTH));
+            }
+            if let Some(script) = &output.script_pubkey {
+                if !script.is_standard() {
+                    return Err(format!("Non-standard script in output {}", i));
+                }
+            }
+        }
+        if let Some(memo) = &transaction.encrypted_note {
+            if memo.plaintext_len() > MAX_MEMO_LENGTH {
+                return Err(format!("Encrypted note is longer than {} bytes", MAX_MEMO_LENGTH));
+            }
+            let recipient_paid = wallet_id_from_public_key_hex(&memo.recipient_public_key)
+                .is_ok_and(|recipient| transaction.outputs.iter().any(|output| output.receiver_wallet_id == recipient));
+            if memo.sender_public_key != transaction.sender_public_key || !recipient_paid {
+                return Err("Encrypted note is not between the sender and a receiver".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks a transaction against the current UTXO set and mempool, as if it
+    /// were included in the next block.
+    pub fn validate_transaction(&self, transaction: &Transaction) -> Result<(), String> {
+        if transaction.has_reserved_sender() {
+            return Err(format!("{} is reserved for transactions made by the node", transaction.sender_wallet_id));
+        }
+        for input in &transaction.inputs {
+            if self.is_spent_in_mempool(&input.tx_id, input.output_index) {
+                return Err(format!("UTXO already spent by a pending transaction: {}:{}", input.tx_id, input.output_index));
+            }
+        }
+        self.check_transaction(transaction, self.chain.len() as u64, self.env.now(), &SignatureCache::default())
+    }
+
+    /// Checks a transaction for inclusion in a block at `height` with time `time`.
+    /// Signatures found in `signatures` are taken as already verified.
+    fn check_transaction(&self, transaction: &Transaction, height: u64, time: i64, signatures: &SignatureCache) -> Result<(), String> {
+        if transaction.sender_wallet_id.is_empty() || transaction.receiver_wallet_id.is_empty() {
+            return Err("Missing sender or receiver".to_string());
+        }
+        if !transaction.is_final(height, time) {
+            return Err(format!("Transaction is locked until {}", transaction.lock_time.unwrap_or_default()));
+        }
         
         // 1. Verify Signature and Hash
-        if !transaction.verify_signature() {
-            println!("Transaction signature verification failed");
-            return false;
+        if !transaction.verify_signature_with(signatures) {
+            return Err("Transaction signature verification failed".to_string());
         }
 
         // 2. Validate Inputs (UTXOs)
-        // Skip UTXO check for system rewards (they have no inputs usually, or special ones)
-        if transaction.sender_wallet_id != "SYSTEM_REWARD" && transaction.sender_wallet_id != "ZAKAT_POOL" {
-            let mut input_sum = 0;
-            for input in &transaction.inputs {
-                if let Some(output) = self.utxos.get(&(input.tx_id.clone(), input.output_index)) {
-                    if output.receiver_wallet_id != transaction.sender_wallet_id {
-                        println!("Input not owned by sender");
-                        return false; // Input not owned by sender
+        if let Some(issuance) = &transaction.issuance {
+            self.check_issuance(transaction, issuance)?;
+        }
+
+        // Single-key spends must come from the key the sender wallet id is derived from
+        let keyless_spend = transaction.is_multisig_spend() || transaction.is_script_spend();
+        if !keyless_spend && wallet_id_from_public_key_hex(&transaction.sender_public_key).ok().as_deref() != Some(transaction.sender_wallet_id.as_str()) {
+            return Err("Sender public key does not match sender wallet".to_string());
+        }
+        if transaction.inputs.is_empty() && self.params.rules_at(height).mint_authority.as_deref() == Some(transaction.sender_wallet_id.as_str()) {
+            return Self::check_mint(transaction, height);
+        }
+
+        let mut input_sums: HashMap<Option<&str>, u64> = HashMap::new();
+        for (i, input) in transaction.inputs.iter().enumerate() {
+            if transaction.inputs[..i].iter().any(|prev| prev.tx_id == input.tx_id && prev.output_index == input.output_index) {
+                return Err(format!("UTXO spent twice by the transaction: {}:{}", input.tx_id, input.output_index));
+            }
+            let key = (input.tx_id.clone(), input.output_index);
+            let output = match self.utxos.get(&key) {
+                Some(output) => output,
+                // UTXO not found (double spend or invalid)
+                None => return Err(format!("UTXO not found for input: {}:{}", input.tx_id, input.output_index)),
+            };
+            if !output.is_unlocked(height, time, self.utxo_heights.get(&key).copied().unwrap_or(height)) {
+                return Err(format!("Input {} is still time-locked", i));
+            }
+            if let Some(script_pubkey) = &output.script_pubkey {
+                self.check_script_input(transaction, input, script_pubkey, height, time)
+                    .map_err(|e| format!("Script failed for input {}: {}", i, e))?;
+                *input_sums.entry(output.asset.as_deref()).or_default() += output.amount;
+                continue;
+            }
+            if output.receiver_wallet_id != transaction.sender_wallet_id {
+                return Err("Input not owned by sender".to_string());
+            }
+            match &input.multisig {
+                Some(witness) => {
+                    if witness.policy.address() != output.receiver_wallet_id {
+                        return Err("Multisig policy does not match the spent address".to_string());
+                    }
+                    if !witness.policy.is_satisfied(transaction.id.as_bytes(), &witness.signatures) {
+                        return Err(format!("Not enough multisig signatures for input {}", i));
+                    }
+                }
+                None => {
+                    if !signatures.verify(&transaction.sender_public_key, input.signing_message().as_bytes(), &input.signature) {
+                        return Err(format!("Invalid signature for input {}", i));
                     }
-                    input_sum += output.amount;
-                } else {
-                    println!("UTXO not found for input: {}:{}", input.tx_id, input.output_index);
-                    return false; // UTXO not found (double spend or invalid)
                 }
             }
+            *input_sums.entry(output.asset.as_deref()).or_default() += output.amount;
+        }
 
-            if input_sum < transaction.amount {
-                println!("Insufficient input balance: {} < {}", input_sum, transaction.amount);
-                return false;
+        let mut output_sums: HashMap<Option<&str>, u64> = HashMap::new();
+        for output in &transaction.outputs {
+            let sum = output_sums.entry(output.asset.as_deref()).or_default();
+            *sum = sum.checked_add(output.amount).ok_or("Output amounts overflow")?;
+        }
+
+        // The native coin may be left behind; assets must carry over exactly,
+        // except the one being issued or minted.
+        let input_sum = input_sums.get(&None).copied().unwrap_or(0);
+        let output_sum = output_sums.get(&None).copied().unwrap_or(0);
+        let native_only = output_sums.keys().all(Option::is_none);
+        if input_sum < output_sum || (native_only && input_sum < transaction.amount) {
+            return Err(format!("Insufficient input balance: {} < {}", input_sum, output_sum.max(transaction.amount)));
+        }
+        let issued = transaction.issued_asset_id();
+        for asset in input_sums.keys().chain(output_sums.keys()).flatten() {
+            if issued.as_deref() == Some(*asset) {
+                continue;
+            }
+            let (spent, created) = (input_sums.get(&Some(*asset)).copied().unwrap_or(0), output_sums.get(&Some(*asset)).copied().unwrap_or(0));
+            if spent != created {
+                return Err(format!("Asset {} does not balance: {} in, {} out", asset, spent, created));
             }
         }
-        
-        self.pending_transactions.push(transaction);
-        true
+        Ok(())
     }
 
-    pub fn create_transaction(&self, sender: &crate::wallet::Wallet, receiver_id: String, amount: u64, note: Option<String>) -> Result<Transaction, String> {
-        let sender_id = sender.get_wallet_id();
+    /// Checks a native coin mint from the mint authority, whose signature has
+    /// already been verified: plain native outputs only, valid at one height.
+    fn check_mint(transaction: &Transaction, height: u64) -> Result<(), String> {
+        if transaction.lock_time != Some(height) {
+            return Err(format!("Mint is not locked to height {}", height));
+        }
+        if transaction.issuance.is_some() || transaction.outputs.is_empty() {
+            return Err("Mint must only create native coins".to_string());
+        }
+        let mut minted = 0u64;
+        for output in &transaction.outputs {
+            if output.asset.is_some() || output.script_pubkey.is_some() || output.amount == 0 {
+                return Err("Mint must only create native coins".to_string());
+            }
+            minted = minted.checked_add(output.amount).ok_or("Output amounts overflow")?;
+        }
+        if minted != transaction.amount {
+            return Err(format!("Mint creates {} but states {}", minted, transaction.amount));
+        }
+        Ok(())
+    }
+
+    /// Checks an issuance or mint against the asset registry. Both must spend an
+    /// input so the transaction cannot be replayed.
+    fn check_issuance(&self, transaction: &Transaction, issuance: &Issuance) -> Result<(), String> {
+        let asset_id = transaction.issued_asset_id().ok_or("Issuance must spend an input")?;
+        match issuance {
+            Issuance::Issue { .. } => {
+                issuance.validate_terms()?;
+                if self.assets.contains_key(&asset_id) {
+                    return Err(format!("Asset {} already exists", asset_id));
+                }
+            }
+            Issuance::Mint { .. } => {
+                let asset = self.assets.get(&asset_id).ok_or_else(|| format!("Unknown asset {}", asset_id))?;
+                if transaction.is_script_spend() || !asset.is_mintable_by(&transaction.sender_wallet_id) {
+                    return Err(format!("{} cannot mint asset {}", transaction.sender_wallet_id, asset_id));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_script_input(&self, transaction: &Transaction, input: &TxInput, script_pubkey: &Script, height: u64, time: i64) -> Result<(), String> {
+        let script_sig = input.script_sig.as_ref().ok_or("Missing unlocking script")?;
+        let utxo_height = self
+            .utxo_heights
+            .get(&(input.tx_id.clone(), input.output_index))
+            .copied()
+            .unwrap_or(height);
+        let ctx = ScriptContext {
+            message: transaction.id.as_bytes(),
+            height,
+            time,
+            utxo_height,
+        };
+        verify_script(script_sig, script_pubkey, &ctx).map_err(|e| e.to_string())
+    }
+
+    fn is_spent_in_mempool(&self, tx_id: &str, output_index: usize) -> bool {
+        self.pending_transactions.iter().any(|tx| {
+            tx.inputs.iter().any(|input| input.tx_id == tx_id && input.output_index == output_index)
+        })
+    }
+
+    pub fn create_transaction(&self, sender: &Wallet, receiver_id: String, amount: u64, note: Option<String>) -> Result<Transaction, String> {
+        let tx = self.create_unsigned_transaction(&sender.get_public_key_hex(), receiver_id, amount, note)?;
+        self.sign_with_wallet(sender, tx)
+    }
+
+    /// Pays `amount` into an output locked by `script_pubkey` instead of a plain wallet id.
+    pub fn create_script_transaction(&self, sender: &Wallet, script_pubkey: Script, amount: u64, note: Option<String>) -> Result<Transaction, String> {
+        self.create_payment(sender, TxOutput::with_script(amount, script_pubkey), note)
+    }
+
+    /// Sends a prepared output, e.g. one with lock times set through
+    /// [`TxOutput::locked_until`] or [`TxOutput::locked_for`].
+    pub fn create_payment(&self, sender: &Wallet, payment: TxOutput, note: Option<String>) -> Result<Transaction, String> {
+        self.create_batch_transaction(sender, vec![payment], note)
+    }
+
+    /// A payment carrying a note encrypted to the receiver, whose ed25519 public key
+    /// is `recipient_public_key`. Only the two parties can read the note.
+    pub fn create_private_payment(&self, sender: &Wallet, payment: TxOutput, recipient_public_key: &str, note: &str) -> Result<Transaction, String> {
+        let recipient_id = wallet_id_from_public_key_hex(recipient_public_key)?;
+        if recipient_id != payment.receiver_wallet_id {
+            return Err("Public key does not belong to the receiver".to_string());
+        }
+        let mut nonce = [0u8; MEMO_NONCE_SIZE];
+        self.env.ids.fill_nonce(&mut nonce);
+        let memo = EncryptedMemo::encrypt_with_nonce(sender, recipient_public_key, note, nonce).map_err(|e| e.to_string())?;
+        let mut tx = self.build_unsigned_transaction(sender.get_wallet_id(), sender.get_public_key_hex(), Spender::Key, vec![payment], None)?;
+        tx.encrypted_note = Some(memo);
+        tx.id = tx.calculate_hash();
+        self.sign_with_wallet(sender, tx)
+    }
+
+    /// Pays every output in `payments` from one selection of the sender's coins,
+    /// in a single signed transaction.
+    pub fn create_batch_transaction(&self, sender: &Wallet, payments: Vec<TxOutput>, note: Option<String>) -> Result<Transaction, String> {
+        let tx = self.build_unsigned_transaction(sender.get_wallet_id(), sender.get_public_key_hex(), Spender::Key, payments, note)?;
+        self.sign_with_wallet(sender, tx)
+    }
+
+    /// Builds one signed transaction per entry of `batches`, each spending coins no
+    /// earlier batch spends, so all of them can sit in the mempool at once. Change is
+    /// not spent before it confirms: a batch the remaining coins cannot cover fails
+    /// alone.
+    pub fn create_batch_transactions(&self, sender: &Wallet, batches: Vec<Vec<TxOutput>>, note: Option<String>) -> Vec<Result<Transaction, String>> {
+        let mut reserved = HashSet::new();
+        batches
+            .into_iter()
+            .map(|payments| {
+                let tx = self.build_unsigned_transaction_avoiding(sender.get_wallet_id(), sender.get_public_key_hex(), Spender::Key, payments, note.clone(), &reserved)?;
+                reserved.extend(tx.inputs.iter().map(|input| (input.tx_id.clone(), input.output_index)));
+                self.sign_with_wallet(sender, tx)
+            })
+            .collect()
+    }
+
+    fn sign_with_wallet(&self, sender: &Wallet, mut tx: Transaction) -> Result<Transaction, String> {
+        // Sign the inputs
+        // Message to sign: tx_id + index.to_string() (Simplified for now, usually sign the whole tx)
+        // In Bitcoin, you sign the transaction hash *after* creating it, but you need the signature *in* the input.
+        // So you sign a modified version of the tx.
+        // For simplicity here, we'll sign the input reference.
+        for input in &mut tx.inputs {
+            let script_locked = self
+                .utxos
+                .get(&(input.tx_id.clone(), input.output_index))
+                .is_some_and(|utxo| utxo.script_pubkey.is_some());
+            if script_locked {
+                // Pay-to-pubkey-hash scripts sign the transaction id
+                let signature = sender.sign_transaction(&tx.id);
+                input.script_sig = Some(Script::p2pkh_unlock(&signature, &sender.get_public_key_hex()).map_err(|e| e.to_string())?);
+            } else {
+                input.signature = sender.sign_transaction(&input.signing_message());
+            }
+        }
+
+        // Sign the transaction ID/Hash
+        tx.signature = sender.sign_transaction(&tx.id);
+
+        Ok(tx)
+    }
+
+    /// Builds a transaction spending the sender's UTXOs without signing it, so the
+    /// signatures can be collected elsewhere (see [`PartiallySignedTransaction`]).
+    pub fn create_unsigned_transaction(&self, sender_public_key: &str, receiver_id: String, amount: u64, note: Option<String>) -> Result<Transaction, String> {
+        let sender_id = wallet_id_from_public_key_hex(sender_public_key)?;
+        self.build_unsigned_transaction(sender_id, sender_public_key.to_string(), Spender::Key, vec![TxOutput::new(amount, receiver_id)], note)
+    }
+
+    /// Builds a PSBT spending coins held by a multisig address. Co-signers add their
+    /// signatures with [`PartiallySignedTransaction::sign`].
+    pub fn create_multisig_psbt(&self, policy: &MultisigPolicy, receiver_id: String, amount: u64, note: Option<String>) -> Result<PartiallySignedTransaction, String> {
+        let tx = self.build_unsigned_transaction(policy.address(), String::new(), Spender::Multisig(policy), vec![TxOutput::new(amount, receiver_id)], note)?;
+        let mut psbt = PartiallySignedTransaction::new(tx);
+        psbt.fill_utxos(&self.utxos);
+        Ok(psbt)
+    }
+
+    /// Builds a PSBT spending outputs locked by `script_pubkey`. Standard single-key and
+    /// multisig scripts are completed by `sign` and `finalize`; for other scripts the
+    /// unlocking script is supplied through [`PsbtInput::final_script_sig`](crate::psbt::PsbtInput).
+    pub fn create_script_psbt(&self, script_pubkey: &Script, receiver_id: String, amount: u64, note: Option<String>) -> Result<PartiallySignedTransaction, String> {
+        let sender_id = TxOutput::with_script(0, script_pubkey.clone()).receiver_wallet_id;
+        let tx = self.build_unsigned_transaction(sender_id, String::new(), Spender::Script(script_pubkey), vec![TxOutput::new(amount, receiver_id)], note)?;
+        let mut psbt = PartiallySignedTransaction::new(tx);
+        psbt.fill_utxos(&self.utxos);
+        Ok(psbt)
+    }
+
+    fn build_unsigned_transaction(&self, sender_id: String, sender_public_key: String, spender: Spender, payments: Vec<TxOutput>, note: Option<String>) -> Result<Transaction, String> {
+        self.build_unsigned_transaction_avoiding(sender_id, sender_public_key, spender, payments, note, &HashSet::new())
+    }
+
+    /// Like `build_unsigned_transaction`, leaving the outputs in `reserved` unspent.
+    fn build_unsigned_transaction_avoiding(
+        &self,
+        sender_id: String,
+        sender_public_key: String,
+        spender: Spender,
+        payments: Vec<TxOutput>,
+        note: Option<String>,
+        reserved: &HashSet<(String, usize)>,
+    ) -> Result<Transaction, String> {
+        if payments.is_empty() {
+            return Err("No payments given".to_string());
+        }
+        if payments.iter().any(|payment| payment.amount == 0 && !payment.is_unspendable()) {
+            return Err("Payment amounts must be positive".to_string());
+        }
+        let amount = payments
+            .iter()
+            .try_fold(0u64, |sum, payment| sum.checked_add(payment.amount))
+            .ok_or("Payment total overflows")?;
+        let receiver_id = match &payments[..] {
+            [first, rest @ ..] if rest.iter().all(|p| p.receiver_wallet_id == first.receiver_wallet_id) => first.receiver_wallet_id.clone(),
+            _ => BATCH_RECEIVER.to_string(),
+        };
+        // Amount needed per asset, the native coin keyed by `None`
+        let mut required: BTreeMap<Option<String>, u64> = BTreeMap::new();
+        for payment in &payments {
+            *required.entry(payment.asset.clone()).or_default() += payment.amount;
+        }
         let mut inputs = Vec::new();
-        let mut input_sum = 0;
+        let mut selected: BTreeMap<Option<String>, u64> = BTreeMap::new();
+        let short = |selected: &BTreeMap<Option<String>, u64>, asset: &Option<String>| {
+            selected.get(asset).copied().unwrap_or(0) < required.get(asset).copied().unwrap_or(0)
+        };
 
-        // 1. Find UTXOs
-        for ((tx_id, index), output) in &self.utxos {
-            if output.receiver_wallet_id == sender_id {
-                input_sum += output.amount;
-                // Sign the input
-                // Message to sign: tx_id + index.to_string() (Simplified for now, usually sign the whole tx)
-                // In Bitcoin, you sign the transaction hash *after* creating it, but you need the signature *in* the input.
-                // So you sign a modified version of the tx.
-                // For simplicity here, we'll sign the input reference.
-                let message = format!("{}{}", tx_id, index);
-                let signature = sender.sign_transaction(&message);
-                
-                inputs.push(crate::transaction::TxInput {
-                    tx_id: tx_id.clone(),
-                    output_index: *index,
-                    signature,
-                });
+        // 1. Find UTXOs, oldest first so the choice does not depend on map order.
+        // Explicit script spends may pick outputs that are still locked, so they can
+        // be signed ahead of the release. At least one coin is spent even when nothing
+        // is owed, so every transaction consumes an input.
+        let (height, time) = (self.chain.len() as u64, self.env.now());
+        let mut candidates: Vec<(&(String, usize), &TxOutput)> = self.utxos.iter().collect();
+        candidates.sort_by_key(|(key, _)| (self.utxo_heights.get(*key).copied().unwrap_or(height), *key));
+        for ((tx_id, index), output) in candidates {
+            let needed = short(&selected, &output.asset) || (inputs.is_empty() && output.asset.is_none());
+            let unlocked = matches!(spender, Spender::Script(_)) || self.is_unlocked(tx_id, *index, output, height, time);
+            let available = !self.is_spent_in_mempool(tx_id, *index) && !reserved.contains(&(tx_id.clone(), *index));
+            if needed && spender.can_spend(output, &sender_id) && unlocked && available {
+                *selected.entry(output.asset.clone()).or_default() += output.amount;
+                let mut input = TxInput::new(tx_id.clone(), *index);
+                if let Spender::Multisig(policy) = spender {
+                    input.multisig = Some(MultisigWitness::new(policy.clone()));
+                }
+                inputs.push(input);
 
-                if input_sum >= amount {
+                if !required.keys().any(|asset| short(&selected, asset)) {
                     break;
                 }
             }
         }
 
-        if input_sum < amount {
+        if required.keys().any(|asset| short(&selected, asset)) || inputs.is_empty() {
             return Err("Insufficient balance".to_string());
         }
 
         // 2. Create Outputs
-        let mut outputs = Vec::new();
-        outputs.push(TxOutput {
-            amount,
-            receiver_wallet_id: receiver_id.clone(),
-        });
+        let mut outputs = payments;
 
-        if input_sum > amount {
-            outputs.push(TxOutput {
-                amount: input_sum - amount,
-                receiver_wallet_id: sender_id.clone(),
-            });
+        for (asset, input_sum) in selected {
+            let owed = required.get(&asset).copied().unwrap_or(0);
+            if input_sum > owed {
+                // Change stays under the same lock
+                let change = match spender {
+                    Spender::Script(script) => TxOutput::with_script(input_sum - owed, script.clone()),
+                    _ => TxOutput::new(input_sum - owed, sender_id.clone()),
+                };
+                outputs.push(match asset {
+                    Some(asset) => change.with_asset(asset),
+                    None => change,
+                });
+            }
         }
 
         // 3. Create Transaction
@@ -157,56 +924,454 @@ impl Blockchain {
             receiver_wallet_id: receiver_id,
             amount,
             note,
-            timestamp: chrono::Utc::now().timestamp(),
-            sender_public_key: sender.get_public_key_hex(),
+            timestamp: self.env.now(),
+            sender_public_key,
             signature: String::new(), // Sign the whole tx
             inputs,
             outputs,
+            lock_time: None,
+            encrypted_note: None,
+            issuance: None,
         };
 
         tx.id = tx.calculate_hash();
-        
-        // Sign the transaction ID/Hash
-        tx.signature = sender.sign_transaction(&tx.id);
 
         Ok(tx)
     }
 
+    /// Locks `amount` from `sender` in an HTLC. The contract is output 0 of the returned transaction.
+    pub fn create_htlc(&self, sender: &Wallet, htlc: &Htlc, amount: u64, note: Option<String>) -> Result<Transaction, String> {
+        let script = htlc.script().map_err(|e| e.to_string())?;
+        self.create_script_transaction(sender, script, amount, note)
+    }
+
+    /// Spends an HTLC output to its recipient by revealing `secret`.
+    pub fn claim_htlc(&self, recipient: &Wallet, tx_id: &str, output_index: usize, secret: &[u8]) -> Result<Transaction, String> {
+        self.spend_htlc(recipient, tx_id, output_index, |htlc, message| htlc.claim_script_sig(recipient, message, secret))
+    }
+
+    /// Returns an expired HTLC output to its refund wallet.
+    pub fn refund_htlc(&self, refund: &Wallet, tx_id: &str, output_index: usize) -> Result<Transaction, String> {
+        self.spend_htlc(refund, tx_id, output_index, |htlc, message| htlc.refund_script_sig(refund, message))
+    }
+
+    fn spend_htlc<F>(&self, spender: &Wallet, tx_id: &str, output_index: usize, unlock: F) -> Result<Transaction, String>
+    where
+        F: FnOnce(&Htlc, &str) -> Result<Script, ScriptError>,
+    {
+        let output = self
+            .utxos
+            .get(&(tx_id.to_string(), output_index))
+            .ok_or("HTLC output not found or already spent")?;
+        let htlc = output.script_pubkey.as_ref().and_then(Htlc::from_script).ok_or("Output is not an HTLC")?;
+        let receiver_id = spender.get_wallet_id();
+
+        let mut tx = Transaction {
+            id: String::new(),
+            sender_wallet_id: output.receiver_wallet_id.clone(),
+            receiver_wallet_id: receiver_id.clone(),
+            amount: output.amount,
+            note: None,
+            timestamp: self.env.now(),
+            sender_public_key: String::new(),
+            signature: String::new(),
+            inputs: vec![TxInput::new(tx_id.to_string(), output_index)],
+            outputs: vec![TxOutput::new(output.amount, receiver_id)],
+            lock_time: None,
+            encrypted_note: None,
+            issuance: None,
+        };
+        tx.id = tx.calculate_hash();
+        tx.inputs[0].script_sig = Some(unlock(&htlc, &tx.id).map_err(|e| e.to_string())?);
+        Ok(tx)
+    }
+
+    /// The contract behind an HTLC output and whether it is still open, claimed or refunded.
+    /// A claim carries the revealed secret.
+    pub fn htlc_state(&self, tx_id: &str, output_index: usize) -> Option<(Htlc, HtlcState)> {
+        let output = self.find_transaction(tx_id)?.outputs.get(output_index)?;
+        let htlc = Htlc::from_script(output.script_pubkey.as_ref()?)?;
+
+        let state = match self.find_spend(tx_id, output_index) {
+            Some((spend, confirmed)) => {
+                let secret = spend
+                    .inputs
+                    .iter()
+                    .find(|input| input.tx_id == tx_id && input.output_index == output_index)
+                    .and_then(|input| input.script_sig.as_ref())
+                    .and_then(|script_sig| htlc.extract_secret(script_sig));
+                match secret {
+                    Some(secret) => HtlcState::Claimed { tx_id: spend.id.clone(), preimage: hex::encode(secret), confirmed },
+                    None => HtlcState::Refunded { tx_id: spend.id.clone(), confirmed },
+                }
+            }
+            None if lock_time_reached(htlc.timeout, self.chain.len() as u64, self.env.now()) => HtlcState::Expired,
+            None => HtlcState::Active,
+        };
+        Some((htlc, state))
+    }
+
+    /// New native coins for `receiver_id`, signed by the mint authority of the
+    /// rules in force. The mint is only valid in the next block: its lock time
+    /// names that height, so it can never be replayed, and a mint whose block is
+    /// reorganized away has to be made again.
+    pub fn create_mint(&self, authority: &Wallet, receiver_id: String, amount: u64, note: Option<String>) -> Result<Transaction, String> {
+        if amount == 0 {
+            return Err("Mint amount must be positive".to_string());
+        }
+        let height = self.chain.len() as u64;
+        if self.params.rules_at(height).mint_authority.as_deref() != Some(authority.get_wallet_id().as_str()) {
+            return Err("Wallet is not the mint authority".to_string());
+        }
+        let mut tx = Transaction {
+            id: String::new(),
+            sender_wallet_id: authority.get_wallet_id(),
+            receiver_wallet_id: receiver_id.clone(),
+            amount,
+            note,
+            timestamp: self.env.now(),
+            sender_public_key: authority.get_public_key_hex(),
+            signature: String::new(),
+            inputs: Vec::new(),
+            outputs: vec![TxOutput::new(amount, receiver_id)],
+            lock_time: Some(height),
+            encrypted_note: None,
+            issuance: None,
+        };
+        tx.id = tx.calculate_hash();
+        tx.signature = authority.sign_transaction(&tx.id);
+        Ok(tx)
+    }
+
+    /// Anchors a document's SHA-256 (hex) in a data output of a transaction from `sender`.
+    pub fn create_notary_transaction(&self, sender: &Wallet, document_hash: &str, note: Option<String>) -> Result<Transaction, String> {
+        let hash = match hex::decode(document_hash) {
+            Ok(bytes) if bytes.len() == 32 => bytes,
+            _ => return Err("Document hash must be a hex encoded SHA-256".to_string()),
+        };
+        let output = TxOutput::data(&hash).map_err(|e| e.to_string())?;
+        self.create_payment(sender, output, note)
+    }
+
+    /// Receipt for a confirmed notary transaction, verifiable with [`crate::notary::verify_receipt`].
+    pub fn notary_receipt(&self, tx_id: &str) -> Option<NotaryReceipt> {
+        let (block, index) = self.locate_transaction(tx_id)?;
+        let transaction = &block.transactions[index];
+        let (output_index, payload) = transaction
+            .outputs
+            .iter()
+            .enumerate()
+            .find_map(|(i, output)| output.data_payload().map(|payload| (i, payload)))?;
+        Some(NotaryReceipt {
+            document_hash: hex::encode(payload),
+            transaction: transaction.clone(),
+            output_index,
+            block: block.header(),
+            merkle_proof: merkle_proof(&block.transactions, index)?,
+        })
+    }
+
+    /// Issues a new asset, crediting `supply` units to the issuer. The asset id is
+    /// [`Transaction::issued_asset_id`] of the returned transaction.
+    pub fn create_asset(&self, issuer: &Wallet, name: String, decimals: u8, policy: SupplyPolicy, supply: u64, note: Option<String>) -> Result<Transaction, String> {
+        let issuance = Issuance::Issue { name, decimals, policy };
+        issuance.validate_terms()?;
+        self.create_issuance(issuer, issuance, issuer.get_wallet_id(), supply, note)
+    }
+
+    /// Mints `amount` more units of a mintable asset to `receiver_id`.
+    pub fn mint_asset(&self, admin: &Wallet, asset_id: &str, receiver_id: String, amount: u64, note: Option<String>) -> Result<Transaction, String> {
+        let asset = self.assets.get(asset_id).ok_or_else(|| format!("Unknown asset {}", asset_id))?;
+        if !asset.is_mintable_by(&admin.get_wallet_id()) {
+            return Err(format!("Asset {} cannot be minted by this wallet", asset_id));
+        }
+        self.create_issuance(admin, Issuance::Mint { asset_id: asset_id.to_string() }, receiver_id, amount, note)
+    }
+
+    /// Spends one of the sender's coins back to them, anchoring the issuance, and
+    /// creates `amount` units of the asset for `receiver_id`.
+    fn create_issuance(&self, sender: &Wallet, issuance: Issuance, receiver_id: String, amount: u64, note: Option<String>) -> Result<Transaction, String> {
+        if amount == 0 {
+            return Err("Issued amount must be positive".to_string());
+        }
+        let sender_id = sender.get_wallet_id();
+        let (height, time) = (self.chain.len() as u64, self.env.now());
+        let ((tx_id, index), anchor) = self
+            .utxos
+            .iter()
+            .find(|((tx_id, index), output)| {
+                output.asset.is_none()
+                    && Spender::Key.can_spend(output, &sender_id)
+                    && self.is_unlocked(tx_id, *index, output, height, time)
+                    && !self.is_spent_in_mempool(tx_id, *index)
+            })
+            .ok_or("Issuing needs a spendable coin to anchor the asset")?;
+        let input = TxInput::new(tx_id.clone(), *index);
+        let asset_id = issuance.asset_id(&input);
+
+        let mut tx = Transaction {
+            id: String::new(),
+            sender_wallet_id: sender_id.clone(),
+            receiver_wallet_id: receiver_id.clone(),
+            amount,
+            note,
+            timestamp: time,
+            sender_public_key: sender.get_public_key_hex(),
+            signature: String::new(),
+            inputs: vec![input],
+            outputs: vec![
+                TxOutput::new(amount, receiver_id).with_asset(asset_id),
+                TxOutput::new(anchor.amount, sender_id),
+            ],
+            lock_time: None,
+            encrypted_note: None,
+            issuance: Some(issuance),
+        };
+        tx.id = tx.calculate_hash();
+        self.sign_with_wallet(sender, tx)
+    }
+
+    /// Up to `limit` block headers starting at height `from`, for light clients.
+    pub fn headers(&self, from: u64, limit: usize) -> Vec<BlockHeader> {
+        self.chain.iter().skip(from as usize).take(limit).map(Block::header).collect()
+    }
+
+    /// Up to `limit` compact block filters starting at height `from`.
+    pub fn filters(&self, from: u64, limit: usize) -> &[BlockFilter] {
+        let start = (from as usize).min(self.filters.len());
+        let end = start.saturating_add(limit).min(self.filters.len());
+        &self.filters[start..end]
+    }
+
+    /// Merkle proof that a confirmed transaction is in its block.
+    pub fn transaction_proof(&self, tx_id: &str) -> Option<TransactionProof> {
+        let (block, index) = self.locate_transaction(tx_id)?;
+        Some(TransactionProof {
+            transaction: block.transactions[index].clone(),
+            block_index: block.index,
+            merkle_proof: merkle_proof(&block.transactions, index)?,
+        })
+    }
+
+    /// Proofs for every confirmed transaction involving `wallet_id`, oldest first.
+    pub fn wallet_proofs(&self, wallet_id: &str) -> Vec<TransactionProof> {
+        self.chain
+            .iter()
+            .flat_map(|block| {
+                block.transactions.iter().enumerate().filter(|(_, tx)| tx.involves(wallet_id)).filter_map(move |(index, tx)| {
+                    Some(TransactionProof {
+                        transaction: tx.clone(),
+                        block_index: block.index,
+                        merkle_proof: merkle_proof(&block.transactions, index)?,
+                    })
+                })
+            })
+            .collect()
+    }
+
+    /// The block holding a confirmed transaction and its position in the block.
+    fn locate_transaction(&self, tx_id: &str) -> Option<(&Block, usize)> {
+        self.chain
+            .iter()
+            .find_map(|block| block.transactions.iter().position(|tx| tx.id == tx_id).map(|index| (block, index)))
+    }
+
+    /// Looks a transaction up in the chain, then in the mempool.
+    pub fn find_transaction(&self, tx_id: &str) -> Option<&Transaction> {
+        self.chain
+            .iter()
+            .flat_map(|block| &block.transactions)
+            .chain(&self.pending_transactions)
+            .find(|tx| tx.id == tx_id)
+    }
+
+    /// The transaction spending an output, and whether it is confirmed.
+    fn find_spend(&self, tx_id: &str, output_index: usize) -> Option<(&Transaction, bool)> {
+        let spends = |tx: &&Transaction| tx.inputs.iter().any(|input| input.tx_id == tx_id && input.output_index == output_index);
+        self.chain
+            .iter()
+            .flat_map(|block| &block.transactions)
+            .find(spends)
+            .map(|tx| (tx, true))
+            .or_else(|| self.pending_transactions.iter().find(spends).map(|tx| (tx, false)))
+    }
+
+    /// Builds an unsigned transaction and wraps it in a PSBT carrying the spent UTXOs,
+    /// ready to be handed to (possibly offline) signers.
+    pub fn create_psbt(&self, sender_public_key: &str, receiver_id: String, amount: u64, note: Option<String>) -> Result<PartiallySignedTransaction, String> {
+        let tx = self.create_unsigned_transaction(sender_public_key, receiver_id, amount, note)?;
+        let mut psbt = PartiallySignedTransaction::new(tx);
+        psbt.fill_utxos(&self.utxos);
+        Ok(psbt)
+    }
+
     fn update_utxos(&mut self, block: &Block) {
         for tx in &block.transactions {
+            if let (Some(issuance), Some(asset_id)) = (&tx.issuance, tx.issued_asset_id()) {
+                self.record_issuance(tx, issuance, asset_id);
+            }
             // Remove spent outputs
             for input in &tx.inputs {
-                self.utxos.remove(&(input.tx_id.clone(), input.output_index));
+                let key = (input.tx_id.clone(), input.output_index);
+                self.utxos.remove(&key);
+                self.utxo_heights.remove(&key);
             }
-            // Add new outputs
-            for (index, output) in tx.outputs.iter().enumerate() {
+            // Add new outputs. Unspendable data outputs are never tracked.
+            for (index, output) in tx.outputs.iter().enumerate().filter(|(_, output)| !output.is_unspendable()) {
                 self.utxos.insert((tx.id.clone(), index), output.clone());
+                self.utxo_heights.insert((tx.id.clone(), index), block.index);
+            }
+        }
+    }
+
+    /// Adds a confirmed issuance or mint to the asset registry. Must run before the
+    /// transaction's inputs are removed from the UTXO set.
+    fn record_issuance(&mut self, tx: &Transaction, issuance: &Issuance, asset_id: String) {
+        let units = |output: &TxOutput| if output.asset.as_deref() == Some(asset_id.as_str()) { output.amount } else { 0 };
+        let created: u64 = tx.outputs.iter().map(units).sum();
+        let spent: u64 = tx
+            .inputs
+            .iter()
+            .filter_map(|input| self.utxos.get(&(input.tx_id.clone(), input.output_index)))
+            .map(units)
+            .sum();
+        let minted = created.saturating_sub(spent);
+        match issuance {
+            Issuance::Issue { name, decimals, policy } => {
+                self.assets.insert(asset_id.clone(), AssetDefinition {
+                    asset_id,
+                    name: name.clone(),
+                    decimals: *decimals,
+                    policy: policy.clone(),
+                    issuer_wallet_id: tx.sender_wallet_id.clone(),
+                    issuance_tx_id: tx.id.clone(),
+                    supply: minted,
+                });
+            }
+            Issuance::Mint { .. } => {
+                if let Some(asset) = self.assets.get_mut(&asset_id) {
+                    asset.supply = asset.supply.saturating_add(minted);
+                }
             }
         }
     }
 
+    /// Native coin balance of `address`.
     pub fn get_balance(&self, address: &str) -> u64 {
         let mut balance = 0;
         for output in self.utxos.values() {
-            if output.receiver_wallet_id == address {
+            if output.receiver_wallet_id == address && output.asset.is_none() {
                 balance += output.amount;
             }
         }
         balance
     }
 
+    /// Splits the balance into what can be spent in the next block and what is still time-locked.
+    pub fn get_balance_details(&self, address: &str) -> Balance {
+        let (height, time) = (self.chain.len() as u64, self.env.now());
+        let mut balance = Balance::default();
+        for ((tx_id, index), output) in &self.utxos {
+            if output.receiver_wallet_id != address || output.asset.is_some() {
+                continue;
+            }
+            if self.is_unlocked(tx_id, *index, output, height, time) {
+                balance.spendable += output.amount;
+            } else {
+                balance.locked += output.amount;
+            }
+        }
+        balance
+    }
+
+    /// Balances of `address` in every issued asset it holds, keyed by asset id.
+    pub fn get_asset_balances(&self, address: &str) -> BTreeMap<String, Balance> {
+        let (height, time) = (self.chain.len() as u64, self.env.now());
+        let mut balances: BTreeMap<String, Balance> = BTreeMap::new();
+        for ((tx_id, index), output) in &self.utxos {
+            let asset = match &output.asset {
+                Some(asset) if output.receiver_wallet_id == address => asset,
+                _ => continue,
+            };
+            let balance = balances.entry(asset.clone()).or_default();
+            if self.is_unlocked(tx_id, *index, output, height, time) {
+                balance.spendable += output.amount;
+            } else {
+                balance.locked += output.amount;
+            }
+        }
+        balances
+    }
+
+    fn is_unlocked(&self, tx_id: &str, index: usize, output: &TxOutput, height: u64, time: i64) -> bool {
+        let confirmed_height = self.utxo_heights.get(&(tx_id.to_string(), index)).copied().unwrap_or(height);
+        output.is_unlocked(height, time, confirmed_height)
+    }
+
     pub fn is_chain_valid(&self) -> bool {
         for i in 1..self.chain.len() {
             let current_block = &self.chain[i];
             let previous_block = &self.chain[i - 1];
 
-            if current_block.hash != current_block.calculate_hash() {
+            if current_block.hash != current_block.calculate_hash() || !current_block.has_valid_merkle_root() {
                 return false;
             }
             if current_block.previous_hash != previous_block.hash {
                 return false;
             }
+            if self.consensus.verify(&current_block.header()).is_err() {
+                return false;
+            }
         }
         true
     }
 }
+
+/// What [`Blockchain::submit_block`] did with a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockStatus {
+    /// Appended to the main chain.
+    Extended,
+    /// Its branch replaced the top `disconnected` blocks of the main chain with `connected` blocks.
+    Reorganized { disconnected: usize, connected: usize },
+    /// Kept on a side branch that is not longer than the main chain.
+    SideChain,
+    /// Kept until its parent arrives.
+    Orphan,
+    /// Already known.
+    Duplicate,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Balance {
+    pub spendable: u64,
+    pub locked: u64,
+}
+
+impl Balance {
+    pub fn total(&self) -> u64 {
+        self.spendable + self.locked
+    }
+}
+
+/// Who authorises the inputs of a transaction being built, which decides the UTXOs it may select.
+#[derive(Clone, Copy)]
+enum Spender<'a> {
+    Key,
+    Multisig(&'a MultisigPolicy),
+    Script(&'a Script),
+}
+
+impl Spender<'_> {
+    fn can_spend(&self, output: &TxOutput, sender_id: &str) -> bool {
+        match (self, &output.script_pubkey) {
+            (Spender::Key, None) | (Spender::Multisig(_), None) => output.receiver_wallet_id == sender_id,
+            // Timelocked and other script outputs need an explicit script spend
+            (Spender::Key, Some(script)) => {
+                output.receiver_wallet_id == sender_id && matches!(script.template(), Some(ScriptTemplate::PayToPubkeyHash { .. }))
+            }
+            (Spender::Script(lock), Some(script)) => *lock == script,
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Block production and seal validation.
+//!
+//! [`Blockchain`](crate::Blockchain) hands every block it produces to its
+//! [`Consensus`] engine to be sealed, and asks the engine to check the seal of
+//! every block it accepts. Transaction rules are the same under every engine.
+
+use thiserror::Error;
+
+use crate::block::{Block, BlockHeader};
+use crate::transaction::verify_hex_signature;
+use crate::wallet::Wallet;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ConsensusError {
+    #[error("block hash does not meet the difficulty target")]
+    InsufficientWork,
+    #[error("this node has no validator key")]
+    NotAValidator,
+    #[error("block {index} is for validator {expected} to seal")]
+    WrongTurn { index: u64, expected: String },
+    #[error("missing or invalid validator signature")]
+    BadSignature,
+    #[error("invalid validator set: {0}")]
+    InvalidValidators(String),
+}
+
+pub trait Consensus: Send + Sync {
+    /// Short name for status reports, e.g. "pow".
+    fn name(&self) -> &'static str;
+
+    /// Seals a block, setting its hash and any seal fields.
+    fn seal(&self, block: &mut Block) -> Result<(), ConsensusError>;
+
+    /// Checks the seal of a block header, which is all light clients have. The
+    /// caller checks the hash matches the header and the header the block.
+    fn verify(&self, header: &BlockHeader) -> Result<(), ConsensusError>;
+
+    /// Leading zero hex digits a block hash needs, if blocks are sealed by work
+    /// that a miner outside the node can do.
+    fn work_target(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Blocks are sealed by finding a nonce whose hash starts with `difficulty` zeros.
+pub struct ProofOfWork {
+    pub difficulty: usize,
+}
+
+impl ProofOfWork {
+    pub fn new(difficulty: usize) -> Self {
+        ProofOfWork { difficulty }
+    }
+}
+
+impl Consensus for ProofOfWork {
+    fn name(&self) -> &'static str {
+        "pow"
+    }
+
+    fn seal(&self, block: &mut Block) -> Result<(), ConsensusError> {
+        block.mine_block(self.difficulty);
+        Ok(())
+    }
+
+    fn verify(&self, header: &BlockHeader) -> Result<(), ConsensusError> {
+        if header.hash.starts_with(&"0".repeat(self.difficulty)) {
+            Ok(())
+        } else {
+            Err(ConsensusError::InsufficientWork)
+        }
+    }
+
+    fn work_target(&self) -> Option<usize> {
+        Some(self.difficulty)
+    }
+}
+
+/// A fixed set of validators seal blocks in turn: block `i` is signed by
+/// validator `i % n`. No work is done, so sealing is instant.
+pub struct ProofOfAuthority {
+    /// Hex encoded ed25519 public keys, in turn order.
+    validators: Vec<String>,
+    /// This node's validator key, if it is one.
+    signer: Option<Wallet>,
+}
+
+impl ProofOfAuthority {
+    pub fn new(validators: Vec<String>, signer: Option<Wallet>) -> Result<Self, ConsensusError> {
+        if validators.is_empty() {
+            return Err(ConsensusError::InvalidValidators("no validators".to_string()));
+        }
+        let validators: Vec<String> = validators.into_iter().map(|key| key.to_lowercase()).collect();
+        for (i, key) in validators.iter().enumerate() {
+            if crate::wallet::wallet_id_from_public_key_hex(key).is_err() {
+                return Err(ConsensusError::InvalidValidators(format!("bad public key {}", key)));
+            }
+            if validators[..i].contains(key) {
+                return Err(ConsensusError::InvalidValidators(format!("duplicate key {}", key)));
+            }
+        }
+        if let Some(signer) = &signer {
+            if !validators.contains(&signer.get_public_key_hex()) {
+                return Err(ConsensusError::NotAValidator);
+            }
+        }
+        Ok(ProofOfAuthority { validators, signer })
+    }
+
+    pub fn validators(&self) -> &[String] {
+        &self.validators
+    }
+
+    /// Public key of the validator whose turn it is to seal block `index`.
+    pub fn validator_for(&self, index: u64) -> &str {
+        &self.validators[(index % self.validators.len() as u64) as usize]
+    }
+}
+
+impl Consensus for ProofOfAuthority {
+    fn name(&self) -> &'static str {
+        "poa"
+    }
+
+    fn seal(&self, block: &mut Block) -> Result<(), ConsensusError> {
+        let signer = self.signer.as_ref().ok_or(ConsensusError::NotAValidator)?;
+        let expected = self.validator_for(block.index);
+        if signer.get_public_key_hex() != expected {
+            return Err(ConsensusError::WrongTurn { index: block.index, expected: expected.to_string() });
+        }
+        block.hash = block.calculate_hash();
+        block.validator = Some(expected.to_string());
+        block.signature = Some(signer.sign_transaction(&block.hash));
+        Ok(())
+    }
+
+    fn verify(&self, header: &BlockHeader) -> Result<(), ConsensusError> {
+        let expected = self.validator_for(header.index);
+        if header.validator.as_deref() != Some(expected) {
+            return Err(ConsensusError::WrongTurn { index: header.index, expected: expected.to_string() });
+        }
+        match &header.signature {
+            Some(signature) if verify_hex_signature(expected, header.hash.as_bytes(), signature) => Ok(()),
+            _ => Err(ConsensusError::BadSignature),
+        }
+    }
+}
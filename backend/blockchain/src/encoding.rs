@@ -0,0 +1,540 @@
+//! Canonical binary encoding of blocks and transactions.
+//!
+//! Fields are written in declaration order: integers little-endian, strings and
+//! byte strings behind a `u32` length, options as a `0`/`1` tag followed by the
+//! value, sequences behind a `u32` count and maps in key order. A value encoded
+//! on its own starts with [`ENCODING_VERSION`] so the layout can change without
+//! making older data unreadable.
+//!
+//! Transaction ids, block hashes and Merkle leaves are hashes of these bytes, and
+//! blocks and transactions are stored and exchanged in this form. JSON is only a
+//! presentation of them for the API. The hash preimages are consensus rules, so
+//! they stay at [`PREIMAGE_VERSION`] when the encoding moves on; a block hash only
+//! changes layout with the block version (see [`crate::params`]).
+
+use std::collections::BTreeMap;
+
+use thiserror::Error;
+
+use crate::asset::{Issuance, SupplyPolicy};
+use crate::block::{Block, BlockHeader, INITIAL_BLOCK_VERSION, VERSIONED_HASH_FROM};
+use crate::memo::EncryptedMemo;
+use crate::multisig::{MultisigPolicy, MultisigWitness};
+use crate::script::Script;
+use crate::transaction::{Transaction, TxInput, TxOutput};
+
+/// Current version of the encoding. Decoding accepts every version up to it.
+/// Version 2 added the block header version.
+pub const ENCODING_VERSION: u8 = 2;
+
+/// Layout version the hash preimages are written in.
+pub const PREIMAGE_VERSION: u8 = 1;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum EncodingError {
+    #[error("unexpected end of data")]
+    UnexpectedEnd,
+    #[error("invalid utf-8")]
+    InvalidUtf8,
+    #[error("invalid tag {0}")]
+    InvalidTag(u8),
+    #[error("unsupported encoding version {0}")]
+    UnsupportedVersion(u8),
+    #[error("trailing bytes")]
+    TrailingBytes,
+    #[error("non-canonical encoding: {0}")]
+    NonCanonical(String),
+}
+
+pub trait Encode {
+    /// Writes the value without a version prefix, for embedding in a larger value.
+    fn encode_to(&self, w: &mut Writer);
+
+    /// The value on its own, behind the encoding version.
+    fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.u8(ENCODING_VERSION);
+        self.encode_to(&mut w);
+        w.into_bytes()
+    }
+}
+
+pub trait Decode: Sized {
+    /// Reads a value written by [`Encode::encode_to`] under encoding `version`.
+    fn decode_from(r: &mut Reader, version: u8) -> Result<Self, EncodingError>;
+
+    /// Reads a value written by [`Encode::encode`], rejecting trailing bytes.
+    fn decode(bytes: &[u8]) -> Result<Self, EncodingError> {
+        let mut r = Reader::new(bytes);
+        let version = r.u8()?;
+        if version == 0 || version > ENCODING_VERSION {
+            return Err(EncodingError::UnsupportedVersion(version));
+        }
+        let value = Self::decode_from(&mut r, version)?;
+        r.finish()?;
+        Ok(value)
+    }
+}
+
+impl Encode for TxOutput {
+    fn encode_to(&self, w: &mut Writer) {
+        w.u64(self.amount);
+        w.str(&self.receiver_wallet_id);
+        w.opt_bytes(self.script_pubkey.as_ref().map(|s| s.as_bytes()));
+        w.opt_u64(self.lock_time);
+        w.opt_u64(self.relative_lock);
+        w.opt_str(self.memo.as_deref());
+        w.opt_str(self.asset.as_deref());
+    }
+}
+
+impl Decode for TxOutput {
+    fn decode_from(r: &mut Reader, _version: u8) -> Result<Self, EncodingError> {
+        Ok(TxOutput {
+            amount: r.u64()?,
+            receiver_wallet_id: r.str()?,
+            script_pubkey: r.opt_bytes()?.map(Script::from_bytes),
+            lock_time: r.opt_u64()?,
+            relative_lock: r.opt_u64()?,
+            memo: r.opt_str()?,
+            asset: r.opt_str()?,
+        })
+    }
+}
+
+impl Encode for TxInput {
+    fn encode_to(&self, w: &mut Writer) {
+        w.str(&self.tx_id);
+        w.u64(self.output_index as u64);
+        w.str(&self.signature);
+        match &self.multisig {
+            Some(witness) => {
+                w.u8(1);
+                w.u64(witness.policy.threshold as u64);
+                w.length(witness.policy.public_keys.len());
+                for key in &witness.policy.public_keys {
+                    w.str(key);
+                }
+                w.map(&witness.signatures);
+            }
+            None => w.u8(0),
+        }
+        w.opt_bytes(self.script_sig.as_ref().map(|s| s.as_bytes()));
+    }
+}
+
+impl Decode for TxInput {
+    fn decode_from(r: &mut Reader, _version: u8) -> Result<Self, EncodingError> {
+        let tx_id = r.str()?;
+        let output_index = r.u64()? as usize;
+        let signature = r.str()?;
+        // Policies are decoded as written; whether they are sound is for validation to decide
+        let multisig = if r.flag()? {
+            let threshold = r.u64()? as usize;
+            let mut public_keys = Vec::new();
+            for _ in 0..r.length()? {
+                public_keys.push(r.str()?);
+            }
+            Some(MultisigWitness { policy: MultisigPolicy { threshold, public_keys }, signatures: r.map()? })
+        } else {
+            None
+        };
+        let script_sig = r.opt_bytes()?.map(Script::from_bytes);
+        Ok(TxInput { tx_id, output_index, signature, multisig, script_sig })
+    }
+}
+
+impl Encode for Transaction {
+    fn encode_to(&self, w: &mut Writer) {
+        w.str(&self.id);
+        w.str(&self.sender_wallet_id);
+        w.str(&self.receiver_wallet_id);
+        w.u64(self.amount);
+        w.opt_str(self.note.as_deref());
+        w.i64(self.timestamp);
+        w.str(&self.sender_public_key);
+        w.str(&self.signature);
+        w.length(self.inputs.len());
+        for input in &self.inputs {
+            input.encode_to(w);
+        }
+        encode_outputs_and_extensions(self, w);
+    }
+}
+
+impl Decode for Transaction {
+    fn decode_from(r: &mut Reader, version: u8) -> Result<Self, EncodingError> {
+        let id = r.str()?;
+        let sender_wallet_id = r.str()?;
+        let receiver_wallet_id = r.str()?;
+        let amount = r.u64()?;
+        let note = r.opt_str()?;
+        let timestamp = r.i64()?;
+        let sender_public_key = r.str()?;
+        let signature = r.str()?;
+        let mut inputs = Vec::new();
+        for _ in 0..r.length()? {
+            inputs.push(TxInput::decode_from(r, version)?);
+        }
+        let mut outputs = Vec::new();
+        for _ in 0..r.length()? {
+            outputs.push(TxOutput::decode_from(r, version)?);
+        }
+        let lock_time = r.opt_u64()?;
+        let encrypted_note = if r.flag()? {
+            Some(EncryptedMemo {
+                sender_public_key: r.str()?,
+                recipient_public_key: r.str()?,
+                nonce: r.str()?,
+                ciphertext: r.str()?,
+            })
+        } else {
+            None
+        };
+        let issuance = match r.u8()? {
+            0 => None,
+            1 => {
+                let name = r.str()?;
+                let decimals = r.u8()?;
+                let policy = if r.flag()? { SupplyPolicy::Mintable { admin_wallet_id: r.str()? } } else { SupplyPolicy::Fixed };
+                Some(Issuance::Issue { name, decimals, policy })
+            }
+            2 => Some(Issuance::Mint { asset_id: r.str()? }),
+            tag => return Err(EncodingError::InvalidTag(tag)),
+        };
+        Ok(Transaction {
+            id,
+            sender_wallet_id,
+            receiver_wallet_id,
+            amount,
+            note,
+            timestamp,
+            sender_public_key,
+            signature,
+            inputs,
+            outputs,
+            lock_time,
+            encrypted_note,
+            issuance,
+        })
+    }
+}
+
+/// The bytes a transaction id is the hash of: everything but the id itself and
+/// the signatures, witnesses and unlocking scripts that authorise it.
+pub fn transaction_id_preimage(transaction: &Transaction) -> Vec<u8> {
+    let mut w = Writer::default();
+    w.u8(PREIMAGE_VERSION);
+    w.str(&transaction.sender_wallet_id);
+    w.str(&transaction.receiver_wallet_id);
+    w.u64(transaction.amount);
+    w.opt_str(transaction.note.as_deref());
+    w.i64(transaction.timestamp);
+    w.str(&transaction.sender_public_key);
+    w.length(transaction.inputs.len());
+    for input in &transaction.inputs {
+        w.str(&input.tx_id);
+        w.u64(input.output_index as u64);
+    }
+    encode_outputs_and_extensions(transaction, &mut w);
+    w.into_bytes()
+}
+
+fn encode_outputs_and_extensions(transaction: &Transaction, w: &mut Writer) {
+    w.length(transaction.outputs.len());
+    for output in &transaction.outputs {
+        output.encode_to(w);
+    }
+    w.opt_u64(transaction.lock_time);
+    match &transaction.encrypted_note {
+        Some(memo) => {
+            w.u8(1);
+            w.str(&memo.sender_public_key);
+            w.str(&memo.recipient_public_key);
+            w.str(&memo.nonce);
+            w.str(&memo.ciphertext);
+        }
+        None => w.u8(0),
+    }
+    match &transaction.issuance {
+        Some(Issuance::Issue { name, decimals, policy }) => {
+            w.u8(1);
+            w.str(name);
+            w.u8(*decimals);
+            match policy {
+                SupplyPolicy::Fixed => w.u8(0),
+                SupplyPolicy::Mintable { admin_wallet_id } => {
+                    w.u8(1);
+                    w.str(admin_wallet_id);
+                }
+            }
+        }
+        Some(Issuance::Mint { asset_id }) => {
+            w.u8(2);
+            w.str(asset_id);
+        }
+        None => w.u8(0),
+    }
+}
+
+impl Encode for BlockHeader {
+    fn encode_to(&self, w: &mut Writer) {
+        w.u32(self.version);
+        w.u64(self.index);
+        w.i64(self.timestamp);
+        w.str(&self.previous_hash);
+        w.str(&self.merkle_root);
+        w.u64(self.nonce);
+        w.str(&self.hash);
+        w.opt_str(self.validator.as_deref());
+        w.opt_str(self.signature.as_deref());
+    }
+}
+
+impl Decode for BlockHeader {
+    fn decode_from(r: &mut Reader, encoding: u8) -> Result<Self, EncodingError> {
+        Ok(BlockHeader {
+            // Version 1 encodings predate header versions; their blocks are all version 1
+            version: if encoding >= 2 { r.u32()? } else { INITIAL_BLOCK_VERSION },
+            index: r.u64()?,
+            timestamp: r.i64()?,
+            previous_hash: r.str()?,
+            merkle_root: r.str()?,
+            nonce: r.u64()?,
+            hash: r.str()?,
+            validator: r.opt_str()?,
+            signature: r.opt_str()?,
+        })
+    }
+}
+
+/// A block is its header followed by its transactions.
+impl Encode for Block {
+    fn encode_to(&self, w: &mut Writer) {
+        self.header().encode_to(w);
+        w.length(self.transactions.len());
+        for tx in &self.transactions {
+            tx.encode_to(w);
+        }
+    }
+}
+
+impl Decode for Block {
+    fn decode_from(r: &mut Reader, version: u8) -> Result<Self, EncodingError> {
+        let header = BlockHeader::decode_from(r, version)?;
+        let mut transactions = Vec::new();
+        for _ in 0..r.length()? {
+            transactions.push(Transaction::decode_from(r, version)?);
+        }
+        Ok(Block {
+            version: header.version,
+            index: header.index,
+            timestamp: header.timestamp,
+            transactions,
+            previous_hash: header.previous_hash,
+            merkle_root: header.merkle_root,
+            nonce: header.nonce,
+            hash: header.hash,
+            validator: header.validator,
+            signature: header.signature,
+        })
+    }
+}
+
+/// The bytes a Merkle leaf is the hash of: the whole transaction, signatures included.
+pub fn transaction_leaf_preimage(transaction: &Transaction) -> Vec<u8> {
+    let mut w = Writer::default();
+    w.u8(PREIMAGE_VERSION);
+    transaction.encode_to(&mut w);
+    w.into_bytes()
+}
+
+/// The bytes a block hash is the hash of: the header fields the seal commits to.
+/// From [`VERSIONED_HASH_FROM`] on that includes the block version.
+pub fn block_hash_preimage(version: u32, index: u64, timestamp: i64, merkle_root: &str, previous_hash: &str, nonce: u64) -> Vec<u8> {
+    let mut w = Writer::default();
+    w.u8(PREIMAGE_VERSION);
+    if version >= VERSIONED_HASH_FROM {
+        w.u32(version);
+    }
+    w.u64(index);
+    w.i64(timestamp);
+    w.str(previous_hash);
+    w.str(merkle_root);
+    w.u64(nonce);
+    w.into_bytes()
+}
+
+#[derive(Default)]
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn i64(&mut self, value: i64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn length(&mut self, len: usize) {
+        self.buf.extend_from_slice(&(len as u32).to_le_bytes());
+    }
+
+    pub fn str(&mut self, value: &str) {
+        self.byte_string(value.as_bytes());
+    }
+
+    /// Bytes behind their length.
+    pub fn byte_string(&mut self, bytes: &[u8]) {
+        self.length(bytes.len());
+        self.bytes(bytes);
+    }
+
+    pub fn opt_str(&mut self, value: Option<&str>) {
+        match value {
+            Some(s) => {
+                self.u8(1);
+                self.str(s);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    pub fn opt_u64(&mut self, value: Option<u64>) {
+        match value {
+            Some(n) => {
+                self.u8(1);
+                self.u64(n);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    pub fn opt_bytes(&mut self, value: Option<&[u8]>) {
+        match value {
+            Some(bytes) => {
+                self.u8(1);
+                self.byte_string(bytes);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    pub fn map(&mut self, map: &BTreeMap<String, String>) {
+        self.length(map.len());
+        for (key, value) in map {
+            self.str(key);
+            self.str(value);
+        }
+    }
+}
+
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Reader { buf, pos: 0 }
+    }
+
+    /// Fails unless every byte has been read.
+    pub fn finish(&self) -> Result<(), EncodingError> {
+        if self.pos == self.buf.len() { Ok(()) } else { Err(EncodingError::TrailingBytes) }
+    }
+
+    pub fn take(&mut self, n: usize) -> Result<&'a [u8], EncodingError> {
+        if self.buf.len() - self.pos < n {
+            return Err(EncodingError::UnexpectedEnd);
+        }
+        let slice = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, EncodingError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Result<u32, EncodingError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().expect("slice of length 4")))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, EncodingError> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().expect("slice of length 8")))
+    }
+
+    pub fn i64(&mut self) -> Result<i64, EncodingError> {
+        let bytes = self.take(8)?;
+        Ok(i64::from_le_bytes(bytes.try_into().expect("slice of length 8")))
+    }
+
+    pub fn length(&mut self) -> Result<usize, EncodingError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().expect("slice of length 4")) as usize)
+    }
+
+    pub fn str(&mut self) -> Result<String, EncodingError> {
+        String::from_utf8(self.byte_string()?).map_err(|_| EncodingError::InvalidUtf8)
+    }
+
+    pub fn byte_string(&mut self) -> Result<Vec<u8>, EncodingError> {
+        let len = self.length()?;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    pub fn flag(&mut self) -> Result<bool, EncodingError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(EncodingError::InvalidTag(tag)),
+        }
+    }
+
+    pub fn opt_u64(&mut self) -> Result<Option<u64>, EncodingError> {
+        if self.flag()? { Ok(Some(self.u64()?)) } else { Ok(None) }
+    }
+
+    pub fn opt_bytes(&mut self) -> Result<Option<Vec<u8>>, EncodingError> {
+        if self.flag()? { Ok(Some(self.byte_string()?)) } else { Ok(None) }
+    }
+
+    pub fn opt_str(&mut self) -> Result<Option<String>, EncodingError> {
+        if self.flag()? { Ok(Some(self.str()?)) } else { Ok(None) }
+    }
+
+    pub fn map(&mut self) -> Result<BTreeMap<String, String>, EncodingError> {
+        let mut map = BTreeMap::new();
+        for _ in 0..self.length()? {
+            let key = self.str()?;
+            let value = self.str()?;
+            if map.last_key_value().is_some_and(|(last, _)| *last >= key) {
+                return Err(EncodingError::NonCanonical("map keys out of order".to_string()));
+            }
+            map.insert(key, value);
+        }
+        Ok(map)
+    }
+}
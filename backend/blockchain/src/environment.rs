@@ -0,0 +1,129 @@
+//! Where the chain gets the current time and fresh ids from.
+//!
+//! [`Blockchain`](crate::Blockchain) never reads the system clock or a random
+//! number generator directly; it asks its [`Environment`]. Nodes use
+//! [`Environment::system`]. Tests and simulations use [`Environment::deterministic`],
+//! under which the same calls produce byte-identical chains.
+
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+pub trait Clock: Send + Sync {
+    /// Current unix time in seconds.
+    fn now(&self) -> i64;
+}
+
+/// Ids for transactions that have no inputs to derive one from, and nonces.
+pub trait IdSource: Send + Sync {
+    fn next_id(&self) -> String;
+
+    fn fill_nonce(&self, nonce: &mut [u8]);
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        chrono::Utc::now().timestamp()
+    }
+}
+
+/// A clock that only moves when told to.
+pub struct ManualClock {
+    time: AtomicI64,
+}
+
+impl ManualClock {
+    pub fn new(time: i64) -> Self {
+        ManualClock { time: AtomicI64::new(time) }
+    }
+
+    pub fn set(&self, time: i64) {
+        self.time.store(time, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, seconds: i64) {
+        self.time.fetch_add(seconds, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> i64 {
+        self.time.load(Ordering::SeqCst)
+    }
+}
+
+/// Random v4 UUIDs and nonces from the operating system.
+pub struct RandomIds;
+
+impl IdSource for RandomIds {
+    fn next_id(&self) -> String {
+        uuid::Uuid::new_v4().to_string()
+    }
+
+    fn fill_nonce(&self, nonce: &mut [u8]) {
+        rand::rngs::OsRng.fill_bytes(nonce);
+    }
+}
+
+/// Ids and nonces hashed from a seed and a counter, so the sequence repeats for the same seed.
+pub struct SeededIds {
+    seed: u64,
+    counter: AtomicU64,
+}
+
+impl SeededIds {
+    pub fn new(seed: u64) -> Self {
+        SeededIds { seed, counter: AtomicU64::new(0) }
+    }
+
+    fn next(&self, domain: &[u8]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(domain);
+        hasher.update(self.seed.to_le_bytes());
+        hasher.update(self.counter.fetch_add(1, Ordering::SeqCst).to_le_bytes());
+        hasher.finalize().into()
+    }
+}
+
+impl IdSource for SeededIds {
+    fn next_id(&self) -> String {
+        hex::encode(self.next(b"walx id"))
+    }
+
+    fn fill_nonce(&self, nonce: &mut [u8]) {
+        for chunk in nonce.chunks_mut(32) {
+            chunk.copy_from_slice(&self.next(b"walx nonce")[..chunk.len()]);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Environment {
+    pub clock: Arc<dyn Clock>,
+    pub ids: Arc<dyn IdSource>,
+}
+
+impl Environment {
+    pub fn system() -> Self {
+        Environment { clock: Arc::new(SystemClock), ids: Arc::new(RandomIds) }
+    }
+
+    /// Time from `clock`, which only moves when the caller moves it, and ids derived from `seed`.
+    pub fn deterministic(clock: Arc<ManualClock>, seed: u64) -> Self {
+        Environment { clock, ids: Arc::new(SeededIds::new(seed)) }
+    }
+
+    pub fn now(&self) -> i64 {
+        self.clock.now()
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::system()
+    }
+}
@@ -0,0 +1,87 @@
+//! Hash-time-locked contracts (HTLCs) for escrow and cross-chain atomic swaps.
+//!
+//! An HTLC output pays `recipient_wallet_id` if they reveal the secret behind
+//! `hash_lock`, and otherwise returns to `refund_wallet_id` once `timeout` (a block
+//! height, or a unix time when at least `LOCKTIME_THRESHOLD`) has passed. Claiming
+//! publishes the secret on chain, which is what lets the other side of a swap
+//! claim the matching HTLC on a second chain.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::script::{Instruction, Script, ScriptError, ScriptTemplate};
+use crate::wallet::Wallet;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Htlc {
+    /// Hex encoded SHA-256 of the secret.
+    pub hash_lock: String,
+    pub recipient_wallet_id: String,
+    pub refund_wallet_id: String,
+    pub timeout: u64,
+}
+
+/// Where an HTLC output stands on a given chain.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum HtlcState {
+    /// Unspent, and only the recipient can spend it.
+    Active,
+    /// Unspent and past its timeout, so the refund path is open as well.
+    Expired,
+    /// Spent by the recipient. `preimage` is the hex encoded secret.
+    Claimed { tx_id: String, preimage: String, confirmed: bool },
+    Refunded { tx_id: String, confirmed: bool },
+}
+
+impl Htlc {
+    pub fn new(hash_lock: String, recipient_wallet_id: String, refund_wallet_id: String, timeout: u64) -> Result<Self, ScriptError> {
+        let htlc = Htlc { hash_lock: hash_lock.to_lowercase(), recipient_wallet_id, refund_wallet_id, timeout };
+        htlc.script()?;
+        Ok(htlc)
+    }
+
+    /// Hex encoded SHA-256 of `secret`, suitable as a `hash_lock`.
+    pub fn hash_secret(secret: &[u8]) -> String {
+        hex::encode(Sha256::digest(secret))
+    }
+
+    pub fn script(&self) -> Result<Script, ScriptError> {
+        Script::htlc(&self.hash_lock, &self.recipient_wallet_id, &self.refund_wallet_id, self.timeout)
+    }
+
+    pub fn from_script(script: &Script) -> Option<Self> {
+        match script.template()? {
+            ScriptTemplate::Htlc { hash, recipient_wallet_id, refund_wallet_id, timeout } => {
+                Some(Htlc { hash_lock: hash, recipient_wallet_id, refund_wallet_id, timeout })
+            }
+            _ => None,
+        }
+    }
+
+    /// Unlocking script for the claim path. `message` is the spending transaction's id.
+    pub fn claim_script_sig(&self, recipient: &Wallet, message: &str, secret: &[u8]) -> Result<Script, ScriptError> {
+        if Self::hash_secret(secret) != self.hash_lock {
+            return Err(ScriptError::EqualVerify);
+        }
+        let signature = hex::decode(recipient.sign_transaction(message)).map_err(|_| ScriptError::BadPush)?;
+        let public_key = hex::decode(recipient.get_public_key_hex()).map_err(|_| ScriptError::BadPush)?;
+        Ok(Script::new().push_slice(&signature).push_slice(&public_key).push_slice(secret).push_int(1))
+    }
+
+    /// Unlocking script for the refund path. `message` is the spending transaction's id.
+    pub fn refund_script_sig(&self, refund: &Wallet, message: &str) -> Result<Script, ScriptError> {
+        let signature = hex::decode(refund.sign_transaction(message)).map_err(|_| ScriptError::BadPush)?;
+        let public_key = hex::decode(refund.get_public_key_hex()).map_err(|_| ScriptError::BadPush)?;
+        Ok(Script::new().push_slice(&signature).push_slice(&public_key).push_int(0))
+    }
+
+    /// Finds the secret in an unlocking script that claimed this HTLC.
+    pub fn extract_secret(&self, script_sig: &Script) -> Option<Vec<u8>> {
+        let instructions = script_sig.instructions().ok()?;
+        instructions.iter().find_map(|instruction| match instruction {
+            Instruction::Push(data) if Self::hash_secret(data) == self.hash_lock => Some(data.to_vec()),
+            _ => None,
+        })
+    }
+}
@@ -0,0 +1,134 @@
+//! Password-protected key files for wallets kept outside the server.
+//!
+//! The secret key is sealed with ChaCha20-Poly1305 under a key stretched from the
+//! password with Argon2id. The wallet id and public key are stored in the clear,
+//! so a wallet can be identified without its password, and are bound to the
+//! ciphertext as associated data so they cannot be swapped for another wallet's.
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::wallet::Wallet;
+
+pub const KEYSTORE_VERSION: u32 = 1;
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum KeystoreError {
+    #[error("unsupported key file version {0}")]
+    UnsupportedVersion(u32),
+    #[error("wrong password or damaged key file")]
+    Decryption,
+    #[error("malformed key file: {0}")]
+    Malformed(String),
+}
+
+/// Argon2id cost settings, kept in the file so they can be raised for new files
+/// without locking out old ones.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams { memory_kib: Params::DEFAULT_M_COST, iterations: Params::DEFAULT_T_COST, parallelism: Params::DEFAULT_P_COST }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Keystore {
+    pub version: u32,
+    pub wallet_id: String,
+    pub public_key: String,
+    pub kdf: KdfParams,
+    /// Hex encoded Argon2 salt, 96-bit nonce and sealed secret key.
+    pub salt: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+impl Keystore {
+    pub fn encrypt(wallet: &Wallet, password: &str) -> Result<Self, KeystoreError> {
+        Self::encrypt_with(wallet, password, KdfParams::default())
+    }
+
+    pub fn encrypt_with(wallet: &Wallet, password: &str, kdf: KdfParams) -> Result<Self, KeystoreError> {
+        let mut salt = [0u8; SALT_SIZE];
+        let mut nonce = [0u8; NONCE_SIZE];
+        rand::rngs::OsRng.fill_bytes(&mut salt);
+        rand::rngs::OsRng.fill_bytes(&mut nonce);
+        let wallet_id = wallet.get_wallet_id();
+        let public_key = wallet.get_public_key_hex();
+
+        let cipher = key_cipher(password, &salt, kdf)?;
+        let aad = associated_data(&wallet_id, &public_key);
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: wallet.keypair.secret.as_bytes(), aad: &aad })
+            .map_err(|_| KeystoreError::Malformed("encryption failed".to_string()))?;
+
+        Ok(Keystore {
+            version: KEYSTORE_VERSION,
+            wallet_id,
+            public_key,
+            kdf,
+            salt: hex::encode(salt),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+
+    pub fn decrypt(&self, password: &str) -> Result<Wallet, KeystoreError> {
+        if self.version != KEYSTORE_VERSION {
+            return Err(KeystoreError::UnsupportedVersion(self.version));
+        }
+        let salt = hex::decode(&self.salt).map_err(|_| KeystoreError::Malformed("salt is not hex".to_string()))?;
+        let nonce = hex::decode(&self.nonce).map_err(|_| KeystoreError::Malformed("nonce is not hex".to_string()))?;
+        if nonce.len() != NONCE_SIZE {
+            return Err(KeystoreError::Malformed("bad nonce length".to_string()));
+        }
+        let ciphertext = hex::decode(&self.ciphertext).map_err(|_| KeystoreError::Malformed("ciphertext is not hex".to_string()))?;
+
+        let cipher = key_cipher(password, &salt, self.kdf)?;
+        let aad = associated_data(&self.wallet_id, &self.public_key);
+        let secret = cipher
+            .decrypt(Nonce::from_slice(&nonce), Payload { msg: &ciphertext, aad: &aad })
+            .map_err(|_| KeystoreError::Decryption)?;
+        let wallet = Wallet::from_private_key(&hex::encode(secret)).map_err(KeystoreError::Malformed)?;
+        if wallet.get_wallet_id() != self.wallet_id {
+            return Err(KeystoreError::Malformed("key does not belong to the wallet id".to_string()));
+        }
+        Ok(wallet)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("key files serialize")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, KeystoreError> {
+        serde_json::from_str(json).map_err(|e| KeystoreError::Malformed(e.to_string()))
+    }
+}
+
+fn key_cipher(password: &str, salt: &[u8], kdf: KdfParams) -> Result<ChaCha20Poly1305, KeystoreError> {
+    let params = Params::new(kdf.memory_kib, kdf.iterations, kdf.parallelism, Some(32)).map_err(|e| KeystoreError::Malformed(e.to_string()))?;
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password.as_bytes(), salt, &mut key)
+        .map_err(|e| KeystoreError::Malformed(e.to_string()))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+fn associated_data(wallet_id: &str, public_key: &str) -> Vec<u8> {
+    let mut aad = b"walx keystore v1".to_vec();
+    aad.extend_from_slice(wallet_id.as_bytes());
+    aad.extend_from_slice(public_key.as_bytes());
+    aad
+}
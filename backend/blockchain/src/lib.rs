@@ -2,10 +2,48 @@ pub mod wallet;
 pub mod transaction;
 pub mod chain;
 pub mod block;
+pub mod psbt;
+pub mod multisig;
+pub mod script;
+pub mod htlc;
+pub mod merkle;
+pub mod notary;
+pub mod memo;
+pub mod asset;
+pub mod consensus;
+pub mod light_client;
+pub mod filter;
+pub mod sigcheck;
+pub mod encoding;
+pub mod environment;
+pub mod params;
+pub mod template;
+pub mod archive;
+pub mod keystore;
+pub mod spend;
 
 mod tests;
 
-pub use block::Block;
-pub use transaction::{Transaction, TxInput, TxOutput};
-pub use chain::Blockchain;
+pub use block::{Block, BlockHeader};
+pub use transaction::{Transaction, TxInput, TxOutput, BATCH_RECEIVER, DATA_RECEIVER, RESERVED_SENDERS};
+pub use chain::{Balance, BlockStatus, Blockchain};
 pub use wallet::Wallet;
+pub use psbt::{PartiallySignedTransaction, PsbtError};
+pub use multisig::{MultisigPolicy, MultisigWitness};
+pub use script::{Script, ScriptError};
+pub use htlc::{Htlc, HtlcState};
+pub use merkle::MerkleProof;
+pub use notary::{verify_receipt, NotaryReceipt};
+pub use memo::{EncryptedMemo, MemoError};
+pub use asset::{AssetDefinition, Issuance, SupplyPolicy};
+pub use consensus::{Consensus, ConsensusError, ProofOfAuthority, ProofOfWork};
+pub use light_client::{LightClient, LightClientError, TransactionProof};
+pub use filter::{BlockFilter, FilterError, WalletScanner};
+pub use sigcheck::{SignatureCache, SignatureCheck};
+pub use encoding::{Decode, Encode, EncodingError};
+pub use environment::{Clock, Environment, IdSource, ManualClock};
+pub use params::{ChainParams, ParamsError, RuleSet};
+pub use template::BlockTemplate;
+pub use archive::{ArchiveError, ChainArchive, InvalidBlock, VerifyReport};
+pub use keystore::{KdfParams, Keystore, KeystoreError};
+pub use spend::{build_payment, Coin};
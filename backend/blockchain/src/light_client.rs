@@ -0,0 +1,159 @@
+//! Light client: follows the chain by block headers only and checks its wallet's
+//! transactions against them with Merkle proofs (simplified payment verification).
+//!
+//! A full node can withhold transactions but cannot forge them: every transaction
+//! the client accepts is proven to sit in a block whose header it has checked for
+//! linkage and a valid seal. The balance is therefore a lower bound if the node
+//! leaves some of the wallet's transactions out.
+
+use std::collections::{BTreeMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::block::BlockHeader;
+use crate::consensus::Consensus;
+use crate::merkle::{verify_merkle_proof, MerkleProof};
+use crate::transaction::Transaction;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum LightClientError {
+    #[error("expected header {expected}, got {got}")]
+    OutOfOrder { expected: u64, got: u64 },
+    #[error("header {0} does not link to the previous header")]
+    BrokenLink(u64),
+    #[error("header {0} does not match its hash")]
+    BadHash(u64),
+    #[error("header {index} has an invalid seal: {reason}")]
+    BadSeal { index: u64, reason: String },
+    #[error("header {0} is older than its parent")]
+    BadTimestamp(u64),
+    #[error("no header at height {0}")]
+    UnknownBlock(u64),
+    #[error("proof does not match the header at height {0}")]
+    BadProof(u64),
+    #[error("transaction does not involve this wallet")]
+    NotOurs,
+}
+
+/// A transaction with the Merkle path tying it to the block at `block_index`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransactionProof {
+    pub transaction: Transaction,
+    pub block_index: u64,
+    pub merkle_proof: MerkleProof,
+}
+
+pub struct LightClient {
+    consensus: Box<dyn Consensus>,
+    wallet_id: String,
+    headers: Vec<BlockHeader>,
+    /// Transactions proven to be in the header chain, by id, with their block height.
+    transactions: BTreeMap<String, (Transaction, u64)>,
+}
+
+impl LightClient {
+    /// Starts from a trusted genesis (or checkpoint) header.
+    pub fn new(consensus: Box<dyn Consensus>, genesis: BlockHeader, wallet_id: String) -> Self {
+        LightClient {
+            consensus,
+            wallet_id,
+            headers: vec![genesis],
+            transactions: BTreeMap::new(),
+        }
+    }
+
+    pub fn tip(&self) -> &BlockHeader {
+        self.headers.last().expect("light client always holds its starting header")
+    }
+
+    pub fn headers(&self) -> &[BlockHeader] {
+        &self.headers
+    }
+
+    /// Height of the next header expected.
+    pub fn next_height(&self) -> u64 {
+        self.tip().index + 1
+    }
+
+    /// Appends headers extending the tip, stopping at the first invalid one.
+    /// Returns how many were added.
+    pub fn sync_headers(&mut self, headers: &[BlockHeader]) -> Result<usize, LightClientError> {
+        for (added, header) in headers.iter().enumerate() {
+            if let Err(e) = self.check_header(header) {
+                return if added == 0 { Err(e) } else { Ok(added) };
+            }
+            self.headers.push(header.clone());
+        }
+        Ok(headers.len())
+    }
+
+    fn check_header(&self, header: &BlockHeader) -> Result<(), LightClientError> {
+        let tip = self.tip();
+        if header.index != tip.index + 1 {
+            return Err(LightClientError::OutOfOrder { expected: tip.index + 1, got: header.index });
+        }
+        if header.previous_hash != tip.hash {
+            return Err(LightClientError::BrokenLink(header.index));
+        }
+        if header.calculate_hash() != header.hash {
+            return Err(LightClientError::BadHash(header.index));
+        }
+        if header.timestamp < tip.timestamp {
+            return Err(LightClientError::BadTimestamp(header.index));
+        }
+        self.consensus
+            .verify(header)
+            .map_err(|e| LightClientError::BadSeal { index: header.index, reason: e.to_string() })
+    }
+
+    fn header_at(&self, height: u64) -> Option<&BlockHeader> {
+        let offset = height.checked_sub(self.headers[0].index)?;
+        self.headers.get(offset as usize)
+    }
+
+    /// Checks a proof against the synced headers and records the transaction.
+    pub fn verify_transaction(&mut self, proof: &TransactionProof) -> Result<(), LightClientError> {
+        let header = self.header_at(proof.block_index).ok_or(LightClientError::UnknownBlock(proof.block_index))?;
+        if !verify_merkle_proof(&proof.transaction, &proof.merkle_proof, &header.merkle_root) {
+            return Err(LightClientError::BadProof(proof.block_index));
+        }
+        if !proof.transaction.involves(&self.wallet_id) {
+            return Err(LightClientError::NotOurs);
+        }
+        self.transactions
+            .insert(proof.transaction.id.clone(), (proof.transaction.clone(), proof.block_index));
+        Ok(())
+    }
+
+    /// Verified transactions with their block heights, oldest first.
+    pub fn transactions(&self) -> Vec<(&Transaction, u64)> {
+        let mut transactions: Vec<_> = self.transactions.values().map(|(tx, height)| (tx, *height)).collect();
+        transactions.sort_by_key(|(_, height)| *height);
+        transactions
+    }
+
+    pub fn confirmations(&self, tx_id: &str) -> Option<u64> {
+        self.transactions.get(tx_id).map(|(_, height)| self.tip().index + 1 - height)
+    }
+
+    /// Native coin in verified outputs to the wallet that no verified transaction spends.
+    pub fn balance(&self) -> u64 {
+        let spent: HashSet<(&str, usize)> = self
+            .transactions
+            .values()
+            .flat_map(|(tx, _)| tx.inputs.iter().map(|input| (input.tx_id.as_str(), input.output_index)))
+            .collect();
+        self.transactions
+            .values()
+            .flat_map(|(tx, _)| tx.outputs.iter().enumerate().map(move |(index, output)| (tx.id.as_str(), index, output)))
+            .filter(|(tx_id, index, output)| {
+                output.receiver_wallet_id == self.wallet_id
+                    && output.asset.is_none()
+                    && !output.is_unspendable()
+                    && !spent.contains(&(*tx_id, *index))
+            })
+            .map(|(_, _, output)| output.amount)
+            .sum()
+    }
+}
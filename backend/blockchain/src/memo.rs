@@ -0,0 +1,150 @@
+//! End-to-end encrypted transaction memos.
+//!
+//! Both parties' ed25519 keys are mapped to X25519 keys (the Edwards public key to
+//! its Montgomery form, the secret to the clamped scalar ed25519 derives from it).
+//! Their Diffie-Hellman secret is the same from either side, so the sender and the
+//! recipient can both decrypt the memo and nobody else can. The memo key is derived
+//! from that secret with HKDF-SHA256 and the text sealed with ChaCha20-Poly1305.
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use curve25519_dalek::scalar::Scalar;
+use hkdf::Hkdf;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use thiserror::Error;
+
+use crate::wallet::Wallet;
+
+const KEY_INFO: &[u8] = b"walx memo v1";
+/// Bytes in a memo nonce.
+pub const MEMO_NONCE_SIZE: usize = 12;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum MemoError {
+    #[error("invalid public key")]
+    InvalidPublicKey,
+    #[error("wallet is neither the sender nor the recipient of the memo")]
+    NotAParty,
+    #[error("malformed memo: {0}")]
+    Malformed(String),
+    #[error("memo could not be decrypted")]
+    Decryption,
+}
+
+/// A memo only the two wallets named in it can read.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EncryptedMemo {
+    /// Hex encoded ed25519 public keys of the two parties.
+    pub sender_public_key: String,
+    pub recipient_public_key: String,
+    /// Hex encoded 96-bit nonce.
+    pub nonce: String,
+    /// Hex encoded ciphertext followed by the 16-byte authentication tag.
+    pub ciphertext: String,
+}
+
+impl EncryptedMemo {
+    pub fn encrypt(sender: &Wallet, recipient_public_key: &str, memo: &str) -> Result<Self, MemoError> {
+        let mut nonce = [0u8; MEMO_NONCE_SIZE];
+        rand::rngs::OsRng.fill_bytes(&mut nonce);
+        Self::encrypt_with_nonce(sender, recipient_public_key, memo, nonce)
+    }
+
+    /// [`EncryptedMemo::encrypt`] with a caller supplied nonce, which must never be
+    /// reused for the same pair of wallets.
+    pub fn encrypt_with_nonce(sender: &Wallet, recipient_public_key: &str, memo: &str, nonce: [u8; MEMO_NONCE_SIZE]) -> Result<Self, MemoError> {
+        let sender_public_key = sender.get_public_key_hex();
+        let recipient_public_key = recipient_public_key.to_lowercase();
+        let cipher = memo_cipher(sender, &recipient_public_key, &sender_public_key, &recipient_public_key)?;
+
+        let aad = associated_data(&sender_public_key, &recipient_public_key);
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: memo.as_bytes(), aad: &aad })
+            .map_err(|_| MemoError::Malformed("encryption failed".to_string()))?;
+
+        Ok(EncryptedMemo {
+            sender_public_key,
+            recipient_public_key,
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+
+    /// Decrypts the memo with the key of either party.
+    pub fn decrypt(&self, wallet: &Wallet) -> Result<String, MemoError> {
+        let own_key = wallet.get_public_key_hex();
+        let counterparty = if own_key == self.recipient_public_key {
+            &self.sender_public_key
+        } else if own_key == self.sender_public_key {
+            &self.recipient_public_key
+        } else {
+            return Err(MemoError::NotAParty);
+        };
+        let cipher = memo_cipher(wallet, counterparty, &self.sender_public_key, &self.recipient_public_key)?;
+
+        let nonce = hex::decode(&self.nonce).map_err(|_| MemoError::Malformed("nonce is not hex".to_string()))?;
+        if nonce.len() != MEMO_NONCE_SIZE {
+            return Err(MemoError::Malformed("bad nonce length".to_string()));
+        }
+        let ciphertext = hex::decode(&self.ciphertext).map_err(|_| MemoError::Malformed("ciphertext is not hex".to_string()))?;
+        let aad = associated_data(&self.sender_public_key, &self.recipient_public_key);
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(&nonce), Payload { msg: &ciphertext, aad: &aad })
+            .map_err(|_| MemoError::Decryption)?;
+        String::from_utf8(plaintext).map_err(|_| MemoError::Decryption)
+    }
+
+    /// Whether the wallet with this id can read the memo.
+    pub fn is_party(&self, wallet_id: &str) -> bool {
+        [&self.sender_public_key, &self.recipient_public_key]
+            .iter()
+            .any(|key| crate::wallet::wallet_id_from_public_key_hex(key).is_ok_and(|id| id == wallet_id))
+    }
+
+    /// Length of the memo text, without the authentication tag.
+    pub fn plaintext_len(&self) -> usize {
+        (self.ciphertext.len() / 2).saturating_sub(16)
+    }
+}
+
+fn memo_cipher(own: &Wallet, counterparty_public_key: &str, sender_public_key: &str, recipient_public_key: &str) -> Result<ChaCha20Poly1305, MemoError> {
+    let shared = x25519_public(counterparty_public_key)? * x25519_secret(own);
+    if shared.as_bytes() == &[0u8; 32] {
+        return Err(MemoError::InvalidPublicKey);
+    }
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(None, shared.as_bytes())
+        .expand(&[KEY_INFO, &associated_data(sender_public_key, recipient_public_key)].concat(), &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+fn associated_data(sender_public_key: &str, recipient_public_key: &str) -> Vec<u8> {
+    [sender_public_key.as_bytes(), recipient_public_key.as_bytes()].concat()
+}
+
+/// The X25519 scalar behind an ed25519 secret key, as in RFC 8032 key generation.
+fn x25519_secret(wallet: &Wallet) -> Scalar {
+    let hash = Sha512::digest(wallet.keypair.secret.as_bytes());
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(&hash[..32]);
+    bytes[0] &= 248;
+    bytes[31] &= 127;
+    bytes[31] |= 64;
+    Scalar::from_bits(bytes)
+}
+
+/// The Montgomery form of a hex encoded ed25519 public key.
+fn x25519_public(public_key_hex: &str) -> Result<MontgomeryPoint, MemoError> {
+    let bytes = hex::decode(public_key_hex).map_err(|_| MemoError::InvalidPublicKey)?;
+    let bytes: [u8; 32] = bytes.try_into().map_err(|_| MemoError::InvalidPublicKey)?;
+    let point = CompressedEdwardsY(bytes).decompress().ok_or(MemoError::InvalidPublicKey)?;
+    if point.is_small_order() {
+        return Err(MemoError::InvalidPublicKey);
+    }
+    Ok(point.to_montgomery())
+}
@@ -0,0 +1,101 @@
+//! Merkle trees over a block's transactions.
+//!
+//! Leaves and inner nodes are hashed with different prefixes so a leaf can never
+//! be passed off as an inner node. A node without a sibling is carried up to the
+//! next level unchanged rather than paired with itself, so two different
+//! transaction lists cannot share a root.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::encoding::transaction_leaf_preimage;
+use crate::transaction::Transaction;
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/// Root of an empty transaction list.
+pub const EMPTY_ROOT: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// One step from a leaf towards the root: the sibling hash and which side it is on.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MerkleStep {
+    pub hash: String,
+    pub sibling_is_left: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct MerkleProof {
+    pub steps: Vec<MerkleStep>,
+}
+
+/// Leaf hash of a transaction. It covers the whole canonical encoding, signatures
+/// included, not only the id.
+pub fn transaction_leaf(transaction: &Transaction) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(transaction_leaf_preimage(transaction));
+    hasher.finalize().into()
+}
+
+fn hash_nodes(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+fn next_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => hash_nodes(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+pub fn merkle_root(transactions: &[Transaction]) -> String {
+    let mut level: Vec<[u8; 32]> = transactions.iter().map(transaction_leaf).collect();
+    if level.is_empty() {
+        return EMPTY_ROOT.to_string();
+    }
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    hex::encode(level[0])
+}
+
+/// Proof that `transactions[index]` is part of the tree.
+pub fn merkle_proof(transactions: &[Transaction], index: usize) -> Option<MerkleProof> {
+    if index >= transactions.len() {
+        return None;
+    }
+    let mut level: Vec<[u8; 32]> = transactions.iter().map(transaction_leaf).collect();
+    let mut position = index;
+    let mut steps = Vec::new();
+    while level.len() > 1 {
+        let sibling = position ^ 1;
+        if let Some(hash) = level.get(sibling) {
+            steps.push(MerkleStep { hash: hex::encode(hash), sibling_is_left: sibling < position });
+        }
+        level = next_level(&level);
+        position /= 2;
+    }
+    Some(MerkleProof { steps })
+}
+
+/// Checks that `transaction` hashes up to `root` along `proof`.
+pub fn verify_merkle_proof(transaction: &Transaction, proof: &MerkleProof, root: &str) -> bool {
+    let mut hash = transaction_leaf(transaction);
+    for step in &proof.steps {
+        let sibling: [u8; 32] = match hex::decode(&step.hash).ok().and_then(|bytes| bytes.try_into().ok()) {
+            Some(bytes) => bytes,
+            None => return false,
+        };
+        hash = if step.sibling_is_left { hash_nodes(&sibling, &hash) } else { hash_nodes(&hash, &sibling) };
+    }
+    hex::encode(hash) == root
+}
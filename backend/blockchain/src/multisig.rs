@@ -0,0 +1,87 @@
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use std::collections::BTreeMap;
+use hex;
+
+use crate::transaction::verify_hex_signature;
+
+/// Largest number of keys a multisig policy may contain.
+pub const MAX_MULTISIG_KEYS: usize = 15;
+
+/// An m-of-n spending policy. Outputs are locked to its [`address`](MultisigPolicy::address),
+/// and the policy itself is only revealed when those outputs are spent.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MultisigPolicy {
+    pub threshold: usize,
+    /// Hex encoded ed25519 public keys, kept sorted so the address does not depend on their order.
+    pub public_keys: Vec<String>,
+}
+
+/// Data placed in a [`TxInput`](crate::TxInput) to spend an output locked to a multisig address.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MultisigWitness {
+    pub policy: MultisigPolicy,
+    /// Signatures over the spending transaction's id, keyed by hex public key.
+    #[serde(default)]
+    pub signatures: BTreeMap<String, String>,
+}
+
+impl MultisigPolicy {
+    pub fn new(threshold: usize, public_keys: Vec<String>) -> Result<Self, String> {
+        if public_keys.is_empty() || public_keys.len() > MAX_MULTISIG_KEYS {
+            return Err(format!("A multisig policy needs between 1 and {} keys", MAX_MULTISIG_KEYS));
+        }
+        if threshold == 0 || threshold > public_keys.len() {
+            return Err(format!("Threshold must be between 1 and {}", public_keys.len()));
+        }
+
+        let mut keys = Vec::with_capacity(public_keys.len());
+        for key in public_keys {
+            let key = key.to_lowercase();
+            match hex::decode(&key) {
+                Ok(bytes) if bytes.len() == 32 => {}
+                _ => return Err(format!("Invalid public key: {}", key)),
+            }
+            keys.push(key);
+        }
+        keys.sort();
+        if keys.windows(2).any(|pair| pair[0] == pair[1]) {
+            return Err("Duplicate public key in multisig policy".to_string());
+        }
+
+        Ok(MultisigPolicy { threshold, public_keys: keys })
+    }
+
+    /// The address outputs are locked to. It commits to the threshold and every key.
+    pub fn address(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(format!("multisig:{}:{}:", self.threshold, self.public_keys.len()));
+        for key in &self.public_keys {
+            hasher.update(key.as_bytes());
+        }
+        hex::encode(hasher.finalize())
+    }
+
+    pub fn contains_key(&self, public_key: &str) -> bool {
+        self.public_keys.iter().any(|key| key == public_key)
+    }
+
+    /// Counts the distinct policy keys with a valid signature over `message`.
+    pub fn count_valid_signatures(&self, message: &[u8], signatures: &BTreeMap<String, String>) -> usize {
+        signatures
+            .iter()
+            .filter(|(key, sig)| self.contains_key(key) && verify_hex_signature(key, message, sig))
+            .count()
+    }
+
+    /// True when at least `threshold` policy keys signed `message`.
+    pub fn is_satisfied(&self, message: &[u8], signatures: &BTreeMap<String, String>) -> bool {
+        self.count_valid_signatures(message, signatures) >= self.threshold
+    }
+}
+
+impl MultisigWitness {
+    pub fn new(policy: MultisigPolicy) -> Self {
+        MultisigWitness { policy, signatures: BTreeMap::new() }
+    }
+}
@@ -0,0 +1,43 @@
+//! Document timestamping: a document's SHA-256 is anchored in a data output, and
+//! the receipt ties that output to a block header through a Merkle proof.
+//!
+//! [`verify_receipt`] needs nothing but the receipt. It shows the hash was in a block
+//! with the given header; whether that block is part of the chain is checked by
+//! comparing its hash with any trusted copy of the chain.
+
+use serde::{Deserialize, Serialize};
+
+use crate::block::BlockHeader;
+use crate::merkle::{verify_merkle_proof, MerkleProof};
+use crate::transaction::Transaction;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NotaryReceipt {
+    /// Hex encoded SHA-256 of the document.
+    pub document_hash: String,
+    pub transaction: Transaction,
+    /// Index of the data output carrying the hash.
+    pub output_index: usize,
+    pub block: BlockHeader,
+    pub merkle_proof: MerkleProof,
+}
+
+/// Checks a receipt offline and returns the time of the block that anchored the document.
+pub fn verify_receipt(receipt: &NotaryReceipt) -> Result<i64, String> {
+    let expected = hex::decode(&receipt.document_hash).map_err(|_| "Document hash is not hex".to_string())?;
+    let output = receipt
+        .transaction
+        .outputs
+        .get(receipt.output_index)
+        .ok_or("Receipt points at a missing output")?;
+    if output.data_payload().as_deref() != Some(expected.as_slice()) {
+        return Err("The output does not carry the document hash".to_string());
+    }
+    if !verify_merkle_proof(&receipt.transaction, &receipt.merkle_proof, &receipt.block.merkle_root) {
+        return Err("Merkle proof does not lead to the block's merkle root".to_string());
+    }
+    if receipt.block.calculate_hash() != receipt.block.hash {
+        return Err("Block header does not match its hash".to_string());
+    }
+    Ok(receipt.block.timestamp)
+}
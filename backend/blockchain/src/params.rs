@@ -0,0 +1,100 @@
+//! Chain parameters: the consensus rules and the heights at which they change.
+//!
+//! Rules are never edited in place. An upgrade is a new [`RuleSet`] with an
+//! activation height; blocks below it are still validated under the rules they
+//! were made with, so chains built before the upgrade stay valid, and every node
+//! with the same parameters switches at the same block.
+
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::block::INITIAL_BLOCK_VERSION;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ParamsError {
+    #[error("the first rule set must activate at height 0, not {0}")]
+    NoGenesisRules(u64),
+    #[error("upgrade at height {0} does not come after the previous one")]
+    OutOfOrder(u64),
+    #[error("upgrade at height {0} does not raise the block version")]
+    VersionNotRaised(u64),
+}
+
+/// The consensus rules in force from `activation_height` until the next upgrade.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct RuleSet {
+    pub activation_height: u64,
+    /// Header version every block under these rules carries. From
+    /// [`VERSIONED_HASH_FROM`](crate::block::VERSIONED_HASH_FROM) the block hash
+    /// commits to it.
+    pub block_version: u32,
+    /// Most a block's reward transaction may pay out.
+    pub mining_reward: u64,
+    /// Most transactions in a block, the reward included.
+    pub max_block_transactions: Option<usize>,
+    /// Wallet whose key may mint native coins (see
+    /// [`Blockchain::create_mint`](crate::chain::Blockchain::create_mint)); no one when `None`.
+    pub mint_authority: Option<String>,
+}
+
+impl RuleSet {
+    /// The rules the chain started with.
+    pub fn genesis() -> Self {
+        RuleSet {
+            activation_height: 0,
+            block_version: INITIAL_BLOCK_VERSION,
+            mining_reward: 100,
+            max_block_transactions: None,
+            mint_authority: None,
+        }
+    }
+}
+
+/// Every rule set of the chain, ordered by activation height.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChainParams {
+    rules: Vec<RuleSet>,
+}
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        ChainParams { rules: vec![RuleSet::genesis()] }
+    }
+}
+
+impl ChainParams {
+    pub fn new(genesis: RuleSet) -> Result<Self, ParamsError> {
+        if genesis.activation_height != 0 {
+            return Err(ParamsError::NoGenesisRules(genesis.activation_height));
+        }
+        Ok(ChainParams { rules: vec![genesis] })
+    }
+
+    /// Schedules `rules` to replace the latest rule set at its activation height.
+    /// Each upgrade must raise the block version, so a block shows which rules
+    /// it claims to follow.
+    pub fn with_upgrade(mut self, rules: RuleSet) -> Result<Self, ParamsError> {
+        let latest = self.rules.last().expect("chain parameters always hold the genesis rules");
+        if rules.activation_height <= latest.activation_height {
+            return Err(ParamsError::OutOfOrder(rules.activation_height));
+        }
+        if rules.block_version <= latest.block_version {
+            return Err(ParamsError::VersionNotRaised(rules.activation_height));
+        }
+        self.rules.push(rules);
+        Ok(self)
+    }
+
+    /// The rules a block at `height` is validated under.
+    pub fn rules_at(&self, height: u64) -> &RuleSet {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rules| rules.activation_height <= height)
+            .expect("the genesis rules activate at height 0")
+    }
+
+    pub fn rules(&self) -> &[RuleSet] {
+        &self.rules
+    }
+}
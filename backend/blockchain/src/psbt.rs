@@ -0,0 +1,557 @@
+//! Partially signed transactions.
+//!
+//! A [`PartiallySignedTransaction`] carries an unsigned [`Transaction`] together with
+//! everything a signer needs to authorise it (the outputs being spent) and the
+//! signatures collected so far. It can be passed between parties, or to an
+//! air-gapped machine, using its binary or base64 encoding:
+//!
+//! 1. an online node builds it with [`Blockchain::create_psbt`](crate::Blockchain::create_psbt),
+//! 2. each signer calls [`PartiallySignedTransaction::sign`] on its own copy,
+//! 3. the copies are merged with [`PartiallySignedTransaction::combine`],
+//! 4. [`PartiallySignedTransaction::finalize`] checks and places the signatures and
+//!    [`PartiallySignedTransaction::extract`] returns a broadcastable transaction.
+
+use std::collections::{BTreeMap, HashMap};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use thiserror::Error;
+
+use crate::asset::{Issuance, SupplyPolicy};
+use crate::encoding::{Decode, Encode, EncodingError, Reader, Writer};
+use crate::memo::EncryptedMemo;
+use crate::multisig::{MultisigPolicy, MultisigWitness};
+use crate::script::{Script, ScriptTemplate};
+use crate::transaction::{verify_hex_signature, Transaction, TxInput, TxOutput};
+use crate::wallet::{wallet_id_from_public_key_hex, Wallet};
+
+/// Magic bytes at the start of every encoded PSBT.
+pub const PSBT_MAGIC: &[u8; 5] = b"wpsbt";
+/// Current version of the binary encoding. Older versions (1: no multisig data,
+/// 2: no scripts, 3: no lock times, 4: no memos, 5: no encrypted notes, 6: no assets,
+/// 7: own transaction layout) are still accepted.
+pub const PSBT_VERSION: u8 = 8;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum PsbtError {
+    #[error("invalid PSBT encoding: {0}")]
+    Encoding(String),
+    #[error("unsupported PSBT version {0}")]
+    UnsupportedVersion(u8),
+    #[error("PSBTs describe different transactions")]
+    Mismatch,
+    #[error("conflicting data for input {0}")]
+    Conflict(usize),
+    #[error("conflicting transaction signatures")]
+    SignatureConflict,
+    #[error("missing UTXO data for input {0}")]
+    MissingUtxo(usize),
+    #[error("input {0} is missing a valid signature")]
+    MissingSignature(usize),
+    #[error("transaction is missing a valid sender signature")]
+    MissingSenderSignature,
+    #[error("PSBT is not finalized")]
+    NotFinalized,
+    #[error("extracted transaction failed verification")]
+    InvalidTransaction,
+}
+
+impl From<EncodingError> for PsbtError {
+    fn from(e: EncodingError) -> Self {
+        PsbtError::Encoding(e.to_string())
+    }
+}
+
+/// Per-input signing data.
+#[derive(Debug, Clone, Default)]
+pub struct PsbtInput {
+    /// The output being spent, needed by signers to know which key owns it.
+    pub utxo: Option<TxOutput>,
+    /// Signatures collected so far, keyed by hex public key.
+    pub partial_signatures: BTreeMap<String, String>,
+    /// Signature chosen by [`PartiallySignedTransaction::finalize`].
+    pub final_signature: Option<String>,
+    /// Threshold signatures chosen by [`PartiallySignedTransaction::finalize`] for multisig inputs.
+    pub final_multisig_signatures: Option<BTreeMap<String, String>>,
+    /// Unlocking script for script-locked inputs. Set by `finalize` for standard
+    /// single-key and multisig scripts, or by the caller for other scripts (e.g. hashlocks).
+    pub final_script_sig: Option<Script>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PartiallySignedTransaction {
+    /// The transaction being signed, with all signature fields empty.
+    pub unsigned_tx: Transaction,
+    pub inputs: Vec<PsbtInput>,
+    /// Signatures over the transaction id, keyed by hex public key.
+    pub partial_signatures: BTreeMap<String, String>,
+    pub final_signature: Option<String>,
+    /// Free-form key/value data (labels, creator, expiry...) carried along with the PSBT.
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl PartiallySignedTransaction {
+    /// Wraps `tx`, discarding any signatures it already carries.
+    pub fn new(mut tx: Transaction) -> Self {
+        tx.signature = String::new();
+        for input in &mut tx.inputs {
+            input.signature = String::new();
+            input.script_sig = None;
+            if let Some(witness) = &mut input.multisig {
+                witness.signatures.clear();
+            }
+        }
+        let inputs = vec![PsbtInput::default(); tx.inputs.len()];
+        PartiallySignedTransaction {
+            unsigned_tx: tx,
+            inputs,
+            partial_signatures: BTreeMap::new(),
+            final_signature: None,
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    /// Attaches UTXO data for every input found in `utxos`.
+    pub fn fill_utxos(&mut self, utxos: &HashMap<(String, usize), TxOutput>) {
+        for (input, psbt_input) in self.unsigned_tx.inputs.iter().zip(self.inputs.iter_mut()) {
+            if psbt_input.utxo.is_none() {
+                psbt_input.utxo = utxos.get(&(input.tx_id.clone(), input.output_index)).cloned();
+            }
+        }
+    }
+
+    /// Adds every signature `wallet` is able to provide and returns how many were added.
+    pub fn sign(&mut self, wallet: &Wallet) -> usize {
+        let public_key = wallet.get_public_key_hex();
+        let wallet_id = wallet.get_wallet_id();
+        let mut added = 0;
+
+        for (input, psbt_input) in self.unsigned_tx.inputs.iter().zip(self.inputs.iter_mut()) {
+            if psbt_input.partial_signatures.contains_key(&public_key) {
+                continue;
+            }
+            // Multisig co-signers and script signers sign the transaction id, single owners the input reference
+            let script_template = psbt_input.utxo.as_ref().and_then(|utxo| utxo.script_pubkey.as_ref()).map(|s| s.template());
+            let message = match (&input.multisig, script_template) {
+                (_, Some(template)) if script_signer(template.as_ref(), &wallet_id, &public_key) => self.unsigned_tx.id.clone(),
+                (_, Some(_)) => continue,
+                (Some(witness), None) if witness.policy.contains_key(&public_key) => self.unsigned_tx.id.clone(),
+                (Some(_), None) => continue,
+                (None, None) if matches!(&psbt_input.utxo, Some(utxo) if utxo.receiver_wallet_id == wallet_id) => input.signing_message(),
+                (None, None) => continue,
+            };
+            psbt_input.partial_signatures.insert(public_key.clone(), wallet.sign_transaction(&message));
+            added += 1;
+        }
+
+        if !public_key.is_empty() && self.unsigned_tx.sender_public_key == public_key && !self.partial_signatures.contains_key(&public_key) {
+            let signature = wallet.sign_transaction(&self.unsigned_tx.id);
+            self.partial_signatures.insert(public_key, signature);
+            added += 1;
+        }
+
+        added
+    }
+
+    /// Merges the UTXO data, signatures and metadata of `other` into `self`.
+    pub fn combine(&mut self, other: &PartiallySignedTransaction) -> Result<(), PsbtError> {
+        if self.unsigned_tx.id != other.unsigned_tx.id || self.inputs.len() != other.inputs.len() {
+            return Err(PsbtError::Mismatch);
+        }
+
+        for (index, (mine, theirs)) in self.inputs.iter_mut().zip(&other.inputs).enumerate() {
+            match (&mine.utxo, &theirs.utxo) {
+                (None, Some(utxo)) => mine.utxo = Some(utxo.clone()),
+                // Every field counts: lock times, scripts and assets change what the input is worth
+                (Some(a), Some(b)) if a.encode() != b.encode() => {
+                    return Err(PsbtError::Conflict(index));
+                }
+                _ => {}
+            }
+            merge_signatures(&mut mine.partial_signatures, &theirs.partial_signatures)
+                .map_err(|_| PsbtError::Conflict(index))?;
+            if mine.final_signature.is_none() {
+                mine.final_signature = theirs.final_signature.clone();
+            }
+            if mine.final_multisig_signatures.is_none() {
+                mine.final_multisig_signatures = theirs.final_multisig_signatures.clone();
+            }
+            if mine.final_script_sig.is_none() {
+                mine.final_script_sig = theirs.final_script_sig.clone();
+            }
+        }
+
+        merge_signatures(&mut self.partial_signatures, &other.partial_signatures)
+            .map_err(|_| PsbtError::SignatureConflict)?;
+        if self.final_signature.is_none() {
+            self.final_signature = other.final_signature.clone();
+        }
+        for (key, value) in &other.metadata {
+            self.metadata.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+        Ok(())
+    }
+
+    /// Checks that every input and the transaction itself carry valid signatures from
+    /// the right keys and moves those signatures into their final slots.
+    pub fn finalize(&mut self) -> Result<(), PsbtError> {
+        let mut finals = Vec::with_capacity(self.inputs.len());
+        for (index, (input, psbt_input)) in self.unsigned_tx.inputs.iter().zip(&self.inputs).enumerate() {
+            let utxo = psbt_input.utxo.as_ref().ok_or(PsbtError::MissingUtxo(index))?;
+            if let Some(script_pubkey) = &utxo.script_pubkey {
+                let script_sig = match &psbt_input.final_script_sig {
+                    Some(script_sig) => script_sig.clone(),
+                    None => build_script_sig(script_pubkey.template().as_ref(), self.unsigned_tx.id.as_bytes(), &psbt_input.partial_signatures)
+                        .ok_or(PsbtError::MissingSignature(index))?,
+                };
+                finals.push(FinalInput::Script(script_sig));
+                continue;
+            }
+            if let Some(witness) = &input.multisig {
+                finals.push(FinalInput::Multisig(select_multisig_signatures(
+                    &witness.policy,
+                    self.unsigned_tx.id.as_bytes(),
+                    &psbt_input.partial_signatures,
+                ).ok_or(PsbtError::MissingSignature(index))?));
+                continue;
+            }
+            let message = input.signing_message();
+            let signature = psbt_input
+                .partial_signatures
+                .iter()
+                .find(|(key, sig)| {
+                    wallet_id_from_public_key_hex(key).ok().as_deref() == Some(utxo.receiver_wallet_id.as_str())
+                        && verify_hex_signature(key, message.as_bytes(), sig)
+                })
+                .map(|(_, sig)| sig.clone())
+                .ok_or(PsbtError::MissingSignature(index))?;
+            finals.push(FinalInput::Single(signature));
+        }
+
+        let sender_signature = if self.requires_sender_signature() {
+            let sender_key = &self.unsigned_tx.sender_public_key;
+            let signature = self
+                .partial_signatures
+                .get(sender_key)
+                .filter(|sig| verify_hex_signature(sender_key, self.unsigned_tx.id.as_bytes(), sig))
+                .cloned()
+                .ok_or(PsbtError::MissingSenderSignature)?;
+            Some(signature)
+        } else {
+            None
+        };
+
+        for (psbt_input, final_input) in self.inputs.iter_mut().zip(finals) {
+            match final_input {
+                FinalInput::Single(signature) => psbt_input.final_signature = Some(signature),
+                FinalInput::Multisig(signatures) => psbt_input.final_multisig_signatures = Some(signatures),
+                FinalInput::Script(script_sig) => psbt_input.final_script_sig = Some(script_sig),
+            }
+            psbt_input.partial_signatures.clear();
+        }
+        self.final_signature = sender_signature;
+        self.partial_signatures.clear();
+        Ok(())
+    }
+
+    /// Multisig spends have no sender key and are authorised by their inputs alone.
+    fn requires_sender_signature(&self) -> bool {
+        !self.unsigned_tx.sender_public_key.is_empty()
+    }
+
+    pub fn is_finalized(&self) -> bool {
+        (self.final_signature.is_some() || !self.requires_sender_signature())
+            && self.inputs.iter().all(|input| {
+                input.final_signature.is_some() || input.final_multisig_signatures.is_some() || input.final_script_sig.is_some()
+            })
+    }
+
+    /// Number of distinct valid co-signer signatures collected so far for input `index`.
+    pub fn multisig_signature_count(&self, index: usize) -> usize {
+        match (self.unsigned_tx.inputs.get(index), self.inputs.get(index)) {
+            (Some(TxInput { multisig: Some(witness), .. }), Some(psbt_input)) => {
+                let signatures = psbt_input.final_multisig_signatures.as_ref().unwrap_or(&psbt_input.partial_signatures);
+                witness.policy.count_valid_signatures(self.unsigned_tx.id.as_bytes(), signatures)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Returns the fully signed transaction of a finalized PSBT.
+    pub fn extract(&self) -> Result<Transaction, PsbtError> {
+        if !self.is_finalized() {
+            return Err(PsbtError::NotFinalized);
+        }
+        let mut tx = self.unsigned_tx.clone();
+        for (input, psbt_input) in tx.inputs.iter_mut().zip(&self.inputs) {
+            if let Some(script_sig) = &psbt_input.final_script_sig {
+                input.script_sig = Some(script_sig.clone());
+                continue;
+            }
+            match (&mut input.multisig, &psbt_input.final_multisig_signatures) {
+                (Some(witness), Some(signatures)) => witness.signatures = signatures.clone(),
+                _ => input.signature = psbt_input.final_signature.clone().unwrap_or_default(),
+            }
+        }
+        tx.signature = self.final_signature.clone().unwrap_or_default();
+        if !tx.verify_signature() {
+            return Err(PsbtError::InvalidTransaction);
+        }
+        Ok(tx)
+    }
+
+    /// Stable binary encoding: magic, version, the unsigned transaction and each
+    /// input's spent output in their [canonical encoding](crate::encoding), then the
+    /// signing data with little-endian integers and `u32` length prefixes. Maps are
+    /// written in key order.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.bytes(PSBT_MAGIC);
+        w.u8(PSBT_VERSION);
+        w.byte_string(&self.unsigned_tx.encode());
+
+        for input in &self.inputs {
+            w.opt_bytes(input.utxo.as_ref().map(Encode::encode).as_deref());
+            w.map(&input.partial_signatures);
+            w.opt_str(input.final_signature.as_deref());
+            match &input.final_multisig_signatures {
+                Some(signatures) => {
+                    w.u8(1);
+                    w.map(signatures);
+                }
+                None => w.u8(0),
+            }
+            w.opt_bytes(input.final_script_sig.as_ref().map(|s| s.as_bytes()));
+        }
+
+        w.map(&self.partial_signatures);
+        w.opt_str(self.final_signature.as_deref());
+        w.map(&self.metadata);
+        w.into_bytes()
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, PsbtError> {
+        let mut r = Reader::new(bytes);
+        if r.take(PSBT_MAGIC.len())? != PSBT_MAGIC {
+            return Err(PsbtError::Encoding("bad magic".to_string()));
+        }
+        let version = r.u8()?;
+        if version == 0 || version > PSBT_VERSION {
+            return Err(PsbtError::UnsupportedVersion(version));
+        }
+
+        let unsigned_tx = if version >= 8 {
+            Transaction::decode(&r.byte_string()?)?
+        } else {
+            // The id of an older PSBT predates the canonical hash, so signatures over it are void
+            let mut tx = read_legacy_transaction(&mut r, version)?;
+            tx.id = tx.calculate_hash();
+            tx
+        };
+
+        let mut psbt_inputs = Vec::with_capacity(unsigned_tx.inputs.len());
+        for _ in 0..unsigned_tx.inputs.len() {
+            let utxo = match (r.flag()?, version >= 8) {
+                (false, _) => None,
+                (true, true) => Some(TxOutput::decode(&r.byte_string()?)?),
+                (true, false) => Some(read_legacy_output(&mut r, version)?),
+            };
+            let partial_signatures = r.map()?;
+            let final_signature = r.opt_str()?;
+            let final_multisig_signatures = if version >= 2 && r.flag()? { Some(r.map()?) } else { None };
+            let final_script_sig = if version >= 3 { r.opt_bytes()?.map(Script::from_bytes) } else { None };
+            psbt_inputs.push(PsbtInput { utxo, partial_signatures, final_signature, final_multisig_signatures, final_script_sig });
+        }
+
+        let partial_signatures = r.map()?;
+        let final_signature = r.opt_str()?;
+        let metadata = r.map()?;
+        r.finish()?;
+
+        Ok(PartiallySignedTransaction {
+            unsigned_tx,
+            inputs: psbt_inputs,
+            partial_signatures,
+            final_signature,
+            metadata,
+        })
+    }
+
+    pub fn to_base64(&self) -> String {
+        BASE64.encode(self.serialize())
+    }
+
+    pub fn from_base64(encoded: &str) -> Result<Self, PsbtError> {
+        let bytes = BASE64
+            .decode(encoded.trim())
+            .map_err(|e| PsbtError::Encoding(e.to_string()))?;
+        Self::deserialize(&bytes)
+    }
+}
+
+enum FinalInput {
+    Single(String),
+    Multisig(BTreeMap<String, String>),
+    Script(Script),
+}
+
+/// Picks `threshold` valid signatures from policy keys, in key order.
+fn select_multisig_signatures(policy: &MultisigPolicy, message: &[u8], signatures: &BTreeMap<String, String>) -> Option<BTreeMap<String, String>> {
+    let selected: BTreeMap<String, String> = signatures
+        .iter()
+        .filter(|(key, sig)| policy.contains_key(key) && verify_hex_signature(key, message, sig))
+        .take(policy.threshold)
+        .map(|(key, sig)| (key.clone(), sig.clone()))
+        .collect();
+    if selected.len() == policy.threshold { Some(selected) } else { None }
+}
+
+fn merge_signatures(into: &mut BTreeMap<String, String>, from: &BTreeMap<String, String>) -> Result<(), ()> {
+    for (key, sig) in from {
+        match into.get(key) {
+            Some(existing) if existing != sig => return Err(()),
+            Some(_) => {}
+            None => {
+                into.insert(key.clone(), sig.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The unsigned transaction as written by PSBT versions 1 to 7.
+fn read_legacy_transaction(r: &mut Reader, version: u8) -> Result<Transaction, PsbtError> {
+    let id = r.str()?;
+    let sender_wallet_id = r.str()?;
+    let receiver_wallet_id = r.str()?;
+    let amount = r.u64()?;
+    let note = r.opt_str()?;
+    let timestamp = r.u64()? as i64;
+    let sender_public_key = r.str()?;
+    let mut inputs = Vec::new();
+    for _ in 0..r.length()? {
+        let tx_id = r.str()?;
+        let output_index = r.u64()? as usize;
+        let multisig = if version >= 2 && r.flag()? {
+            let threshold = r.u64()? as usize;
+            let mut public_keys = Vec::new();
+            for _ in 0..r.length()? {
+                public_keys.push(r.str()?);
+            }
+            let policy = MultisigPolicy::new(threshold, public_keys).map_err(PsbtError::Encoding)?;
+            Some(MultisigWitness::new(policy))
+        } else {
+            None
+        };
+        let mut input = TxInput::new(tx_id, output_index);
+        input.multisig = multisig;
+        inputs.push(input);
+    }
+    let mut outputs = Vec::new();
+    for _ in 0..r.length()? {
+        outputs.push(read_legacy_output(r, version)?);
+    }
+    let lock_time = if version >= 4 { r.opt_u64()? } else { None };
+    let encrypted_note = if version >= 6 && r.flag()? {
+        Some(EncryptedMemo {
+            sender_public_key: r.str()?,
+            recipient_public_key: r.str()?,
+            nonce: r.str()?,
+            ciphertext: r.str()?,
+        })
+    } else {
+        None
+    };
+    let issuance = if version >= 7 {
+        match r.u8()? {
+            0 => None,
+            1 => {
+                let name = r.str()?;
+                let decimals = r.u8()?;
+                let policy = if r.flag()? { SupplyPolicy::Mintable { admin_wallet_id: r.str()? } } else { SupplyPolicy::Fixed };
+                Some(Issuance::Issue { name, decimals, policy })
+            }
+            2 => Some(Issuance::Mint { asset_id: r.str()? }),
+            tag => return Err(PsbtError::Encoding(format!("bad issuance tag {}", tag))),
+        }
+    } else {
+        None
+    };
+    Ok(Transaction {
+        id,
+        sender_wallet_id,
+        receiver_wallet_id,
+        amount,
+        note,
+        timestamp,
+        sender_public_key,
+        signature: String::new(),
+        inputs,
+        outputs,
+        lock_time,
+        encrypted_note,
+        issuance,
+    })
+}
+
+/// A spent output as written by PSBT versions 1 to 7.
+fn read_legacy_output(r: &mut Reader, version: u8) -> Result<TxOutput, PsbtError> {
+    let amount = r.u64()?;
+    let receiver_wallet_id = r.str()?;
+    let mut output = TxOutput::new(amount, receiver_wallet_id);
+    if version >= 3 {
+        output.script_pubkey = r.opt_bytes()?.map(Script::from_bytes);
+    }
+    if version >= 4 {
+        output.lock_time = r.opt_u64()?;
+        output.relative_lock = r.opt_u64()?;
+    }
+    if version >= 5 {
+        output.memo = r.opt_str()?;
+    }
+    if version >= 7 {
+        output.asset = r.opt_str()?;
+    }
+    Ok(output)
+}
+
+/// Strips timelock wrappers, which do not change who has to sign.
+fn signing_template(template: &ScriptTemplate) -> &ScriptTemplate {
+    match template {
+        ScriptTemplate::AbsoluteTimelock { inner, .. } | ScriptTemplate::RelativeTimelock { inner, .. } => signing_template(inner),
+        other => other,
+    }
+}
+
+fn script_signer(template: Option<&ScriptTemplate>, wallet_id: &str, public_key: &str) -> bool {
+    match template.map(signing_template) {
+        Some(ScriptTemplate::PayToPubkeyHash { wallet_id: owner }) => owner == wallet_id,
+        Some(ScriptTemplate::Multisig { public_keys, .. }) => public_keys.iter().any(|k| k == public_key),
+        Some(ScriptTemplate::Htlc { recipient_wallet_id, refund_wallet_id, .. }) => {
+            recipient_wallet_id == wallet_id || refund_wallet_id == wallet_id
+        }
+        _ => false,
+    }
+}
+
+/// Builds the unlocking script for standard single-key and multisig templates.
+fn build_script_sig(template: Option<&ScriptTemplate>, message: &[u8], signatures: &BTreeMap<String, String>) -> Option<Script> {
+    match template.map(signing_template)? {
+        ScriptTemplate::PayToPubkeyHash { wallet_id } => signatures
+            .iter()
+            .find(|(key, sig)| {
+                wallet_id_from_public_key_hex(key).ok().as_deref() == Some(wallet_id.as_str()) && verify_hex_signature(key, message, sig)
+            })
+            .and_then(|(key, sig)| Script::p2pkh_unlock(sig, key).ok()),
+        ScriptTemplate::Multisig { threshold, public_keys } => {
+            // Signatures are consumed in key order by CHECKMULTISIG
+            let ordered: Vec<String> = public_keys
+                .iter()
+                .filter_map(|key| signatures.get(key).filter(|sig| verify_hex_signature(key, message, sig)).cloned())
+                .take(*threshold)
+                .collect();
+            if ordered.len() == *threshold { Script::multisig_unlock(&ordered).ok() } else { None }
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,720 @@
+//! A small stack-based script language for locking outputs.
+//!
+//! Scripts are byte strings in the spirit of Bitcoin script, restricted to a
+//! handful of opcodes with no loops or jumps, so every script runs in time
+//! linear to its length. An output's `script_pubkey` states the spending
+//! condition; the spending input supplies a push-only `script_sig`. The two are
+//! run one after the other on the same stack and the spend is valid when the
+//! top of the stack is true at the end.
+//!
+//! Signature opcodes check ed25519 signatures over the spending transaction's id.
+
+use std::fmt;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::transaction::{lock_time_reached, verify_hex_signature};
+
+pub const OP_0: u8 = 0x00;
+pub const OP_PUSHDATA1: u8 = 0x4c;
+pub const OP_PUSHDATA2: u8 = 0x4d;
+pub const OP_1: u8 = 0x51;
+pub const OP_16: u8 = 0x60;
+pub const OP_IF: u8 = 0x63;
+pub const OP_NOTIF: u8 = 0x64;
+pub const OP_ELSE: u8 = 0x67;
+pub const OP_ENDIF: u8 = 0x68;
+pub const OP_VERIFY: u8 = 0x69;
+pub const OP_RETURN: u8 = 0x6a;
+pub const OP_DROP: u8 = 0x75;
+pub const OP_DUP: u8 = 0x76;
+pub const OP_EQUAL: u8 = 0x87;
+pub const OP_EQUALVERIFY: u8 = 0x88;
+pub const OP_SHA256: u8 = 0xa8;
+pub const OP_CHECKSIG: u8 = 0xac;
+pub const OP_CHECKSIGVERIFY: u8 = 0xad;
+pub const OP_CHECKMULTISIG: u8 = 0xae;
+pub const OP_CHECKMULTISIGVERIFY: u8 = 0xaf;
+pub const OP_CHECKLOCKTIMEVERIFY: u8 = 0xb1;
+pub const OP_CHECKSEQUENCEVERIFY: u8 = 0xb2;
+
+/// Largest script accepted, in bytes.
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+/// Largest single stack element, in bytes.
+pub const MAX_ELEMENT_SIZE: usize = 520;
+/// Largest number of non-push opcodes executed per script.
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+/// Largest stack depth at any point.
+pub const MAX_STACK_SIZE: usize = 1_000;
+/// Largest number of keys in a CHECKMULTISIG.
+pub const MAX_PUBKEYS_PER_MULTISIG: usize = 15;
+/// Largest payload of a standard data-carrying (`OP_RETURN`) output, in bytes.
+pub const MAX_DATA_CARRIER_SIZE: usize = 80;
+/// Lock time values below this are block heights, values at or above are unix timestamps.
+pub const LOCKTIME_THRESHOLD: u64 = 500_000_000;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ScriptError {
+    #[error("script exceeds {MAX_SCRIPT_SIZE} bytes")]
+    ScriptSize,
+    #[error("push exceeds {MAX_ELEMENT_SIZE} bytes")]
+    PushSize,
+    #[error("too many operations")]
+    OpCount,
+    #[error("stack overflow")]
+    StackSize,
+    #[error("truncated push")]
+    BadPush,
+    #[error("unknown opcode 0x{0:02x}")]
+    BadOpcode(u8),
+    #[error("unbalanced conditional")]
+    UnbalancedConditional,
+    #[error("operation on empty stack")]
+    StackUnderflow,
+    #[error("invalid number encoding")]
+    BadNumber,
+    #[error("VERIFY failed")]
+    Verify,
+    #[error("EQUALVERIFY failed")]
+    EqualVerify,
+    #[error("CHECKSIGVERIFY failed")]
+    CheckSigVerify,
+    #[error("CHECKMULTISIGVERIFY failed")]
+    CheckMultisigVerify,
+    #[error("invalid key or signature count")]
+    PubkeyCount,
+    #[error("output is provably unspendable")]
+    OpReturn,
+    #[error("lock time not yet reached")]
+    UnsatisfiedLockTime,
+    #[error("unlocking script must only push data")]
+    SigPushOnly,
+    #[error("script evaluated to false")]
+    EvalFalse,
+}
+
+/// A single parsed instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instruction<'a> {
+    Push(&'a [u8]),
+    Op(u8),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Script(pub Vec<u8>);
+
+impl Script {
+    pub fn new() -> Self {
+        Script(Vec::new())
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Script(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn push_opcode(mut self, opcode: u8) -> Self {
+        self.0.push(opcode);
+        self
+    }
+
+    pub fn push_slice(mut self, data: &[u8]) -> Self {
+        match data.len() {
+            0 => self.0.push(OP_0),
+            len if len < OP_PUSHDATA1 as usize => self.0.push(len as u8),
+            len if len <= u8::MAX as usize => {
+                self.0.push(OP_PUSHDATA1);
+                self.0.push(len as u8);
+            }
+            len => {
+                self.0.push(OP_PUSHDATA2);
+                self.0.extend_from_slice(&(len as u16).to_le_bytes());
+            }
+        }
+        self.0.extend_from_slice(data);
+        self
+    }
+
+    /// Pushes a non-negative number, using OP_0..OP_16 when possible.
+    pub fn push_int(self, n: u64) -> Self {
+        match n {
+            0 => self.push_opcode(OP_0),
+            1..=16 => self.push_opcode(OP_1 + (n as u8) - 1),
+            _ => self.push_slice(&encode_num(n)),
+        }
+    }
+
+    /// Parses the script into instructions, checking pushes are well formed.
+    pub fn instructions(&self) -> Result<Vec<Instruction<'_>>, ScriptError> {
+        if self.0.len() > MAX_SCRIPT_SIZE {
+            return Err(ScriptError::ScriptSize);
+        }
+        let bytes = &self.0;
+        let mut out = Vec::new();
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let opcode = bytes[pos];
+            pos += 1;
+            let len = match opcode {
+                OP_0 => 0,
+                0x01..=0x4b => opcode as usize,
+                OP_PUSHDATA1 => {
+                    let len = *bytes.get(pos).ok_or(ScriptError::BadPush)? as usize;
+                    pos += 1;
+                    len
+                }
+                OP_PUSHDATA2 => {
+                    let raw = bytes.get(pos..pos + 2).ok_or(ScriptError::BadPush)?;
+                    pos += 2;
+                    u16::from_le_bytes([raw[0], raw[1]]) as usize
+                }
+                _ => {
+                    out.push(Instruction::Op(opcode));
+                    continue;
+                }
+            };
+            let data = bytes.get(pos..pos + len).ok_or(ScriptError::BadPush)?;
+            if len > MAX_ELEMENT_SIZE {
+                return Err(ScriptError::PushSize);
+            }
+            pos += len;
+            out.push(Instruction::Push(data));
+        }
+        Ok(out)
+    }
+
+    pub fn is_push_only(&self) -> bool {
+        match self.instructions() {
+            Ok(instructions) => instructions.iter().all(|i| match i {
+                Instruction::Push(_) => true,
+                Instruction::Op(op) => (OP_1..=OP_16).contains(op),
+            }),
+            Err(_) => false,
+        }
+    }
+
+    /// Hex encoded SHA-256 of the script, used as the address of outputs it locks.
+    pub fn script_hash(&self) -> String {
+        hex::encode(Sha256::digest(&self.0))
+    }
+
+    // ----- Standard templates -----
+
+    /// Pay to public key hash: spendable by the key whose hash is `wallet_id`.
+    /// Unlocked with `<signature> <public key>`.
+    pub fn p2pkh(wallet_id: &str) -> Result<Self, ScriptError> {
+        let hash = decode_hash(wallet_id)?;
+        Ok(Script::new()
+            .push_opcode(OP_DUP)
+            .push_opcode(OP_SHA256)
+            .push_slice(&hash)
+            .push_opcode(OP_EQUALVERIFY)
+            .push_opcode(OP_CHECKSIG))
+    }
+
+    /// Bare m-of-n multisig. Unlocked with m signatures in the same order as their keys.
+    pub fn multisig(threshold: usize, public_keys: &[String]) -> Result<Self, ScriptError> {
+        if threshold == 0 || threshold > public_keys.len() || public_keys.len() > MAX_PUBKEYS_PER_MULTISIG {
+            return Err(ScriptError::PubkeyCount);
+        }
+        let mut script = Script::new().push_int(threshold as u64);
+        for key in public_keys {
+            let bytes = hex::decode(key).map_err(|_| ScriptError::BadPush)?;
+            if bytes.len() != 32 {
+                return Err(ScriptError::BadPush);
+            }
+            script = script.push_slice(&bytes);
+        }
+        Ok(script.push_int(public_keys.len() as u64).push_opcode(OP_CHECKMULTISIG))
+    }
+
+    /// Spendable by anyone revealing the preimage of the hex encoded SHA-256 `hash`.
+    pub fn hashlock(hash: &str) -> Result<Self, ScriptError> {
+        let hash = decode_hash(hash)?;
+        Ok(Script::new().push_opcode(OP_SHA256).push_slice(&hash).push_opcode(OP_EQUAL))
+    }
+
+    /// Provably unspendable output carrying `data`: `OP_RETURN <data>`.
+    pub fn null_data(data: &[u8]) -> Result<Self, ScriptError> {
+        if data.len() > MAX_DATA_CARRIER_SIZE {
+            return Err(ScriptError::PushSize);
+        }
+        Ok(Script::new().push_opcode(OP_RETURN).push_slice(data))
+    }
+
+    /// Scripts starting with `OP_RETURN` fail whatever unlocks them, so their
+    /// outputs never enter the UTXO set.
+    pub fn is_unspendable(&self) -> bool {
+        self.0.first() == Some(&OP_RETURN)
+    }
+
+    /// Hash-time-locked contract: `recipient` spends with the preimage of `hash`
+    /// (`<signature> <public key> <preimage> OP_1`), or `refund` spends once
+    /// `timeout` is reached (`<signature> <public key> OP_0`).
+    pub fn htlc(hash: &str, recipient_wallet_id: &str, refund_wallet_id: &str, timeout: u64) -> Result<Self, ScriptError> {
+        let hash = decode_hash(hash)?;
+        let recipient = decode_hash(recipient_wallet_id)?;
+        let refund = decode_hash(refund_wallet_id)?;
+        Ok(Script::new()
+            .push_opcode(OP_IF)
+            .push_opcode(OP_SHA256)
+            .push_slice(&hash)
+            .push_opcode(OP_EQUALVERIFY)
+            .push_opcode(OP_DUP)
+            .push_opcode(OP_SHA256)
+            .push_slice(&recipient)
+            .push_opcode(OP_EQUALVERIFY)
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_ELSE)
+            .push_int(timeout)
+            .push_opcode(OP_CHECKLOCKTIMEVERIFY)
+            .push_opcode(OP_DROP)
+            .push_opcode(OP_DUP)
+            .push_opcode(OP_SHA256)
+            .push_slice(&refund)
+            .push_opcode(OP_EQUALVERIFY)
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_ENDIF))
+    }
+
+    /// Prefixes `inner` with an absolute lock: a block height, or a unix time when
+    /// `lock_time >= LOCKTIME_THRESHOLD`.
+    pub fn absolute_timelock(lock_time: u64, inner: Script) -> Self {
+        let mut script = Script::new().push_int(lock_time).push_opcode(OP_CHECKLOCKTIMEVERIFY).push_opcode(OP_DROP);
+        script.0.extend_from_slice(&inner.0);
+        script
+    }
+
+    /// Prefixes `inner` with a relative lock of `blocks` confirmations of the spent output.
+    pub fn relative_timelock(blocks: u64, inner: Script) -> Self {
+        let mut script = Script::new().push_int(blocks).push_opcode(OP_CHECKSEQUENCEVERIFY).push_opcode(OP_DROP);
+        script.0.extend_from_slice(&inner.0);
+        script
+    }
+
+    /// Unlocking script for [`Script::p2pkh`].
+    pub fn p2pkh_unlock(signature_hex: &str, public_key_hex: &str) -> Result<Self, ScriptError> {
+        let signature = hex::decode(signature_hex).map_err(|_| ScriptError::BadPush)?;
+        let public_key = hex::decode(public_key_hex).map_err(|_| ScriptError::BadPush)?;
+        Ok(Script::new().push_slice(&signature).push_slice(&public_key))
+    }
+
+    /// Unlocking script for [`Script::multisig`]; signatures must follow key order.
+    pub fn multisig_unlock(signatures_hex: &[String]) -> Result<Self, ScriptError> {
+        let mut script = Script::new();
+        for signature in signatures_hex {
+            script = script.push_slice(&hex::decode(signature).map_err(|_| ScriptError::BadPush)?);
+        }
+        Ok(script)
+    }
+
+    /// Recognises the standard templates relayed by the mempool.
+    pub fn template(&self) -> Option<ScriptTemplate> {
+        let instructions = self.instructions().ok()?;
+        classify(&instructions)
+    }
+
+    pub fn is_standard(&self) -> bool {
+        self.template().is_some()
+    }
+}
+
+/// Standard script forms accepted into the mempool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptTemplate {
+    PayToPubkeyHash { wallet_id: String },
+    Multisig { threshold: usize, public_keys: Vec<String> },
+    Hashlock { hash: String },
+    AbsoluteTimelock { lock_time: u64, inner: Box<ScriptTemplate> },
+    RelativeTimelock { blocks: u64, inner: Box<ScriptTemplate> },
+    /// Hex encoded payload of an `OP_RETURN` output.
+    NullData { data: String },
+    /// See [`Htlc`](crate::htlc::Htlc).
+    Htlc { hash: String, recipient_wallet_id: String, refund_wallet_id: String, timeout: u64 },
+}
+
+fn classify(instructions: &[Instruction]) -> Option<ScriptTemplate> {
+    use Instruction::*;
+    match instructions {
+        [Op(OP_DUP), Op(OP_SHA256), Push(hash), Op(OP_EQUALVERIFY), Op(OP_CHECKSIG)] if hash.len() == 32 => {
+            Some(ScriptTemplate::PayToPubkeyHash { wallet_id: hex::encode(hash) })
+        }
+        [Op(OP_RETURN), Push(data)] if data.len() <= MAX_DATA_CARRIER_SIZE => {
+            Some(ScriptTemplate::NullData { data: hex::encode(data) })
+        }
+        [Op(OP_SHA256), Push(hash), Op(OP_EQUAL)] if hash.len() == 32 => {
+            Some(ScriptTemplate::Hashlock { hash: hex::encode(hash) })
+        }
+        [
+            Op(OP_IF),
+            Op(OP_SHA256), Push(hash), Op(OP_EQUALVERIFY),
+            Op(OP_DUP), Op(OP_SHA256), Push(recipient), Op(OP_EQUALVERIFY), Op(OP_CHECKSIG),
+            Op(OP_ELSE),
+            timeout, Op(OP_CHECKLOCKTIMEVERIFY), Op(OP_DROP),
+            Op(OP_DUP), Op(OP_SHA256), Push(refund), Op(OP_EQUALVERIFY), Op(OP_CHECKSIG),
+            Op(OP_ENDIF),
+        ] if hash.len() == 32 && recipient.len() == 32 && refund.len() == 32 => Some(ScriptTemplate::Htlc {
+            hash: hex::encode(hash),
+            recipient_wallet_id: hex::encode(recipient),
+            refund_wallet_id: hex::encode(refund),
+            timeout: instruction_num(timeout)?,
+        }),
+        [lock, Op(op @ (OP_CHECKLOCKTIMEVERIFY | OP_CHECKSEQUENCEVERIFY)), Op(OP_DROP), rest @ ..] => {
+            let value = instruction_num(lock)?;
+            let inner = Box::new(classify(rest)?);
+            if *op == OP_CHECKLOCKTIMEVERIFY {
+                Some(ScriptTemplate::AbsoluteTimelock { lock_time: value, inner })
+            } else {
+                Some(ScriptTemplate::RelativeTimelock { blocks: value, inner })
+            }
+        }
+        [m, keys @ .., n, Op(OP_CHECKMULTISIG)] => {
+            let threshold = instruction_num(m)? as usize;
+            let count = instruction_num(n)? as usize;
+            if count != keys.len() || threshold == 0 || threshold > count || count > MAX_PUBKEYS_PER_MULTISIG {
+                return None;
+            }
+            let public_keys = keys
+                .iter()
+                .map(|k| match k {
+                    Push(key) if key.len() == 32 => Some(hex::encode(key)),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?;
+            Some(ScriptTemplate::Multisig { threshold, public_keys })
+        }
+        _ => None,
+    }
+}
+
+fn instruction_num(instruction: &Instruction) -> Option<u64> {
+    match instruction {
+        Instruction::Push(data) => decode_num(data).ok(),
+        Instruction::Op(op) if (OP_1..=OP_16).contains(op) => Some((op - OP_1 + 1) as u64),
+        _ => None,
+    }
+}
+
+fn decode_hash(hex_hash: &str) -> Result<Vec<u8>, ScriptError> {
+    match hex::decode(hex_hash) {
+        Ok(bytes) if bytes.len() == 32 => Ok(bytes),
+        _ => Err(ScriptError::BadPush),
+    }
+}
+
+/// Minimal little-endian encoding of a non-negative number.
+fn encode_num(n: u64) -> Vec<u8> {
+    let mut bytes = n.to_le_bytes().to_vec();
+    while bytes.last() == Some(&0) {
+        bytes.pop();
+    }
+    bytes
+}
+
+fn decode_num(bytes: &[u8]) -> Result<u64, ScriptError> {
+    if bytes.len() > 8 || bytes.last() == Some(&0) {
+        return Err(ScriptError::BadNumber);
+    }
+    let mut buf = [0u8; 8];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn cast_to_bool(bytes: &[u8]) -> bool {
+    bytes.iter().any(|b| *b != 0)
+}
+
+/// Facts about the spend that scripts may check.
+#[derive(Debug, Clone, Copy)]
+pub struct ScriptContext<'a> {
+    /// Message signatures commit to: the spending transaction's id.
+    pub message: &'a [u8],
+    /// Height of the block the spending transaction is (or will be) included in.
+    pub height: u64,
+    /// Time of that block.
+    pub time: i64,
+    /// Height of the block that created the output being spent.
+    pub utxo_height: u64,
+}
+
+/// Runs `script_sig` then `script_pubkey` and succeeds if the spend is authorised.
+pub fn verify_script(script_sig: &Script, script_pubkey: &Script, ctx: &ScriptContext) -> Result<(), ScriptError> {
+    if !script_sig.is_push_only() {
+        return Err(ScriptError::SigPushOnly);
+    }
+    let mut stack = Vec::new();
+    execute(script_sig, &mut stack, ctx)?;
+    execute(script_pubkey, &mut stack, ctx)?;
+    match stack.last() {
+        Some(top) if cast_to_bool(top) => Ok(()),
+        _ => Err(ScriptError::EvalFalse),
+    }
+}
+
+fn pop(stack: &mut Vec<Vec<u8>>) -> Result<Vec<u8>, ScriptError> {
+    stack.pop().ok_or(ScriptError::StackUnderflow)
+}
+
+fn pop_num(stack: &mut Vec<Vec<u8>>) -> Result<u64, ScriptError> {
+    decode_num(&pop(stack)?)
+}
+
+fn push_bool(stack: &mut Vec<Vec<u8>>, value: bool) {
+    stack.push(if value { vec![1] } else { Vec::new() });
+}
+
+fn check_sig(signature: &[u8], public_key: &[u8], ctx: &ScriptContext) -> bool {
+    !signature.is_empty() && verify_hex_signature(&hex::encode(public_key), ctx.message, &hex::encode(signature))
+}
+
+/// Executes one script on `stack`. Conditionals are tracked with a stack of
+/// branch flags; there are no jumps, so execution is bounded by the script length.
+pub fn execute(script: &Script, stack: &mut Vec<Vec<u8>>, ctx: &ScriptContext) -> Result<(), ScriptError> {
+    let instructions = script.instructions()?;
+    let mut conditions: Vec<bool> = Vec::new();
+    let mut op_count = 0;
+
+    for instruction in instructions {
+        let executing = conditions.iter().all(|c| *c);
+        let opcode = match instruction {
+            Instruction::Push(data) => {
+                if executing {
+                    stack.push(data.to_vec());
+                }
+                if stack.len() > MAX_STACK_SIZE {
+                    return Err(ScriptError::StackSize);
+                }
+                continue;
+            }
+            Instruction::Op(op) => op,
+        };
+
+        if opcode > OP_16 {
+            op_count += 1;
+            if op_count > MAX_OPS_PER_SCRIPT {
+                return Err(ScriptError::OpCount);
+            }
+        }
+
+        match opcode {
+            OP_IF | OP_NOTIF => {
+                let mut value = false;
+                if executing {
+                    value = cast_to_bool(&pop(stack)?);
+                    if opcode == OP_NOTIF {
+                        value = !value;
+                    }
+                }
+                conditions.push(value);
+                continue;
+            }
+            OP_ELSE => {
+                let last = conditions.last_mut().ok_or(ScriptError::UnbalancedConditional)?;
+                *last = !*last;
+                continue;
+            }
+            OP_ENDIF => {
+                conditions.pop().ok_or(ScriptError::UnbalancedConditional)?;
+                continue;
+            }
+            _ => {}
+        }
+
+        if !executing {
+            // Unknown opcodes are rejected even in branches that are not taken
+            if !is_known_opcode(opcode) {
+                return Err(ScriptError::BadOpcode(opcode));
+            }
+            continue;
+        }
+
+        match opcode {
+            OP_1..=OP_16 => stack.push(vec![opcode - OP_1 + 1]),
+            OP_VERIFY => {
+                if !cast_to_bool(&pop(stack)?) {
+                    return Err(ScriptError::Verify);
+                }
+            }
+            OP_RETURN => return Err(ScriptError::OpReturn),
+            OP_DROP => {
+                pop(stack)?;
+            }
+            OP_DUP => {
+                let top = stack.last().ok_or(ScriptError::StackUnderflow)?.clone();
+                stack.push(top);
+            }
+            OP_EQUAL | OP_EQUALVERIFY => {
+                let a = pop(stack)?;
+                let b = pop(stack)?;
+                if opcode == OP_EQUALVERIFY {
+                    if a != b {
+                        return Err(ScriptError::EqualVerify);
+                    }
+                } else {
+                    push_bool(stack, a == b);
+                }
+            }
+            OP_SHA256 => {
+                let data = pop(stack)?;
+                stack.push(Sha256::digest(&data).to_vec());
+            }
+            OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                let public_key = pop(stack)?;
+                let signature = pop(stack)?;
+                let valid = check_sig(&signature, &public_key, ctx);
+                if opcode == OP_CHECKSIGVERIFY {
+                    if !valid {
+                        return Err(ScriptError::CheckSigVerify);
+                    }
+                } else {
+                    push_bool(stack, valid);
+                }
+            }
+            OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                let key_count = pop_num(stack)? as usize;
+                if key_count > MAX_PUBKEYS_PER_MULTISIG {
+                    return Err(ScriptError::PubkeyCount);
+                }
+                op_count += key_count;
+                if op_count > MAX_OPS_PER_SCRIPT {
+                    return Err(ScriptError::OpCount);
+                }
+                let mut keys = Vec::with_capacity(key_count);
+                for _ in 0..key_count {
+                    keys.push(pop(stack)?);
+                }
+                keys.reverse();
+                let sig_count = pop_num(stack)? as usize;
+                if sig_count > key_count {
+                    return Err(ScriptError::PubkeyCount);
+                }
+                let mut signatures = Vec::with_capacity(sig_count);
+                for _ in 0..sig_count {
+                    signatures.push(pop(stack)?);
+                }
+                signatures.reverse();
+
+                // Signatures must appear in the same order as the keys they match
+                let mut key_iter = keys.iter();
+                let valid = signatures
+                    .iter()
+                    .all(|sig| key_iter.by_ref().any(|key| check_sig(sig, key, ctx)));
+                if opcode == OP_CHECKMULTISIGVERIFY {
+                    if !valid {
+                        return Err(ScriptError::CheckMultisigVerify);
+                    }
+                } else {
+                    push_bool(stack, valid);
+                }
+            }
+            OP_CHECKLOCKTIMEVERIFY => {
+                let lock_time = decode_num(stack.last().ok_or(ScriptError::StackUnderflow)?)?;
+                if !lock_time_reached(lock_time, ctx.height, ctx.time) {
+                    return Err(ScriptError::UnsatisfiedLockTime);
+                }
+            }
+            OP_CHECKSEQUENCEVERIFY => {
+                let blocks = decode_num(stack.last().ok_or(ScriptError::StackUnderflow)?)?;
+                if ctx.height.saturating_sub(ctx.utxo_height) < blocks {
+                    return Err(ScriptError::UnsatisfiedLockTime);
+                }
+            }
+            other => return Err(ScriptError::BadOpcode(other)),
+        }
+
+        if stack.len() > MAX_STACK_SIZE {
+            return Err(ScriptError::StackSize);
+        }
+    }
+
+    if !conditions.is_empty() {
+        return Err(ScriptError::UnbalancedConditional);
+    }
+    Ok(())
+}
+
+fn is_known_opcode(opcode: u8) -> bool {
+    matches!(
+        opcode,
+        OP_1..=OP_16
+            | OP_VERIFY
+            | OP_RETURN
+            | OP_DROP
+            | OP_DUP
+            | OP_EQUAL
+            | OP_EQUALVERIFY
+            | OP_SHA256
+            | OP_CHECKSIG
+            | OP_CHECKSIGVERIFY
+            | OP_CHECKMULTISIG
+            | OP_CHECKMULTISIGVERIFY
+            | OP_CHECKLOCKTIMEVERIFY
+            | OP_CHECKSEQUENCEVERIFY
+    )
+}
+
+fn opcode_name(opcode: u8) -> String {
+    match opcode {
+        OP_1..=OP_16 => format!("OP_{}", opcode - OP_1 + 1),
+        OP_IF => "OP_IF".to_string(),
+        OP_NOTIF => "OP_NOTIF".to_string(),
+        OP_ELSE => "OP_ELSE".to_string(),
+        OP_ENDIF => "OP_ENDIF".to_string(),
+        OP_VERIFY => "OP_VERIFY".to_string(),
+        OP_RETURN => "OP_RETURN".to_string(),
+        OP_DROP => "OP_DROP".to_string(),
+        OP_DUP => "OP_DUP".to_string(),
+        OP_EQUAL => "OP_EQUAL".to_string(),
+        OP_EQUALVERIFY => "OP_EQUALVERIFY".to_string(),
+        OP_SHA256 => "OP_SHA256".to_string(),
+        OP_CHECKSIG => "OP_CHECKSIG".to_string(),
+        OP_CHECKSIGVERIFY => "OP_CHECKSIGVERIFY".to_string(),
+        OP_CHECKMULTISIG => "OP_CHECKMULTISIG".to_string(),
+        OP_CHECKMULTISIGVERIFY => "OP_CHECKMULTISIGVERIFY".to_string(),
+        OP_CHECKLOCKTIMEVERIFY => "OP_CHECKLOCKTIMEVERIFY".to_string(),
+        OP_CHECKSEQUENCEVERIFY => "OP_CHECKSEQUENCEVERIFY".to_string(),
+        other => format!("OP_UNKNOWN_{:02x}", other),
+    }
+}
+
+/// Human readable disassembly, e.g. `OP_DUP OP_SHA256 <ab12..> OP_EQUALVERIFY OP_CHECKSIG`.
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let instructions = match self.instructions() {
+            Ok(i) => i,
+            Err(_) => return write!(f, "<invalid script {}>", hex::encode(&self.0)),
+        };
+        let parts: Vec<String> = instructions
+            .iter()
+            .map(|i| match i {
+                Instruction::Push([]) => "OP_0".to_string(),
+                Instruction::Push(data) => format!("<{}>", hex::encode(data)),
+                Instruction::Op(op) => opcode_name(*op),
+            })
+            .collect();
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+// Scripts appear as hex strings in JSON.
+impl Serialize for Script {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(&self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for Script {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        hex::decode(&s).map(Script).map_err(serde::de::Error::custom)
+    }
+}
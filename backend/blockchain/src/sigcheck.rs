@@ -0,0 +1,149 @@
+//! Parallel, batched signature verification for block validation.
+//!
+//! Before a block's transactions are checked against the UTXO set one by one,
+//! every ed25519 signature that can be found without the UTXO set is verified up
+//! front: the signatures are cut into fixed-size batches in block order and the
+//! batches are verified on the rayon thread pool with ed25519 batch verification.
+//! A batch that fails is verified signature by signature, so one bad signature
+//! does not hide the good ones. The verified signatures go into a
+//! [`SignatureCache`] that the sequential contextual checks consult instead of
+//! verifying again; anything missing from the cache is verified on the spot, so
+//! the cache only ever saves work and never changes which blocks are valid.
+//!
+//! A batch must reach the same verdict as the strict single verifier,
+//! [`verify_hex_signature`]. The two can only differ on keys and nonces with a
+//! small-order component: the batch equation weighs each signature by a
+//! coefficient derived from the batch contents, and a signer can grind those
+//! until a torsion discrepancy cancels out. So only signatures whose key and
+//! nonce are canonical points of the prime-order subgroup go into a batch; any
+//! other signature is left to the strict verifier.
+//!
+//! Batch coefficients are derived from the batch contents rather than drawn at
+//! random, and batches are formed the same way whatever the thread count, so
+//! every node reaches the same verdict on the same block.
+
+use std::collections::HashSet;
+
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use ed25519_dalek::{PublicKey, Signature};
+use rayon::prelude::*;
+
+use crate::block::Block;
+use crate::transaction::verify_hex_signature;
+
+/// Signatures verified together in one batch.
+pub const BATCH_SIZE: usize = 64;
+
+/// An ed25519 signature over `message`, with the key and signature hex encoded.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SignatureCheck {
+    pub public_key: String,
+    pub message: Vec<u8>,
+    pub signature: String,
+}
+
+impl SignatureCheck {
+    pub fn new(public_key: &str, message: &[u8], signature: &str) -> Self {
+        SignatureCheck {
+            public_key: public_key.to_string(),
+            message: message.to_vec(),
+            signature: signature.to_string(),
+        }
+    }
+
+    pub fn verify(&self) -> bool {
+        verify_hex_signature(&self.public_key, &self.message, &self.signature)
+    }
+
+    /// The key and signature, if both are well formed and their points are safe
+    /// to verify in a batch.
+    fn batchable(&self) -> Option<(PublicKey, Signature)> {
+        let key_bytes = hex::decode(&self.public_key).ok()?;
+        let signature_bytes = hex::decode(&self.signature).ok()?;
+        if key_bytes.len() != 32 || signature_bytes.len() != 64 {
+            return None;
+        }
+        if !is_prime_order_point(&key_bytes) || !is_prime_order_point(&signature_bytes[..32]) {
+            return None;
+        }
+        Some((PublicKey::from_bytes(&key_bytes).ok()?, Signature::try_from(&signature_bytes[..]).ok()?))
+    }
+}
+
+/// Whether `bytes` is the canonical encoding of a point of the prime-order subgroup
+/// other than the identity.
+fn is_prime_order_point(bytes: &[u8]) -> bool {
+    let compressed = CompressedEdwardsY::from_slice(bytes);
+    match compressed.decompress() {
+        Some(point) => point.compress() == compressed && !point.is_small_order() && point.is_torsion_free(),
+        None => false,
+    }
+}
+
+/// Signatures already known to be valid.
+#[derive(Debug, Default)]
+pub struct SignatureCache {
+    verified: HashSet<SignatureCheck>,
+}
+
+impl SignatureCache {
+    /// Verifies a signature, skipping the work if it is already in the cache.
+    pub fn verify(&self, public_key: &str, message: &[u8], signature: &str) -> bool {
+        self.verified.contains(&SignatureCheck::new(public_key, message, signature))
+            || verify_hex_signature(public_key, message, signature)
+    }
+
+    pub fn len(&self) -> usize {
+        self.verified.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.verified.is_empty()
+    }
+}
+
+/// Verifies the signatures of every transaction in `block` across threads. The
+/// coinbase, which comes first, has none.
+pub fn verify_block_signatures(block: &Block) -> SignatureCache {
+    let checks: Vec<SignatureCheck> = block.transactions.iter().skip(1).flat_map(|tx| tx.signature_checks()).collect();
+    verify_parallel(&checks)
+}
+
+/// Verifies `checks` in batches of [`BATCH_SIZE`] across threads and returns
+/// the ones that are valid.
+pub fn verify_parallel(checks: &[SignatureCheck]) -> SignatureCache {
+    let verified = checks
+        .par_chunks(BATCH_SIZE)
+        .flat_map_iter(|chunk| {
+            let (batch, others): (Vec<&SignatureCheck>, Vec<&SignatureCheck>) = chunk.iter().partition(|check| check.batchable().is_some());
+            let batch: Vec<SignatureCheck> = batch.into_iter().cloned().collect();
+            let mut valid: Vec<SignatureCheck> = if verify_batch(&batch) {
+                batch
+            } else {
+                batch.into_iter().filter(SignatureCheck::verify).collect()
+            };
+            valid.extend(others.into_iter().filter(|check| check.verify()).cloned());
+            valid
+        })
+        .collect();
+    SignatureCache { verified }
+}
+
+/// Whether every signature in `checks` is valid, checked as one ed25519 batch.
+/// Signatures with keys or nonces outside the prime-order subgroup fail the batch.
+pub fn verify_batch(checks: &[SignatureCheck]) -> bool {
+    let mut messages = Vec::with_capacity(checks.len());
+    let mut signatures = Vec::with_capacity(checks.len());
+    let mut public_keys = Vec::with_capacity(checks.len());
+    for check in checks {
+        match check.batchable() {
+            Some((public_key, signature)) => {
+                messages.push(check.message.as_slice());
+                signatures.push(signature);
+                public_keys.push(public_key);
+            }
+            None => return false,
+        }
+    }
+    ed25519_dalek::verify_batch(&messages, &signatures, &public_keys).is_ok()
+}
@@ -0,0 +1,66 @@
+//! Payments built and signed away from a node.
+//!
+//! A wallet that keeps its key to itself learns its coins from a node (for example
+//! the `/blockchain/utxos` endpoint), builds the transaction here and hands the
+//! node only the signed result. The node validates it like any other transaction,
+//! so a coin list that is stale or wrong costs a rejection, never funds.
+
+use serde::{Deserialize, Serialize};
+
+use crate::transaction::{Transaction, TxInput, TxOutput};
+use crate::wallet::Wallet;
+
+/// A native-coin output the wallet can spend with its key.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Coin {
+    pub tx_id: String,
+    pub output_index: usize,
+    pub amount: u64,
+}
+
+/// Pays `amount` to `receiver_id` from `coins`, taken in the given order until they
+/// cover it, with the rest returned to the sender as change.
+pub fn build_payment(wallet: &Wallet, coins: &[Coin], receiver_id: String, amount: u64, note: Option<String>, timestamp: i64) -> Result<Transaction, String> {
+    if amount == 0 {
+        return Err("Payment amounts must be positive".to_string());
+    }
+    let sender_id = wallet.get_wallet_id();
+    let mut inputs = Vec::new();
+    let mut selected = 0u64;
+    for coin in coins {
+        if selected >= amount {
+            break;
+        }
+        selected = selected.checked_add(coin.amount).ok_or("Coin total overflows")?;
+        inputs.push(TxInput::new(coin.tx_id.clone(), coin.output_index));
+    }
+    if selected < amount {
+        return Err("Insufficient balance".to_string());
+    }
+
+    let mut outputs = vec![TxOutput::new(amount, receiver_id.clone())];
+    if selected > amount {
+        outputs.push(TxOutput::new(selected - amount, sender_id.clone()));
+    }
+    let mut tx = Transaction {
+        id: String::new(),
+        sender_wallet_id: sender_id,
+        receiver_wallet_id: receiver_id,
+        amount,
+        note,
+        timestamp,
+        sender_public_key: wallet.get_public_key_hex(),
+        signature: String::new(),
+        inputs,
+        outputs,
+        lock_time: None,
+        encrypted_note: None,
+        issuance: None,
+    };
+    tx.id = tx.calculate_hash();
+    for input in &mut tx.inputs {
+        input.signature = wallet.sign_transaction(&input.signing_message());
+    }
+    tx.signature = wallet.sign_transaction(&tx.id);
+    Ok(tx)
+}
@@ -0,0 +1,99 @@
+//! Block templates for miners running outside the node.
+//!
+//! The node picks the parent, timestamp and mempool transactions and hands them
+//! out as a [`BlockTemplate`]. The miner puts its own coinbase first, searches for
+//! a nonce that meets the target and sends back only the coinbase and the nonce;
+//! the node rebuilds the block from the template it issued and validates it like
+//! any other block.
+
+use serde::{Deserialize, Serialize};
+
+use crate::block::Block;
+use crate::transaction::{Transaction, TxOutput};
+
+/// Templates a node remembers per miner; older ones can no longer be submitted.
+pub const MAX_TEMPLATES: usize = 16;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockTemplate {
+    pub template_id: String,
+    pub version: u32,
+    pub height: u64,
+    pub previous_hash: String,
+    pub timestamp: i64,
+    /// Mempool transactions in block order. The coinbase goes before them.
+    pub transactions: Vec<Transaction>,
+    /// Most the coinbase may pay out.
+    pub coinbase_value: u64,
+    /// Leading zero hex digits the block hash needs.
+    pub difficulty: usize,
+    /// The same target as the highest acceptable hash.
+    pub target: String,
+}
+
+impl BlockTemplate {
+    /// A coinbase paying the whole coinbase value to `miner_wallet_id`.
+    pub fn coinbase(&self, miner_wallet_id: &str) -> Transaction {
+        coinbase(miner_wallet_id, self.coinbase_value, self.height, self.timestamp)
+    }
+
+    /// The block the template describes with `coinbase` first and `nonce` set.
+    pub fn block(&self, coinbase: Transaction, nonce: u64) -> Block {
+        let mut transactions = vec![coinbase];
+        transactions.extend(self.transactions.iter().cloned());
+        let mut block = Block::unsealed_versioned(self.version, self.height, transactions, self.previous_hash.clone(), self.timestamp);
+        block.nonce = nonce;
+        block.hash = block.calculate_hash();
+        block
+    }
+
+    /// Whether `hash` meets the template's target.
+    pub fn meets_target(&self, hash: &str) -> bool {
+        hash.starts_with(&"0".repeat(self.difficulty))
+    }
+}
+
+/// A coinbase paying `value` to `miner_wallet_id` in the block at `height`. Its
+/// id commits to the height, so coinbases of different blocks never share an id.
+pub fn coinbase(miner_wallet_id: &str, value: u64, height: u64, timestamp: i64) -> Transaction {
+    let mut coinbase = Transaction {
+        id: String::new(),
+        sender_wallet_id: "SYSTEM_REWARD".to_string(),
+        receiver_wallet_id: miner_wallet_id.to_string(),
+        amount: value,
+        note: Some(format!("Mining Reward at height {}", height)),
+        timestamp,
+        sender_public_key: String::new(),
+        signature: String::new(),
+        inputs: Vec::new(),
+        outputs: vec![TxOutput::new(value, miner_wallet_id.to_string())],
+        lock_time: None,
+        encrypted_note: None,
+        issuance: None,
+    };
+    coinbase.id = coinbase.calculate_hash();
+    coinbase
+}
+
+/// Checks a coinbase on its own: a reward transaction without inputs, paying native
+/// coins to its receiver only, whose id is the hash of its contents.
+pub fn check_coinbase(coinbase: &Transaction) -> Result<(), String> {
+    if coinbase.sender_wallet_id != "SYSTEM_REWARD" {
+        return Err("Coinbase must be a reward transaction".to_string());
+    }
+    if coinbase.receiver_wallet_id.is_empty() {
+        return Err("Coinbase has no receiver".to_string());
+    }
+    if !coinbase.inputs.is_empty() || coinbase.issuance.is_some() || coinbase.encrypted_note.is_some() {
+        return Err("Coinbase may only have outputs".to_string());
+    }
+    if coinbase.outputs.is_empty()
+        || coinbase.outputs.iter().any(|output| output.receiver_wallet_id != coinbase.receiver_wallet_id || output.asset.is_some() || output.script_pubkey.is_some())
+    {
+        return Err("Coinbase must pay native coins to its receiver".to_string());
+    }
+    if coinbase.id != coinbase.calculate_hash() {
+        return Err("Coinbase id is not the hash of its contents".to_string());
+    }
+    Ok(())
+}
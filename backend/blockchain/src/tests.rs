@@ -1,4 +1,5 @@
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
     #[test]
     fn test_invalid_signature_transaction() {
@@ -36,6 +37,13 @@ mod tests {
     use crate::wallet::Wallet;
     use crate::transaction::Transaction;
 
+    /// `transactions` behind a coinbase, for a block built by hand at the next height of `chain`.
+    fn with_coinbase(chain: &Blockchain, transactions: Vec<Transaction>) -> Vec<Transaction> {
+        let mut block_transactions = vec![crate::template::coinbase("miner", 0, chain.chain.len() as u64, 0)];
+        block_transactions.extend(transactions);
+        block_transactions
+    }
+
     #[test]
     fn test_wallet_creation() {
         let wallet = Wallet::new();
@@ -65,6 +73,9 @@ mod tests {
             signature: String::new(),
             inputs: vec![],
             outputs: vec![],
+            lock_time: None,
+            encrypted_note: None,
+            issuance: None,
         };
         
         tx.id = tx.calculate_hash();
@@ -117,4 +128,1688 @@ mod tests {
         assert_eq!(chain.get_balance(&sender.get_wallet_id()), 50); // 100 - 50
         assert_eq!(chain.get_balance(&receiver.get_wallet_id()), 50);
     }
+
+    #[test]
+    fn test_psbt_air_gapped_signing() {
+        use crate::psbt::PartiallySignedTransaction;
+
+        let mut chain = Blockchain::new();
+        let sender = Wallet::new();
+        let receiver = Wallet::new();
+        let miner = Wallet::new();
+        chain.mine_pending_transactions(&sender.get_wallet_id());
+
+        // Online machine only knows the public key
+        let mut psbt = chain.create_psbt(&sender.get_public_key_hex(), receiver.get_wallet_id(), 30, None).unwrap();
+        psbt.metadata.insert("label".to_string(), "rent".to_string());
+        let exported = psbt.to_base64();
+
+        // Offline signer
+        let mut offline = PartiallySignedTransaction::from_base64(&exported).unwrap();
+        assert!(offline.sign(&sender) >= 2);
+        let returned = offline.to_base64();
+
+        // Back online: merge, finalize, extract
+        psbt.combine(&PartiallySignedTransaction::from_base64(&returned).unwrap()).unwrap();
+        psbt.finalize().unwrap();
+        let tx = psbt.extract().unwrap();
+        assert!(chain.add_transaction(tx));
+        chain.mine_pending_transactions(&miner.get_wallet_id());
+
+        assert_eq!(chain.get_balance(&sender.get_wallet_id()), 70);
+        assert_eq!(chain.get_balance(&receiver.get_wallet_id()), 30);
+    }
+
+    #[test]
+    fn test_psbt_encoding_round_trip_and_errors() {
+        use crate::psbt::{PartiallySignedTransaction, PsbtError};
+
+        let mut chain = Blockchain::new();
+        let sender = Wallet::new();
+        let stranger = Wallet::new();
+        chain.mine_pending_transactions(&sender.get_wallet_id());
+
+        let mut psbt = chain.create_psbt(&sender.get_public_key_hex(), stranger.get_wallet_id(), 10, Some("memo".to_string())).unwrap();
+        let bytes = psbt.serialize();
+        assert_eq!(PartiallySignedTransaction::deserialize(&bytes).unwrap().serialize(), bytes);
+        assert!(PartiallySignedTransaction::deserialize(&bytes[..bytes.len() - 1]).is_err());
+
+        // A key that owns nothing cannot sign, and unsigned PSBTs cannot be finalized
+        assert_eq!(psbt.sign(&stranger), 0);
+        assert_eq!(psbt.finalize(), Err(PsbtError::MissingSignature(0)));
+        assert_eq!(psbt.extract().unwrap_err(), PsbtError::NotFinalized);
+
+        let other = chain.create_psbt(&sender.get_public_key_hex(), stranger.get_wallet_id(), 20, None).unwrap();
+        assert_eq!(psbt.combine(&other), Err(PsbtError::Mismatch));
+
+        // Copies must agree on every field of a spent output, not just its amount and owner
+        for tamper in [
+            (|utxo: &mut crate::transaction::TxOutput| utxo.asset = Some("gold".to_string())) as fn(&mut crate::transaction::TxOutput),
+            |utxo| utxo.lock_time = Some(1_000),
+            |utxo| utxo.memo = Some("memo".to_string()),
+        ] {
+            let mut copy = psbt.clone();
+            tamper(copy.inputs[0].utxo.as_mut().unwrap());
+            assert_eq!(psbt.clone().combine(&copy), Err(PsbtError::Conflict(0)));
+        }
+        assert_eq!(psbt.clone().combine(&psbt), Ok(()));
+    }
+
+    #[test]
+    fn test_multisig_spend_requires_threshold() {
+        use crate::multisig::MultisigPolicy;
+        use crate::psbt::{PartiallySignedTransaction, PsbtError};
+
+        let mut chain = Blockchain::new();
+        let (a, b, c) = (Wallet::new(), Wallet::new(), Wallet::new());
+        let funder = Wallet::new();
+        let receiver = Wallet::new();
+        let policy = MultisigPolicy::new(2, vec![a.get_public_key_hex(), b.get_public_key_hex(), c.get_public_key_hex()]).unwrap();
+        let treasury = policy.address();
+
+        // Key order does not change the address
+        let reordered = MultisigPolicy::new(2, vec![c.get_public_key_hex(), a.get_public_key_hex(), b.get_public_key_hex()]).unwrap();
+        assert_eq!(reordered.address(), treasury);
+        assert!(MultisigPolicy::new(4, vec![a.get_public_key_hex()]).is_err());
+
+        chain.mine_pending_transactions(&funder.get_wallet_id());
+        let fund = chain.create_transaction(&funder, treasury.clone(), 80, None).unwrap();
+        assert!(chain.add_transaction(fund));
+        chain.mine_pending_transactions(&funder.get_wallet_id());
+        assert_eq!(chain.get_balance(&treasury), 80);
+
+        let mut proposal = chain.create_multisig_psbt(&policy, receiver.get_wallet_id(), 50, Some("grant".to_string())).unwrap();
+        assert_eq!(proposal.sign(&funder), 0);
+        assert_eq!(proposal.sign(&a), 1);
+        assert_eq!(proposal.multisig_signature_count(0), 1);
+        assert_eq!(proposal.clone().finalize(), Err(PsbtError::MissingSignature(0)));
+
+        // The second co-signer signs an exported copy
+        let mut copy = PartiallySignedTransaction::from_base64(&proposal.to_base64()).unwrap();
+        assert_eq!(copy.sign(&c), 1);
+        proposal.combine(&copy).unwrap();
+        proposal.finalize().unwrap();
+        let tx = proposal.extract().unwrap();
+        assert!(tx.is_multisig_spend());
+
+        // Dropping a signature breaks the threshold
+        let mut stripped = tx.clone();
+        let witness = stripped.inputs[0].multisig.as_mut().unwrap();
+        let first_key = witness.signatures.keys().next().unwrap().clone();
+        witness.signatures.remove(&first_key);
+        assert!(!chain.add_transaction(stripped));
+
+        assert!(chain.add_transaction(tx));
+        chain.mine_pending_transactions(&funder.get_wallet_id());
+        assert_eq!(chain.get_balance(&treasury), 30);
+        assert_eq!(chain.get_balance(&receiver.get_wallet_id()), 50);
+    }
+
+    #[test]
+    fn test_sender_key_must_match_wallet() {
+        let mut chain = Blockchain::new();
+        let victim = Wallet::new();
+        let thief = Wallet::new();
+        chain.mine_pending_transactions(&victim.get_wallet_id());
+
+        // Build a spend of the victim's coins and sign it with another key
+        let mut tx = chain.create_transaction(&victim, thief.get_wallet_id(), 100, None).unwrap();
+        tx.sender_public_key = thief.get_public_key_hex();
+        tx.id = tx.calculate_hash();
+        for input in &mut tx.inputs {
+            input.signature = thief.sign_transaction(&input.signing_message());
+        }
+        tx.signature = thief.sign_transaction(&tx.id);
+        assert!(tx.verify_signature());
+        assert!(!chain.add_transaction(tx));
+    }
+
+    #[test]
+    fn test_script_pubkey_hash_and_multisig_spends() {
+        use crate::script::Script;
+
+        let mut chain = Blockchain::new();
+        let (alice, bob, carol) = (Wallet::new(), Wallet::new(), Wallet::new());
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+
+        // Pay-to-pubkey-hash outputs count towards the owner's balance and spend like plain ones
+        let p2pkh = Script::p2pkh(&bob.get_wallet_id()).unwrap();
+        assert!(chain.add_transaction(chain.create_script_transaction(&alice, p2pkh, 40, None).unwrap()));
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        assert_eq!(chain.get_balance(&bob.get_wallet_id()), 40);
+        let spend = chain.create_transaction(&bob, carol.get_wallet_id(), 15, None).unwrap();
+        assert!(spend.inputs[0].script_sig.is_some());
+        assert!(chain.add_transaction(spend));
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        assert_eq!(chain.get_balance(&carol.get_wallet_id()), 15);
+
+        // 2-of-3 multisig script, completed through a PSBT
+        let keys = vec![alice.get_public_key_hex(), bob.get_public_key_hex(), carol.get_public_key_hex()];
+        let multisig = Script::multisig(2, &keys).unwrap();
+        assert!(chain.add_transaction(chain.create_script_transaction(&alice, multisig.clone(), 60, None).unwrap()));
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+
+        let mut psbt = chain.create_script_psbt(&multisig, carol.get_wallet_id(), 60, None).unwrap();
+        assert_eq!(psbt.sign(&carol), 1);
+        assert!(psbt.clone().finalize().is_err());
+        assert_eq!(psbt.sign(&alice), 1);
+        psbt.finalize().unwrap();
+        let tx = psbt.extract().unwrap();
+        assert!(tx.is_script_spend());
+
+        // A signature from outside the script's keys does not unlock it
+        let mut forged = chain.create_script_psbt(&multisig, carol.get_wallet_id(), 60, None).unwrap();
+        assert_eq!(forged.sign(&Wallet::new()), 0);
+        let forged_sig = Wallet::new().sign_transaction(&forged.unsigned_tx.id);
+        forged.inputs[0].final_script_sig = Some(Script::multisig_unlock(&[forged_sig.clone(), forged_sig]).unwrap());
+        forged.finalize().unwrap();
+        assert!(!chain.add_transaction(forged.extract().unwrap()));
+
+        assert!(chain.add_transaction(tx));
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        assert_eq!(chain.get_balance(&carol.get_wallet_id()), 75);
+    }
+
+    #[test]
+    fn test_script_hashlock_and_timelocks() {
+        use crate::script::Script;
+        use sha2::{Digest, Sha256};
+
+        let mut chain = Blockchain::new();
+        let (alice, bob) = (Wallet::new(), Wallet::new());
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+
+        // Hashlock: anyone holding the preimage can spend
+        let preimage = b"open sesame";
+        let hashlock = Script::hashlock(&hex::encode(Sha256::digest(preimage))).unwrap();
+        assert!(chain.add_transaction(chain.create_script_transaction(&alice, hashlock.clone(), 10, None).unwrap()));
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+
+        let mut wrong = chain.create_script_psbt(&hashlock, bob.get_wallet_id(), 10, None).unwrap();
+        wrong.inputs[0].final_script_sig = Some(Script::new().push_slice(b"guess"));
+        wrong.finalize().unwrap();
+        assert!(!chain.add_transaction(wrong.extract().unwrap()));
+
+        let mut claim = chain.create_script_psbt(&hashlock, bob.get_wallet_id(), 10, None).unwrap();
+        claim.inputs[0].final_script_sig = Some(Script::new().push_slice(preimage));
+        claim.finalize().unwrap();
+        assert!(chain.add_transaction(claim.extract().unwrap()));
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        assert_eq!(chain.get_balance(&bob.get_wallet_id()), 10);
+
+        // Absolute timelock: unspendable before the given block height
+        let unlock_height = chain.chain.len() as u64 + 2;
+        let cltv = Script::absolute_timelock(unlock_height, Script::p2pkh(&bob.get_wallet_id()).unwrap());
+        assert!(chain.add_transaction(chain.create_script_transaction(&alice, cltv.clone(), 20, None).unwrap()));
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        let spend_cltv = |chain: &Blockchain| {
+            let mut psbt = chain.create_script_psbt(&cltv, bob.get_wallet_id(), 20, None).unwrap();
+            assert_eq!(psbt.sign(&bob), 1);
+            psbt.finalize().unwrap();
+            psbt.extract().unwrap()
+        };
+        assert!(!chain.add_transaction(spend_cltv(&chain)));
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        assert!(chain.add_transaction(spend_cltv(&chain)));
+
+        // Relative timelock: unspendable until the output is 3 blocks deep
+        let csv = Script::relative_timelock(3, Script::p2pkh(&bob.get_wallet_id()).unwrap());
+        assert!(chain.add_transaction(chain.create_script_transaction(&alice, csv.clone(), 5, None).unwrap()));
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        let spend_csv = |chain: &Blockchain| {
+            let mut psbt = chain.create_script_psbt(&csv, bob.get_wallet_id(), 5, None).unwrap();
+            psbt.sign(&bob);
+            psbt.finalize().unwrap();
+            psbt.extract().unwrap()
+        };
+        assert!(!chain.add_transaction(spend_csv(&chain)));
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        assert!(chain.add_transaction(spend_csv(&chain)));
+    }
+
+    #[test]
+    fn test_script_limits_and_standardness() {
+        use crate::script::{verify_script, Script, ScriptContext, ScriptError, MAX_ELEMENT_SIZE, OP_1, OP_DUP, OP_RETURN};
+
+        let ctx = ScriptContext { message: b"msg", height: 0, time: 0, utxo_height: 0 };
+        let truthy = Script::new().push_opcode(OP_1);
+        assert_eq!(verify_script(&Script::new(), &truthy, &ctx), Ok(()));
+        assert_eq!(verify_script(&Script::new(), &Script::new().push_opcode(OP_RETURN), &ctx), Err(ScriptError::OpReturn));
+        assert_eq!(verify_script(&truthy.clone().push_opcode(OP_DUP), &truthy, &ctx), Err(ScriptError::SigPushOnly));
+
+        let oversized = Script::new().push_slice(&vec![1u8; MAX_ELEMENT_SIZE + 1]);
+        assert_eq!(verify_script(&Script::new(), &oversized, &ctx), Err(ScriptError::PushSize));
+
+        let mut ops = Script::new().push_opcode(OP_1);
+        for _ in 0..250 {
+            ops = ops.push_opcode(OP_DUP);
+        }
+        assert_eq!(verify_script(&Script::new(), &ops, &ctx), Err(ScriptError::OpCount));
+
+        // Valid but non-standard scripts are kept out of the mempool
+        let mut chain = Blockchain::new();
+        let alice = Wallet::new();
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        assert!(!truthy.is_standard());
+        assert!(Script::p2pkh(&alice.get_wallet_id()).unwrap().is_standard());
+        let anyone_can_spend = chain.create_script_transaction(&alice, truthy, 10, None).unwrap();
+        assert!(chain.validate_transaction(&anyone_can_spend).is_ok());
+        assert!(!chain.add_transaction(anyone_can_spend));
+    }
+
+    #[test]
+    fn test_timelocked_outputs_and_balances() {
+        use crate::transaction::TxOutput;
+
+        let mut chain = Blockchain::new();
+        let (alice, bob, carol) = (Wallet::new(), Wallet::new(), Wallet::new());
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+
+        // A savings lock until an absolute height and a vesting lock relative to confirmation
+        let unlock_height = chain.chain.len() as u64 + 3;
+        let savings = TxOutput::new(30, bob.get_wallet_id()).locked_until(unlock_height);
+        assert!(chain.add_transaction(chain.create_payment(&alice, savings, Some("savings".to_string())).unwrap()));
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        let vesting = TxOutput::new(20, bob.get_wallet_id()).locked_for(2);
+        assert!(chain.add_transaction(chain.create_payment(&alice, vesting, None).unwrap()));
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+
+        let balance = chain.get_balance_details(&bob.get_wallet_id());
+        assert_eq!((balance.spendable, balance.locked), (0, 50));
+        assert_eq!(chain.get_balance(&bob.get_wallet_id()), 50);
+        assert!(chain.create_transaction(&bob, carol.get_wallet_id(), 10, None).is_err());
+
+        // Locked inputs picked by hand are rejected
+        let locked_key = chain
+            .utxos
+            .iter()
+            .find(|(_, output)| output.lock_time == Some(unlock_height))
+            .map(|(key, _)| key.clone())
+            .unwrap();
+        let mut forced = Transaction {
+            id: String::new(),
+            sender_wallet_id: bob.get_wallet_id(),
+            receiver_wallet_id: carol.get_wallet_id(),
+            amount: 30,
+            note: None,
+            timestamp: chrono::Utc::now().timestamp(),
+            sender_public_key: bob.get_public_key_hex(),
+            signature: String::new(),
+            inputs: vec![crate::transaction::TxInput::new(locked_key.0, locked_key.1)],
+            outputs: vec![TxOutput::new(30, carol.get_wallet_id())],
+            lock_time: None,
+            encrypted_note: None,
+            issuance: None,
+        };
+        forced.id = forced.calculate_hash();
+        forced.inputs[0].signature = bob.sign_transaction(&forced.inputs[0].signing_message());
+        forced.signature = bob.sign_transaction(&forced.id);
+        assert!(chain.validate_transaction(&forced).unwrap_err().contains("time-locked"));
+
+        // Both locks open with the next block
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        let balance = chain.get_balance_details(&bob.get_wallet_id());
+        assert_eq!((balance.spendable, balance.locked), (50, 0));
+        assert!(chain.add_transaction(forced));
+        assert!(chain.add_transaction(chain.create_transaction(&bob, carol.get_wallet_id(), 15, None).unwrap()));
+    }
+
+    #[test]
+    fn test_transaction_lock_time_and_block_validation() {
+        use crate::block::Block;
+
+        let mut chain = Blockchain::new();
+        let (alice, bob) = (Wallet::new(), Wallet::new());
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+
+        // A transaction that may only be mined from a later height
+        let release_height = chain.chain.len() as u64 + 1;
+        let mut tx = chain.create_unsigned_transaction(&alice.get_public_key_hex(), bob.get_wallet_id(), 25, None).unwrap();
+        tx.lock_time = Some(release_height);
+        tx.id = tx.calculate_hash();
+        for input in &mut tx.inputs {
+            input.signature = alice.sign_transaction(&input.signing_message());
+        }
+        tx.signature = alice.sign_transaction(&tx.id);
+        assert!(!chain.add_transaction(tx.clone()));
+
+        // Blocks carrying it too early are rejected
+        let early = Block::new(chain.chain.len() as u64, with_coinbase(&chain, vec![tx.clone()]), chain.get_latest_block().hash.clone());
+        assert!(chain.add_block(early).unwrap_err().contains("locked"));
+
+        let mut tampered = Block::new(chain.chain.len() as u64, with_coinbase(&chain, Vec::new()), chain.get_latest_block().hash.clone());
+        tampered.nonce += 1;
+        assert!(chain.add_block(tampered).is_err());
+        let orphan = Block::new(chain.chain.len() as u64, with_coinbase(&chain, Vec::new()), "f".repeat(64));
+        assert!(chain.add_block(orphan).is_err());
+
+        let empty = Block::new(chain.chain.len() as u64, with_coinbase(&chain, Vec::new()), chain.get_latest_block().hash.clone());
+        chain.add_block(empty).unwrap();
+        assert!(chain.add_transaction(tx.clone()));
+
+        // Accepting a block that confirms it clears it from the mempool
+        let block = Block::new(chain.chain.len() as u64, with_coinbase(&chain, vec![tx]), chain.get_latest_block().hash.clone());
+        chain.add_block(block).unwrap();
+        assert!(chain.pending_transactions.is_empty());
+        assert_eq!(chain.get_balance(&bob.get_wallet_id()), 25);
+        assert!(chain.is_chain_valid());
+    }
+
+    #[test]
+    fn test_htlc_escrow_claim_and_refund() {
+        use crate::htlc::{Htlc, HtlcState};
+
+        let mut chain = Blockchain::new();
+        let (buyer, seller) = (Wallet::new(), Wallet::new());
+        chain.mine_pending_transactions(&buyer.get_wallet_id());
+
+        let secret = b"delivery confirmed";
+        let timeout = chain.chain.len() as u64 + 3;
+        let htlc = Htlc::new(Htlc::hash_secret(secret), seller.get_wallet_id(), buyer.get_wallet_id(), timeout).unwrap();
+        assert_eq!(Htlc::from_script(&htlc.script().unwrap()), Some(htlc.clone()));
+
+        let funding = chain.create_htlc(&buyer, &htlc, 40, Some("order #1".to_string())).unwrap();
+        assert!(chain.add_transaction(funding.clone()));
+        chain.mine_pending_transactions(&buyer.get_wallet_id());
+        assert_eq!(chain.htlc_state(&funding.id, 0).unwrap().1, HtlcState::Active);
+
+        // Wrong secret, wrong claimant, and an early refund all fail
+        assert!(chain.claim_htlc(&seller, &funding.id, 0, b"guess").is_err());
+        assert!(!chain.add_transaction(chain.claim_htlc(&buyer, &funding.id, 0, secret).unwrap()));
+        assert!(!chain.add_transaction(chain.refund_htlc(&buyer, &funding.id, 0).unwrap()));
+
+        let claim = chain.claim_htlc(&seller, &funding.id, 0, secret).unwrap();
+        assert!(chain.add_transaction(claim.clone()));
+        let expected = HtlcState::Claimed { tx_id: claim.id.clone(), preimage: hex::encode(secret), confirmed: false };
+        assert_eq!(chain.htlc_state(&funding.id, 0).unwrap().1, expected);
+        chain.mine_pending_transactions(&buyer.get_wallet_id());
+        assert_eq!(chain.get_balance(&seller.get_wallet_id()), 40);
+
+        // A second escrow the seller never claims goes back to the buyer after the timeout
+        let timeout = chain.chain.len() as u64 + 2;
+        let htlc = Htlc::new(Htlc::hash_secret(b"unused"), seller.get_wallet_id(), buyer.get_wallet_id(), timeout).unwrap();
+        let funding = chain.create_htlc(&buyer, &htlc, 25, None).unwrap();
+        assert!(chain.add_transaction(funding.clone()));
+        chain.mine_pending_transactions(&seller.get_wallet_id());
+        assert!(!chain.add_transaction(chain.refund_htlc(&buyer, &funding.id, 0).unwrap()));
+        chain.mine_pending_transactions(&seller.get_wallet_id());
+        assert_eq!(chain.htlc_state(&funding.id, 0).unwrap().1, HtlcState::Expired);
+        assert!(!chain.add_transaction(chain.refund_htlc(&seller, &funding.id, 0).unwrap()));
+        let refund = chain.refund_htlc(&buyer, &funding.id, 0).unwrap();
+        assert!(chain.add_transaction(refund.clone()));
+        chain.mine_pending_transactions(&seller.get_wallet_id());
+        assert_eq!(chain.htlc_state(&funding.id, 0).unwrap().1, HtlcState::Refunded { tx_id: refund.id, confirmed: true });
+    }
+
+    #[test]
+    fn test_htlc_atomic_swap_between_chains() {
+        use crate::htlc::{Htlc, HtlcState};
+
+        // Alice has coins on network A and wants Bob's coins on network B
+        let (mut network_a, mut network_b) = (Blockchain::new(), Blockchain::new());
+        let (alice, bob) = (Wallet::new(), Wallet::new());
+        network_a.mine_pending_transactions(&alice.get_wallet_id());
+        network_b.mine_pending_transactions(&bob.get_wallet_id());
+
+        // Alice picks the secret and locks first, with the longer timeout
+        let secret = b"swap secret 42";
+        let hash_lock = Htlc::hash_secret(secret);
+        let htlc_a = Htlc::new(hash_lock.clone(), bob.get_wallet_id(), alice.get_wallet_id(), network_a.chain.len() as u64 + 10).unwrap();
+        let lock_a = network_a.create_htlc(&alice, &htlc_a, 70, None).unwrap();
+        assert!(network_a.add_transaction(lock_a.clone()));
+        network_a.mine_pending_transactions(&alice.get_wallet_id());
+
+        // Bob checks Alice's contract on A and locks on B under the same hash
+        let (seen, state) = network_a.htlc_state(&lock_a.id, 0).unwrap();
+        assert_eq!((seen.hash_lock.as_str(), state), (hash_lock.as_str(), HtlcState::Active));
+        let htlc_b = Htlc::new(hash_lock, alice.get_wallet_id(), bob.get_wallet_id(), network_b.chain.len() as u64 + 5).unwrap();
+        let lock_b = network_b.create_htlc(&bob, &htlc_b, 30, None).unwrap();
+        assert!(network_b.add_transaction(lock_b.clone()));
+        network_b.mine_pending_transactions(&bob.get_wallet_id());
+
+        // Alice claims on B, which reveals the secret there
+        assert!(network_b.add_transaction(network_b.claim_htlc(&alice, &lock_b.id, 0, secret).unwrap()));
+        network_b.mine_pending_transactions(&bob.get_wallet_id());
+        let revealed = match network_b.htlc_state(&lock_b.id, 0).unwrap().1 {
+            HtlcState::Claimed { preimage, .. } => hex::decode(preimage).unwrap(),
+            other => panic!("expected a claim, got {:?}", other),
+        };
+
+        // Bob uses the revealed secret to claim on A
+        assert!(network_a.add_transaction(network_a.claim_htlc(&bob, &lock_a.id, 0, &revealed).unwrap()));
+        network_a.mine_pending_transactions(&alice.get_wallet_id());
+        assert_eq!(network_a.get_balance(&bob.get_wallet_id()), 70);
+        assert_eq!(network_b.get_balance(&alice.get_wallet_id()), 30);
+    }
+
+    #[test]
+    fn test_batch_transaction_pays_many_outputs() {
+        use crate::chain::MAX_MEMO_LENGTH;
+        use crate::psbt::PartiallySignedTransaction;
+        use crate::transaction::{TxOutput, BATCH_RECEIVER};
+
+        let mut chain = Blockchain::new();
+        let employer = Wallet::new();
+        chain.mine_pending_transactions(&employer.get_wallet_id());
+        chain.mine_pending_transactions(&employer.get_wallet_id());
+
+        let employees: Vec<Wallet> = (0..50).map(|_| Wallet::new()).collect();
+        let payroll: Vec<TxOutput> = employees
+            .iter()
+            .enumerate()
+            .map(|(i, e)| TxOutput::new(3, e.get_wallet_id()).with_memo(format!("payslip {}", i)))
+            .collect();
+
+        let tx = chain.create_batch_transaction(&employer, payroll.clone(), Some("March payroll".to_string())).unwrap();
+        assert!(tx.is_batch());
+        assert_eq!(tx.receiver_wallet_id, BATCH_RECEIVER);
+        assert_eq!(tx.amount, 150);
+        assert_eq!(tx.outputs.len(), 51);
+        assert_eq!(tx.amount_to(&employer.get_wallet_id()), 50);
+        assert!(tx.involves(&employees[7].get_wallet_id()));
+        assert!(!tx.involves(&Wallet::new().get_wallet_id()));
+        assert!(chain.add_transaction(tx));
+        chain.mine_pending_transactions(&employer.get_wallet_id());
+
+        assert!(employees.iter().all(|e| chain.get_balance(&e.get_wallet_id()) == 3));
+        assert_eq!(chain.get_balance(&employer.get_wallet_id()), 150);
+
+        // Memos survive the PSBT encoding
+        let psbt = chain.create_psbt(&employer.get_public_key_hex(), employees[0].get_wallet_id(), 1, None).unwrap();
+        let mut psbt = PartiallySignedTransaction::deserialize(&psbt.serialize()).unwrap();
+        psbt.unsigned_tx.outputs[0].memo = Some("refund".to_string());
+        let decoded = PartiallySignedTransaction::deserialize(&psbt.serialize()).unwrap();
+        assert_eq!(decoded.unsigned_tx.outputs[0].memo.as_deref(), Some("refund"));
+
+        // A single receiver keeps the old top-level meaning
+        let single = chain.create_batch_transaction(&employer, payroll[..2].iter().map(|o| TxOutput::new(o.amount, payroll[0].receiver_wallet_id.clone())).collect(), None).unwrap();
+        assert_eq!(single.receiver_wallet_id, payroll[0].receiver_wallet_id);
+        assert_eq!(single.amount, 6);
+
+        assert!(chain.create_batch_transaction(&employer, Vec::new(), None).is_err());
+        assert!(chain.create_batch_transaction(&employer, vec![TxOutput::new(0, employees[0].get_wallet_id())], None).is_err());
+        assert!(chain.create_batch_transaction(&employer, vec![TxOutput::new(1_000, employees[0].get_wallet_id())], None).is_err());
+        let long_memo = TxOutput::new(1, employees[0].get_wallet_id()).with_memo("x".repeat(MAX_MEMO_LENGTH + 1));
+        assert!(!chain.add_transaction(chain.create_payment(&employer, long_memo, None).unwrap()));
+    }
+
+    #[test]
+    fn test_merkle_proofs() {
+        use crate::merkle::{merkle_proof, merkle_root, verify_merkle_proof, EMPTY_ROOT};
+
+        let transactions: Vec<Transaction> = (0..9)
+            .map(|i| {
+                let mut tx = Transaction {
+                    id: String::new(),
+                    sender_wallet_id: "SYSTEM_REWARD".to_string(),
+                    receiver_wallet_id: format!("wallet-{}", i),
+                    amount: i,
+                    note: None,
+                    timestamp: 0,
+                    sender_public_key: String::new(),
+                    signature: String::new(),
+                    inputs: vec![],
+                    outputs: vec![],
+                    lock_time: None,
+                    encrypted_note: None,
+                    issuance: None,
+                };
+                tx.id = tx.calculate_hash();
+                tx
+            })
+            .collect();
+
+        assert_eq!(merkle_root(&[]), EMPTY_ROOT);
+        for len in 1..=transactions.len() {
+            let set = &transactions[..len];
+            let root = merkle_root(set);
+            for (i, tx) in set.iter().enumerate() {
+                let proof = merkle_proof(set, i).unwrap();
+                assert!(verify_merkle_proof(tx, &proof, &root), "leaf {} of {}", i, len);
+            }
+            assert!(merkle_proof(set, len).is_none());
+        }
+
+        // Proofs do not carry over to other transactions or roots
+        let root = merkle_root(&transactions);
+        let proof = merkle_proof(&transactions, 4).unwrap();
+        assert!(!verify_merkle_proof(&transactions[5], &proof, &root));
+        assert!(!verify_merkle_proof(&transactions[4], &proof, &merkle_root(&transactions[..8])));
+
+        // Repeating the last transaction changes the root
+        let mut padded = transactions.clone();
+        padded.push(transactions[8].clone());
+        assert_ne!(merkle_root(&padded), root);
+    }
+
+    #[test]
+    fn test_notary_receipt_verifies_offline() {
+        use crate::notary::{verify_receipt, NotaryReceipt};
+        use crate::script::MAX_DATA_CARRIER_SIZE;
+        use crate::transaction::TxOutput;
+        use sha2::{Digest, Sha256};
+
+        let mut chain = Blockchain::new();
+        let alice = Wallet::new();
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+
+        let document_hash = hex::encode(Sha256::digest(b"tenancy agreement, signed 2024-03-01"));
+        assert!(chain.create_notary_transaction(&alice, "abcd", None).is_err());
+        let tx = chain.create_notary_transaction(&alice, &document_hash, Some("lease".to_string())).unwrap();
+        assert!(chain.add_transaction(tx.clone()));
+        assert!(chain.notary_receipt(&tx.id).is_none());
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+
+        // Data outputs cost nothing and never become spendable
+        assert_eq!(chain.get_balance(&alice.get_wallet_id()), 200);
+        assert!(chain.utxos.values().all(|output| !output.is_unspendable()));
+
+        // The receipt survives a JSON round trip and checks out on its own
+        let receipt = chain.notary_receipt(&tx.id).unwrap();
+        assert_eq!(receipt.document_hash, document_hash);
+        let receipt: NotaryReceipt = serde_json::from_str(&serde_json::to_string(&receipt).unwrap()).unwrap();
+        assert_eq!(verify_receipt(&receipt), Ok(chain.chain[2].timestamp));
+        assert!(chain.is_chain_valid());
+
+        let mut forged = receipt.clone();
+        forged.document_hash = hex::encode(Sha256::digest(b"a different document"));
+        assert!(verify_receipt(&forged).is_err());
+        let mut forged = receipt.clone();
+        forged.block.timestamp -= 3600;
+        assert!(verify_receipt(&forged).is_err());
+        let mut forged = receipt.clone();
+        forged.transaction.note = Some("edited".to_string());
+        assert!(verify_receipt(&forged).is_err());
+
+        // Oversized payloads, burnt coins and several data outputs are not relayed
+        assert!(TxOutput::data(&[0u8; MAX_DATA_CARRIER_SIZE + 1]).is_err());
+        let mut burn = TxOutput::data(b"burn").unwrap();
+        burn.amount = 5;
+        assert!(!chain.add_transaction(chain.create_payment(&alice, burn, None).unwrap()));
+        let two = vec![TxOutput::data(b"one").unwrap(), TxOutput::data(b"two").unwrap()];
+        assert!(!chain.add_transaction(chain.create_batch_transaction(&alice, two, None).unwrap()));
+    }
+
+    #[test]
+    fn test_encrypted_note_readable_by_both_parties_only() {
+        use crate::memo::{EncryptedMemo, MemoError};
+        use crate::transaction::TxOutput;
+
+        let mut chain = Blockchain::new();
+        let alice = Wallet::new();
+        let bob = Wallet::new();
+        let eve = Wallet::new();
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+
+        let payment = TxOutput::new(30, bob.get_wallet_id());
+        assert!(chain.create_private_payment(&alice, payment.clone(), &eve.get_public_key_hex(), "rent").is_err());
+        let tx = chain.create_private_payment(&alice, payment, &bob.get_public_key_hex(), "rent for March").unwrap();
+        assert!(tx.note.is_none());
+        assert!(chain.add_transaction(tx.clone()));
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        assert_eq!(chain.get_balance(&bob.get_wallet_id()), 30);
+
+        let memo = chain.chain[2].transactions[1].encrypted_note.clone().unwrap();
+        assert!(!memo.ciphertext.contains(&hex::encode("rent")));
+        assert_eq!(memo.decrypt(&bob).unwrap(), "rent for March");
+        assert_eq!(memo.decrypt(&alice).unwrap(), "rent for March");
+        assert_eq!(memo.decrypt(&eve), Err(MemoError::NotAParty));
+        assert!(memo.is_party(&bob.get_wallet_id()) && !memo.is_party(&eve.get_wallet_id()));
+
+        // Claiming to be a party does not help without the key
+        let mut relabelled = memo.clone();
+        relabelled.recipient_public_key = eve.get_public_key_hex();
+        assert_eq!(relabelled.decrypt(&eve), Err(MemoError::Decryption));
+        let mut tampered = memo.clone();
+        tampered.ciphertext.replace_range(0..2, if &tampered.ciphertext[0..2] == "00" { "01" } else { "00" });
+        assert_eq!(tampered.decrypt(&bob), Err(MemoError::Decryption));
+
+        // The note is covered by the transaction id
+        let mut swapped = tx.clone();
+        swapped.encrypted_note = Some(EncryptedMemo::encrypt(&alice, &bob.get_public_key_hex(), "something else").unwrap());
+        assert_ne!(swapped.calculate_hash(), tx.id);
+
+        // The note survives a PSBT round trip
+        let unsigned = chain.create_unsigned_transaction(&alice.get_public_key_hex(), bob.get_wallet_id(), 5, None).unwrap();
+        let mut psbt = crate::psbt::PartiallySignedTransaction::new(unsigned);
+        psbt.unsigned_tx.encrypted_note = Some(memo.clone());
+        let decoded = crate::psbt::PartiallySignedTransaction::deserialize(&psbt.serialize()).unwrap();
+        assert_eq!(decoded.unsigned_tx.encrypted_note, Some(memo));
+    }
+
+    #[test]
+    fn test_asset_issuance_transfer_and_mint() {
+        use crate::asset::{Issuance, SupplyPolicy};
+        use crate::transaction::TxOutput;
+
+        let mut chain = Blockchain::new();
+        let alice = Wallet::new();
+        let bob = Wallet::new();
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        chain.mine_pending_transactions(&bob.get_wallet_id());
+
+        let issue = chain.create_asset(&alice, "Gold".to_string(), 2, SupplyPolicy::Fixed, 1_000, None).unwrap();
+        let gold = issue.issued_asset_id().unwrap();
+        assert!(chain.add_transaction(issue));
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        assert_eq!(chain.assets[&gold].supply, 1_000);
+        assert_eq!(chain.assets[&gold].decimals, 2);
+        assert_eq!(chain.get_asset_balances(&alice.get_wallet_id())[&gold].total(), 1_000);
+        // Tokens are not counted as coins
+        assert_eq!(chain.get_balance(&alice.get_wallet_id()), 200);
+
+        let transfer = chain.create_payment(&alice, TxOutput::new(250, bob.get_wallet_id()).with_asset(gold.clone()), None).unwrap();
+        assert!(chain.create_payment(&alice, TxOutput::new(2_000, bob.get_wallet_id()).with_asset(gold.clone()), None).is_err());
+
+        // Outputs of an asset may not exceed its inputs
+        let mut inflated = transfer.clone();
+        inflated.outputs[0].amount = 900;
+        inflated.id = inflated.calculate_hash();
+        inflated.signature = alice.sign_transaction(&inflated.id);
+        assert!(!chain.add_transaction(inflated));
+
+        assert!(chain.add_transaction(transfer));
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        assert_eq!(chain.get_asset_balances(&alice.get_wallet_id())[&gold].total(), 750);
+        assert_eq!(chain.get_asset_balances(&bob.get_wallet_id())[&gold].total(), 250);
+        assert_eq!(chain.get_balance(&bob.get_wallet_id()), 100);
+
+        // A fixed supply cannot grow
+        assert!(chain.mint_asset(&alice, &gold, alice.get_wallet_id(), 1, None).is_err());
+
+        let issue = chain.create_asset(&alice, "Points".to_string(), 0, SupplyPolicy::Mintable { admin_wallet_id: alice.get_wallet_id() }, 10, None).unwrap();
+        let points = issue.issued_asset_id().unwrap();
+        assert!(chain.add_transaction(issue));
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        assert!(chain.mint_asset(&bob, &points, bob.get_wallet_id(), 5, None).is_err());
+        assert!(chain.add_transaction(chain.mint_asset(&alice, &points, bob.get_wallet_id(), 40, None).unwrap()));
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        assert_eq!(chain.assets[&points].supply, 50);
+        assert_eq!(chain.get_asset_balances(&bob.get_wallet_id())[&points].total(), 40);
+
+        // Someone other than the admin cannot mint by signing their own issuance
+        let mut forged = chain.create_transaction(&bob, alice.get_wallet_id(), 1, None).unwrap();
+        forged.outputs.push(TxOutput::new(1_000, bob.get_wallet_id()).with_asset(points.clone()));
+        forged.issuance = Some(Issuance::Mint { asset_id: points.clone() });
+        forged.id = forged.calculate_hash();
+        forged.signature = bob.sign_transaction(&forged.id);
+        assert!(!chain.add_transaction(forged));
+
+        // System transactions cannot carry assets
+        let mut reward = chain.chain[1].transactions[0].clone();
+        reward.outputs[0] = TxOutput::new(5, bob.get_wallet_id()).with_asset(gold.clone());
+        reward.id = reward.calculate_hash();
+        assert!(!chain.add_transaction(reward));
+        assert!(chain.is_chain_valid());
+    }
+
+    #[test]
+    fn test_asset_transactions_in_psbt() {
+        use crate::asset::SupplyPolicy;
+        use crate::psbt::PartiallySignedTransaction;
+
+        let mut chain = Blockchain::new();
+        let alice = Wallet::new();
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        let policy = SupplyPolicy::Mintable { admin_wallet_id: alice.get_wallet_id() };
+        assert!(chain.create_asset(&alice, String::new(), 0, policy.clone(), 10, None).is_err());
+        assert!(chain.create_asset(&alice, "Shares".to_string(), 19, policy.clone(), 10, None).is_err());
+
+        let issue = chain.create_asset(&alice, "Shares".to_string(), 4, policy, 10, None).unwrap();
+        let decoded = PartiallySignedTransaction::deserialize(&PartiallySignedTransaction::new(issue.clone()).serialize()).unwrap();
+        assert_eq!(decoded.unsigned_tx.issuance, issue.issuance);
+        assert_eq!(decoded.unsigned_tx.outputs[0].asset, issue.issued_asset_id());
+        assert_eq!(decoded.unsigned_tx.calculate_hash(), issue.id);
+    }
+
+    #[test]
+    fn test_proof_of_authority_round_robin() {
+        use crate::block::Block;
+        use crate::consensus::{ConsensusError, ProofOfAuthority};
+
+        let validators: Vec<Wallet> = (0..3).map(|_| Wallet::new()).collect();
+        let keys: Vec<String> = validators.iter().map(|v| v.get_public_key_hex()).collect();
+        let node = |signer: usize| {
+            let signer = Wallet::from_private_key(&hex::encode(validators[signer].keypair.secret.as_bytes())).unwrap();
+            Blockchain::with_consensus(Box::new(ProofOfAuthority::new(keys.clone(), Some(signer)).unwrap()))
+        };
+        assert!(ProofOfAuthority::new(Vec::new(), None).is_err());
+        assert!(ProofOfAuthority::new(vec![keys[0].clone(), keys[0].clone()], None).is_err());
+        assert_eq!(ProofOfAuthority::new(keys[..1].to_vec(), Some(Wallet::new())).err(), Some(ConsensusError::NotAValidator));
+
+        // Block 1 is validator 1's turn, block 2 validator 2's
+        let mut first = node(1);
+        let mut second = node(2);
+        first.produce_block(&validators[1].get_wallet_id()).unwrap();
+        assert!(first.produce_block(&validators[1].get_wallet_id()).unwrap_err().contains("validator"));
+        let block = first.chain[1].clone();
+        assert_eq!(block.validator.as_deref(), Some(keys[1].as_str()));
+
+        // Forged or out-of-turn seals are rejected
+        let mut forged = block.clone();
+        forged.signature = Some(validators[2].sign_transaction(&forged.hash));
+        assert!(second.add_block(forged).is_err());
+        let mut impostor = block.clone();
+        impostor.validator = Some(keys[2].clone());
+        impostor.signature = Some(validators[2].sign_transaction(&impostor.hash));
+        assert!(second.add_block(impostor).is_err());
+        let mined = Block::new(1, Vec::new(), second.get_latest_block().hash.clone());
+        assert!(second.add_block(mined).is_err());
+
+        second.add_block(block).unwrap();
+        second.produce_block(&validators[2].get_wallet_id()).unwrap();
+        first.add_block(second.chain[2].clone()).unwrap();
+        assert_eq!(first.get_balance(&validators[2].get_wallet_id()), 100);
+        assert!(first.is_chain_valid() && second.is_chain_valid());
+
+        // A node without a key follows the chain but cannot seal
+        let mut follower = Blockchain::with_consensus(Box::new(ProofOfAuthority::new(keys.clone(), None).unwrap()));
+        follower.add_block(first.chain[1].clone()).unwrap();
+        assert!(follower.produce_block("anyone").is_err());
+    }
+
+    #[test]
+    fn test_light_client_verifies_headers_and_proofs() {
+        use crate::consensus::ProofOfWork;
+        use crate::light_client::{LightClient, LightClientError};
+
+        let mut chain = Blockchain::new();
+        let alice = Wallet::new();
+        let bob = Wallet::new();
+        let carol = Wallet::new();
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        assert!(chain.add_transaction(chain.create_transaction(&alice, bob.get_wallet_id(), 40, None).unwrap()));
+        assert!(chain.add_transaction(chain.create_transaction(&alice, carol.get_wallet_id(), 10, None).unwrap()));
+        chain.mine_pending_transactions(&carol.get_wallet_id());
+        assert!(chain.add_transaction(chain.create_transaction(&bob, carol.get_wallet_id(), 15, None).unwrap()));
+        chain.mine_pending_transactions(&bob.get_wallet_id());
+
+        let mut client = LightClient::new(Box::new(ProofOfWork::new(2)), chain.chain[0].header(), bob.get_wallet_id());
+        assert_eq!(client.sync_headers(&chain.headers(1, 3)).unwrap(), 3);
+
+        // Proofs for blocks the client has not synced yet wait for their headers
+        let proofs = chain.wallet_proofs(&bob.get_wallet_id());
+        assert_eq!(proofs.len(), 3);
+        assert_eq!(client.verify_transaction(&proofs[2]), Err(LightClientError::UnknownBlock(4)));
+
+        // Headers that break the link, the hash or the work are refused
+        let mut next = chain.chain[4].header();
+        next.merkle_root = chain.chain[3].merkle_root.clone();
+        assert_eq!(client.sync_headers(std::slice::from_ref(&next)), Err(LightClientError::BadHash(4)));
+        let mut weak = chain.chain[4].header();
+        while weak.calculate_hash().starts_with("00") {
+            weak.nonce += 1;
+        }
+        weak.hash = weak.calculate_hash();
+        assert!(matches!(client.sync_headers(&[weak]), Err(LightClientError::BadSeal { index: 4, .. })));
+        assert_eq!(client.sync_headers(&chain.headers(3, 10)).unwrap_err(), LightClientError::OutOfOrder { expected: 4, got: 3 });
+        assert_eq!(client.sync_headers(&chain.headers(4, 10)).unwrap(), 1);
+        assert_eq!(client.tip().hash, chain.get_latest_block().hash);
+
+        for proof in &proofs {
+            client.verify_transaction(proof).unwrap();
+        }
+        assert_eq!(client.balance(), chain.get_balance(&bob.get_wallet_id()));
+        assert_eq!(client.confirmations(&proofs[0].transaction.id), Some(2));
+
+        // Altered transactions and other wallets' transactions are refused
+        let mut forged = proofs[0].clone();
+        forged.transaction.outputs[0].amount = 4_000;
+        assert_eq!(client.verify_transaction(&forged), Err(LightClientError::BadProof(3)));
+        let carol_only = chain.wallet_proofs(&carol.get_wallet_id()).into_iter().find(|p| !p.transaction.involves(&bob.get_wallet_id())).unwrap();
+        assert_eq!(client.verify_transaction(&carol_only), Err(LightClientError::NotOurs));
+    }
+
+    #[test]
+    fn test_block_filters_match_receivers_and_spends() {
+        use crate::filter::{outpoint_element, wallet_element, BlockFilter};
+
+        let mut chain = Blockchain::new();
+        let alice = Wallet::new();
+        let bob = Wallet::new();
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        let tx = chain.create_transaction(&alice, bob.get_wallet_id(), 30, None).unwrap();
+        let spent = tx.inputs[0].clone();
+        assert!(chain.add_transaction(tx));
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+
+        assert_eq!(chain.filters.len(), chain.chain.len());
+        let filter = chain.filters[3].clone();
+        assert_eq!(filter, BlockFilter::build(&chain.chain[3]));
+        assert!(filter.match_any(&[wallet_element(&bob.get_wallet_id())]).unwrap());
+        assert!(filter.match_any(&[outpoint_element(&spent.tx_id, spent.output_index)]).unwrap());
+        assert!(!chain.filters[1].match_any(&[wallet_element(&bob.get_wallet_id())]).unwrap());
+
+        let strangers: Vec<Vec<u8>> = (0..200).map(|_| wallet_element(&Wallet::new().get_wallet_id())).collect();
+        let false_positives = strangers.iter().filter(|e| filter.match_any(std::slice::from_ref(e)).unwrap()).count();
+        assert!(false_positives <= 1);
+        assert!(!chain.filters[0].match_any(&strangers).unwrap());
+    }
+
+    #[test]
+    fn test_wallet_scanner_fetches_only_matching_blocks() {
+        use crate::filter::{FilterError, WalletScanner};
+
+        let mut chain = Blockchain::new();
+        let alice = Wallet::new();
+        let bob = Wallet::new();
+        let carol = Wallet::new();
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        assert!(chain.add_transaction(chain.create_transaction(&alice, bob.get_wallet_id(), 40, None).unwrap()));
+        chain.mine_pending_transactions(&carol.get_wallet_id());
+        chain.mine_pending_transactions(&carol.get_wallet_id());
+        assert!(chain.add_transaction(chain.create_transaction(&bob, carol.get_wallet_id(), 15, None).unwrap()));
+        chain.mine_pending_transactions(&carol.get_wallet_id());
+
+        let mut scanner = WalletScanner::new(bob.get_wallet_id());
+        let mut fetched = Vec::new();
+        let count = scanner
+            .scan(&chain.filters, |index| {
+                fetched.push(index);
+                chain.chain.get(index as usize).cloned()
+            })
+            .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(fetched, vec![3, 5]);
+        assert_eq!(scanner.history().len(), 2);
+        assert_eq!(scanner.balance(), chain.get_balance(&bob.get_wallet_id()));
+        assert_eq!(scanner.next_height(), chain.chain.len() as u64);
+
+        // A server answering with a different block is caught
+        let mut fresh = WalletScanner::new(bob.get_wallet_id());
+        let result = fresh.scan(&chain.filters, |index| chain.chain.get(index as usize + 1).cloned());
+        assert_eq!(result, Err(FilterError::BlockMismatch(3)));
+    }
+
+    #[test]
+    fn test_batched_signature_verification_isolates_bad_signatures() {
+        use crate::sigcheck::{verify_batch, verify_parallel, SignatureCheck, BATCH_SIZE};
+
+        let wallets: Vec<Wallet> = (0..BATCH_SIZE + 10).map(|_| Wallet::new()).collect();
+        let mut checks: Vec<SignatureCheck> = wallets
+            .iter()
+            .enumerate()
+            .map(|(i, w)| SignatureCheck::new(&w.get_public_key_hex(), format!("message {}", i).as_bytes(), &w.sign_transaction(&format!("message {}", i))))
+            .collect();
+        assert!(verify_batch(&checks));
+        assert_eq!(verify_parallel(&checks).len(), checks.len());
+        checks[3].message = b"something else".to_vec();
+        assert!(!verify_batch(&checks));
+
+        let cache = verify_parallel(&checks);
+        assert_eq!(cache.len(), checks.len() - 1);
+        assert!(!cache.verify(&checks[3].public_key, &checks[3].message, &checks[3].signature));
+        assert!(cache.verify(&checks[4].public_key, &checks[4].message, &checks[4].signature));
+    }
+
+    #[test]
+    fn test_small_order_key_signs_nothing() {
+        use crate::sigcheck::{verify_batch, verify_parallel, SignatureCheck};
+        use crate::transaction::verify_hex_signature;
+
+        // The identity point as key and nonce with s = 0 satisfies the plain ed25519
+        // equation for every message
+        let identity = format!("01{}", "00".repeat(31));
+        let signature = format!("{}{}", identity, "00".repeat(32));
+        for message in [&b"pay alice"[..], b"pay bob"] {
+            assert!(!verify_hex_signature(&identity, message, &signature));
+            let check = SignatureCheck::new(&identity, message, &signature);
+            let cache = verify_parallel(std::slice::from_ref(&check));
+            assert!(cache.is_empty());
+            assert!(!cache.verify(&check.public_key, &check.message, &check.signature));
+            assert!(!verify_batch(std::slice::from_ref(&check)));
+        }
+
+        // Mixed into a batch of good signatures it is still the only one rejected
+        let wallet = Wallet::new();
+        let mut checks = vec![SignatureCheck::new(&identity, b"pay alice", &signature)];
+        checks.push(SignatureCheck::new(&wallet.get_public_key_hex(), b"pay bob", &wallet.sign_transaction("pay bob")));
+        let cache = verify_parallel(&checks);
+        assert_eq!(cache.len(), 1);
+        assert!(cache.verify(&checks[1].public_key, &checks[1].message, &checks[1].signature));
+    }
+
+    #[test]
+    fn test_block_with_bad_input_signature_is_rejected() {
+        use crate::block::Block;
+
+        let mut chain = Blockchain::new();
+        let alice = Wallet::new();
+        let bob = Wallet::new();
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        chain.mine_pending_transactions(&bob.get_wallet_id());
+        let good = chain.create_transaction(&bob, alice.get_wallet_id(), 20, None).unwrap();
+        let mut bad = chain.create_transaction(&alice, bob.get_wallet_id(), 20, None).unwrap();
+        bad.inputs[0].signature = alice.sign_transaction("not the input");
+
+        let tip = chain.get_latest_block().hash.clone();
+        let mut block = Block::unsealed(chain.chain.len() as u64, with_coinbase(&chain, vec![good.clone(), bad]), tip.clone());
+        chain.consensus.seal(&mut block).unwrap();
+        let err = chain.add_block(block).unwrap_err();
+        assert!(err.contains("Invalid signature for input 0"), "{}", err);
+
+        let mut block = Block::unsealed(chain.chain.len() as u64, with_coinbase(&chain, vec![good]), tip);
+        chain.consensus.seal(&mut block).unwrap();
+        chain.add_block(block).unwrap();
+        assert_eq!(chain.get_balance(&alice.get_wallet_id()), 120);
+    }
+
+    #[test]
+    fn test_reserved_senders_cannot_spend_other_wallets() {
+        use crate::encoding::{Decode, Encode};
+        use crate::transaction::{TxInput, TxOutput, RESERVED_SENDERS};
+
+        let mut chain = Blockchain::new();
+        let victim = Wallet::new();
+        let attacker = Wallet::new();
+        chain.mine_pending_transactions(&victim.get_wallet_id());
+        let (utxo, output) = chain.utxos.iter().next().map(|(key, output)| (key.clone(), output.clone())).unwrap();
+
+        for sender in RESERVED_SENDERS {
+            // An unsigned spend of the victim's coins, as it would arrive at the raw endpoint
+            let mut theft = Transaction {
+                id: String::new(),
+                sender_wallet_id: sender.to_string(),
+                receiver_wallet_id: attacker.get_wallet_id(),
+                amount: output.amount,
+                note: None,
+                timestamp: 0,
+                sender_public_key: String::new(),
+                signature: String::new(),
+                inputs: vec![TxInput::new(utxo.0.clone(), utxo.1)],
+                outputs: vec![TxOutput::new(output.amount, attacker.get_wallet_id())],
+                lock_time: None,
+                encrypted_note: None,
+                issuance: None,
+            };
+            theft.id = theft.calculate_hash();
+            let theft = Transaction::decode(&theft.encode()).unwrap();
+            assert!(theft.has_reserved_sender());
+            assert!(chain.validate_transaction(&theft).is_err(), "{}", sender);
+            assert!(!chain.add_transaction(theft));
+        }
+        assert_eq!(chain.get_balance(&victim.get_wallet_id()), output.amount);
+        assert_eq!(chain.get_balance(&attacker.get_wallet_id()), 0);
+    }
+
+    #[test]
+    fn test_block_must_open_with_one_fresh_coinbase() {
+        use crate::block::Block;
+        use crate::template::coinbase;
+        use crate::transaction::{TxInput, TxOutput};
+
+        let mut chain = Blockchain::new();
+        let victim = Wallet::new();
+        let miner = Wallet::new().get_wallet_id();
+        chain.mine_pending_transactions(&victim.get_wallet_id());
+        let reward = chain.chain[1].transactions[0].clone();
+        let payment = chain.create_transaction(&victim, miner.clone(), 10, None).unwrap();
+        let height = chain.chain.len() as u64;
+        let fresh = coinbase(&miner, 100, height, 0);
+
+        // A system transaction taking the victim's reward without a signature
+        let steal = |sender: &str| {
+            let mut tx = coinbase(&miner, 100, height, 0);
+            tx.sender_wallet_id = sender.to_string();
+            tx.inputs = vec![TxInput::new(reward.id.clone(), 0)];
+            tx.id = tx.calculate_hash();
+            tx
+        };
+        let mut reused = reward.clone();
+        reused.outputs = vec![TxOutput::new(100, miner.clone())];
+        reused.receiver_wallet_id = miner.clone();
+
+        let rejected = [
+            (Vec::new(), "no coinbase"),
+            (vec![payment.clone(), fresh.clone()], "reward transaction"),
+            (vec![fresh.clone(), coinbase(&miner, 0, height, 1)], "reserved sender"),
+            (vec![fresh.clone(), steal("ZAKAT_POOL")], "reserved sender"),
+            (vec![steal("SYSTEM_REWARD")], "only have outputs"),
+            (vec![reused], "hash of its contents"),
+            (vec![reward.clone()], "already on the chain"),
+            (vec![fresh.clone(), payment.clone(), payment.clone()], "twice"),
+        ];
+        for (transactions, reason) in rejected {
+            let mut block = Block::unsealed(height, transactions, chain.get_latest_block().hash.clone());
+            chain.consensus.seal(&mut block).unwrap();
+            let err = chain.add_block(block).unwrap_err();
+            assert!(err.contains(reason), "expected {}: {}", reason, err);
+        }
+        assert_eq!(chain.get_balance(&victim.get_wallet_id()), 100);
+
+        let mut block = Block::unsealed(height, vec![fresh, payment], chain.get_latest_block().hash.clone());
+        chain.consensus.seal(&mut block).unwrap();
+        chain.add_block(block).unwrap();
+        assert_eq!(chain.get_balance(&miner), 110);
+    }
+
+    #[test]
+    fn test_block_from_the_far_future_is_rejected() {
+        use std::sync::Arc;
+
+        use crate::block::Block;
+        use crate::chain::MAX_FUTURE_BLOCK_TIME;
+        use crate::consensus::ProofOfWork;
+        use crate::environment::{Clock, Environment, ManualClock};
+
+        let clock = Arc::new(ManualClock::new(1_700_000_000));
+        let mut chain = Blockchain::with_environment(Box::new(ProofOfWork::new(1)), Environment::deterministic(clock.clone(), 3));
+        let block_at = |chain: &Blockchain, timestamp: i64| {
+            let mut block = Block::unsealed_at(1, with_coinbase(chain, Vec::new()), chain.get_latest_block().hash.clone(), timestamp);
+            chain.consensus.seal(&mut block).unwrap();
+            block
+        };
+
+        let err = chain.add_block(block_at(&chain, clock.now() + MAX_FUTURE_BLOCK_TIME + 1)).unwrap_err();
+        assert!(err.contains("future"), "{}", err);
+        // The same block is accepted once the clock catches up to put it right at the limit
+        let block = block_at(&chain, clock.now() + MAX_FUTURE_BLOCK_TIME + 1);
+        clock.advance(1);
+        chain.add_block(block).unwrap();
+    }
+
+    #[test]
+    fn test_canonical_encoding_round_trips_blocks_and_transactions() {
+        use crate::asset::SupplyPolicy;
+        use crate::block::Block;
+        use crate::encoding::{Decode, Encode, EncodingError, ENCODING_VERSION};
+        use crate::transaction::TxOutput;
+
+        let mut chain = Blockchain::new();
+        let alice = Wallet::new();
+        let bob = Wallet::new();
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        let payment = TxOutput::new(10, bob.get_wallet_id()).with_memo("invoice 7".to_string()).locked_for(2);
+        assert!(chain.add_transaction(chain.create_private_payment(&alice, payment, &bob.get_public_key_hex(), "thanks").unwrap()));
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        let issue = chain.create_asset(&alice, "Points".to_string(), 2, SupplyPolicy::Mintable { admin_wallet_id: alice.get_wallet_id() }, 500, None).unwrap();
+        assert!(chain.add_transaction(issue));
+        chain.mine_pending_transactions(&bob.get_wallet_id());
+
+        for block in &chain.chain {
+            let bytes = block.encode();
+            assert_eq!(bytes[0], ENCODING_VERSION);
+            let decoded = Block::decode(&bytes).unwrap();
+            assert_eq!(decoded.encode(), bytes);
+            assert_eq!(decoded.calculate_hash(), block.hash);
+            assert!(decoded.has_valid_merkle_root());
+            for tx in &decoded.transactions {
+                let tx_bytes = tx.encode();
+                let tx_decoded = Transaction::decode(&tx_bytes).unwrap();
+                assert_eq!(tx_decoded.encode(), tx_bytes);
+                if tx.sender_wallet_id != "SYSTEM_REWARD" {
+                    assert_eq!(tx_decoded.calculate_hash(), tx.id);
+                }
+            }
+        }
+
+        let bytes = chain.get_latest_block().encode();
+        assert_eq!(Block::decode(&bytes[..bytes.len() - 1]).unwrap_err(), EncodingError::UnexpectedEnd);
+        assert_eq!(Block::decode(&[bytes.as_slice(), &[0]].concat()).unwrap_err(), EncodingError::TrailingBytes);
+        let mut future = bytes.clone();
+        future[0] = ENCODING_VERSION + 1;
+        assert_eq!(Block::decode(&future).unwrap_err(), EncodingError::UnsupportedVersion(ENCODING_VERSION + 1));
+    }
+
+    #[test]
+    fn test_transaction_id_commits_to_every_field_unambiguously() {
+        use crate::transaction::{TxInput, TxOutput};
+
+        let base = Transaction {
+            id: String::new(),
+            sender_wallet_id: "ab".to_string(),
+            receiver_wallet_id: "c".to_string(),
+            amount: 12,
+            note: None,
+            timestamp: 3,
+            sender_public_key: String::new(),
+            signature: String::new(),
+            inputs: vec![TxInput::new("f".repeat(64), 0)],
+            outputs: vec![TxOutput::new(12, "c".to_string())],
+            lock_time: None,
+            encrypted_note: None,
+            issuance: None,
+        };
+        let id = base.calculate_hash();
+
+        // Field boundaries are length prefixed, so shifting characters or digits changes the id
+        let mut shifted = base.clone();
+        shifted.sender_wallet_id = "a".to_string();
+        shifted.receiver_wallet_id = "bc".to_string();
+        assert_ne!(shifted.calculate_hash(), id);
+        let mut shifted = base.clone();
+        shifted.amount = 1;
+        shifted.timestamp = 23;
+        assert_ne!(shifted.calculate_hash(), id);
+
+        // Inputs and outputs are covered
+        let mut changed = base.clone();
+        changed.outputs[0].amount = 11;
+        assert_ne!(changed.calculate_hash(), id);
+        let mut changed = base.clone();
+        changed.inputs[0].output_index = 1;
+        assert_ne!(changed.calculate_hash(), id);
+        let mut changed = base.clone();
+        changed.note = Some(String::new());
+        assert_ne!(changed.calculate_hash(), id);
+
+        // Signatures are not, since they sign the id
+        let mut signed = base.clone();
+        signed.signature = "00".repeat(64);
+        signed.inputs[0].signature = "11".repeat(64);
+        assert_eq!(signed.calculate_hash(), id);
+    }
+
+    #[test]
+    fn test_encoding_is_stable_across_versions() {
+        use crate::encoding::{Decode, Encode, Writer};
+        use crate::psbt::{PartiallySignedTransaction, PsbtError, PSBT_MAGIC, PSBT_VERSION};
+        use crate::transaction::{TxInput, TxOutput};
+
+        // Version 1 bytes of a fixed transaction must keep decoding to the same id
+        let tx = Transaction {
+            id: String::new(),
+            sender_wallet_id: "sender".to_string(),
+            receiver_wallet_id: "receiver".to_string(),
+            amount: 5,
+            note: Some("hi".to_string()),
+            timestamp: 1_700_000_000,
+            sender_public_key: String::new(),
+            signature: String::new(),
+            inputs: vec![TxInput::new("ab".to_string(), 1)],
+            outputs: vec![TxOutput::new(5, "receiver".to_string())],
+            lock_time: Some(9),
+            encrypted_note: None,
+            issuance: None,
+        };
+        let golden = concat!(
+            "01", "00000000", "06000000", "73656e646572", "08000000", "7265636569766572", "0500000000000000",
+            "01", "02000000", "6869", "00f1536500000000", "00000000", "00000000",
+            "01000000", "02000000", "6162", "0100000000000000", "00000000", "00", "00",
+            "01000000", "0500000000000000", "08000000", "7265636569766572", "00", "00", "00", "00", "00",
+            "01", "0900000000000000", "00", "00",
+        );
+        // Version 2 left the transaction layout alone; only the prefix moved on
+        assert_eq!(tx.encode()[0], 2);
+        assert_eq!(hex::encode(&tx.encode()[1..]), golden[2..]);
+        let decoded = Transaction::decode(&hex::decode(golden).unwrap()).unwrap();
+        assert_eq!(decoded.calculate_hash(), "97ea98efe32b2e026bc19cb18a7166cf3da5ad270de852a39cdbdf41b9b4305c");
+
+        // A version 1 PSBT, from before the canonical encoding, still decodes
+        let mut w = Writer::default();
+        w.bytes(PSBT_MAGIC);
+        w.u8(1);
+        w.str("old-id");
+        w.str("sender");
+        w.str("receiver");
+        w.u64(5);
+        w.opt_str(None);
+        w.u64(1_700_000_000);
+        w.str("");
+        w.length(0);
+        w.length(1);
+        w.u64(5);
+        w.str("receiver");
+        w.length(0);
+        w.opt_str(None);
+        w.length(0);
+        let psbt = PartiallySignedTransaction::deserialize(&w.into_bytes()).unwrap();
+        assert_eq!(psbt.unsigned_tx.outputs[0].amount, 5);
+        assert_eq!(psbt.unsigned_tx.id, psbt.unsigned_tx.calculate_hash());
+        let reencoded = PartiallySignedTransaction::deserialize(&psbt.serialize()).unwrap();
+        assert_eq!(reencoded.unsigned_tx.encode(), psbt.unsigned_tx.encode());
+        assert_eq!(Transaction::decode(&psbt.unsigned_tx.encode()).unwrap().id, psbt.unsigned_tx.id);
+
+        // Versions that were never written are refused
+        for version in [0, PSBT_VERSION + 1] {
+            let mut w = Writer::default();
+            w.bytes(PSBT_MAGIC);
+            w.u8(version);
+            assert_eq!(PartiallySignedTransaction::deserialize(&w.into_bytes()).unwrap_err(), PsbtError::UnsupportedVersion(version));
+        }
+    }
+
+    #[test]
+    fn test_deterministic_environment_reproduces_chains() {
+        use std::sync::Arc;
+
+        use crate::consensus::ProofOfWork;
+        use crate::encoding::Encode;
+        use crate::environment::{Environment, ManualClock};
+        use crate::transaction::TxOutput;
+
+        let run = |seed: u64| {
+            let clock = Arc::new(ManualClock::new(1_700_000_000));
+            let mut chain = Blockchain::with_environment(Box::new(ProofOfWork::new(2)), Environment::deterministic(clock.clone(), seed));
+            let alice = Wallet::from_private_key(&"11".repeat(32)).unwrap();
+            let bob = Wallet::from_private_key(&"22".repeat(32)).unwrap();
+            chain.mine_pending_transactions(&alice.get_wallet_id());
+            clock.advance(60);
+            chain.mine_pending_transactions(&alice.get_wallet_id());
+            clock.advance(60);
+            assert!(chain.add_transaction(chain.create_transaction(&alice, bob.get_wallet_id(), 150, Some("rent".to_string())).unwrap()));
+            chain.mine_pending_transactions(&bob.get_wallet_id());
+            clock.advance(60);
+            let payment = TxOutput::new(5, alice.get_wallet_id());
+            assert!(chain.add_transaction(chain.create_private_payment(&bob, payment, &alice.get_public_key_hex(), "thanks").unwrap()));
+            chain.mine_pending_transactions(&bob.get_wallet_id());
+
+            assert_eq!(chain.get_latest_block().timestamp, 1_700_000_180);
+            chain.chain.iter().flat_map(|block| block.encode()).collect::<Vec<u8>>()
+        };
+
+        let first = run(7);
+        assert_eq!(first, run(7));
+        assert_ne!(first, run(8));
+    }
+
+    #[test]
+    fn test_longer_side_branch_reorganizes_the_chain() {
+        use crate::chain::BlockStatus;
+        use crate::consensus::ProofOfWork;
+        use crate::block::Block;
+        use crate::environment::{Clock, Environment, ManualClock};
+        use std::sync::Arc;
+
+        let clock = Arc::new(ManualClock::new(1_700_000_000));
+        let node = |seed| Blockchain::with_environment(Box::new(ProofOfWork::new(1)), Environment::deterministic(clock.clone(), seed));
+        let (mut a, mut b) = (node(1), node(2));
+        let (alice, bob, carol) = (Wallet::new(), Wallet::new(), Wallet::new());
+        a.mine_pending_transactions(&alice.get_wallet_id());
+        assert_eq!(b.submit_block(a.chain[1].clone()), Ok(BlockStatus::Extended));
+
+        // The chains fork: a confirms a payment to bob, b mines two empty blocks
+        clock.advance(10);
+        let payment = a.create_transaction(&alice, bob.get_wallet_id(), 40, None).unwrap();
+        assert!(a.add_transaction(payment.clone()));
+        a.mine_pending_transactions(&alice.get_wallet_id());
+        b.mine_pending_transactions(&carol.get_wallet_id());
+        b.mine_pending_transactions(&carol.get_wallet_id());
+
+        // Delivered child first: it waits for its parent
+        assert_eq!(a.submit_block(b.chain[3].clone()), Ok(BlockStatus::Orphan));
+        assert_eq!(
+            a.submit_block(b.chain[2].clone()),
+            Ok(BlockStatus::Reorganized { disconnected: 1, connected: 2 })
+        );
+        assert_eq!(a.get_latest_block().hash, b.get_latest_block().hash);
+        assert_eq!(a.get_balance(&bob.get_wallet_id()), 0);
+        assert_eq!(a.get_balance(&carol.get_wallet_id()), 200);
+        assert_eq!(a.pending_transactions.iter().map(|tx| &tx.id).collect::<Vec<_>>(), vec![&payment.id]);
+        assert_eq!(a.filters.len(), a.chain.len());
+        assert_eq!(a.submit_block(b.chain[3].clone()), Ok(BlockStatus::Duplicate));
+
+        // A longer branch with an invalid block is dropped and the chain left alone
+        let mut forged = payment.clone();
+        forged.signature = a.chain[1].hash.clone();
+        let mut parent = a.chain[1].clone();
+        let mut branch = Vec::new();
+        for (index, payments) in [(2, vec![forged]), (3, Vec::new()), (4, Vec::new())] {
+            let mut transactions = vec![crate::template::coinbase(&carol.get_wallet_id(), 100, index, clock.now())];
+            transactions.extend(payments);
+            let mut block = Block::unsealed_at(index, transactions, parent.hash.clone(), clock.now());
+            a.consensus.seal(&mut block).unwrap();
+            parent = block.clone();
+            branch.push(block);
+        }
+        assert_eq!(a.submit_block(branch[0].clone()), Ok(BlockStatus::SideChain));
+        assert_eq!(a.submit_block(branch[1].clone()), Ok(BlockStatus::SideChain));
+        assert!(a.submit_block(branch[2].clone()).is_err());
+        assert_eq!(a.get_latest_block().hash, b.get_latest_block().hash);
+        assert_eq!(a.get_balance(&carol.get_wallet_id()), 200);
+        assert!(branch.iter().all(|block| a.block_by_hash(&block.hash).is_none()));
+
+        // When the invalid block comes later, the valid blocks before it are kept
+        let (forged, mut parent) = (branch[0].transactions[1].clone(), a.chain[1].clone());
+        let mut branch = Vec::new();
+        for (index, payments) in [(2, Vec::new()), (3, vec![forged]), (4, Vec::new())] {
+            let mut transactions = vec![crate::template::coinbase(&bob.get_wallet_id(), 100, index, clock.now())];
+            transactions.extend(payments);
+            let mut block = Block::unsealed_at(index, transactions, parent.hash.clone(), clock.now());
+            a.consensus.seal(&mut block).unwrap();
+            parent = block.clone();
+            branch.push(block);
+        }
+        assert_eq!(a.submit_block(branch[0].clone()), Ok(BlockStatus::SideChain));
+        assert_eq!(a.submit_block(branch[1].clone()), Ok(BlockStatus::SideChain));
+        assert!(a.submit_block(branch[2].clone()).is_err());
+        assert_eq!(a.get_latest_block().hash, b.get_latest_block().hash);
+        assert!(a.side_blocks.contains_key(&branch[0].hash));
+        assert!(branch[1..].iter().all(|block| a.block_by_hash(&block.hash).is_none()));
+    }
+
+    #[test]
+    fn test_side_blocks_are_pruned() {
+        use crate::block::Block;
+        use crate::chain::{BlockStatus, MAX_SIDE_BLOCKS, MAX_SIDE_BLOCK_DEPTH};
+
+        let mut chain = Blockchain::new();
+        let orphan = |chain: &Blockchain, index: u64, parent: String| {
+            let mut block = Block::unsealed_at(index, vec![crate::template::coinbase("miner", 0, index, 0)], parent, 0);
+            chain.consensus.seal(&mut block).unwrap();
+            block
+        };
+
+        // Orphans far above the tip are not kept
+        let far = orphan(&chain, MAX_SIDE_BLOCK_DEPTH + 1, "00".repeat(32));
+        assert_eq!(chain.submit_block(far.clone()), Ok(BlockStatus::Orphan));
+        assert!(chain.block_by_hash(&far.hash).is_none());
+
+        // Past the limit the blocks furthest from the tip go first
+        let near = orphan(&chain, 2, "11".repeat(32));
+        assert_eq!(chain.submit_block(near.clone()), Ok(BlockStatus::Orphan));
+        for i in 0..MAX_SIDE_BLOCKS {
+            let block = orphan(&chain, 3, format!("{:064x}", i));
+            assert_eq!(chain.submit_block(block), Ok(BlockStatus::Orphan));
+        }
+        assert_eq!(chain.side_blocks.len(), MAX_SIDE_BLOCKS);
+        assert!(chain.side_blocks.contains_key(&near.hash));
+    }
+
+    #[test]
+    fn test_rule_upgrade_applies_from_its_activation_height() {
+        use crate::block::Block;
+        use crate::consensus::ProofOfWork;
+        use crate::encoding::{Decode, Encode, Writer};
+        use crate::environment::{Environment, ManualClock};
+        use crate::params::{ChainParams, ParamsError, RuleSet};
+        use std::sync::Arc;
+
+        let upgrade = RuleSet { activation_height: 3, block_version: 2, mining_reward: 50, max_block_transactions: Some(2), mint_authority: None };
+        let params = ChainParams::default().with_upgrade(upgrade.clone()).unwrap();
+        let clock = Arc::new(ManualClock::new(1_700_000_000));
+        let new_chain = || Blockchain::with_params(Box::new(ProofOfWork::new(1)), Environment::deterministic(clock.clone(), 7), params.clone());
+        // All nodes start together so they share a genesis block
+        let (mut chain, mut replica, mut early) = (new_chain(), new_chain(), new_chain());
+        let miner = Wallet::new();
+        let bob = Wallet::new();
+        // Every block a minute after the last, so timestamps never depend on the wall clock
+        let mine = |chain: &mut Blockchain| {
+            chain.mine_pending_transactions(&miner.get_wallet_id());
+            clock.advance(60);
+        };
+        for _ in 0..4 {
+            mine(&mut chain);
+        }
+        assert_eq!(chain.chain.iter().map(|b| b.version).collect::<Vec<_>>(), vec![1, 1, 1, 2, 2]);
+        assert_eq!(chain.get_balance(&miner.get_wallet_id()), 100 + 100 + 50 + 50);
+
+        // At most one payment fits beside the reward after the upgrade
+        for amount in [1, 2] {
+            let tx = chain.create_transaction(&miner, bob.get_wallet_id(), amount, None).unwrap();
+            assert!(chain.add_transaction(tx));
+        }
+        mine(&mut chain);
+        assert_eq!(chain.get_latest_block().transactions.len(), 2);
+        assert_eq!(chain.pending_transactions.len(), 1);
+
+        // A second node following the same parameters accepts the whole chain
+        for block in &chain.chain[1..] {
+            replica.add_block(block.clone()).unwrap();
+        }
+        assert_eq!(replica.get_balance(&bob.get_wallet_id()), 1);
+
+        // Blocks made under the wrong rules for their height are rejected
+        let parent = early.get_latest_block().clone();
+        let mut block = Block::unsealed_versioned(2, 1, Vec::new(), parent.hash.clone(), parent.timestamp);
+        early.consensus.seal(&mut block).unwrap();
+        assert!(early.add_block(block).unwrap_err().contains("version"));
+        for _ in 0..2 {
+            mine(&mut early);
+        }
+        let mut late = chain.chain[3].clone();
+        late.previous_hash = early.get_latest_block().hash.clone();
+        late.timestamp = early.get_latest_block().timestamp;
+        late.transactions[0].outputs[0].amount = 100;
+        late.transactions[0].id = late.transactions[0].calculate_hash();
+        late.merkle_root = crate::merkle::merkle_root(&late.transactions);
+        early.consensus.seal(&mut late).unwrap();
+        assert!(early.add_block(late).unwrap_err().contains("Reward"));
+
+        // Version 2 hashes commit to the version; version 1 hashes are unchanged
+        let mut relabelled = chain.chain[4].clone();
+        relabelled.version = 3;
+        assert_ne!(relabelled.calculate_hash(), chain.chain[4].hash);
+        let v1 = chain.chain[1].clone();
+        let mut w = Writer::default();
+        w.u8(1);
+        let v2_bytes = v1.encode();
+        w.bytes(&v2_bytes[5..]);
+        let decoded = Block::decode(&w.into_bytes()).unwrap();
+        assert_eq!((decoded.version, decoded.calculate_hash()), (1, v1.hash.clone()));
+
+        assert_eq!(ChainParams::default().with_upgrade(RuleSet { activation_height: 0, ..upgrade.clone() }), Err(ParamsError::OutOfOrder(0)));
+        assert_eq!(params.with_upgrade(RuleSet { activation_height: 9, ..upgrade }), Err(ParamsError::VersionNotRaised(9)));
+    }
+
+    #[test]
+    fn test_external_miner_completes_a_block_template() {
+        use crate::consensus::ProofOfAuthority;
+        use crate::template::{BlockTemplate, MAX_TEMPLATES};
+
+        let mut chain = Blockchain::new();
+        let alice = Wallet::new();
+        let bob = Wallet::new();
+        let miner = Wallet::new();
+        let miner_id = miner.get_wallet_id();
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        let payment = chain.create_transaction(&alice, bob.get_wallet_id(), 30, None).unwrap();
+        assert!(chain.add_transaction(payment.clone()));
+
+        let template = chain.block_template(&miner_id).unwrap();
+        assert_eq!((template.height, template.coinbase_value, template.difficulty), (2, 100, 2));
+        assert_eq!(template.previous_hash, chain.get_latest_block().hash);
+        assert!(template.target.starts_with("00f") && template.target.len() == 64);
+        assert_eq!(template.transactions.iter().map(|tx| &tx.id).collect::<Vec<_>>(), vec![&payment.id]);
+
+        let solve = |template: &BlockTemplate, coinbase: &crate::transaction::Transaction| {
+            (0u64..).find(|nonce| template.meets_target(&template.block(coinbase.clone(), *nonce).hash)).unwrap()
+        };
+
+        // Other miners can neither submit the template nor crowd it out
+        for _ in 0..MAX_TEMPLATES + 1 {
+            chain.block_template("other miner").unwrap();
+        }
+        assert_eq!(chain.templates["other miner"].len(), MAX_TEMPLATES);
+        let coinbase = template.coinbase(&miner_id);
+        let nonce = solve(&template, &coinbase);
+        assert!(chain.submit_template_block("other miner", &template.template_id, coinbase, nonce).unwrap_err().contains("Unknown"));
+
+        // Work that misses the target and coinbases paying too much are refused
+        let coinbase = template.coinbase(&miner.get_wallet_id());
+        let nonce = solve(&template, &coinbase);
+        let miss = (0u64..).find(|n| !template.meets_target(&template.block(coinbase.clone(), *n).hash)).unwrap();
+        assert!(chain.submit_template_block(&miner_id, &template.template_id, coinbase.clone(), miss).is_err());
+        let mut greedy = BlockTemplate { coinbase_value: 1_000, ..template.clone() }.coinbase(&miner.get_wallet_id());
+        assert!(chain.submit_template_block(&miner_id, &template.template_id, greedy.clone(), solve(&template, &greedy)).unwrap_err().contains("Reward"));
+        greedy.id = coinbase.id.clone();
+        assert!(chain.submit_template_block(&miner_id, &template.template_id, greedy, nonce).unwrap_err().contains("hash of its contents"));
+
+        let hash = chain.submit_template_block(&miner_id, &template.template_id, coinbase.clone(), nonce).unwrap().hash.clone();
+        assert_eq!(chain.get_latest_block().hash, hash);
+        assert_eq!(chain.get_balance(&miner.get_wallet_id()), 100);
+        assert_eq!(chain.get_balance(&bob.get_wallet_id()), 30);
+        assert!(chain.pending_transactions.is_empty());
+
+        // Templates do not outlive their parent
+        let stale = chain.block_template(&miner_id).unwrap();
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        let coinbase = stale.coinbase(&miner.get_wallet_id());
+        assert!(chain.submit_template_block(&miner_id, &stale.template_id, coinbase.clone(), solve(&stale, &coinbase)).unwrap_err().contains("stale"));
+        assert!(chain.submit_template_block(&miner_id, "unknown", coinbase, 0).is_err());
+
+        let validator = Wallet::new();
+        let mut poa = Blockchain::with_consensus(Box::new(ProofOfAuthority::new(vec![validator.get_public_key_hex()], Some(validator)).unwrap()));
+        assert!(poa.block_template("miner").is_err());
+    }
+
+    #[test]
+    fn test_chain_file_export_import_and_verify() {
+        use crate::archive::{ArchiveError, ChainArchive, InvalidBlock};
+        use crate::consensus::ProofOfWork;
+        use crate::environment::Environment;
+        use crate::params::ChainParams;
+
+        let mut chain = Blockchain::new();
+        let alice = Wallet::new();
+        let bob = Wallet::new();
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        assert!(chain.add_transaction(chain.create_transaction(&alice, bob.get_wallet_id(), 25, None).unwrap()));
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        chain.mine_pending_transactions(&bob.get_wallet_id());
+
+        let bytes = ChainArchive::new(chain.chain.clone()).to_bytes();
+        let archive = ChainArchive::from_bytes(&bytes).unwrap();
+        let report = archive.verify(Box::new(ProofOfWork::new(2)), ChainParams::default()).unwrap();
+        assert_eq!((report.first, report.last, report.transactions, report.fully_validated), (0, 3, 4, true));
+
+        let imported = Blockchain::import(Box::new(ProofOfWork::new(2)), Environment::system(), ChainParams::default(), archive.blocks).unwrap();
+        assert_eq!(imported.get_latest_block().hash, chain.get_latest_block().hash);
+        assert_eq!(imported.get_balance(&bob.get_wallet_id()), 125);
+        assert_eq!(imported.filters.len(), 4);
+
+        // A range is checked header by header
+        let range = ChainArchive::new(chain.chain[2..].to_vec());
+        assert!(!range.verify(Box::new(ProofOfWork::new(2)), ChainParams::default()).unwrap().fully_validated);
+
+        // The first bad block is named, whether its contents or only its spends are wrong
+        let mut tampered = chain.chain.clone();
+        tampered[2].transactions[0].outputs[0].amount = 1;
+        let invalid = ChainArchive::new(tampered).verify(Box::new(ProofOfWork::new(2)), ChainParams::default()).unwrap_err();
+        assert_eq!(invalid.index, 2);
+        assert!(invalid.reason.contains("Merkle"), "{}", invalid.reason);
+        let mut skipped = chain.chain.clone();
+        skipped.remove(1);
+        assert_eq!(ChainArchive::new(skipped).verify(Box::new(ProofOfWork::new(2)), ChainParams::default()).unwrap_err().index, 1);
+
+        // A failed load leaves the node alone
+        let mut fresh = Blockchain::new();
+        let genesis = fresh.chain[0].hash.clone();
+        let mut broken = chain.chain.clone();
+        broken[3].previous_hash = genesis.clone();
+        assert_eq!(fresh.load(broken).unwrap_err(), InvalidBlock { index: 3, reason: "Block does not extend the current tip".to_string() });
+        assert_eq!((fresh.chain.len(), fresh.chain[0].hash.clone(), fresh.utxos.len()), (1, genesis, 0));
+
+        let mut corrupt = bytes.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] = 7;
+        assert!(matches!(ChainArchive::from_bytes(&corrupt), Err(ArchiveError::BadBlock { position: 3, .. })));
+        assert_eq!(ChainArchive::from_bytes(b"not a chain").unwrap_err(), ArchiveError::NotAnArchive);
+    }
+
+    #[test]
+    fn test_minted_coins_survive_export_and_import() {
+        use crate::archive::ChainArchive;
+        use crate::consensus::ProofOfWork;
+        use crate::environment::Environment;
+        use crate::params::{ChainParams, RuleSet};
+
+        let authority = Wallet::new();
+        let alice = Wallet::new();
+        let bob = Wallet::new();
+        let params = ChainParams::new(RuleSet { mint_authority: Some(authority.get_wallet_id()), ..RuleSet::genesis() }).unwrap();
+        let mut chain = Blockchain::with_params(Box::new(ProofOfWork::new(2)), Environment::system(), params.clone());
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+
+        let mint = chain.create_mint(&authority, bob.get_wallet_id(), 500, Some("Admin minted 500 coins".to_string())).unwrap();
+        assert!(chain.add_transaction(mint.clone()));
+        chain.mine_pending_transactions(&bob.get_wallet_id());
+        assert_eq!(chain.get_balance(&bob.get_wallet_id()), 600);
+
+        // Only the authority mints, and a mint is good for one height only
+        assert!(chain.create_mint(&alice, bob.get_wallet_id(), 500, None).is_err());
+        let mut forged = chain.create_mint(&authority, alice.get_wallet_id(), 500, None).unwrap();
+        forged.sender_wallet_id = alice.get_wallet_id();
+        forged.sender_public_key = alice.get_public_key_hex();
+        forged.id = forged.calculate_hash();
+        forged.signature = alice.sign_transaction(&forged.id);
+        assert!(!chain.add_transaction(forged));
+        assert!(!chain.add_transaction(mint));
+
+        let archive = ChainArchive::from_bytes(&ChainArchive::new(chain.chain.clone()).to_bytes()).unwrap();
+        assert!(archive.verify(Box::new(ProofOfWork::new(2)), params.clone()).unwrap().fully_validated);
+        let imported = Blockchain::import(Box::new(ProofOfWork::new(2)), Environment::system(), params, archive.blocks.clone()).unwrap();
+        assert_eq!(imported.get_latest_block().hash, chain.get_latest_block().hash);
+        assert_eq!(imported.get_balance(&bob.get_wallet_id()), 600);
+
+        // Without the authority in its rules a node refuses the minted coins
+        let invalid = archive.verify(Box::new(ProofOfWork::new(2)), ChainParams::default()).unwrap_err();
+        assert_eq!(invalid.index, 2);
+    }
+
+    #[test]
+    fn test_reindex_rebuilds_state_and_drops_stale_pending() {
+        let mut chain = Blockchain::new();
+        let alice = Wallet::new();
+        let bob = Wallet::new();
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        assert!(chain.add_transaction(chain.create_transaction(&alice, bob.get_wallet_id(), 30, None).unwrap()));
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        assert!(chain.add_transaction(chain.create_transaction(&bob, alice.get_wallet_id(), 10, None).unwrap()));
+        let (utxos, filters) = (chain.utxos.len(), chain.filters.len());
+
+        // Damaged indexes are rebuilt from the blocks; the pending spend still holds
+        chain.utxos.clear();
+        chain.filters.clear();
+        assert_eq!(chain.reindex(), 0);
+        assert_eq!(chain.utxos.len(), utxos);
+        assert_eq!(chain.filters.len(), filters);
+        assert_eq!(chain.get_balance(&bob.get_wallet_id()), 30);
+
+        // A pending transaction whose input is gone is dropped
+        chain.pending_transactions[0].inputs[0].output_index = 9;
+        assert_eq!(chain.reindex(), 1);
+        assert!(chain.pending_transactions.is_empty());
+    }
+
+    #[test]
+    fn test_keystore_round_trip_and_wrong_password() {
+        use crate::keystore::{KdfParams, Keystore, KeystoreError};
+
+        let wallet = Wallet::new();
+        let kdf = KdfParams { memory_kib: 64, iterations: 1, parallelism: 1 };
+        let keystore = Keystore::from_json(&Keystore::encrypt_with(&wallet, "correct horse", kdf).unwrap().to_json()).unwrap();
+        assert_eq!(keystore.wallet_id, wallet.get_wallet_id());
+        assert!(!keystore.to_json().contains(&hex::encode(wallet.keypair.secret.as_bytes())));
+
+        let restored = keystore.decrypt("correct horse").unwrap();
+        assert_eq!(restored.get_public_key_hex(), wallet.get_public_key_hex());
+        assert_eq!(keystore.decrypt("wrong").err(), Some(KeystoreError::Decryption));
+
+        // The wallet id is bound to the sealed key
+        let mut swapped = keystore.clone();
+        swapped.wallet_id = Wallet::new().get_wallet_id();
+        assert_eq!(swapped.decrypt("correct horse").err(), Some(KeystoreError::Decryption));
+    }
+
+    #[test]
+    fn test_payment_built_offline_is_accepted_by_the_node() {
+        use crate::spend::{build_payment, Coin};
+
+        let mut chain = Blockchain::new();
+        let alice = Wallet::new();
+        let bob = Wallet::new();
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        chain.mine_pending_transactions(&alice.get_wallet_id());
+        let coins: Vec<Coin> = chain
+            .utxos
+            .iter()
+            .filter(|(_, output)| output.receiver_wallet_id == alice.get_wallet_id())
+            .map(|((tx_id, output_index), output)| Coin { tx_id: tx_id.clone(), output_index: *output_index, amount: output.amount })
+            .collect();
+
+        let tx = build_payment(&alice, &coins, bob.get_wallet_id(), 150, Some("rent".to_string()), chain.env.now()).unwrap();
+        assert_eq!(tx.inputs.len(), 2);
+        assert!(chain.add_transaction(tx));
+        chain.mine_pending_transactions(&bob.get_wallet_id());
+        assert_eq!(chain.get_balance(&bob.get_wallet_id()), 250);
+        assert_eq!(chain.get_balance(&alice.get_wallet_id()), 50);
+
+        assert_eq!(build_payment(&alice, &coins, bob.get_wallet_id(), 500, None, 0).unwrap_err(), "Insufficient balance");
+    }
 }
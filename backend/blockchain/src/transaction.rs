@@ -1,26 +1,188 @@
 use serde::{Serialize, Deserialize};
 use sha2::{Sha256, Digest};
-use ed25519_dalek::{Verifier, Signature, PublicKey};
+use ed25519_dalek::{Signature, PublicKey};
 use hex;
+use crate::asset::Issuance;
+use crate::encoding::transaction_id_preimage;
+use crate::memo::EncryptedMemo;
+use crate::sigcheck::{SignatureCache, SignatureCheck};
+use crate::multisig::MultisigWitness;
+use crate::script::{Script, ScriptError, ScriptTemplate, LOCKTIME_THRESHOLD};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TxInput {
     pub tx_id: String,
     pub output_index: usize,
     pub signature: String,
+    /// Present when spending an output locked to a multisig address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multisig: Option<MultisigWitness>,
+    /// Unlocking data for an output carrying a `script_pubkey`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script_sig: Option<Script>,
+}
+
+impl TxInput {
+    pub fn new(tx_id: String, output_index: usize) -> Self {
+        TxInput {
+            tx_id,
+            output_index,
+            signature: String::new(),
+            multisig: None,
+            script_sig: None,
+        }
+    }
+
+    /// Message signed by the owner of the referenced output.
+    pub fn signing_message(&self) -> String {
+        format!("{}{}", self.tx_id, self.output_index)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TxOutput {
     pub amount: u64,
     pub receiver_wallet_id: String,
+    /// Spending condition. Outputs without a script are spendable by `receiver_wallet_id`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script_pubkey: Option<Script>,
+    /// Not spendable before this block height, or this unix time when at least `LOCKTIME_THRESHOLD`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock_time: Option<u64>,
+    /// Not spendable until this many blocks after the output was confirmed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relative_lock: Option<u64>,
+    /// Free text for the receiver of this output, e.g. an invoice or payroll reference.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+    /// Issued asset the amount is denominated in; the native coin when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asset: Option<String>,
+}
+
+impl TxOutput {
+    pub fn new(amount: u64, receiver_wallet_id: String) -> Self {
+        TxOutput {
+            amount,
+            receiver_wallet_id,
+            script_pubkey: None,
+            lock_time: None,
+            relative_lock: None,
+            memo: None,
+            asset: None,
+        }
+    }
+
+    /// A zero-value, unspendable output carrying up to `MAX_DATA_CARRIER_SIZE` bytes.
+    pub fn data(payload: &[u8]) -> Result<Self, ScriptError> {
+        Ok(TxOutput {
+            script_pubkey: Some(Script::null_data(payload)?),
+            ..TxOutput::new(0, DATA_RECEIVER.to_string())
+        })
+    }
+
+    /// Payload of a data-carrying output.
+    pub fn data_payload(&self) -> Option<Vec<u8>> {
+        match self.script_pubkey.as_ref()?.template()? {
+            ScriptTemplate::NullData { data } => hex::decode(data).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn is_unspendable(&self) -> bool {
+        self.script_pubkey.as_ref().is_some_and(|script| script.is_unspendable())
+    }
+
+    pub fn with_asset(mut self, asset_id: String) -> Self {
+        self.asset = Some(asset_id);
+        self
+    }
+
+    pub fn with_memo(mut self, memo: String) -> Self {
+        self.memo = Some(memo);
+        self
+    }
+
+    pub fn locked_until(mut self, lock_time: u64) -> Self {
+        self.lock_time = Some(lock_time);
+        self
+    }
+
+    pub fn locked_for(mut self, blocks: u64) -> Self {
+        self.relative_lock = Some(blocks);
+        self
+    }
+
+    /// Whether the output's lock times, including those of a timelock script, allow
+    /// spending it in a block at `height` with time `time`. `confirmed_height` is the
+    /// height of the block that created it.
+    pub fn is_unlocked(&self, height: u64, time: i64, confirmed_height: u64) -> bool {
+        let blocks_since = height.saturating_sub(confirmed_height);
+        self.lock_time.is_none_or(|lock_time| lock_time_reached(lock_time, height, time))
+            && self.relative_lock.is_none_or(|blocks| blocks_since >= blocks)
+            && self
+                .script_pubkey
+                .as_ref()
+                .and_then(|script| script.template())
+                .is_none_or(|template| script_unlocked(&template, height, time, blocks_since))
+    }
+
+    /// An output locked by `script`. It is credited to the wallet the script pays to
+    /// when there is one, and to the script hash otherwise.
+    pub fn with_script(amount: u64, script: Script) -> Self {
+        let receiver_wallet_id = script_owner(script.template().as_ref()).unwrap_or_else(|| script.script_hash());
+        TxOutput {
+            script_pubkey: Some(script),
+            ..TxOutput::new(amount, receiver_wallet_id)
+        }
+    }
+}
+
+/// Absolute lock times below `LOCKTIME_THRESHOLD` are block heights, others unix timestamps.
+pub fn lock_time_reached(lock_time: u64, height: u64, time: i64) -> bool {
+    if lock_time < LOCKTIME_THRESHOLD {
+        height >= lock_time
+    } else {
+        time >= lock_time as i64
+    }
+}
+
+fn script_unlocked(template: &ScriptTemplate, height: u64, time: i64, blocks_since: u64) -> bool {
+    match template {
+        ScriptTemplate::AbsoluteTimelock { lock_time, inner } => {
+            lock_time_reached(*lock_time, height, time) && script_unlocked(inner, height, time, blocks_since)
+        }
+        ScriptTemplate::RelativeTimelock { blocks, inner } => *blocks <= blocks_since && script_unlocked(inner, height, time, blocks_since),
+        _ => true,
+    }
+}
+
+fn script_owner(template: Option<&ScriptTemplate>) -> Option<String> {
+    match template? {
+        ScriptTemplate::PayToPubkeyHash { wallet_id } => Some(wallet_id.clone()),
+        ScriptTemplate::AbsoluteTimelock { inner, .. } | ScriptTemplate::RelativeTimelock { inner, .. } => script_owner(Some(inner)),
+        _ => None,
+    }
 }
 
+/// `receiver_wallet_id` of data-carrying outputs, which pay no one.
+pub const DATA_RECEIVER: &str = "DATA";
+
+/// `receiver_wallet_id` of a transaction paying more than one wallet. The
+/// recipients and their amounts are in the outputs.
+pub const BATCH_RECEIVER: &str = "BATCH";
+
+/// Sender ids that no key derives from. Only the node itself may use them, so a
+/// transaction arriving from outside claiming one is always rejected.
+pub const RESERVED_SENDERS: [&str; 3] = ["SYSTEM_REWARD", "ZAKAT_POOL", "SYSTEM_MINT"];
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Transaction {
     pub id: String,
     pub sender_wallet_id: String,
+    /// The wallet paid, or [`BATCH_RECEIVER`] when several wallets are paid.
     pub receiver_wallet_id: String,
+    /// Total paid to the receivers, excluding change returned to the sender.
     pub amount: u64,
     pub note: Option<String>,
     pub timestamp: i64,
@@ -28,58 +190,93 @@ pub struct Transaction {
     pub signature: String,
     pub inputs: Vec<TxInput>,
     pub outputs: Vec<TxOutput>,
+    /// The transaction cannot be included in a block before this height or time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock_time: Option<u64>,
+    /// A note only the sender and the recipient can read, kept off the public `note`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted_note: Option<EncryptedMemo>,
+    /// Set on transactions that issue a new asset or mint more of one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuance: Option<Issuance>,
 }
 
 impl Transaction {
+    /// The transaction id: a hash of the canonical encoding of everything except
+    /// the authorising signatures, witnesses and unlocking scripts.
     pub fn calculate_hash(&self) -> String {
-        let input_data = format!("{}{}{}{}{}", 
-            self.sender_wallet_id, 
-            self.receiver_wallet_id, 
-            self.amount, 
-            self.timestamp, 
-            match &self.note {
-                Some(n) => n.clone(),
-                None => String::new(),
-            }
-        );
         let mut hasher = Sha256::new();
-        hasher.update(input_data);
+        hasher.update(transaction_id_preimage(self));
         hex::encode(hasher.finalize())
     }
 
-    pub fn verify_signature(&self) -> bool {
-        if self.sender_wallet_id == "SYSTEM_REWARD" || self.sender_wallet_id == "ZAKAT_POOL" {
-            return true; 
-        }
+    pub fn has_reserved_sender(&self) -> bool {
+        RESERVED_SENDERS.contains(&self.sender_wallet_id.as_str())
+    }
 
-        let pub_key_bytes = match hex::decode(&self.sender_public_key) {
-            Ok(bytes) => bytes,
-            Err(_) => return false,
-        };
+    pub fn is_batch(&self) -> bool {
+        self.receiver_wallet_id == BATCH_RECEIVER
+    }
 
-        let signature_bytes = match hex::decode(&self.signature) {
-            Ok(bytes) => bytes,
-            Err(_) => return false,
-        };
+    /// Sum of the outputs paying `wallet_id`.
+    pub fn amount_to(&self, wallet_id: &str) -> u64 {
+        self.outputs
+            .iter()
+            .filter(|output| output.receiver_wallet_id == wallet_id)
+            .map(|output| output.amount)
+            .sum()
+    }
 
-        if pub_key_bytes.len() != 32 || signature_bytes.len() != 64 {
-            return false;
-        }
+    /// Id of the asset the transaction issues or mints.
+    pub fn issued_asset_id(&self) -> Option<String> {
+        Some(self.issuance.as_ref()?.asset_id(self.inputs.first()?))
+    }
+
+    /// Whether `wallet_id` sends the transaction or receives any of its outputs.
+    pub fn involves(&self, wallet_id: &str) -> bool {
+        self.sender_wallet_id == wallet_id
+            || self.receiver_wallet_id == wallet_id
+            || self.outputs.iter().any(|output| output.receiver_wallet_id == wallet_id)
+    }
+
+    /// Whether the transaction's own lock time allows it in a block at `height` and `time`.
+    pub fn is_final(&self, height: u64, time: i64) -> bool {
+        self.lock_time.is_none_or(|lock_time| lock_time_reached(lock_time, height, time))
+    }
+
+    /// A multisig spend has no single sender key; every input instead carries
+    /// threshold signatures over the transaction id.
+    pub fn is_multisig_spend(&self) -> bool {
+        self.sender_public_key.is_empty()
+            && !self.inputs.is_empty()
+            && self.inputs.iter().all(|input| input.multisig.is_some())
+    }
+
+    /// A spend of script-locked outputs authorised only by the inputs' unlocking scripts.
+    pub fn is_script_spend(&self) -> bool {
+        self.sender_public_key.is_empty()
+            && !self.inputs.is_empty()
+            && self.inputs.iter().all(|input| input.script_sig.is_some())
+    }
 
-        let pub_key_array: [u8; 32] = match pub_key_bytes.try_into() {
-            Ok(arr) => arr,
-            Err(_) => return false,
-        };
+    pub fn verify_signature(&self) -> bool {
+        self.verify_signature_with(&SignatureCache::default())
+    }
 
-        let public_key = match PublicKey::from_bytes(&pub_key_array) {
-            Ok(pk) => pk,
-            Err(_) => return false,
-        };
+    /// [`verify_signature`](Self::verify_signature), skipping signatures already in `signatures`.
+    pub fn verify_signature_with(&self, signatures: &SignatureCache) -> bool {
+        if self.is_multisig_spend() {
+            return self.id == self.calculate_hash()
+                && self.inputs.iter().all(|input| match &input.multisig {
+                    Some(witness) => witness.policy.is_satisfied(self.id.as_bytes(), &witness.signatures),
+                    None => false,
+                });
+        }
 
-        let signature = match Signature::try_from(&signature_bytes[..]) {
-            Ok(sig) => sig,
-            Err(_) => return false,
-        };
+        // Unlocking scripts are checked against the spent outputs by the chain
+        if self.is_script_spend() {
+            return self.id == self.calculate_hash();
+        }
 
         // Verify that the signature matches the transaction ID (which is the hash of content)
         // AND that the ID is actually the hash of the content
@@ -87,6 +284,52 @@ impl Transaction {
             return false;
         }
 
-        public_key.verify(self.id.as_bytes(), &signature).is_ok()
+        signatures.verify(&self.sender_public_key, self.id.as_bytes(), &self.signature)
     }
+
+    /// The single-key signatures that can be checked without the UTXO set: the
+    /// signature over the id and those of inputs without a witness or script.
+    pub fn signature_checks(&self) -> Vec<SignatureCheck> {
+        if self.is_multisig_spend() || self.is_script_spend() {
+            return Vec::new();
+        }
+        let mut checks = vec![SignatureCheck::new(&self.sender_public_key, self.id.as_bytes(), &self.signature)];
+        checks.extend(
+            self.inputs
+                .iter()
+                .filter(|input| input.multisig.is_none() && input.script_sig.is_none())
+                .map(|input| SignatureCheck::new(&self.sender_public_key, input.signing_message().as_bytes(), &input.signature)),
+        );
+        checks
+    }
+}
+
+/// Verifies a hex encoded ed25519 signature over `message` against a hex encoded public key.
+pub fn verify_hex_signature(public_key_hex: &str, message: &[u8], signature_hex: &str) -> bool {
+    let pub_key_bytes = match hex::decode(public_key_hex) {
+        Ok(bytes) => bytes,
+        Err(_) => return false,
+    };
+
+    let signature_bytes = match hex::decode(signature_hex) {
+        Ok(bytes) => bytes,
+        Err(_) => return false,
+    };
+
+    if pub_key_bytes.len() != 32 || signature_bytes.len() != 64 {
+        return false;
+    }
+
+    let public_key = match PublicKey::from_bytes(&pub_key_bytes) {
+        Ok(pk) => pk,
+        Err(_) => return false,
+    };
+
+    let signature = match Signature::try_from(&signature_bytes[..]) {
+        Ok(sig) => sig,
+        Err(_) => return false,
+    };
+
+    // Strict: rejects small order keys and nonces, which would let one signature pass for many messages
+    public_key.verify_strict(message, &signature).is_ok()
 }
@@ -7,6 +7,12 @@ pub struct Wallet {
     pub keypair: Keypair,
 }
 
+impl Default for Wallet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Wallet {
     pub fn new() -> Self {
         let mut csprng = OsRng{};
@@ -24,9 +30,7 @@ impl Wallet {
     }
 
     pub fn get_wallet_id(&self) -> String {
-        let mut hasher = Sha256::new();
-        hasher.update(self.keypair.public.as_bytes());
-        hex::encode(hasher.finalize())
+        wallet_id_from_public_key(self.keypair.public.as_bytes())
     }
 
     pub fn sign_transaction(&self, message: &str) -> String {
@@ -38,3 +42,19 @@ impl Wallet {
         hex::encode(self.keypair.public.as_bytes())
     }
 }
+
+/// Wallet ids are the SHA-256 of the owner's ed25519 public key.
+pub fn wallet_id_from_public_key(public_key: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(public_key);
+    hex::encode(hasher.finalize())
+}
+
+/// Same as [`wallet_id_from_public_key`] for a hex encoded key.
+pub fn wallet_id_from_public_key_hex(public_key_hex: &str) -> Result<String, String> {
+    let bytes = hex::decode(public_key_hex).map_err(|_| "Invalid hex".to_string())?;
+    if bytes.len() != 32 {
+        return Err("Invalid key length".to_string());
+    }
+    Ok(wallet_id_from_public_key(&bytes))
+}
@@ -1,41 +1,112 @@
 use actix_web::{web, HttpResponse, Responder, HttpRequest};
 use crate::db::AppState;
+use crate::logging;
 use crate::models::{User, UserRole};
+use crate::session::Caller;
+use super::auth::{deliver_otp, generate_otp, OTP_TTL};
 use mongodb::bson::doc;
 use futures::stream::TryStreamExt;
-use blockchain::{Transaction, TxOutput};
 
-// Helper to check if wallet_id belongs to an Admin
-async fn is_admin(data: &web::Data<AppState>, wallet_id: &str) -> bool {
+// Whether the authenticated caller is an admin. The role is read from the database
+// on every call, not from the session token, so a demotion takes effect at once.
+pub(crate) async fn is_admin(data: &web::Data<AppState>, caller: &Caller) -> bool {
     let collection = data.db.collection::<User>("users");
-    if let Ok(Some(user)) = collection.find_one(doc! { "wallet_id": wallet_id }, None).await {
+    if let Ok(Some(user)) = collection.find_one(doc! { "wallet_id": &caller.wallet_id }, None).await {
         return user.role == UserRole::Admin;
     }
     false
 }
 
+/// Admin actions that need a fresh OTP on top of the session.
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SensitiveAction {
+    Mint,
+    Promote,
+    ExecutePayout,
+    ImportChain,
+}
+
+impl SensitiveAction {
+    fn name(self) -> &'static str {
+        match self {
+            SensitiveAction::Mint => "mint",
+            SensitiveAction::Promote => "promote",
+            SensitiveAction::ExecutePayout => "execute_payout",
+            SensitiveAction::ImportChain => "import_chain",
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct StepUpRequest {
+    pub action: SensitiveAction,
+}
+
+// Email the admin an OTP confirming one sensitive action. It is sent back in the
+// X-Step-Up-OTP header of that action's request.
+pub async fn request_step_up(data: web::Data<AppState>, caller: Caller, req: web::Json<StepUpRequest>) -> impl Responder {
+    if !is_admin(&data, &caller).await {
+        return HttpResponse::Forbidden().json("Admin access required");
+    }
+    let otp = generate_otp();
+    let collection = data.db.collection::<User>("users");
+    let user = collection.find_one_and_update(
+        doc! { "wallet_id": &caller.wallet_id },
+        doc! { "$set": {
+            "step_up_otp": &otp,
+            "step_up_action": req.action.name(),
+            "step_up_expiry": chrono::Utc::now().timestamp() + OTP_TTL
+        } },
+        None
+    ).await;
+    match user {
+        Ok(Some(user)) => match deliver_otp(&data, &user, &otp, "ADMIN STEP-UP").await {
+            Ok(body) => HttpResponse::Ok().json(body),
+            Err(resp) => resp,
+        },
+        Ok(None) => HttpResponse::NotFound().json("User not found"),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to generate OTP"),
+    }
+}
+
+// Whether the request carries the step-up OTP issued to the caller for `action`.
+// The OTP is used up by the first attempt, right or wrong, so it cannot be guessed.
+pub(crate) async fn confirm_step_up(data: &web::Data<AppState>, caller: &Caller, action: SensitiveAction, req: &HttpRequest) -> bool {
+    let otp = match req.headers().get("X-Step-Up-OTP").and_then(|h| h.to_str().ok()) {
+        Some(otp) => otp.trim(),
+        None => return false,
+    };
+    let collection = data.db.collection::<User>("users");
+    let pending = collection.find_one_and_update(
+        doc! {
+            "wallet_id": &caller.wallet_id,
+            "step_up_action": action.name(),
+            "step_up_expiry": { "$gte": chrono::Utc::now().timestamp() }
+        },
+        doc! { "$unset": { "step_up_otp": "", "step_up_action": "", "step_up_expiry": "" } },
+        None
+    ).await;
+    let confirmed = matches!(pending, Ok(Some(user)) if user.step_up_otp.as_deref() == Some(otp));
+    if !confirmed {
+        logging::log_action(data, "StepUpFailed", &format!("{} attempted {} without a valid step-up OTP", caller.wallet_id, action.name()), "error", None, None).await;
+    }
+    confirmed
+}
+
 // Get System Stats (Admin only)
-pub async fn get_system_stats(data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
-    // Check for wallet_id in query or header
-    let wallet_id = req.headers()
-        .get("X-Wallet-Id")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("");
-
-    if !is_admin(&data, wallet_id).await {
+pub async fn get_system_stats(data: web::Data<AppState>, caller: Caller) -> impl Responder {
+    if !is_admin(&data, &caller).await {
         return HttpResponse::Forbidden().json("Admin access required");
     }
 
     let user_collection = data.db.collection::<User>("users");
+    let total_users: u64 = user_collection.count_documents(doc! {}, None).await.unwrap_or_default();
+
     let blockchain = match data.blockchain.lock() {
         Ok(b) => b,
         Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
     };
-    
-    let total_users = match user_collection.count_documents(doc! {}, None).await {
-        Ok(count) => count,
-        Err(_) => 0,
-    };
 
     let total_blocks = blockchain.chain.len();
     let total_transactions = blockchain.chain.iter().map(|b| b.transactions.len()).sum::<usize>();
@@ -48,19 +119,13 @@ pub async fn get_system_stats(data: web::Data<AppState>, req: HttpRequest) -> im
         "total_blocks": total_blocks,
         "total_transactions": total_transactions,
         "total_coins_mined": total_coins,
-        "difficulty": blockchain.difficulty
+        "consensus": blockchain.consensus.name()
     }))
 }
 
 // Get All Users (Admin only)
-pub async fn get_all_users(data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
-    // Check for wallet_id in header
-    let wallet_id = req.headers()
-        .get("X-Wallet-Id")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("");
-
-    if !is_admin(&data, wallet_id).await {
+pub async fn get_all_users(data: web::Data<AppState>, caller: Caller) -> impl Responder {
+    if !is_admin(&data, &caller).await {
         return HttpResponse::Forbidden().json("Admin access required");
     }
 
@@ -90,17 +155,14 @@ pub struct PromoteRequest {
     pub target_wallet_id: String,
 }
 
-// Promote User to Admin (Admin only)
-pub async fn promote_to_admin(data: web::Data<AppState>, req: HttpRequest, body: web::Json<PromoteRequest>) -> impl Responder {
-    // Check for wallet_id in header
-    let wallet_id = req.headers()
-        .get("X-Wallet-Id")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("");
-
-    if !is_admin(&data, wallet_id).await {
+// Promote User to Admin (Admin only, confirmed with a step-up OTP)
+pub async fn promote_to_admin(data: web::Data<AppState>, caller: Caller, req: HttpRequest, body: web::Json<PromoteRequest>) -> impl Responder {
+    if !is_admin(&data, &caller).await {
         return HttpResponse::Forbidden().json("Admin access required");
     }
+    if !confirm_step_up(&data, &caller, SensitiveAction::Promote, &req).await {
+        return HttpResponse::Forbidden().json("Step-up OTP required: request one at /admin/step-up");
+    }
 
     let collection = data.db.collection::<User>("users");
     let result = collection.update_one(
@@ -112,6 +174,7 @@ pub async fn promote_to_admin(data: web::Data<AppState>, req: HttpRequest, body:
     match result {
         Ok(update_result) => {
             if update_result.modified_count > 0 {
+                logging::log_action(&data, "AdminPromoted", &format!("{} promoted {} to Admin", caller.wallet_id, body.target_wallet_id), "success", None, None).await;
                 HttpResponse::Ok().json("User promoted to Admin")
             } else {
                 HttpResponse::NotFound().json("User not found")
@@ -128,16 +191,17 @@ pub struct MintRequest {
 }
 
 // Mint coins to a wallet (Admin only) - Creates coins from system
-pub async fn mint_coins(data: web::Data<AppState>, req: HttpRequest, body: web::Json<MintRequest>) -> impl Responder {
-    // Check for wallet_id in header
-    let wallet_id = req.headers()
-        .get("X-Wallet-Id")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("");
-
-    if !is_admin(&data, wallet_id).await {
+pub async fn mint_coins(data: web::Data<AppState>, caller: Caller, req: HttpRequest, body: web::Json<MintRequest>) -> impl Responder {
+    if !is_admin(&data, &caller).await {
         return HttpResponse::Forbidden().json("Admin access required");
     }
+    let minter = match &data.minter {
+        Some(minter) => minter.clone(),
+        None => return HttpResponse::ServiceUnavailable().json("Minting is not configured on this node (MINT_AUTHORITY, MINT_SIGNING_KEY)"),
+    };
+    if body.amount == 0 {
+        return HttpResponse::BadRequest().json("Mint amount must be positive");
+    }
 
     // Verify target wallet exists
     let collection = data.db.collection::<User>("users");
@@ -146,38 +210,37 @@ pub async fn mint_coins(data: web::Data<AppState>, req: HttpRequest, body: web::
     if let Ok(None) = target_user {
         return HttpResponse::NotFound().json("Target wallet not found");
     }
-    if let Err(_) = target_user {
+    if target_user.is_err() {
         return HttpResponse::InternalServerError().json("Database error");
     }
+    // Checked after the request itself so a mistyped wallet does not use up the OTP
+    if !confirm_step_up(&data, &caller, SensitiveAction::Mint, &req).await {
+        return HttpResponse::Forbidden().json("Step-up OTP required: request one at /admin/step-up");
+    }
 
-    let mut blockchain = match data.blockchain.lock() {
-        Ok(b) => b,
-        Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
-    };
-
-    // Create a system mint transaction
-    let tx_id = format!("mint_{}_{}", body.target_wallet_id, chrono::Utc::now().timestamp_millis());
-    let mint_tx = Transaction {
-        id: tx_id,
-        sender_wallet_id: "SYSTEM_MINT".to_string(),
-        receiver_wallet_id: body.target_wallet_id.clone(),
-        amount: body.amount,
-        note: Some(format!("Admin minted {} coins", body.amount)),
-        timestamp: chrono::Utc::now().timestamp(),
-        sender_public_key: String::new(),
-        signature: String::new(),
-        inputs: Vec::new(),
-        outputs: vec![TxOutput {
-            amount: body.amount,
-            receiver_wallet_id: body.target_wallet_id.clone(),
-        }],
+    let new_balance = {
+        let mut blockchain = match data.blockchain.lock() {
+            Ok(b) => b,
+            Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+        };
+
+        // A mint signed by the mint authority, valid only in the next block, so mine it at once
+        let mint_tx = match blockchain.create_mint(&minter, body.target_wallet_id.clone(), body.amount, Some(format!("Admin minted {} coins", body.amount))) {
+            Ok(tx) => tx,
+            Err(e) => return HttpResponse::InternalServerError().json(format!("Failed to create mint: {}", e)),
+        };
+        let mint_id = mint_tx.id.clone();
+        if !blockchain.add_transaction(mint_tx) {
+            return HttpResponse::InternalServerError().json("Mint transaction rejected");
+        }
+        if let Err(e) = blockchain.produce_block(&body.target_wallet_id) {
+            blockchain.pending_transactions.retain(|tx| tx.id != mint_id);
+            return HttpResponse::InternalServerError().json(format!("Failed to produce block: {}", e));
+        }
+
+        blockchain.get_balance(&body.target_wallet_id)
     };
-
-    // Add directly to pending and mine immediately
-    blockchain.pending_transactions.push(mint_tx);
-    blockchain.mine_pending_transactions(&body.target_wallet_id);
-
-    let new_balance = blockchain.get_balance(&body.target_wallet_id);
+    logging::log_action(&data, "AdminMint", &format!("{} minted {} coins to {}", caller.wallet_id, body.amount, body.target_wallet_id), "success", None, None).await;
 
     HttpResponse::Ok().json(serde_json::json!({
         "status": "success",
@@ -0,0 +1,129 @@
+use actix_web::{web, HttpResponse, Responder};
+use crate::logging;
+use crate::db::AppState;
+use crate::session::Caller;
+use super::multisig::load_signer;
+use blockchain::{SupplyPolicy, Transaction};
+
+#[derive(serde::Deserialize)]
+pub struct IssueAssetRequest {
+    pub issuer_wallet_id: String,
+    pub name: String,
+    #[serde(default)]
+    pub decimals: u8,
+    /// Units credited to the issuer
+    pub supply: u64,
+    /// Whether more can be minted later
+    #[serde(default)]
+    pub mintable: bool,
+    /// Wallet allowed to mint; defaults to the issuer
+    pub admin_wallet_id: Option<String>,
+    pub note: Option<String>,
+}
+
+// Issue a new asset, crediting the initial supply to the issuer
+pub async fn issue_asset(data: web::Data<AppState>, caller: Caller, req: web::Json<IssueAssetRequest>) -> impl Responder {
+    if !caller.owns(&req.issuer_wallet_id) {
+        return HttpResponse::Forbidden().json("You do not own this wallet");
+    }
+    let issuer = match load_signer(&data, &req.issuer_wallet_id).await {
+        Ok(w) => w,
+        Err(resp) => return resp,
+    };
+    let policy = if req.mintable {
+        SupplyPolicy::Mintable { admin_wallet_id: req.admin_wallet_id.clone().unwrap_or_else(|| req.issuer_wallet_id.clone()) }
+    } else {
+        SupplyPolicy::Fixed
+    };
+    let result = submit(&data, |blockchain| blockchain.create_asset(&issuer, req.name.clone(), req.decimals, policy, req.supply, req.note.clone()));
+    match result {
+        Ok(tx) => {
+            let asset_id = tx.issued_asset_id().unwrap_or_default();
+            logging::log_action(&data, "AssetIssued", &format!("Asset {} ({}) issued by {} with supply {}", req.name, asset_id, req.issuer_wallet_id, req.supply), "success", None, None).await;
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "pending",
+                "asset_id": asset_id,
+                "transaction_id": tx.id
+            }))
+        },
+        Err(e) => {
+            logging::log_action(&data, "AssetIssued", &format!("Asset {} could not be issued: {}", req.name, e), "error", None, None).await;
+            HttpResponse::BadRequest().json(e)
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct MintAssetRequest {
+    pub receiver_wallet_id: String,
+    pub amount: u64,
+    pub note: Option<String>,
+}
+
+// Mint more of a mintable asset; only its admin, signed in, may do so
+pub async fn mint_asset(data: web::Data<AppState>, path: web::Path<String>, caller: Caller, req: web::Json<MintAssetRequest>) -> impl Responder {
+    let asset_id = path.into_inner();
+    let admin_id = caller.wallet_id;
+    let mintable = match data.blockchain.lock() {
+        Ok(b) => b.assets.get(&asset_id).map(|asset| asset.is_mintable_by(&admin_id)),
+        Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    };
+    match mintable {
+        None => return HttpResponse::NotFound().json("Asset not found"),
+        Some(false) => return HttpResponse::Forbidden().json("Only the asset's admin can mint it"),
+        Some(true) => {},
+    }
+    let admin = match load_signer(&data, &admin_id).await {
+        Ok(w) => w,
+        Err(resp) => return resp,
+    };
+    let result = submit(&data, |blockchain| blockchain.mint_asset(&admin, &asset_id, req.receiver_wallet_id.clone(), req.amount, req.note.clone()));
+    match result {
+        Ok(tx) => {
+            logging::log_action(&data, "AssetMinted", &format!("{} units of {} minted to {} in tx {}", req.amount, asset_id, req.receiver_wallet_id, tx.id), "success", None, None).await;
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "pending",
+                "asset_id": asset_id,
+                "transaction_id": tx.id
+            }))
+        },
+        Err(e) => HttpResponse::BadRequest().json(e),
+    }
+}
+
+// List every issued asset
+pub async fn list_assets(data: web::Data<AppState>) -> impl Responder {
+    match data.blockchain.lock() {
+        Ok(b) => {
+            let mut assets: Vec<_> = b.assets.values().cloned().collect();
+            assets.sort_by(|a, b| a.name.cmp(&b.name));
+            HttpResponse::Ok().json(assets)
+        },
+        Err(_) => HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    }
+}
+
+pub async fn get_asset(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+    let asset_id = path.into_inner();
+    match data.blockchain.lock() {
+        Ok(b) => match b.assets.get(&asset_id) {
+            Some(asset) => HttpResponse::Ok().json(asset),
+            None => HttpResponse::NotFound().json("Asset not found"),
+        },
+        Err(_) => HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    }
+}
+
+/// Builds an issuance or mint and adds it to the pending transactions.
+fn submit<F>(data: &web::Data<AppState>, build: F) -> Result<Transaction, String>
+where
+    F: FnOnce(&blockchain::Blockchain) -> Result<Transaction, String>,
+{
+    let mut blockchain = data.blockchain.lock().map_err(|_| "Blockchain lock poisoned".to_string())?;
+    let transaction = build(&blockchain)?;
+    if blockchain.add_transaction(transaction.clone()) {
+        Ok(transaction)
+    } else {
+        Err("Transaction rejected by the chain".to_string())
+    }
+}
@@ -2,8 +2,11 @@ use actix_web::{web, HttpResponse, Responder};
 use crate::logging;
 use crate::db::AppState;
 use crate::models::User;
+use crate::session::{login_message, TokenKind, CHALLENGE_TTL};
 use mongodb::bson::doc;
 use blockchain::Wallet;
+use blockchain::transaction::verify_hex_signature;
+use blockchain::wallet::wallet_id_from_public_key_hex;
 
 pub async fn register(data: web::Data<AppState>, mut user: web::Json<User>) -> impl Responder {
     let collection = data.db.collection::<User>("users");
@@ -19,13 +22,13 @@ pub async fn register(data: web::Data<AppState>, mut user: web::Json<User>) -> i
     user.public_key = wallet.get_public_key_hex();
     // In a real app, we'd encrypt the private key with a user password here
     // For now, we'll just store the hex (INSECURE for production, but per requirements "encrypted using AES/RSA" - we'll simulate or implement basic encryption later)
-    user.encrypted_private_key = hex::encode(wallet.keypair.to_bytes()); 
+    user.encrypted_private_key = User::stored_key(&wallet);
     user.created_at = chrono::Utc::now().timestamp();
     user.role = crate::models::UserRole::User; // Explicitly set default role
 
     let email = user.email.clone();
     let wallet_id_clone = user.wallet_id.clone();
-    let private_key = hex::encode(wallet.keypair.to_bytes());
+    let private_key = User::stored_key(&wallet);
     
     match collection.insert_one(user.into_inner(), None).await {
         Ok(_) => {
@@ -46,86 +49,144 @@ pub async fn register(data: web::Data<AppState>, mut user: web::Json<User>) -> i
 
 use rand::Rng;
 
+/// Seconds an emailed OTP stays valid.
+pub(crate) const OTP_TTL: i64 = 300;
+
+/// A random 6-digit code.
+pub(crate) fn generate_otp() -> String {
+    rand::thread_rng().gen_range(100000..999999).to_string()
+}
+
+/// Emails `otp` to the user and returns the response body telling the client it was
+/// sent. The OTP is never shown anywhere else, so a failed email fails the request.
+pub(crate) async fn deliver_otp(data: &web::Data<AppState>, user: &User, otp: &str, purpose: &str) -> Result<serde_json::Value, HttpResponse> {
+    match crate::email::send_otp_email(&user.email, otp, &user.full_name).await {
+        Ok(_) => {
+            println!("OTP email sent successfully to: {}", user.email);
+            logging::log_action(data, "OTPSent", &format!("{} OTP email sent to {}", purpose, user.email), "success", None, None).await;
+            Ok(serde_json::json!({ 
+                "status": "otp_sent", 
+                "message": format!("OTP sent to {}", user.email) 
+            }))
+        },
+        Err(e) => {
+            let error_message = format!("Failed to send {} OTP email to {}: {}", purpose, user.email, e);
+            eprintln!("{}", error_message);
+            logging::log_action(data, "OTPFailed", &error_message, "error", None, None).await;
+            Err(HttpResponse::ServiceUnavailable().json("The OTP email could not be sent. Try again later."))
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ChallengeRequest {
+    pub wallet_id: String,
+}
+
+/// Answer to a challenge: the wallet's signature over [`login_message`].
 #[derive(serde::Deserialize)]
 pub struct LoginRequest {
     pub wallet_id: String,
-    pub private_key: String,
+    pub nonce: String,
+    pub signature: String,
 }
 
 #[derive(serde::Deserialize)]
 pub struct OtpRequest {
     pub wallet_id: String,
+    /// Ticket returned by a successful [`login`]
+    pub ticket: String,
     pub otp: String,
 }
 
+// Issue a one-time nonce for the wallet to sign. A new challenge replaces any
+// outstanding one.
+pub async fn challenge(data: web::Data<AppState>, req: web::Json<ChallengeRequest>) -> impl Responder {
+    let collection = data.db.collection::<User>("users");
+    let mut nonce = [0u8; 32];
+    rand::thread_rng().fill(&mut nonce);
+    let nonce = hex::encode(nonce);
+    let expires_at = chrono::Utc::now().timestamp() + CHALLENGE_TTL;
+
+    let update = collection.update_one(
+        doc! { "wallet_id": &req.wallet_id },
+        doc! { "$set": { "login_nonce": &nonce, "login_nonce_expiry": expires_at } },
+        None
+    ).await;
+    match update {
+        Ok(result) if result.matched_count == 0 => HttpResponse::BadRequest().json("User not registered. Please register first."),
+        Ok(_) => HttpResponse::Ok().json(serde_json::json!({
+            "nonce": nonce,
+            "message": login_message(&req.wallet_id, &nonce),
+            "expires_at": expires_at
+        })),
+        Err(_) => HttpResponse::InternalServerError().json("Database error"),
+    }
+}
+
 pub async fn login(data: web::Data<AppState>, req: web::Json<LoginRequest>) -> impl Responder {
     let collection = data.db.collection::<User>("users");
     
-    // Find user by wallet_id
-    let user_result = collection.find_one(doc! { "wallet_id": &req.wallet_id }, None).await;
+    // Use up the challenge whether or not the signature checks out, so each
+    // nonce gets exactly one answer
+    let user_result = collection.find_one_and_update(
+        doc! {
+            "wallet_id": &req.wallet_id,
+            "login_nonce": &req.nonce,
+            "login_nonce_expiry": { "$gte": chrono::Utc::now().timestamp() }
+        },
+        doc! { "$unset": { "login_nonce": "", "login_nonce_expiry": "" } },
+        None
+    ).await;
     
     match user_result {
         Ok(Some(user)) => {
-            // Verify private key (In production, use proper hashing/encryption)
-            // Here we assume the client sends the key, and we check if it matches the stored one (or derived one)
-            // For this project, we'll do a simple check. 
-            // NOTE: In a real app, NEVER send private key over wire like this without TLS and proper auth flow.
-            // We are simulating "Login with Wallet"
-            
-            // Generate 6-digit OTP
-            let otp: String = rand::thread_rng().gen_range(100000..999999).to_string();
-            let expiry = chrono::Utc::now().timestamp() + 300; // 5 minutes
+            let key_matches = wallet_id_from_public_key_hex(&user.public_key).is_ok_and(|id| id == user.wallet_id);
+            if !key_matches || !verify_hex_signature(&user.public_key, login_message(&user.wallet_id, &req.nonce).as_bytes(), &req.signature) {
+                logging::log_action(&data, "LoginSignature", &format!("Bad challenge signature for {}", user.email), "error", None, None).await;
+                return HttpResponse::Unauthorized().json("Invalid signature");
+            }
+
+            let otp = generate_otp();
+            let expiry = chrono::Utc::now().timestamp() + OTP_TTL;
+            // Only the holder of the ticket can try the OTP, so nobody else can use it up
+            let mut ticket = [0u8; 32];
+            rand::thread_rng().fill(&mut ticket);
+            let ticket = hex::encode(ticket);
 
             // Update user with OTP
             let update_result = collection.update_one(
                 doc! { "wallet_id": &req.wallet_id },
-                doc! { "$set": { "otp": &otp, "otp_expiry": expiry } },
+                doc! { "$set": { "otp": &otp, "otp_expiry": expiry, "otp_ticket": &ticket } },
                 None
             ).await;
 
-            if update_result.is_ok() {
-                // Send OTP via email
-                match crate::email::send_otp_email(&user.email, &otp, &user.full_name).await {
-                    Ok(_) => {
-                        println!("OTP email sent successfully to: {}", user.email);
-                        logging::log_action(&data, "OTPSent", &format!("OTP email sent to {}", user.email), "success", None, None).await;
-                        HttpResponse::Ok().json(serde_json::json!({ 
-                            "status": "otp_sent", 
-                            "message": format!("OTP sent to {}", user.email) 
-                        }))
-                    },
-                    Err(e) => {
-                        let error_message = format!("Failed to send OTP email to {}: {}", user.email, e);
-                        eprintln!("{}", error_message);
-                        // Fallback: print to console for development
-                        println!("--------------------------------------------------");
-                        println!("LOGIN OTP for {}: {}", user.email, otp);
-                        println!("--------------------------------------------------");
-                        logging::log_action(&data, "OTPFailed", &error_message, "error", None, None).await;
-                        HttpResponse::Ok().json(serde_json::json!({ 
-                            "status": "otp_sent", 
-                            "message": "OTP sent (check console - email service unavailable)",
-                            "error": error_message
-                        }))
-                    }
-                }
-            } else {
-                HttpResponse::InternalServerError().json("Failed to generate OTP")
+            if update_result.is_err() {
+                return HttpResponse::InternalServerError().json("Failed to generate OTP");
+            }
+            match deliver_otp(&data, &user, &otp, "LOGIN").await {
+                Ok(mut body) => {
+                    body["ticket"] = ticket.into();
+                    HttpResponse::Ok().json(body)
+                },
+                Err(resp) => resp,
             }
         },
-        Ok(None) => {
-            // If user not found in DB (anonymous wallet?), we might skip OTP or fail.
-            // Requirement says "Login + OTP", implying registered users.
-            HttpResponse::BadRequest().json("User not registered. Please register first.")
-        },
+        Ok(None) => HttpResponse::Unauthorized().json("Challenge expired or already used. Request a new one."),
         Err(_) => HttpResponse::InternalServerError().json("Database error"),
     }
 }
 
 pub async fn verify_otp(data: web::Data<AppState>, req: web::Json<OtpRequest>) -> impl Responder {
     let collection = data.db.collection::<User>("users");
-    
-    let user_result = collection.find_one(doc! { "wallet_id": &req.wallet_id }, None).await;
+
+    // The OTP is used up by the first attempt with the login's ticket, right or wrong,
+    // so it cannot be guessed; attempts without the ticket leave it alone
+    let user_result = collection.find_one_and_update(
+        doc! { "wallet_id": &req.wallet_id, "otp_ticket": &req.ticket },
+        doc! { "$unset": { "otp": "", "otp_expiry": "", "otp_ticket": "" } },
+        None
+    ).await;
 
     match user_result {
         Ok(Some(user)) => {
@@ -134,27 +195,48 @@ pub async fn verify_otp(data: web::Data<AppState>, req: web::Json<OtpRequest>) -
                     return HttpResponse::BadRequest().json("OTP expired");
                 }
                 if otp != req.otp {
-                    return HttpResponse::BadRequest().json("Invalid OTP");
+                    logging::log_action(&data, "UserLogin", &format!("Wrong OTP for {}; a new one must be requested", user.email), "error", None, None).await;
+                    return HttpResponse::BadRequest().json("Invalid OTP: request a new one");
                 }
 
-                // Clear OTP
-                let _ = collection.update_one(
-                    doc! { "wallet_id": &req.wallet_id },
-                    doc! { "$unset": { "otp": "", "otp_expiry": "" } },
-                    None
-                ).await;
-
+                let tokens = data.sessions.issue_pair(&user.wallet_id, user.role.clone(), chrono::Utc::now().timestamp());
                 logging::log_action(&data, "UserLogin", &format!("User {} logged in successfully", user.email), "success", None, None).await;
                 HttpResponse::Ok().json(serde_json::json!({ 
                     "status": "success", 
                     "message": "Login successful",
-                    "role": format!("{:?}", user.role)
+                    "role": format!("{:?}", user.role),
+                    "access_token": tokens.access_token,
+                    "refresh_token": tokens.refresh_token,
+                    "token_type": tokens.token_type,
+                    "expires_in": tokens.expires_in
                 }))
             } else {
                 HttpResponse::BadRequest().json("No OTP request found")
             }
         },
-        _ => HttpResponse::BadRequest().json("User not found")
+        Ok(None) => HttpResponse::BadRequest().json("Unknown or used login ticket: log in again"),
+        Err(_) => HttpResponse::InternalServerError().json("Database error"),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+// Trade a refresh token for a new token pair. The user is looked up again so a
+// deleted account or changed role takes effect at the next refresh.
+pub async fn refresh(data: web::Data<AppState>, req: web::Json<RefreshRequest>) -> impl Responder {
+    let now = chrono::Utc::now().timestamp();
+    let claims = match data.sessions.verify(&req.refresh_token, TokenKind::Refresh, now) {
+        Ok(claims) => claims,
+        Err(e) => return HttpResponse::Unauthorized().json(format!("Invalid session: {}", e)),
+    };
+    let collection = data.db.collection::<User>("users");
+    match collection.find_one(doc! { "wallet_id": &claims.sub }, None).await {
+        Ok(Some(user)) => HttpResponse::Ok().json(data.sessions.issue_pair(&user.wallet_id, user.role, now)),
+        Ok(None) => HttpResponse::Unauthorized().json("User not found"),
+        Err(_) => HttpResponse::InternalServerError().json("Database error"),
     }
 }
 
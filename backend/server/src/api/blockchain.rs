@@ -1,5 +1,10 @@
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use blockchain::{ChainArchive, Decode, Encode, Transaction};
 use crate::db::AppState;
+use crate::inspect;
+use crate::logging;
+use crate::session::Caller;
+use super::admin::{confirm_step_up, is_admin, SensitiveAction};
 
 #[derive(serde::Deserialize)]
 pub struct MineRequest {
@@ -14,11 +19,285 @@ pub async fn get_blocks(data: web::Data<AppState>) -> impl Responder {
     HttpResponse::Ok().json(&blockchain.chain)
 }
 
-pub async fn mine_block(data: web::Data<AppState>, req: web::Json<MineRequest>) -> impl Responder {
+/// Most headers served per request.
+const MAX_HEADERS: usize = 2000;
+
+#[derive(serde::Deserialize)]
+pub struct HeadersQuery {
+    #[serde(default)]
+    pub from: u64,
+    pub limit: Option<usize>,
+}
+
+// Block headers for light clients, starting at height `from`
+pub async fn get_headers(data: web::Data<AppState>, query: web::Query<HeadersQuery>) -> impl Responder {
+    let limit = query.limit.unwrap_or(MAX_HEADERS).min(MAX_HEADERS);
+    match data.blockchain.lock() {
+        Ok(b) => HttpResponse::Ok().json(serde_json::json!({
+            "height": b.chain.len() as u64 - 1,
+            "consensus": b.consensus.name(),
+            "headers": b.headers(query.from, limit)
+        })),
+        Err(_) => HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    }
+}
+
+// Compact block filters for wallet scanning, starting at height `from`
+pub async fn get_filters(data: web::Data<AppState>, query: web::Query<HeadersQuery>) -> impl Responder {
+    let limit = query.limit.unwrap_or(MAX_HEADERS).min(MAX_HEADERS);
+    match data.blockchain.lock() {
+        Ok(b) => HttpResponse::Ok().json(serde_json::json!({
+            "height": b.chain.len() as u64 - 1,
+            "filters": b.filters(query.from, limit)
+        })),
+        Err(_) => HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    }
+}
+
+// A single block by height, for scanners that matched its filter
+pub async fn get_block(data: web::Data<AppState>, path: web::Path<u64>) -> impl Responder {
+    let index = path.into_inner();
+    match data.blockchain.lock() {
+        Ok(b) => match b.chain.get(index as usize) {
+            Some(block) => HttpResponse::Ok().json(block),
+            None => HttpResponse::NotFound().json("No block at that height"),
+        },
+        Err(_) => HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    }
+}
+
+// A block in its canonical binary encoding, for nodes and tools rather than the UI
+pub async fn get_raw_block(data: web::Data<AppState>, path: web::Path<u64>) -> impl Responder {
+    let index = path.into_inner();
+    let encoded = match data.blockchain.lock() {
+        Ok(b) => b.chain.get(index as usize).map(Encode::encode),
+        Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    };
+    match encoded {
+        Some(bytes) => HttpResponse::Ok().content_type("application/octet-stream").body(bytes),
+        None => HttpResponse::NotFound().json("No block at that height"),
+    }
+}
+
+/// Largest chain file accepted for import.
+pub const MAX_CHAIN_FILE: usize = 256 * 1024 * 1024;
+
+#[derive(serde::Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub from: u64,
+    pub to: Option<u64>,
+}
+
+// Blocks `from..=to` (the whole chain by default) as a portable chain file
+pub async fn export_chain(data: web::Data<AppState>, query: web::Query<ExportQuery>) -> impl Responder {
+    let archive = match data.blockchain.lock() {
+        Ok(b) => {
+            let to = query.to.unwrap_or(u64::MAX).min(b.chain.len() as u64 - 1);
+            if query.from > to {
+                return HttpResponse::BadRequest().json("Empty block range");
+            }
+            ChainArchive::new(b.chain[query.from as usize..=to as usize].to_vec())
+        }
+        Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    };
+    HttpResponse::Ok().content_type("application/octet-stream").body(archive.to_bytes())
+}
+
+// Load a chain file into a node that has nothing but its genesis block, validating every block
+// (Admin only, confirmed with a step-up OTP)
+pub async fn import_chain(data: web::Data<AppState>, caller: Caller, req: HttpRequest, body: web::Bytes) -> impl Responder {
+    if !is_admin(&data, &caller).await {
+        return HttpResponse::Forbidden().json("Admin access required");
+    }
+    let archive = match ChainArchive::from_bytes(&body) {
+        Ok(archive) => archive,
+        Err(e) => return HttpResponse::BadRequest().json(format!("Invalid chain file: {}", e)),
+    };
+    // Checked after the file itself so a bad file or a busy node does not use up the OTP
+    match data.blockchain.lock() {
+        Ok(b) if b.chain.len() > 1 => return HttpResponse::Conflict().json("Only a fresh node can import a chain"),
+        Ok(_) => {}
+        Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    }
+    if !confirm_step_up(&data, &caller, SensitiveAction::ImportChain, &req).await {
+        return HttpResponse::Forbidden().json("Step-up OTP required: request one at /admin/step-up");
+    }
+    let result = match data.blockchain.lock() {
+        Ok(mut b) => {
+            if b.chain.len() > 1 {
+                return HttpResponse::Conflict().json("Only a fresh node can import a chain");
+            }
+            b.load(archive.blocks).map(|()| b.chain.len() as u64 - 1)
+        }
+        Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    };
+    match result {
+        Ok(height) => {
+            logging::log_action(&data, "ChainImported", &format!("{} imported a chain up to height {}", caller.wallet_id, height), "success", None, None).await;
+            HttpResponse::Ok().json(serde_json::json!({ "height": height }))
+        }
+        Err(e) => {
+            logging::log_action(&data, "ChainImported", &e.to_string(), "error", None, None).await;
+            HttpResponse::BadRequest().json(serde_json::json!({ "invalid_block": e.index, "reason": e.reason }))
+        }
+    }
+}
+
+// Submit a signed transaction in its canonical binary encoding to the mempool
+pub async fn submit_raw_transaction(data: web::Data<AppState>, body: web::Bytes) -> impl Responder {
+    let transaction = match Transaction::decode(&body) {
+        Ok(tx) => tx,
+        Err(e) => return HttpResponse::BadRequest().json(format!("Invalid transaction encoding: {}", e)),
+    };
+    // Rewards, zakat and mints are made by the node itself, never submitted
+    if transaction.has_reserved_sender() {
+        logging::log_action(&data, "RawTransactionSubmitted", &format!("Transaction {} claims reserved sender {}", transaction.id, transaction.sender_wallet_id), "error", None, None).await;
+        return HttpResponse::BadRequest().json("Transactions from reserved senders cannot be submitted");
+    }
+    let accepted = match data.blockchain.lock() {
+        Ok(mut b) => b.add_transaction(transaction.clone()),
+        Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    };
+    if !accepted {
+        logging::log_action(&data, "RawTransactionSubmitted", &format!("Transaction {} rejected", transaction.id), "error", None, None).await;
+        return HttpResponse::BadRequest().json("Transaction rejected");
+    }
+    logging::log_action(&data, "RawTransactionSubmitted", &format!("Transaction {} accepted", transaction.id), "success", None, None).await;
+    HttpResponse::Ok().json(serde_json::json!({ "tx_id": transaction.id }))
+}
+
+// Merkle proof that a confirmed transaction is in its block
+pub async fn get_transaction_proof(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+    let tx_id = path.into_inner();
+    let proof = match data.blockchain.lock() {
+        Ok(b) => b.transaction_proof(&tx_id),
+        Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    };
+    match proof {
+        Some(proof) => HttpResponse::Ok().json(proof),
+        None => HttpResponse::NotFound().json("Transaction is not confirmed"),
+    }
+}
+
+// Look a transaction up with each of its outputs
+pub async fn get_transaction(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+    let tx_id = path.into_inner();
+    let transaction = match data.blockchain.lock() {
+        Ok(b) => inspect::transaction(&b, &tx_id),
+        Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    };
+    match transaction {
+        Some(transaction) => HttpResponse::Ok().json(transaction),
+        None => HttpResponse::NotFound().json("Transaction not found"),
+    }
+}
+
+// Tip of the main chain and the size of the mempool and UTXO set
+pub async fn get_tip(data: web::Data<AppState>) -> impl Responder {
+    match data.blockchain.lock() {
+        Ok(b) => HttpResponse::Ok().json(inspect::tip(&b)),
+        Err(_) => HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    }
+}
+
+// A block by hash, on the main chain or a side branch
+pub async fn get_block_by_hash(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+    let hash = path.into_inner();
+    let block = match data.blockchain.lock() {
+        Ok(b) => b.block_by_hash(&hash).map(|block| inspect::block(&b, block)),
+        Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    };
+    match block {
+        Some(block) => HttpResponse::Ok().json(block),
+        None => HttpResponse::NotFound().json("No block with that hash"),
+    }
+}
+
+pub async fn get_mempool(data: web::Data<AppState>) -> impl Responder {
+    match data.blockchain.lock() {
+        Ok(b) => HttpResponse::Ok().json(inspect::mempool(&b)),
+        Err(_) => HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    }
+}
+
+// Unspent outputs of a wallet, oldest first
+pub async fn get_utxos(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+    let wallet_id = path.into_inner();
+    match data.blockchain.lock() {
+        Ok(b) => HttpResponse::Ok().json(inspect::utxos(&b, &wallet_id)),
+        Err(_) => HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    }
+}
+
+// Rebuild the UTXO set, asset registry and filters from the blocks (Admin only)
+pub async fn reindex(data: web::Data<AppState>, caller: Caller) -> impl Responder {
+    if !is_admin(&data, &caller).await {
+        return HttpResponse::Forbidden().json("Admin access required");
+    }
+    let summary = match data.blockchain.lock() {
+        Ok(mut b) => {
+            let dropped = b.reindex();
+            serde_json::json!({ "height": b.chain.len() as u64 - 1, "utxos": b.utxos.len(), "dropped_pending": dropped })
+        }
+        Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    };
+    logging::log_action(&data, "Reindex", &format!("{} rebuilt the chain state: {}", caller.wallet_id, summary), "success", None, None).await;
+    HttpResponse::Ok().json(summary)
+}
+
+#[derive(serde::Deserialize)]
+pub struct SubmitBlockRequest {
+    pub template_id: String,
+    pub nonce: u64,
+    pub coinbase: Transaction,
+}
+
+// Block template for an external miner, signed in: parent, transactions, coinbase value and target
+pub async fn get_block_template(data: web::Data<AppState>, caller: Caller) -> impl Responder {
+    let template = match data.blockchain.lock() {
+        Ok(mut b) => b.block_template(&caller.wallet_id),
+        Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    };
+    match template {
+        Ok(template) => HttpResponse::Ok().json(template),
+        Err(e) => HttpResponse::BadRequest().json(e),
+    }
+}
+
+// A block mined from a template the caller was handed: the miner's coinbase and the nonce that meets the target
+pub async fn submit_block(data: web::Data<AppState>, caller: Caller, req: web::Json<SubmitBlockRequest>) -> impl Responder {
+    let req = req.into_inner();
+    let miner = req.coinbase.receiver_wallet_id.clone();
+    let result = match data.blockchain.lock() {
+        Ok(mut b) => b
+            .submit_template_block(&caller.wallet_id, &req.template_id, req.coinbase, req.nonce)
+            .map(|block| (block.index, block.hash.clone())),
+        Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    };
+    match result {
+        Ok((index, hash)) => {
+            logging::log_action(&data, "BlockSubmitted", &format!("Block {} at height {} mined by {}", hash, index, miner), "success", None, None).await;
+            HttpResponse::Ok().json(serde_json::json!({ "index": index, "hash": hash, "miner_wallet_id": miner }))
+        }
+        Err(e) => {
+            logging::log_action(&data, "BlockSubmitted", &format!("Block from {} rejected: {}", miner, e), "error", None, None).await;
+            HttpResponse::BadRequest().json(format!("Block rejected: {}", e))
+        }
+    }
+}
+
+// Produce a block from the mempool on this node (Admin only)
+pub async fn mine_block(data: web::Data<AppState>, caller: Caller, req: web::Json<MineRequest>) -> impl Responder {
+    if !is_admin(&data, &caller).await {
+        return HttpResponse::Forbidden().json("Admin access required");
+    }
     let mut blockchain = match data.blockchain.lock() {
         Ok(b) => b,
         Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
     };
-    blockchain.mine_pending_transactions(&req.miner_wallet_id);
-    HttpResponse::Ok().json("Block Mined Successfully")
+    match blockchain.produce_block(&req.miner_wallet_id) {
+        Ok(()) => HttpResponse::Ok().json("Block Mined Successfully"),
+        Err(e) => HttpResponse::BadRequest().json(format!("Block not produced: {}", e)),
+    }
 }
@@ -0,0 +1,226 @@
+use actix_web::{web, HttpResponse, Responder};
+use crate::logging;
+use crate::db::AppState;
+use crate::models::Escrow;
+use crate::session::Caller;
+use super::multisig::load_signer;
+use blockchain::{Htlc, HtlcState, Transaction};
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId};
+
+#[derive(serde::Deserialize)]
+pub struct CreateEscrowRequest {
+    pub buyer_wallet_id: String,
+    pub seller_wallet_id: String,
+    pub amount: u64,
+    /// Hex SHA-256 of a secret chosen by the buyer (or the swap initiator)
+    pub hash_lock: String,
+    /// Blocks from now until the buyer can reclaim the funds
+    pub timeout_blocks: u64,
+    pub note: Option<String>,
+}
+
+// Lock the buyer's funds in an HTLC the seller redeems with the secret
+pub async fn create_escrow(data: web::Data<AppState>, caller: Caller, req: web::Json<CreateEscrowRequest>) -> impl Responder {
+    if !caller.owns(&req.buyer_wallet_id) {
+        return HttpResponse::Forbidden().json("You do not own this wallet");
+    }
+    if req.timeout_blocks == 0 {
+        return HttpResponse::BadRequest().json("Timeout must be at least one block");
+    }
+    let buyer = match load_signer(&data, &req.buyer_wallet_id).await {
+        Ok(w) => w,
+        Err(resp) => return resp,
+    };
+
+    let (htlc, transaction) = {
+        let mut blockchain = match data.blockchain.lock() {
+            Ok(b) => b,
+            Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+        };
+        let timeout = blockchain.chain.len() as u64 + req.timeout_blocks;
+        let htlc = match Htlc::new(req.hash_lock.clone(), req.seller_wallet_id.clone(), req.buyer_wallet_id.clone(), timeout) {
+            Ok(h) => h,
+            Err(e) => return HttpResponse::BadRequest().json(format!("Invalid escrow terms: {}", e)),
+        };
+        let transaction = match blockchain.create_htlc(&buyer, &htlc, req.amount, req.note.clone()) {
+            Ok(tx) => tx,
+            Err(e) => return HttpResponse::BadRequest().json(e),
+        };
+        if !blockchain.add_transaction(transaction.clone()) {
+            return HttpResponse::BadRequest().json("Failed to add transaction");
+        }
+        (htlc, transaction)
+    };
+
+    let mut escrow = Escrow {
+        id: None,
+        buyer_wallet_id: req.buyer_wallet_id.clone(),
+        seller_wallet_id: req.seller_wallet_id.clone(),
+        amount: req.amount,
+        hash_lock: htlc.hash_lock,
+        timeout: htlc.timeout,
+        funding_tx_id: transaction.id.clone(),
+        output_index: 0,
+        status: "funded".to_string(),
+        settlement_tx_id: None,
+        note: req.note.clone(),
+        created_at: chrono::Utc::now().timestamp(),
+    };
+
+    let collection = data.db.collection::<Escrow>("escrows");
+    match collection.insert_one(&escrow, None).await {
+        Ok(result) => {
+            logging::log_action(&data, "EscrowCreated", &format!("Escrow of {} from {} to {} in tx {}", req.amount, req.buyer_wallet_id, req.seller_wallet_id, transaction.id), "success", None, None).await;
+            escrow.id = result.inserted_id.as_object_id();
+            let state = current_state(&data, &escrow);
+            HttpResponse::Ok().json(escrow_json(&escrow, state))
+        },
+        Err(_) => HttpResponse::InternalServerError().json("Failed to store escrow"),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ClaimEscrowRequest {
+    /// Hex encoded secret behind the escrow's hash lock
+    pub secret: String,
+}
+
+// Seller redeems the escrow, revealing the secret on chain
+pub async fn claim_escrow(data: web::Data<AppState>, caller: Caller, path: web::Path<String>, req: web::Json<ClaimEscrowRequest>) -> impl Responder {
+    let mut escrow = match find_escrow(&data, path.into_inner()).await {
+        Ok(e) => e,
+        Err(resp) => return resp,
+    };
+    if !caller.owns(settling_party(&escrow, "claimed")) {
+        return HttpResponse::Forbidden().json("Only the seller can claim this escrow");
+    }
+    let secret = match hex::decode(&req.secret) {
+        Ok(s) => s,
+        Err(_) => return HttpResponse::BadRequest().json("Secret must be hex encoded"),
+    };
+    let seller = match load_signer(&data, &escrow.seller_wallet_id).await {
+        Ok(w) => w,
+        Err(resp) => return resp,
+    };
+    let result = submit(&data, |blockchain| blockchain.claim_htlc(&seller, &escrow.funding_tx_id, escrow.output_index, &secret));
+    settle(&data, &mut escrow, "claimed", result).await
+}
+
+// Buyer takes the funds back once the escrow has timed out
+pub async fn refund_escrow(data: web::Data<AppState>, caller: Caller, path: web::Path<String>) -> impl Responder {
+    let mut escrow = match find_escrow(&data, path.into_inner()).await {
+        Ok(e) => e,
+        Err(resp) => return resp,
+    };
+    if !caller.owns(settling_party(&escrow, "refunded")) {
+        return HttpResponse::Forbidden().json("Only the buyer can refund this escrow");
+    }
+    let buyer = match load_signer(&data, &escrow.buyer_wallet_id).await {
+        Ok(w) => w,
+        Err(resp) => return resp,
+    };
+    let result = submit(&data, |blockchain| blockchain.refund_htlc(&buyer, &escrow.funding_tx_id, escrow.output_index));
+    settle(&data, &mut escrow, "refunded", result).await
+}
+
+// Get an escrow with its state on chain
+pub async fn get_escrow(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+    match find_escrow(&data, path.into_inner()).await {
+        Ok(escrow) => {
+            let state = current_state(&data, &escrow);
+            HttpResponse::Ok().json(escrow_json(&escrow, state))
+        },
+        Err(resp) => resp,
+    }
+}
+
+// List escrows where the wallet is the buyer or the seller, newest first
+pub async fn get_wallet_escrows(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+    let wallet_id = path.into_inner();
+    let collection = data.db.collection::<Escrow>("escrows");
+    let filter = doc! { "$or": [{ "buyer_wallet_id": &wallet_id }, { "seller_wallet_id": &wallet_id }] };
+    let options = mongodb::options::FindOptions::builder().sort(doc! { "created_at": -1 }).build();
+    let mut cursor = match collection.find(filter, options).await {
+        Ok(c) => c,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+    let mut escrows = Vec::new();
+    while let Ok(Some(escrow)) = cursor.try_next().await {
+        let state = current_state(&data, &escrow);
+        escrows.push(escrow_json(&escrow, state));
+    }
+    HttpResponse::Ok().json(escrows)
+}
+
+/// The wallet that signs an escrow's settlement: the seller claims, the buyer refunds.
+pub(crate) fn settling_party<'a>(escrow: &'a Escrow, status: &str) -> &'a str {
+    if status == "claimed" { &escrow.seller_wallet_id } else { &escrow.buyer_wallet_id }
+}
+
+async fn find_escrow(data: &web::Data<AppState>, id: String) -> Result<Escrow, HttpResponse> {
+    let id = ObjectId::parse_str(id).map_err(|_| HttpResponse::BadRequest().json("Invalid escrow id"))?;
+    let collection = data.db.collection::<Escrow>("escrows");
+    match collection.find_one(doc! { "_id": id }, None).await {
+        Ok(Some(e)) => Ok(e),
+        Ok(None) => Err(HttpResponse::NotFound().json("Escrow not found")),
+        Err(_) => Err(HttpResponse::InternalServerError().json("Database error")),
+    }
+}
+
+/// Builds a spend of the escrow output and adds it to the pending transactions.
+fn submit<F>(data: &web::Data<AppState>, build: F) -> Result<Transaction, String>
+where
+    F: FnOnce(&blockchain::Blockchain) -> Result<Transaction, String>,
+{
+    let mut blockchain = data.blockchain.lock().map_err(|_| "Blockchain lock poisoned".to_string())?;
+    let transaction = build(&blockchain)?;
+    if blockchain.add_transaction(transaction.clone()) {
+        Ok(transaction)
+    } else {
+        Err("Transaction rejected by the chain".to_string())
+    }
+}
+
+async fn settle(data: &web::Data<AppState>, escrow: &mut Escrow, status: &str, result: Result<Transaction, String>) -> HttpResponse {
+    let transaction = match result {
+        Ok(tx) => tx,
+        Err(e) => {
+            logging::log_action(data, "EscrowSettled", &format!("Escrow {} could not be {}: {}", escrow.funding_tx_id, status, e), "error", None, None).await;
+            return HttpResponse::BadRequest().json(e);
+        }
+    };
+    escrow.status = status.to_string();
+    escrow.settlement_tx_id = Some(transaction.id.clone());
+
+    let collection = data.db.collection::<Escrow>("escrows");
+    if collection.replace_one(doc! { "_id": escrow.id }, &*escrow, None).await.is_err() {
+        return HttpResponse::InternalServerError().json("Failed to update escrow");
+    }
+    logging::log_action(data, "EscrowSettled", &format!("Escrow {} {} in tx {}", escrow.funding_tx_id, status, transaction.id), "success", None, None).await;
+    let state = current_state(data, escrow);
+    HttpResponse::Ok().json(escrow_json(escrow, state))
+}
+
+fn current_state(data: &web::Data<AppState>, escrow: &Escrow) -> Option<HtlcState> {
+    let blockchain = data.blockchain.lock().ok()?;
+    blockchain.htlc_state(&escrow.funding_tx_id, escrow.output_index).map(|(_, state)| state)
+}
+
+fn escrow_json(escrow: &Escrow, state: Option<HtlcState>) -> serde_json::Value {
+    serde_json::json!({
+        "id": escrow.id.map(|id| id.to_hex()),
+        "buyer_wallet_id": escrow.buyer_wallet_id,
+        "seller_wallet_id": escrow.seller_wallet_id,
+        "amount": escrow.amount,
+        "hash_lock": escrow.hash_lock,
+        "timeout": escrow.timeout,
+        "funding_tx_id": escrow.funding_tx_id,
+        "output_index": escrow.output_index,
+        "status": escrow.status,
+        "settlement_tx_id": escrow.settlement_tx_id,
+        "on_chain": state,
+        "note": escrow.note,
+        "created_at": escrow.created_at
+    })
+}
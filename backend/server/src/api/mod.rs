@@ -6,13 +6,20 @@ pub mod blockchain;
 pub mod logs;
 pub mod user;
 pub mod admin;
+pub mod multisig;
+pub mod escrow;
+pub mod payout;
+pub mod notary;
+pub mod asset;
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/auth")
             .route("/register", web::post().to(auth::register))
+            .route("/challenge", web::post().to(auth::challenge))
             .route("/login", web::post().to(auth::login))
             .route("/verify-otp", web::post().to(auth::verify_otp))
+            .route("/refresh", web::post().to(auth::refresh))
             .route("/generate", web::post().to(auth::generate_wallet))
     );
     cfg.service(
@@ -21,6 +28,11 @@ pub fn config(cfg: &mut web::ServiceConfig) {
             .route("/users", web::get().to(admin::get_all_users))
             .route("/promote", web::post().to(admin::promote_to_admin))
             .route("/mint", web::post().to(admin::mint_coins))
+            .route("/step-up", web::post().to(admin::request_step_up))
+            .route("/payouts/preview", web::post().to(payout::preview_payout))
+            .route("/payouts/{id}", web::get().to(payout::get_payout))
+            .route("/payouts/{id}/execute", web::post().to(payout::execute_payout))
+            .route("/payouts/{id}/report.csv", web::get().to(payout::get_payout_report))
     );
     cfg.service(
         web::scope("/user")
@@ -32,12 +44,64 @@ pub fn config(cfg: &mut web::ServiceConfig) {
         web::scope("/wallet")
             .route("/{id}/balance", web::get().to(wallet::get_balance))
             .route("/{id}/history", web::get().to(wallet::get_history))
+            .route("/{id}/proofs", web::get().to(wallet::get_proofs))
             .route("/send", web::post().to(wallet::send_transaction))
+            .route("/send-batch", web::post().to(wallet::send_batch_transaction))
     );
     cfg.service(
         web::scope("/blockchain")
             .route("/blocks", web::get().to(blockchain::get_blocks))
+            .route("/headers", web::get().to(blockchain::get_headers))
+            .route("/filters", web::get().to(blockchain::get_filters))
+            .route("/tip", web::get().to(blockchain::get_tip))
+            .route("/blocks/hash/{hash}", web::get().to(blockchain::get_block_by_hash))
+            .route("/blocks/{index}", web::get().to(blockchain::get_block))
+            .route("/blocks/{index}/raw", web::get().to(blockchain::get_raw_block))
+            .route("/transactions/raw", web::post().to(blockchain::submit_raw_transaction))
+            .route("/transactions/{id}", web::get().to(blockchain::get_transaction))
+            .route("/transactions/{id}/proof", web::get().to(blockchain::get_transaction_proof))
             .route("/mine", web::post().to(blockchain::mine_block))
+            .route("/template", web::get().to(blockchain::get_block_template))
+            .route("/submit", web::post().to(blockchain::submit_block))
+            .route("/mempool", web::get().to(blockchain::get_mempool))
+            .route("/utxos/{wallet_id}", web::get().to(blockchain::get_utxos))
+            .route("/reindex", web::post().to(blockchain::reindex))
+            .route("/export", web::get().to(blockchain::export_chain))
+            .service(
+                web::resource("/import")
+                    .app_data(web::PayloadConfig::new(blockchain::MAX_CHAIN_FILE))
+                    .route(web::post().to(blockchain::import_chain))
+            )
+    );
+    cfg.service(
+        web::scope("/multisig")
+            .route("/create", web::post().to(multisig::create_multisig_wallet))
+            .route("/proposals/{id}", web::get().to(multisig::get_proposal))
+            .route("/proposals/{id}/approve", web::post().to(multisig::approve_proposal))
+            .route("/{address}", web::get().to(multisig::get_multisig_wallet))
+            .route("/{address}/propose", web::post().to(multisig::propose_spend))
+            .route("/{address}/proposals", web::get().to(multisig::get_proposals))
+    );
+    cfg.service(
+        web::scope("/escrow")
+            .route("/create", web::post().to(escrow::create_escrow))
+            .route("/wallet/{wallet_id}", web::get().to(escrow::get_wallet_escrows))
+            .route("/{id}", web::get().to(escrow::get_escrow))
+            .route("/{id}/claim", web::post().to(escrow::claim_escrow))
+            .route("/{id}/refund", web::post().to(escrow::refund_escrow))
+    );
+    cfg.service(
+        web::scope("/assets")
+            .route("", web::get().to(asset::list_assets))
+            .route("/issue", web::post().to(asset::issue_asset))
+            .route("/{id}", web::get().to(asset::get_asset))
+            .route("/{id}/mint", web::post().to(asset::mint_asset))
+    );
+    cfg.service(
+        web::scope("/notary")
+            .route("/anchor", web::post().to(notary::anchor_document))
+            .route("/verify", web::post().to(notary::verify_receipt))
+            .route("/{document_hash}", web::get().to(notary::get_receipt))
     );
     cfg.service(
         web::scope("/logs")
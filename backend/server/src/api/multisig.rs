@@ -0,0 +1,371 @@
+use actix_web::{web, HttpResponse, Responder};
+use crate::logging;
+use crate::db::AppState;
+use crate::models::{MultisigProposal, MultisigWallet, User};
+use crate::session::Caller;
+use blockchain::{MultisigPolicy, PartiallySignedTransaction, Wallet};
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId};
+use std::collections::BTreeSet;
+
+#[derive(serde::Deserialize)]
+pub struct CreateMultisigRequest {
+    pub creator_wallet_id: String,
+    pub name: String,
+    pub threshold: usize,
+    pub member_wallet_ids: Vec<String>,
+}
+
+// Create an m-of-n wallet shared by registered users
+pub async fn create_multisig_wallet(data: web::Data<AppState>, caller: Caller, req: web::Json<CreateMultisigRequest>) -> impl Responder {
+    if !caller.owns(&req.creator_wallet_id) {
+        return HttpResponse::Forbidden().json("You do not own this wallet");
+    }
+    if !req.member_wallet_ids.contains(&req.creator_wallet_id) {
+        return HttpResponse::BadRequest().json("Creator must be one of the members");
+    }
+
+    let users = data.db.collection::<User>("users");
+    let mut public_keys = Vec::new();
+    for member in &req.member_wallet_ids {
+        match users.find_one(doc! { "wallet_id": member }, None).await {
+            Ok(Some(user)) => public_keys.push(user.public_key),
+            Ok(None) => return HttpResponse::BadRequest().json(format!("Member {} not found", member)),
+            Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+        }
+    }
+
+    let policy = match MultisigPolicy::new(req.threshold, public_keys) {
+        Ok(p) => p,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+    let address = policy.address();
+
+    let collection = data.db.collection::<MultisigWallet>("multisig_wallets");
+    if let Ok(Some(_)) = collection.find_one(doc! { "address": &address }, None).await {
+        return HttpResponse::BadRequest().json("Multisig wallet already exists");
+    }
+
+    let wallet = MultisigWallet {
+        id: None,
+        name: req.name.clone(),
+        address: address.clone(),
+        threshold: policy.threshold,
+        public_keys: policy.public_keys,
+        members: req.member_wallet_ids.clone(),
+        created_by: req.creator_wallet_id.clone(),
+        created_at: chrono::Utc::now().timestamp(),
+    };
+
+    match collection.insert_one(&wallet, None).await {
+        Ok(_) => {
+            logging::log_action(&data, "MultisigCreated", &format!("Multisig {} ({}-of-{}) created", address, wallet.threshold, wallet.members.len()), "success", None, None).await;
+            HttpResponse::Ok().json(serde_json::json!({
+                "address": address,
+                "threshold": wallet.threshold,
+                "members": wallet.members
+            }))
+        },
+        Err(_) => HttpResponse::InternalServerError().json("Failed to create multisig wallet"),
+    }
+}
+
+// Get a multisig wallet with its balance
+pub async fn get_multisig_wallet(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+    let address = path.into_inner();
+    let collection = data.db.collection::<MultisigWallet>("multisig_wallets");
+    let wallet = match collection.find_one(doc! { "address": &address }, None).await {
+        Ok(Some(w)) => w,
+        Ok(None) => return HttpResponse::NotFound().json("Multisig wallet not found"),
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let balance = match data.blockchain.lock() {
+        Ok(b) => b.get_balance(&address),
+        Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    };
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "name": wallet.name,
+        "address": wallet.address,
+        "threshold": wallet.threshold,
+        "members": wallet.members,
+        "balance": balance
+    }))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ProposeRequest {
+    pub proposer_wallet_id: String,
+    pub receiver_wallet_id: String,
+    pub amount: u64,
+    pub note: Option<String>,
+}
+
+// Propose a spend from a multisig wallet. The proposer's approval is added immediately.
+pub async fn propose_spend(data: web::Data<AppState>, caller: Caller, path: web::Path<String>, req: web::Json<ProposeRequest>) -> impl Responder {
+    if !caller.owns(&req.proposer_wallet_id) {
+        return HttpResponse::Forbidden().json("You do not own this wallet");
+    }
+    let address = path.into_inner();
+    let wallet = match find_wallet(&data, &address).await {
+        Ok(w) => w,
+        Err(resp) => return resp,
+    };
+    if !wallet.members.contains(&req.proposer_wallet_id) {
+        return HttpResponse::Forbidden().json("Only members can propose spends");
+    }
+    let signer = match load_signer(&data, &req.proposer_wallet_id).await {
+        Ok(s) => s,
+        Err(resp) => return resp,
+    };
+
+    let policy = MultisigPolicy { threshold: wallet.threshold, public_keys: wallet.public_keys.clone() };
+    let mut psbt = {
+        let blockchain = match data.blockchain.lock() {
+            Ok(b) => b,
+            Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+        };
+        match blockchain.create_multisig_psbt(&policy, req.receiver_wallet_id.clone(), req.amount, req.note.clone()) {
+            Ok(p) => p,
+            Err(e) => return HttpResponse::BadRequest().json(e),
+        }
+    };
+    psbt.sign(&signer);
+
+    let mut proposal = MultisigProposal {
+        id: None,
+        address: address.clone(),
+        receiver_wallet_id: req.receiver_wallet_id.clone(),
+        amount: req.amount,
+        note: req.note.clone(),
+        psbt: String::new(),
+        approvals: vec![req.proposer_wallet_id.clone()],
+        status: "pending".to_string(),
+        transaction_id: None,
+        proposed_by: req.proposer_wallet_id.clone(),
+        created_at: chrono::Utc::now().timestamp(),
+    };
+    execute_if_ready(&data, &wallet, &mut proposal, psbt);
+
+    let collection = data.db.collection::<MultisigProposal>("multisig_proposals");
+    match collection.insert_one(&proposal, None).await {
+        Ok(result) => {
+            logging::log_action(&data, "MultisigProposed", &format!("Spend of {} from {} proposed", req.amount, address), "success", None, None).await;
+            proposal.id = result.inserted_id.as_object_id();
+            HttpResponse::Ok().json(proposal_json(&proposal, wallet.threshold))
+        },
+        Err(_) => HttpResponse::InternalServerError().json("Failed to store proposal"),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ApproveRequest {
+    pub wallet_id: String,
+    /// A PSBT signed elsewhere (e.g. on an offline machine). When omitted the
+    /// member's stored key is used.
+    pub psbt: Option<String>,
+}
+
+// Add a co-signer approval. The spend is broadcast once the threshold is reached.
+pub async fn approve_proposal(data: web::Data<AppState>, caller: Caller, path: web::Path<String>, req: web::Json<ApproveRequest>) -> impl Responder {
+    if !caller.owns(&req.wallet_id) {
+        return HttpResponse::Forbidden().json("You do not own this wallet");
+    }
+    let collection = data.db.collection::<MultisigProposal>("multisig_proposals");
+    let id = match ObjectId::parse_str(path.into_inner()) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid proposal id"),
+    };
+    let mut proposal = match collection.find_one(doc! { "_id": id }, None).await {
+        Ok(Some(p)) => p,
+        Ok(None) => return HttpResponse::NotFound().json("Proposal not found"),
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+    if proposal.status != "pending" {
+        return HttpResponse::BadRequest().json(format!("Proposal is already {}", proposal.status));
+    }
+    let wallet = match find_wallet(&data, &proposal.address).await {
+        Ok(w) => w,
+        Err(resp) => return resp,
+    };
+    if !wallet.members.contains(&req.wallet_id) {
+        return HttpResponse::Forbidden().json("Only members can approve spends");
+    }
+
+    let mut psbt = match PartiallySignedTransaction::from_base64(&proposal.psbt) {
+        Ok(p) => p,
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    };
+    let before = psbt.clone();
+    match &req.psbt {
+        Some(encoded) => {
+            let signed = match PartiallySignedTransaction::from_base64(encoded) {
+                Ok(p) => p,
+                Err(e) => return HttpResponse::BadRequest().json(e.to_string()),
+            };
+            if let Err(e) = psbt.combine(&signed) {
+                return HttpResponse::BadRequest().json(e.to_string());
+            }
+        },
+        None => {
+            let signer = match load_signer(&data, &req.wallet_id).await {
+                Ok(s) => s,
+                Err(resp) => return resp,
+            };
+            psbt.sign(&signer);
+        }
+    }
+    // An approval only counts for the approving member's own key
+    let public_key = match member_public_key(&data, &req.wallet_id).await {
+        Ok(key) => key,
+        Err(resp) => return resp,
+    };
+    let added = added_signers(&before, &psbt);
+    if added.is_empty() || psbt.multisig_signature_count(0) <= before.multisig_signature_count(0) {
+        return HttpResponse::BadRequest().json("No new valid signature in approval");
+    }
+    if added.iter().any(|key| *key != public_key) {
+        return HttpResponse::Forbidden().json("An approval may only add the approving member's signature");
+    }
+    if !proposal.approvals.contains(&req.wallet_id) {
+        proposal.approvals.push(req.wallet_id.clone());
+    }
+
+    execute_if_ready(&data, &wallet, &mut proposal, psbt);
+
+    match collection.replace_one(doc! { "_id": id }, &proposal, None).await {
+        Ok(_) => {
+            logging::log_action(&data, "MultisigApproved", &format!("Proposal {} approved by {} ({})", id, req.wallet_id, proposal.status), "success", None, None).await;
+            HttpResponse::Ok().json(proposal_json(&proposal, wallet.threshold))
+        },
+        Err(_) => HttpResponse::InternalServerError().json("Failed to update proposal"),
+    }
+}
+
+// Get a single proposal
+pub async fn get_proposal(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+    let id = match ObjectId::parse_str(path.into_inner()) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid proposal id"),
+    };
+    let collection = data.db.collection::<MultisigProposal>("multisig_proposals");
+    let proposal = match collection.find_one(doc! { "_id": id }, None).await {
+        Ok(Some(p)) => p,
+        Ok(None) => return HttpResponse::NotFound().json("Proposal not found"),
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+    match find_wallet(&data, &proposal.address).await {
+        Ok(wallet) => HttpResponse::Ok().json(proposal_json(&proposal, wallet.threshold)),
+        Err(resp) => resp,
+    }
+}
+
+// List proposals for a multisig wallet, newest first
+pub async fn get_proposals(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+    let address = path.into_inner();
+    let wallet = match find_wallet(&data, &address).await {
+        Ok(w) => w,
+        Err(resp) => return resp,
+    };
+    let collection = data.db.collection::<MultisigProposal>("multisig_proposals");
+    let options = mongodb::options::FindOptions::builder().sort(doc! { "created_at": -1 }).build();
+    let mut cursor = match collection.find(doc! { "address": &address }, options).await {
+        Ok(c) => c,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+    let mut proposals = Vec::new();
+    while let Ok(Some(proposal)) = cursor.try_next().await {
+        proposals.push(proposal_json(&proposal, wallet.threshold));
+    }
+    HttpResponse::Ok().json(proposals)
+}
+
+async fn find_wallet(data: &web::Data<AppState>, address: &str) -> Result<MultisigWallet, HttpResponse> {
+    let collection = data.db.collection::<MultisigWallet>("multisig_wallets");
+    match collection.find_one(doc! { "address": address }, None).await {
+        Ok(Some(w)) => Ok(w),
+        Ok(None) => Err(HttpResponse::NotFound().json("Multisig wallet not found")),
+        Err(_) => Err(HttpResponse::InternalServerError().json("Database error")),
+    }
+}
+
+async fn member_public_key(data: &web::Data<AppState>, wallet_id: &str) -> Result<String, HttpResponse> {
+    let users = data.db.collection::<User>("users");
+    match users.find_one(doc! { "wallet_id": wallet_id }, None).await {
+        Ok(Some(u)) => Ok(u.public_key),
+        Ok(None) => Err(HttpResponse::BadRequest().json(format!("User {} not found", wallet_id))),
+        Err(_) => Err(HttpResponse::InternalServerError().json("Database error")),
+    }
+}
+
+/// Public keys with a signature in `after` on some input that `before` did not have.
+pub(crate) fn added_signers(before: &PartiallySignedTransaction, after: &PartiallySignedTransaction) -> BTreeSet<String> {
+    after
+        .inputs
+        .iter()
+        .zip(&before.inputs)
+        .flat_map(|(new, old)| new.partial_signatures.keys().filter(move |key| !old.partial_signatures.contains_key(*key)))
+        .cloned()
+        .collect()
+}
+
+/// Loads the custodial key of a registered user.
+pub(crate) async fn load_signer(data: &web::Data<AppState>, wallet_id: &str) -> Result<Wallet, HttpResponse> {
+    let users = data.db.collection::<User>("users");
+    let user = match users.find_one(doc! { "wallet_id": wallet_id }, None).await {
+        Ok(Some(u)) => u,
+        Ok(None) => return Err(HttpResponse::BadRequest().json(format!("User {} not found", wallet_id))),
+        Err(_) => return Err(HttpResponse::InternalServerError().json("Database error")),
+    };
+    user.custodial_wallet()
+        .map_err(|_| HttpResponse::InternalServerError().json("Failed to load wallet"))
+}
+
+/// Stores the PSBT on the proposal and, once every input has enough signatures,
+/// finalizes it and submits the transaction.
+fn execute_if_ready(data: &web::Data<AppState>, wallet: &MultisigWallet, proposal: &mut MultisigProposal, mut psbt: PartiallySignedTransaction) {
+    let ready = (0..psbt.inputs.len()).all(|i| psbt.multisig_signature_count(i) >= wallet.threshold);
+    if ready {
+        let result = psbt
+            .finalize()
+            .and_then(|_| psbt.extract())
+            .map_err(|e| e.to_string())
+            .and_then(|tx| {
+                let mut blockchain = data.blockchain.lock().map_err(|_| "Blockchain lock poisoned".to_string())?;
+                if blockchain.add_transaction(tx.clone()) {
+                    Ok(tx.id)
+                } else {
+                    Err("Transaction rejected by the chain".to_string())
+                }
+            });
+        match result {
+            Ok(tx_id) => {
+                proposal.status = "executed".to_string();
+                proposal.transaction_id = Some(tx_id);
+            },
+            Err(e) => {
+                log::warn!("Multisig proposal for {} failed: {}", proposal.address, e);
+                proposal.status = "failed".to_string();
+            }
+        }
+    }
+    proposal.psbt = psbt.to_base64();
+}
+
+fn proposal_json(proposal: &MultisigProposal, threshold: usize) -> serde_json::Value {
+    serde_json::json!({
+        "id": proposal.id.map(|id| id.to_hex()),
+        "address": proposal.address,
+        "receiver_wallet_id": proposal.receiver_wallet_id,
+        "amount": proposal.amount,
+        "note": proposal.note,
+        "approvals": proposal.approvals,
+        "threshold": threshold,
+        "status": proposal.status,
+        "transaction_id": proposal.transaction_id,
+        "psbt": proposal.psbt,
+        "proposed_by": proposal.proposed_by,
+        "created_at": proposal.created_at
+    })
+}
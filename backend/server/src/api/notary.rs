@@ -0,0 +1,124 @@
+use actix_web::{web, HttpResponse, Responder};
+use crate::logging;
+use crate::db::AppState;
+use crate::models::NotaryRecord;
+use crate::session::Caller;
+use super::multisig::load_signer;
+use blockchain::NotaryReceipt;
+use mongodb::bson::doc;
+use sha2::{Digest, Sha256};
+
+#[derive(serde::Deserialize)]
+pub struct AnchorQuery {
+    pub wallet_id: String,
+    /// Hex SHA-256 of the document; when missing the request body is hashed instead
+    pub sha256: Option<String>,
+    pub note: Option<String>,
+}
+
+// Anchor a document's hash in the next block. Accepts the file itself or its SHA-256.
+pub async fn anchor_document(data: web::Data<AppState>, caller: Caller, query: web::Query<AnchorQuery>, body: web::Bytes) -> impl Responder {
+    if !caller.owns(&query.wallet_id) {
+        return HttpResponse::Forbidden().json("You do not own this wallet");
+    }
+    let document_hash = match &query.sha256 {
+        Some(hash) => hash.to_lowercase(),
+        None if body.is_empty() => return HttpResponse::BadRequest().json("Upload a file or pass its sha256"),
+        None => hex::encode(Sha256::digest(&body)),
+    };
+
+    let collection = data.db.collection::<NotaryRecord>("notary_records");
+    match collection.find_one(doc! { "document_hash": &document_hash }, None).await {
+        Ok(Some(_)) => return HttpResponse::BadRequest().json("Document is already anchored"),
+        Ok(None) => {},
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    }
+
+    let wallet = match load_signer(&data, &query.wallet_id).await {
+        Ok(w) => w,
+        Err(resp) => return resp,
+    };
+    let transaction = {
+        let mut blockchain = match data.blockchain.lock() {
+            Ok(b) => b,
+            Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+        };
+        let transaction = match blockchain.create_notary_transaction(&wallet, &document_hash, query.note.clone()) {
+            Ok(tx) => tx,
+            Err(e) => return HttpResponse::BadRequest().json(e),
+        };
+        if !blockchain.add_transaction(transaction.clone()) {
+            return HttpResponse::BadRequest().json("Failed to add transaction");
+        }
+        transaction
+    };
+
+    let record = NotaryRecord {
+        id: None,
+        wallet_id: query.wallet_id.clone(),
+        document_hash: document_hash.clone(),
+        transaction_id: transaction.id.clone(),
+        note: query.note.clone(),
+        created_at: chrono::Utc::now().timestamp(),
+    };
+    match collection.insert_one(&record, None).await {
+        Ok(_) => {
+            logging::log_action(&data, "DocumentAnchored", &format!("Document {} anchored by {} in tx {}", document_hash, query.wallet_id, transaction.id), "success", None, None).await;
+            HttpResponse::Ok().json(serde_json::json!({
+                "document_hash": document_hash,
+                "transaction_id": transaction.id,
+                "status": "pending"
+            }))
+        },
+        Err(_) => HttpResponse::InternalServerError().json("Failed to store notary record"),
+    }
+}
+
+// Get the receipt for an anchored document, once its transaction is in a block
+pub async fn get_receipt(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+    let document_hash = path.into_inner().to_lowercase();
+    let collection = data.db.collection::<NotaryRecord>("notary_records");
+    let record = match collection.find_one(doc! { "document_hash": &document_hash }, None).await {
+        Ok(Some(r)) => r,
+        Ok(None) => return HttpResponse::NotFound().json("Document has not been anchored"),
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let receipt = match data.blockchain.lock() {
+        Ok(blockchain) => blockchain.notary_receipt(&record.transaction_id),
+        Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    };
+    match receipt {
+        Some(receipt) => HttpResponse::Ok().json(serde_json::json!({
+            "status": "confirmed",
+            "timestamp": receipt.block.timestamp,
+            "receipt": receipt
+        })),
+        None => HttpResponse::Ok().json(serde_json::json!({
+            "status": "pending",
+            "document_hash": record.document_hash,
+            "transaction_id": record.transaction_id
+        })),
+    }
+}
+
+// Check a receipt, and whether its block is part of this node's chain
+pub async fn verify_receipt(data: web::Data<AppState>, receipt: web::Json<NotaryReceipt>) -> impl Responder {
+    let timestamp = match blockchain::verify_receipt(&receipt) {
+        Ok(t) => t,
+        Err(e) => return HttpResponse::Ok().json(serde_json::json!({ "valid": false, "error": e })),
+    };
+    let in_chain = match data.blockchain.lock() {
+        Ok(blockchain) => blockchain
+            .chain
+            .get(receipt.block.index as usize)
+            .is_some_and(|block| block.hash == receipt.block.hash),
+        Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    };
+    HttpResponse::Ok().json(serde_json::json!({
+        "valid": true,
+        "in_chain": in_chain,
+        "timestamp": timestamp,
+        "document_hash": receipt.document_hash
+    }))
+}
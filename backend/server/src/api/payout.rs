@@ -0,0 +1,359 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use crate::logging;
+use crate::db::AppState;
+use crate::models::{Payout, PayoutRow, User};
+use crate::session::Caller;
+use super::admin::{confirm_step_up, is_admin, SensitiveAction};
+use super::multisig::load_signer;
+use blockchain::chain::{MAX_MEMO_LENGTH, MAX_TX_OUTPUTS};
+use blockchain::{Blockchain, TxOutput, Wallet};
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId};
+use std::collections::HashMap;
+
+/// Largest sheet accepted in one upload.
+const MAX_PAYOUT_ROWS: usize = 5_000;
+
+#[derive(serde::Deserialize)]
+pub struct PreviewQuery {
+    /// Organization wallet the payouts are sent from
+    pub sender_wallet_id: String,
+}
+
+// Upload a CSV of `recipient,amount,memo` rows (recipient is a wallet id or an email)
+// and get a dry-run report. Nothing is sent until the payout is executed.
+pub async fn preview_payout(data: web::Data<AppState>, caller: Caller, query: web::Query<PreviewQuery>, body: String) -> impl Responder {
+    if !is_admin(&data, &caller).await {
+        return HttpResponse::Forbidden().json("Admin access required");
+    }
+    if !may_pay_from(&caller, &query.sender_wallet_id, data.organization_wallet.as_deref()) {
+        return HttpResponse::Forbidden().json("Payouts can only be sent from your own wallet or the organization wallet");
+    }
+
+    let mut rows = match parse_rows(&body) {
+        Ok(r) => r,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+    if rows.is_empty() {
+        return HttpResponse::BadRequest().json("The CSV has no payout rows");
+    }
+    if rows.len() > MAX_PAYOUT_ROWS {
+        return HttpResponse::BadRequest().json(format!("At most {} rows can be paid at once", MAX_PAYOUT_ROWS));
+    }
+
+    let users = data.db.collection::<User>("users");
+    if !matches!(users.find_one(doc! { "wallet_id": &query.sender_wallet_id }, None).await, Ok(Some(_))) {
+        return HttpResponse::BadRequest().json("Sender wallet not found");
+    }
+    if let Err(resp) = resolve_recipients(&data, &mut rows, &query.sender_wallet_id).await {
+        return resp;
+    }
+
+    let spendable = match data.blockchain.lock() {
+        Ok(b) => b.get_balance_details(&query.sender_wallet_id).spendable,
+        Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    };
+
+    let mut payout = Payout {
+        id: None,
+        created_by: caller.wallet_id.clone(),
+        sender_wallet_id: query.sender_wallet_id.clone(),
+        total: rows.iter().filter(|r| r.status == "valid").map(|r| r.amount).sum(),
+        rows,
+        status: "previewed".to_string(),
+        transaction_ids: Vec::new(),
+        created_at: chrono::Utc::now().timestamp(),
+        executed_at: None,
+    };
+
+    let collection = data.db.collection::<Payout>("payouts");
+    match collection.insert_one(&payout, None).await {
+        Ok(result) => {
+            payout.id = result.inserted_id.as_object_id();
+            logging::log_action(&data, "PayoutPreviewed", &format!("Payout of {} from {} previewed by {} ({} rows)", payout.total, payout.sender_wallet_id, caller.wallet_id, payout.rows.len()), "success", None, None).await;
+            HttpResponse::Ok().json(payout_json(&payout, Some(spendable)))
+        },
+        Err(_) => HttpResponse::InternalServerError().json("Failed to store payout"),
+    }
+}
+
+// Pay every valid row of a previewed payout, packing as many rows per transaction as allowed
+pub async fn execute_payout(data: web::Data<AppState>, caller: Caller, req: HttpRequest, path: web::Path<String>) -> impl Responder {
+    if !is_admin(&data, &caller).await {
+        return HttpResponse::Forbidden().json("Admin access required");
+    }
+
+    let mut payout = match find_payout(&data, path.into_inner()).await {
+        Ok(p) => p,
+        Err(resp) => return resp,
+    };
+    if payout.status != "previewed" {
+        return HttpResponse::BadRequest().json(format!("Payout is already {}", payout.status));
+    }
+    // Checked against whoever executes it, not whoever previewed it
+    if !may_pay_from(&caller, &payout.sender_wallet_id, data.organization_wallet.as_deref()) {
+        return HttpResponse::Forbidden().json("Payouts can only be sent from your own wallet or the organization wallet");
+    }
+    if !confirm_step_up(&data, &caller, SensitiveAction::ExecutePayout, &req).await {
+        return HttpResponse::Forbidden().json("Step-up OTP required: request one at /admin/step-up");
+    }
+    let sender = match load_signer(&data, &payout.sender_wallet_id).await {
+        Ok(w) => w,
+        Err(resp) => return resp,
+    };
+
+    {
+        let mut blockchain = match data.blockchain.lock() {
+            Ok(b) => b,
+            Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+        };
+        if blockchain.get_balance_details(&payout.sender_wallet_id).spendable < payout.total {
+            return HttpResponse::BadRequest().json("Insufficient balance for this payout");
+        }
+
+        // One output per transaction is kept for change
+        let valid: Vec<usize> = (0..payout.rows.len()).filter(|&i| payout.rows[i].status == "valid").collect();
+        let chunks: Vec<&[usize]> = valid.chunks(MAX_TX_OUTPUTS - 1).collect();
+        let batches = chunks
+            .iter()
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .map(|&i| {
+                        let row = &payout.rows[i];
+                        let output = TxOutput::new(row.amount, row.wallet_id.clone().unwrap_or_default());
+                        match &row.memo {
+                            Some(memo) => output.with_memo(memo.clone()),
+                            None => output,
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let note = format!("Bulk payout {}", payout.id.map(|id| id.to_hex()).unwrap_or_default());
+        let results = pay_in_batches(&mut blockchain, &sender, batches, &note);
+
+        for (chunk, result) in chunks.into_iter().zip(results) {
+            for &i in chunk {
+                let row = &mut payout.rows[i];
+                match &result {
+                    Ok(tx_id) => {
+                        row.status = "paid".to_string();
+                        row.transaction_id = Some(tx_id.clone());
+                    },
+                    Err(e) => {
+                        row.status = "failed".to_string();
+                        row.error = Some(e.clone());
+                    }
+                }
+            }
+            if let Ok(tx_id) = result {
+                payout.transaction_ids.push(tx_id);
+            }
+        }
+    }
+
+    payout.status = "executed".to_string();
+    payout.executed_at = Some(chrono::Utc::now().timestamp());
+    let collection = data.db.collection::<Payout>("payouts");
+    if collection.replace_one(doc! { "_id": payout.id }, &payout, None).await.is_err() {
+        return HttpResponse::InternalServerError().json("Failed to update payout");
+    }
+
+    let paid = payout.rows.iter().filter(|r| r.status == "paid").count();
+    let status = if paid == payout.rows.iter().filter(|r| r.status != "invalid").count() { "success" } else { "error" };
+    logging::log_action(&data, "PayoutExecuted", &format!("Payout from {} paid {} rows in {} transactions", payout.sender_wallet_id, paid, payout.transaction_ids.len()), status, None, None).await;
+    HttpResponse::Ok().json(payout_json(&payout, None))
+}
+
+// Get a payout with its per-row results
+pub async fn get_payout(data: web::Data<AppState>, caller: Caller, path: web::Path<String>) -> impl Responder {
+    if !is_admin(&data, &caller).await {
+        return HttpResponse::Forbidden().json("Admin access required");
+    }
+
+    match find_payout(&data, path.into_inner()).await {
+        Ok(payout) => HttpResponse::Ok().json(payout_json(&payout, None)),
+        Err(resp) => resp,
+    }
+}
+
+// Download the per-row report as CSV
+pub async fn get_payout_report(data: web::Data<AppState>, caller: Caller, path: web::Path<String>) -> impl Responder {
+    if !is_admin(&data, &caller).await {
+        return HttpResponse::Forbidden().json("Admin access required");
+    }
+
+    let payout = match find_payout(&data, path.into_inner()).await {
+        Ok(p) => p,
+        Err(resp) => return resp,
+    };
+    let report = match write_report(&payout.rows) {
+        Ok(r) => r,
+        Err(e) => return HttpResponse::InternalServerError().json(e),
+    };
+    let filename = format!("payout-{}.csv", payout.id.map(|id| id.to_hex()).unwrap_or_default());
+    HttpResponse::Ok()
+        .content_type("text/csv")
+        .insert_header(("Content-Disposition", format!("attachment; filename=\"{}\"", filename)))
+        .body(report)
+}
+
+/// Whether `caller` may send a payout from `sender_wallet_id`: their own wallet, or
+/// the organization wallet when one is configured.
+pub(crate) fn may_pay_from(caller: &Caller, sender_wallet_id: &str, organization_wallet: Option<&str>) -> bool {
+    caller.owns(sender_wallet_id) || organization_wallet == Some(sender_wallet_id)
+}
+
+/// Sends each batch of payments in a transaction of its own. The mempool does not
+/// chain unconfirmed spends, so the batches are funded up front from disjoint
+/// confirmed coins; a batch left without coins fails and the rest are still sent.
+pub(crate) fn pay_in_batches(blockchain: &mut Blockchain, sender: &Wallet, batches: Vec<Vec<TxOutput>>, note: &str) -> Vec<Result<String, String>> {
+    blockchain
+        .create_batch_transactions(sender, batches, Some(note.to_string()))
+        .into_iter()
+        .map(|result| {
+            let tx = result?;
+            if blockchain.add_transaction(tx.clone()) {
+                Ok(tx.id)
+            } else {
+                Err("Transaction rejected by the chain".to_string())
+            }
+        })
+        .collect()
+}
+
+async fn find_payout(data: &web::Data<AppState>, id: String) -> Result<Payout, HttpResponse> {
+    let id = ObjectId::parse_str(id).map_err(|_| HttpResponse::BadRequest().json("Invalid payout id"))?;
+    let collection = data.db.collection::<Payout>("payouts");
+    match collection.find_one(doc! { "_id": id }, None).await {
+        Ok(Some(p)) => Ok(p),
+        Ok(None) => Err(HttpResponse::NotFound().json("Payout not found")),
+        Err(_) => Err(HttpResponse::InternalServerError().json("Database error")),
+    }
+}
+
+/// Reads `recipient,amount,memo` rows. A first line naming the columns is skipped.
+/// Malformed rows are kept and marked invalid so they show up in the report.
+fn parse_rows(csv_text: &str) -> Result<Vec<PayoutRow>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(csv_text.as_bytes());
+
+    let mut rows = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        let record = record.map_err(|e| format!("Could not read the CSV: {}", e))?;
+        if record.iter().all(|field| field.is_empty()) {
+            continue;
+        }
+        let amount = record.get(1).unwrap_or("");
+        if i == 0 && amount.eq_ignore_ascii_case("amount") {
+            continue;
+        }
+
+        let mut row = PayoutRow {
+            line: record.position().map(|p| p.line() as usize).unwrap_or(i + 1),
+            recipient: record.get(0).unwrap_or("").to_string(),
+            wallet_id: None,
+            amount: 0,
+            memo: record.get(2).filter(|memo| !memo.is_empty()).map(str::to_string),
+            status: "valid".to_string(),
+            error: None,
+            transaction_id: None,
+        };
+        match amount.parse::<u64>() {
+            Ok(value) if value > 0 => row.amount = value,
+            _ => reject(&mut row, format!("Invalid amount '{}'", amount)),
+        }
+        if record.len() > 3 {
+            reject(&mut row, "Expected recipient,amount,memo".to_string());
+        }
+        if row.memo.as_ref().is_some_and(|memo| memo.len() > MAX_MEMO_LENGTH) {
+            reject(&mut row, format!("Memo is longer than {} bytes", MAX_MEMO_LENGTH));
+        }
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// Maps each row's wallet id or email to a registered user's wallet.
+async fn resolve_recipients(data: &web::Data<AppState>, rows: &mut [PayoutRow], sender_wallet_id: &str) -> Result<(), HttpResponse> {
+    let (emails, wallet_ids): (Vec<String>, Vec<String>) = rows
+        .iter()
+        .map(|row| row.recipient.to_lowercase())
+        .partition(|recipient| recipient.contains('@'));
+
+    let users = data.db.collection::<User>("users");
+    let filter = doc! { "$or": [{ "wallet_id": { "$in": &wallet_ids } }, { "email": { "$in": &emails } }] };
+    let mut cursor = users
+        .find(filter, None)
+        .await
+        .map_err(|_| HttpResponse::InternalServerError().json("Database error"))?;
+    let mut known = HashMap::new();
+    while let Ok(Some(user)) = cursor.try_next().await {
+        known.insert(user.email.to_lowercase(), user.wallet_id.clone());
+        known.insert(user.wallet_id.clone(), user.wallet_id);
+    }
+
+    for row in rows.iter_mut() {
+        match known.get(&row.recipient.to_lowercase()) {
+            Some(wallet_id) if wallet_id == sender_wallet_id => reject(row, "Recipient is the sending wallet".to_string()),
+            Some(wallet_id) => row.wallet_id = Some(wallet_id.clone()),
+            None => reject(row, "Recipient is not a registered user".to_string()),
+        }
+    }
+    Ok(())
+}
+
+fn reject(row: &mut PayoutRow, error: String) {
+    row.status = "invalid".to_string();
+    if row.error.is_none() {
+        row.error = Some(error);
+    }
+}
+
+fn write_report(rows: &[PayoutRow]) -> Result<Vec<u8>, String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer
+        .write_record(["line", "recipient", "wallet_id", "amount", "memo", "status", "transaction_id", "error"])
+        .map_err(|e| e.to_string())?;
+    for row in rows {
+        writer
+            .write_record([
+                row.line.to_string(),
+                row.recipient.clone(),
+                row.wallet_id.clone().unwrap_or_default(),
+                row.amount.to_string(),
+                row.memo.clone().unwrap_or_default(),
+                row.status.clone(),
+                row.transaction_id.clone().unwrap_or_default(),
+                row.error.clone().unwrap_or_default(),
+            ])
+            .map_err(|e| e.to_string())?;
+    }
+    writer.into_inner().map_err(|e| e.to_string())
+}
+
+/// `spendable` is included for previews so the sheet can be checked against the sender's funds.
+fn payout_json(payout: &Payout, spendable: Option<u64>) -> serde_json::Value {
+    let count = |status: &str| payout.rows.iter().filter(|r| r.status == status).count();
+    serde_json::json!({
+        "id": payout.id.map(|id| id.to_hex()),
+        "sender_wallet_id": payout.sender_wallet_id,
+        "status": payout.status,
+        "total": payout.total,
+        "spendable_balance": spendable,
+        "sufficient_balance": spendable.map(|s| s >= payout.total),
+        "valid_rows": count("valid"),
+        "invalid_rows": count("invalid"),
+        "paid_rows": count("paid"),
+        "failed_rows": count("failed"),
+        "transaction_ids": payout.transaction_ids,
+        "rows": payout.rows,
+        "created_by": payout.created_by,
+        "created_at": payout.created_at,
+        "executed_at": payout.executed_at
+    })
+}
@@ -1,15 +1,19 @@
 use actix_web::{web, HttpResponse, Responder};
 use crate::db::AppState;
-use crate::models::User;
+use crate::models::{User, UserProfile};
+use crate::session::Caller;
 use mongodb::bson::doc;
 
 // Get User Profile
-pub async fn get_profile(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+pub async fn get_profile(data: web::Data<AppState>, path: web::Path<String>, caller: Caller) -> impl Responder {
     let wallet_id = path.into_inner();
+    if !caller.owns(&wallet_id) {
+        return HttpResponse::Forbidden().json("You do not own this wallet");
+    }
     let collection = data.db.collection::<User>("users");
     
     match collection.find_one(doc! { "wallet_id": &wallet_id }, None).await {
-        Ok(Some(user)) => HttpResponse::Ok().json(user),
+        Ok(Some(user)) => HttpResponse::Ok().json(UserProfile::from(user)),
         Ok(None) => HttpResponse::NotFound().json("User not found"),
         Err(_) => HttpResponse::InternalServerError().json("Database error"),
     }
@@ -22,7 +26,10 @@ pub struct AddBeneficiaryRequest {
 }
 
 // Add Beneficiary
-pub async fn add_beneficiary(data: web::Data<AppState>, req: web::Json<AddBeneficiaryRequest>) -> impl Responder {
+pub async fn add_beneficiary(data: web::Data<AppState>, req: web::Json<AddBeneficiaryRequest>, caller: Caller) -> impl Responder {
+    if !caller.owns(&req.wallet_id) {
+        return HttpResponse::Forbidden().json("You do not own this wallet");
+    }
     let collection = data.db.collection::<User>("users");
 
     // Check if beneficiary wallet exists
@@ -49,8 +56,11 @@ pub async fn add_beneficiary(data: web::Data<AppState>, req: web::Json<AddBenefi
 }
 
 // Get Beneficiaries (Simple list for now)
-pub async fn get_beneficiaries(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+pub async fn get_beneficiaries(data: web::Data<AppState>, path: web::Path<String>, caller: Caller) -> impl Responder {
     let wallet_id = path.into_inner();
+    if !caller.owns(&wallet_id) {
+        return HttpResponse::Forbidden().json("You do not own this wallet");
+    }
     let collection = data.db.collection::<User>("users");
     
     match collection.find_one(doc! { "wallet_id": &wallet_id }, None).await {
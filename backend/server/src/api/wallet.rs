@@ -2,22 +2,45 @@ use actix_web::{web, HttpResponse, Responder};
 use crate::logging;
 use crate::db::AppState;
 use crate::models::User;
-use blockchain::{Transaction, TxInput, TxOutput};
+use crate::session::Caller;
+use super::multisig::load_signer;
 use mongodb::bson::doc;
 
-pub async fn get_balance(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+pub async fn get_balance(data: web::Data<AppState>, path: web::Path<String>, caller: Caller) -> impl Responder {
     let wallet_id = path.into_inner();
-    let blockchain = match data.blockchain.lock() {
-        Ok(b) => b,
+    if !caller.owns(&wallet_id) {
+        return HttpResponse::Forbidden().json("You do not own this wallet");
+    }
+    let (balance, assets) = match data.blockchain.lock() {
+        Ok(b) => {
+            let assets: Vec<serde_json::Value> = b
+                .get_asset_balances(&wallet_id)
+                .into_iter()
+                .map(|(asset_id, balance)| {
+                    let definition = b.assets.get(&asset_id);
+                    serde_json::json!({
+                        "asset_id": asset_id,
+                        "name": definition.map(|a| a.name.clone()),
+                        "decimals": definition.map(|a| a.decimals),
+                        "balance": balance.total(),
+                        "spendable": balance.spendable,
+                        "locked": balance.locked
+                    })
+                })
+                .collect();
+            (b.get_balance_details(&wallet_id), assets)
+        },
         Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
     };
-    let balance = blockchain.get_balance(&wallet_id);
-    
+
     // Log balance query
     logging::log_action(&data, "GetBalance", &format!("Wallet {} balance queried", wallet_id), "success", None, None).await;
     HttpResponse::Ok().json(serde_json::json!({
         "wallet_id": wallet_id,
-        "balance": balance
+        "balance": balance.total(),
+        "spendable": balance.spendable,
+        "locked": balance.locked,
+        "assets": assets
     }))
 }
 
@@ -27,15 +50,22 @@ pub struct SendRequest {
     pub receiver_wallet_id: String,
     pub amount: u64,
     pub note: Option<String>,
+    /// Block height (or unix time) before which the receiver cannot spend the funds.
+    pub lock_time: Option<u64>,
+    /// Number of blocks after confirmation before the receiver can spend the funds.
+    pub relative_lock: Option<u64>,
+    /// Encrypt the note so only the sender and the receiver can read it.
+    #[serde(default)]
+    pub encrypt_note: bool,
+    /// Issued asset to send instead of the native coin.
+    pub asset_id: Option<String>,
 }
 
-pub async fn send_transaction(data: web::Data<AppState>, req: web::Json<SendRequest>) -> impl Responder {
-    let mut blockchain = match data.blockchain.lock() {
-        Ok(b) => b,
-        Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
-    };
-    
-    // 1. Fetch sender user to get keys (In real app, we need auth token here)
+pub async fn send_transaction(data: web::Data<AppState>, req: web::Json<SendRequest>, caller: Caller) -> impl Responder {
+    if !caller.owns(&req.sender_wallet_id) {
+        return HttpResponse::Forbidden().json("You do not own this wallet");
+    }
+    // 1. Fetch sender user to get keys
     let collection = data.db.collection::<User>("users");
     let sender = collection.find_one(doc! { "wallet_id": &req.sender_wallet_id }, None).await;
     
@@ -44,30 +74,58 @@ pub async fn send_transaction(data: web::Data<AppState>, req: web::Json<SendRequ
         _ => return HttpResponse::BadRequest().json("Sender not found"),
     };
 
-    // 2. Check balance (Simple check, real check happens in add_transaction)
-    if blockchain.get_balance(&req.sender_wallet_id) < req.amount {
-        return HttpResponse::BadRequest().json("Insufficient balance");
-    }
-
-    // 3. Create Transaction
     // Reconstruct wallet from encrypted key (Mock decryption)
-    let wallet = match blockchain::Wallet::from_private_key(&sender_user.encrypted_private_key) {
+    let wallet = match sender_user.custodial_wallet() {
         Ok(w) => w,
         Err(_) => return HttpResponse::InternalServerError().json("Failed to load wallet"),
     };
 
-    let transaction = match blockchain.create_transaction(
-        &wallet, 
-        req.receiver_wallet_id.clone(), 
-        req.amount, 
-        req.note.clone()
-    ) {
-        Ok(tx) => tx,
-        Err(e) => return HttpResponse::BadRequest().json(e),
+    // Encrypted notes need the receiver's public key
+    let receiver_public_key = if req.encrypt_note && req.note.is_some() {
+        match collection.find_one(doc! { "wallet_id": &req.receiver_wallet_id }, None).await {
+            Ok(Some(u)) => Some(u.public_key),
+            Ok(None) => return HttpResponse::BadRequest().json("Encrypted notes can only be sent to registered users"),
+            Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+        }
+    } else {
+        None
+    };
+
+    let (transaction, added) = {
+        let mut blockchain = match data.blockchain.lock() {
+            Ok(b) => b,
+            Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+        };
+
+        // 2. Check balance (Simple check, real check happens in add_transaction)
+        let spendable = match &req.asset_id {
+            Some(asset_id) => blockchain.get_asset_balances(&req.sender_wallet_id).get(asset_id).map_or(0, |b| b.spendable),
+            None => blockchain.get_balance_details(&req.sender_wallet_id).spendable,
+        };
+        if spendable < req.amount {
+            return HttpResponse::BadRequest().json("Insufficient balance");
+        }
+
+        // 3. Create Transaction, locking the payment if requested
+        let mut payment = blockchain::TxOutput::new(req.amount, req.receiver_wallet_id.clone());
+        payment.asset = req.asset_id.clone();
+        payment.lock_time = req.lock_time;
+        payment.relative_lock = req.relative_lock;
+        let result = match (&receiver_public_key, &req.note) {
+            (Some(public_key), Some(note)) => blockchain.create_private_payment(&wallet, payment, public_key, note),
+            _ => blockchain.create_payment(&wallet, payment, req.note.clone()),
+        };
+        let transaction = match result {
+            Ok(tx) => tx,
+            Err(e) => return HttpResponse::BadRequest().json(e),
+        };
+
+        // 4. Add to pending
+        let added = blockchain.add_transaction(transaction.clone());
+        (transaction, added)
     };
 
-    // 4. Add to pending
-    if blockchain.add_transaction(transaction.clone()) {
+    if added {
         // Log successful transaction submission
         logging::log_action(&data, "TransactionSent", &format!("Tx {} sent", transaction.id), "success", None, None).await;
         HttpResponse::Ok().json(serde_json::json!({
@@ -80,9 +138,86 @@ pub async fn send_transaction(data: web::Data<AppState>, req: web::Json<SendRequ
     }
 }
 
-// Get transaction history for a wallet
-pub async fn get_history(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+#[derive(serde::Deserialize)]
+pub struct BatchPayment {
+    pub receiver_wallet_id: String,
+    pub amount: u64,
+    pub memo: Option<String>,
+    pub asset_id: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SendBatchRequest {
+    pub sender_wallet_id: String,
+    pub payments: Vec<BatchPayment>,
+    pub note: Option<String>,
+}
+
+// Pay several wallets in one transaction
+pub async fn send_batch_transaction(data: web::Data<AppState>, req: web::Json<SendBatchRequest>, caller: Caller) -> impl Responder {
+    if !caller.owns(&req.sender_wallet_id) {
+        return HttpResponse::Forbidden().json("You do not own this wallet");
+    }
+    let collection = data.db.collection::<User>("users");
+    let sender_user = match collection.find_one(doc! { "wallet_id": &req.sender_wallet_id }, None).await {
+        Ok(Some(u)) => u,
+        _ => return HttpResponse::BadRequest().json("Sender not found"),
+    };
+    let wallet = match sender_user.custodial_wallet() {
+        Ok(w) => w,
+        Err(_) => return HttpResponse::InternalServerError().json("Failed to load wallet"),
+    };
+
+    let payments: Vec<blockchain::TxOutput> = req
+        .payments
+        .iter()
+        .map(|p| {
+            let mut output = blockchain::TxOutput::new(p.amount, p.receiver_wallet_id.clone());
+            output.asset = p.asset_id.clone();
+            match &p.memo {
+                Some(memo) => output.with_memo(memo.clone()),
+                None => output,
+            }
+        })
+        .collect();
+
+    let (transaction, added) = {
+        let mut blockchain = match data.blockchain.lock() {
+            Ok(b) => b,
+            Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+        };
+        let transaction = match blockchain.create_batch_transaction(&wallet, payments, req.note.clone()) {
+            Ok(tx) => tx,
+            Err(e) => return HttpResponse::BadRequest().json(e),
+        };
+        let added = blockchain.add_transaction(transaction.clone());
+        (transaction, added)
+    };
+
+    if added {
+        logging::log_action(&data, "BatchTransactionSent", &format!("Tx {} pays {} outputs totalling {}", transaction.id, req.payments.len(), transaction.amount), "success", None, None).await;
+        HttpResponse::Ok().json(serde_json::json!({
+            "status": "success",
+            "transaction_id": transaction.id,
+            "total_amount": transaction.amount,
+            "recipients": req.payments.len()
+        }))
+    } else {
+        logging::log_action(&data, "BatchTransactionSent", &format!("Tx {} failed to add", transaction.id), "error", None, None).await;
+        HttpResponse::BadRequest().json("Failed to add transaction")
+    }
+}
+
+// Get transaction history for a wallet. Each entry carries the wallet's side of the
+// transaction, since batch payments have several receivers. Encrypted notes are
+// decrypted for the owner, the only caller allowed to ask.
+pub async fn get_history(data: web::Data<AppState>, path: web::Path<String>, caller: Caller) -> impl Responder {
     let wallet_id = path.into_inner();
+    if !caller.owns(&wallet_id) {
+        return HttpResponse::Forbidden().json("You do not own this wallet");
+    }
+    let owner_wallet = load_signer(&data, &wallet_id).await.ok();
+
     let blockchain = match data.blockchain.lock() {
         Ok(b) => b,
         Err(_) => return HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
@@ -90,10 +225,43 @@ pub async fn get_history(data: web::Data<AppState>, path: web::Path<String>) ->
     let mut txs = Vec::new();
     for block in &blockchain.chain {
         for tx in &block.transactions {
-            if tx.sender_wallet_id == wallet_id || tx.receiver_wallet_id == wallet_id {
-                txs.push(tx.clone());
+            if !tx.involves(&wallet_id) {
+                continue;
+            }
+            let received = tx.amount_to(&wallet_id);
+            let (direction, wallet_amount) = if tx.sender_wallet_id != wallet_id {
+                ("received", received)
+            } else if tx.receiver_wallet_id == wallet_id {
+                ("self", tx.amount)
+            } else {
+                // Outputs back to the sender are change
+                ("sent", tx.outputs.iter().map(|o| o.amount).sum::<u64>().saturating_sub(received))
+            };
+            let mut entry = serde_json::to_value(tx).unwrap_or_default();
+            entry["direction"] = direction.into();
+            entry["wallet_amount"] = wallet_amount.into();
+            entry["block_index"] = block.index.into();
+            entry["asset_id"] = tx.outputs.iter().find_map(|o| o.asset.clone()).into();
+            if let (Some(memo), Some(owner)) = (&tx.encrypted_note, &owner_wallet) {
+                if let Ok(note) = memo.decrypt(owner) {
+                    entry["decrypted_note"] = note.into();
+                }
             }
+            txs.push(entry);
         }
     }
     HttpResponse::Ok().json(txs)
 }
+
+// Merkle proofs for every confirmed transaction of a wallet, for light clients
+// that check them against their own header chain instead of trusting the balance
+pub async fn get_proofs(data: web::Data<AppState>, path: web::Path<String>, caller: Caller) -> impl Responder {
+    let wallet_id = path.into_inner();
+    if !caller.owns(&wallet_id) {
+        return HttpResponse::Forbidden().json("You do not own this wallet");
+    }
+    match data.blockchain.lock() {
+        Ok(b) => HttpResponse::Ok().json(b.wallet_proofs(&wallet_id)),
+        Err(_) => HttpResponse::InternalServerError().json("Blockchain lock poisoned"),
+    }
+}
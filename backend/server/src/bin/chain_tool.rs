@@ -0,0 +1,87 @@
+use blockchain::ChainArchive;
+use server::config::{consensus_from_env, params_from_env};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Read};
+use std::process;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    dotenv::dotenv().ok();
+    let args: Vec<String> = env::args().collect();
+
+    match args.get(1).map(String::as_str) {
+        // Download blocks from a running node into a chain file
+        Some("export") if args.len() >= 4 => {
+            let mut url = format!("{}/api/blockchain/export?from={}", args[2].trim_end_matches('/'), args.get(4).map_or("0", String::as_str));
+            if let Some(to) = args.get(5) {
+                url.push_str(&format!("&to={}", to));
+            }
+            let mut bytes = Vec::new();
+            ureq::get(&url).call()?.into_reader().read_to_end(&mut bytes)?;
+            let archive = ChainArchive::from_bytes(&bytes)?;
+            fs::write(&args[3], &bytes)?;
+            match (archive.blocks.first(), archive.blocks.last()) {
+                (Some(first), Some(last)) => println!("Exported blocks {} to {} to {}", first.index, last.index, args[3]),
+                _ => println!("Exported an empty chain file to {}", args[3]),
+            }
+        }
+        // Check a chain file without a node
+        Some("verify") if args.len() >= 3 => {
+            let archive = ChainArchive::from_bytes(&fs::read(&args[2])?)?;
+            match archive.verify(consensus_from_env()?, params_from_env()) {
+                Ok(report) => {
+                    println!("Blocks {} to {} are valid ({} transactions)", report.first, report.last, report.transactions);
+                    if !report.fully_validated {
+                        println!("The file does not start at genesis: headers, seals and Merkle roots were checked, transactions were not replayed.");
+                    }
+                }
+                Err(invalid) => {
+                    println!("Block {} is invalid: {}", invalid.index, invalid.reason);
+                    process::exit(1);
+                }
+            }
+        }
+        // Load a chain file into a fresh node, after checking it here first
+        Some("import") if args.len() >= 4 => {
+            let bytes = fs::read(&args[2])?;
+            let archive = ChainArchive::from_bytes(&bytes)?;
+            if let Err(invalid) = archive.verify(consensus_from_env()?, params_from_env()) {
+                println!("Not importing: block {} is invalid: {}", invalid.index, invalid.reason);
+                process::exit(1);
+            }
+            // Importing is an admin action confirmed with an emailed step-up OTP
+            let node = args[3].trim_end_matches('/');
+            let token = env::var("WALX_ACCESS_TOKEN").map_err(|_| "Set WALX_ACCESS_TOKEN to an admin's access token")?;
+            let authorization = format!("Bearer {}", token);
+            if let Err(e) = ureq::post(&format!("{}/api/admin/step-up", node)).set("Authorization", &authorization).send_json(serde_json::json!({ "action": "import_chain" })) {
+                println!("Could not request a step-up OTP: {}", e);
+                process::exit(1);
+            }
+            eprint!("Step-up OTP: ");
+            let mut otp = String::new();
+            io::stdin().lock().read_line(&mut otp)?;
+            let url = format!("{}/api/blockchain/import", node);
+            let request = ureq::post(&url)
+                .set("Content-Type", "application/octet-stream")
+                .set("Authorization", &authorization)
+                .set("X-Step-Up-OTP", otp.trim());
+            match request.send_bytes(&bytes) {
+                Ok(response) => println!("Imported: {}", response.into_string()?),
+                Err(ureq::Error::Status(code, response)) => {
+                    println!("Node refused the import ({}): {}", code, response.into_string()?);
+                    process::exit(1);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        _ => {
+            println!("Usage:");
+            println!("  cargo run --bin chain_tool export <node-url> <file> [from] [to]  # Save blocks to a chain file");
+            println!("  cargo run --bin chain_tool verify <file>                         # Validate a chain file offline");
+            println!("  cargo run --bin chain_tool import <file> <node-url>              # Load a chain file into a fresh node");
+            println!("The consensus settings (CONSENSUS, POA_VALIDATORS, MINT_AUTHORITY) must match the chain's.");
+            println!("Importing needs an admin's access token in WALX_ACCESS_TOKEN and the step-up OTP it emails.");
+        }
+    }
+    Ok(())
+}
@@ -21,7 +21,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         let email = if args.len() >= 3 { &args[2] } else { "admin@walx.com" };
         
         // Check if user already exists
-        if let Some(_) = collection.find_one(doc! { "email": email }, None).await? {
+        if collection.find_one(doc! { "email": email }, None).await?.is_some() {
             println!("User with email {} already exists.", email);
             return Ok(());
         }
@@ -30,7 +30,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         let wallet = Wallet::new();
         let wallet_id = wallet.get_wallet_id();
         let public_key = wallet.get_public_key_hex();
-        let private_key = hex::encode(wallet.keypair.to_bytes());
+        let private_key = User::stored_key(&wallet);
         
         let admin_user = User {
             id: None,
@@ -44,6 +44,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             created_at: chrono::Utc::now().timestamp(),
             otp: None,
             otp_expiry: None,
+            otp_ticket: None,
+            login_nonce: None,
+            login_nonce_expiry: None,
+            step_up_otp: None,
+            step_up_action: None,
+            step_up_expiry: None,
             role: UserRole::Admin,
         };
         
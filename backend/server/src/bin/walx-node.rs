@@ -0,0 +1,218 @@
+use blockchain::{ChainArchive, Environment};
+use clap::{Parser, Subcommand};
+use serde_json::Value;
+use server::config::{consensus_from_env, params_from_env};
+use server::inspect;
+use std::fs;
+use std::process;
+
+/// Inspect the chain state of a running node, or of a chain file written by `chain_tool export`.
+#[derive(Parser)]
+#[command(name = "walx-node")]
+struct Cli {
+    /// Base URL of the node.
+    #[arg(long, env = "WALX_NODE_URL", default_value = "http://localhost:8080")]
+    url: String,
+    /// Read a chain file instead of asking a node. Takes precedence over `--url`.
+    #[arg(long)]
+    file: Option<String>,
+    /// An admin's access token, needed by `reindex`.
+    #[arg(long, env = "WALX_ACCESS_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+    /// Print the raw JSON instead of a summary.
+    #[arg(long)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Height and hash of the tip, with mempool and UTXO set sizes.
+    Tip,
+    /// A block by height or hash.
+    Block { id: String },
+    /// A transaction by id.
+    Tx { id: String },
+    /// Pending transactions.
+    Mempool,
+    /// Unspent outputs of a wallet.
+    Utxos { wallet_id: String },
+    /// Rebuild the node's UTXO set, asset registry and filters from its blocks (admin only).
+    Reindex,
+}
+
+enum Source {
+    Node(String),
+    File(Box<blockchain::Blockchain>),
+}
+
+impl Source {
+    fn get(&self, path: &str) -> Result<Value, String> {
+        match self {
+            Source::Node(url) => request(ureq::get(&format!("{}/api/blockchain{}", url, path)).call()),
+            Source::File(_) => Err("not available for chain files".to_string()),
+        }
+    }
+}
+
+fn request(result: Result<ureq::Response, ureq::Error>) -> Result<Value, String> {
+    match result {
+        Ok(response) => response.into_json().map_err(|e| e.to_string()),
+        Err(ureq::Error::Status(_, response)) => Err(response.into_string().unwrap_or_default().trim_matches('"').to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn main() {
+    dotenv::dotenv().ok();
+    let cli = Cli::parse();
+    let source = match &cli.file {
+        Some(path) => match load(path) {
+            Ok(chain) => Source::File(Box::new(chain)),
+            Err(e) => fail(&e),
+        },
+        None => Source::Node(cli.url.trim_end_matches('/').to_string()),
+    };
+    let value = match run(&source, &cli.command, cli.token.as_deref()) {
+        Ok(value) => value,
+        Err(e) => fail(&e),
+    };
+    if cli.json {
+        println!("{}", serde_json::to_string_pretty(&value).unwrap_or_default());
+    } else {
+        print_summary(&cli.command, &value);
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(1);
+}
+
+/// A chain file replayed into a scratch node, so its state can be inspected.
+fn load(path: &str) -> Result<blockchain::Blockchain, String> {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let archive = ChainArchive::from_bytes(&bytes).map_err(|e| e.to_string())?;
+    blockchain::Blockchain::import(consensus_from_env()?, Environment::system(), params_from_env(), archive.blocks).map_err(|e| e.to_string())
+}
+
+fn run(source: &Source, command: &Command, token: Option<&str>) -> Result<Value, String> {
+    match (source, command) {
+        (Source::File(b), Command::Tip) => Ok(inspect::tip(b)),
+        (Source::File(b), Command::Block { id }) => {
+            let block = match id.parse::<usize>() {
+                Ok(height) => b.chain.get(height),
+                Err(_) => b.block_by_hash(id),
+            };
+            block.map(|block| inspect::block(b, block)).ok_or_else(|| format!("no block {}", id))
+        }
+        (Source::File(b), Command::Tx { id }) => inspect::transaction(b, id).ok_or_else(|| format!("no transaction {}", id)),
+        (Source::File(b), Command::Mempool) => Ok(inspect::mempool(b)),
+        (Source::File(b), Command::Utxos { wallet_id }) => Ok(inspect::utxos(b, wallet_id)),
+        (Source::File(_), Command::Reindex) => Err("reindex needs a running node; a chain file is indexed as it is read".to_string()),
+        (Source::Node(_), Command::Tip) => source.get("/tip"),
+        (Source::Node(_), Command::Block { id }) => {
+            let hash = match id.parse::<u64>() {
+                Ok(height) => source.get(&format!("/blocks/{}", height))?["hash"].as_str().unwrap_or_default().to_string(),
+                Err(_) => id.clone(),
+            };
+            source.get(&format!("/blocks/hash/{}", hash))
+        }
+        (Source::Node(_), Command::Tx { id }) => source.get(&format!("/transactions/{}", id)),
+        (Source::Node(_), Command::Mempool) => source.get("/mempool"),
+        (Source::Node(_), Command::Utxos { wallet_id }) => source.get(&format!("/utxos/{}", wallet_id)),
+        (Source::Node(url), Command::Reindex) => {
+            let token = token.ok_or("reindex needs an admin's access token (--token or WALX_ACCESS_TOKEN)")?;
+            request(ureq::post(&format!("{}/api/blockchain/reindex", url)).set("Authorization", &format!("Bearer {}", token)).call())
+        }
+    }
+}
+
+fn time(value: &Value) -> String {
+    value
+        .as_i64()
+        .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_else(|| value.to_string())
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => "-".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn print_summary(command: &Command, v: &Value) {
+    match command {
+        Command::Tip => {
+            println!("Height      {}", v["height"]);
+            println!("Hash        {}", text(&v["hash"]));
+            println!("Time        {}", time(&v["timestamp"]));
+            println!("Version     {}", v["version"]);
+            println!("Consensus   {}", text(&v["consensus"]));
+            println!("Mempool     {} transactions", v["pending_transactions"]);
+            println!("Side blocks {}", v["side_blocks"]);
+            println!("UTXOs       {}", v["utxos"]);
+        }
+        Command::Block { .. } => {
+            let block = &v["block"];
+            let branch = if v["main_chain"].as_bool() == Some(true) { format!("main chain, {} confirmations", v["confirmations"]) } else { "side branch".to_string() };
+            println!("Block {} ({})", block["index"], branch);
+            println!("Hash        {}", text(&block["hash"]));
+            println!("Previous    {}", text(&block["previous_hash"]));
+            println!("Merkle root {}", text(&block["merkle_root"]));
+            println!("Time        {}", time(&block["timestamp"]));
+            println!("Version     {}  Nonce {}", block["version"], block["nonce"]);
+            let transactions = block["transactions"].as_array().cloned().unwrap_or_default();
+            println!("{} transactions:", transactions.len());
+            for tx in &transactions {
+                println!("  {}  {} -> {}  {}", text(&tx["id"]), text(&tx["sender_wallet_id"]), text(&tx["receiver_wallet_id"]), tx["amount"]);
+            }
+        }
+        Command::Tx { .. } => {
+            println!("Transaction {}", text(&v["id"]));
+            println!("From        {}", text(&v["sender_wallet_id"]));
+            println!("To          {}", text(&v["receiver_wallet_id"]));
+            println!("Amount      {}", v["amount"]);
+            println!("Time        {}", time(&v["timestamp"]));
+            match v["block_index"].as_u64() {
+                Some(index) => println!("Block       {} ({} confirmations)", index, v["confirmations"]),
+                None => println!("Block       unconfirmed"),
+            }
+            if !v["note"].is_null() {
+                println!("Note        {}", text(&v["note"]));
+            }
+            for output in v["outputs"].as_array().cloned().unwrap_or_default() {
+                let spent = if output["spent"].as_bool() == Some(true) { "spent" } else { "unspent" };
+                println!("  #{}  {}  {}  {}", output["index"], text(&output["receiver_wallet_id"]), output["amount"], spent);
+            }
+        }
+        Command::Mempool => {
+            println!("{} pending transactions", v["count"]);
+            for tx in v["transactions"].as_array().cloned().unwrap_or_default() {
+                println!("  {}  {} -> {}  {}  ({} in, {} out)", text(&tx["id"]), text(&tx["sender_wallet_id"]), text(&tx["receiver_wallet_id"]), tx["amount"], tx["inputs"], tx["outputs"]);
+            }
+        }
+        Command::Utxos { .. } => {
+            let utxos = v["utxos"].as_array().cloned().unwrap_or_default();
+            println!("{} unspent outputs of {}, {} coins", utxos.len(), text(&v["wallet_id"]), v["total"]);
+            for utxo in utxos {
+                let mut flags = Vec::new();
+                if utxo["spendable"].as_bool() != Some(true) {
+                    flags.push("locked");
+                }
+                if utxo["pending_spend"].as_bool() == Some(true) {
+                    flags.push("spent in mempool");
+                }
+                let asset = utxo["asset"].as_str().map(|asset| format!(" {}", asset)).unwrap_or_default();
+                println!("  {}:{}  {}{}  height {}  {}", text(&utxo["tx_id"]), utxo["output_index"], utxo["amount"], asset, utxo["height"], flags.join(", "));
+            }
+        }
+        Command::Reindex => {
+            println!("Reindexed up to height {}: {} UTXOs, {} pending transactions dropped", v["height"], v["utxos"], v["dropped_pending"]);
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use blockchain::{build_payment, Coin, Decode, Encode, Keystore, Transaction, Wallet};
+use clap::{Parser, Subcommand};
+use server::session;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// A Walx wallet whose key never leaves this machine. Balances and history come
+/// from a node; payments are built and signed here and only the signed
+/// transaction is sent.
+#[derive(Parser)]
+#[command(name = "walx-wallet")]
+struct Cli {
+    /// Base URL of the node.
+    #[arg(long, env = "WALX_NODE_URL", default_value = "http://localhost:8080")]
+    url: String,
+    /// Encrypted key file. Defaults to ~/.walx/wallet.json.
+    #[arg(long, env = "WALX_WALLET")]
+    wallet: Option<PathBuf>,
+    /// Access token of a session with the node, needed for the history.
+    /// Defaults to the session saved by `login`.
+    #[arg(long, env = "WALX_ACCESS_TOKEN")]
+    token: Option<String>,
+    /// Print the raw JSON instead of a summary.
+    #[arg(long)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate a new key into the key file.
+    Create {
+        #[arg(long)]
+        force: bool,
+    },
+    /// Store an existing private key (hex, read from stdin) in the key file.
+    Restore {
+        #[arg(long)]
+        force: bool,
+    },
+    /// Wallet id and public key.
+    Address,
+    /// Print the private key in hex, for backups.
+    ExportKey,
+    /// Sign in to the node by answering its challenge with the key, then an emailed OTP.
+    Login,
+    Balance,
+    History,
+    /// Pay another wallet.
+    Send {
+        to: String,
+        amount: u64,
+        #[arg(long)]
+        note: Option<String>,
+        /// Print the signed transaction in hex instead of broadcasting it.
+        #[arg(long)]
+        sign_only: bool,
+    },
+    /// Broadcast a transaction signed earlier with `send --sign-only`.
+    Broadcast { hex: String },
+}
+
+fn main() {
+    dotenv::dotenv().ok();
+    let cli = Cli::parse();
+    let url = cli.url.trim_end_matches('/').to_string();
+    let path = cli.wallet.clone().unwrap_or_else(default_wallet_path);
+    if let Err(e) = run(&cli, &url, &path) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn default_wallet_path() -> PathBuf {
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")).unwrap_or_default();
+    Path::new(&home).join(".walx").join("wallet.json")
+}
+
+fn run(cli: &Cli, url: &str, path: &Path) -> Result<(), String> {
+    match &cli.command {
+        Command::Create { force } => save(path, &Wallet::new(), *force),
+        Command::Restore { force } => {
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line).map_err(|e| e.to_string())?;
+            // Keys exported by the server hold the secret and public halves; the secret comes first
+            let key = line.trim();
+            let wallet = Wallet::from_private_key(key.get(..64).filter(|_| key.len() == 128).unwrap_or(key))?;
+            save(path, &wallet, *force)
+        }
+        Command::Address => {
+            let keystore = read_keystore(path)?;
+            println!("Wallet id   {}", keystore.wallet_id);
+            println!("Public key  {}", keystore.public_key);
+            Ok(())
+        }
+        Command::ExportKey => {
+            let wallet = unlock(path)?;
+            println!("{}", hex::encode(wallet.keypair.secret.as_bytes()));
+            Ok(())
+        }
+        Command::Login => {
+            let wallet = unlock(path)?;
+            let wallet_id = wallet.get_wallet_id();
+            let challenge = post(&format!("{}/api/auth/challenge", url), serde_json::json!({ "wallet_id": wallet_id }))?;
+            let nonce = challenge["nonce"].as_str().ok_or("The node sent no challenge")?;
+            let signature = wallet.sign_transaction(&session::login_message(&wallet_id, nonce));
+            let sent = post(&format!("{}/api/auth/login", url), serde_json::json!({ "wallet_id": wallet_id, "nonce": nonce, "signature": signature }))?;
+            let ticket = sent["ticket"].as_str().ok_or("The node sent no login ticket")?;
+            println!("{}", sent["message"].as_str().unwrap_or("OTP sent"));
+            eprint!("OTP: ");
+            let mut otp = String::new();
+            io::stdin().lock().read_line(&mut otp).map_err(|e| e.to_string())?;
+            let tokens = post(&format!("{}/api/auth/verify-otp", url), serde_json::json!({ "wallet_id": wallet_id, "ticket": ticket, "otp": otp.trim() }))?;
+            save_session(path, &tokens)?;
+            println!("Logged in as {}", wallet_id);
+            Ok(())
+        }
+        Command::Balance => {
+            // Worked out from the public UTXO set, so no session is needed
+            let wallet_id = read_keystore(path)?.wallet_id;
+            let utxos = get(&format!("{}/api/blockchain/utxos/{}", url, wallet_id), None)?;
+            if cli.json {
+                return print_json(&utxos);
+            }
+            let mut balances: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+            for utxo in utxos["utxos"].as_array().cloned().unwrap_or_default() {
+                let amount = utxo["amount"].as_u64().unwrap_or_default();
+                let entry = balances.entry(utxo["asset"].as_str().unwrap_or_default().to_string()).or_default();
+                entry.0 += amount;
+                if utxo["spendable"] == true {
+                    entry.1 += amount;
+                }
+            }
+            let (total, spendable) = balances.remove("").unwrap_or_default();
+            println!("Balance     {}", total);
+            println!("Spendable   {}", spendable);
+            println!("Locked      {}", total - spendable);
+            for (asset, (total, _)) in balances {
+                println!("{}  {}", asset, total);
+            }
+            Ok(())
+        }
+        Command::History => {
+            let wallet_id = read_keystore(path)?.wallet_id;
+            let token = match &cli.token {
+                Some(token) => token.clone(),
+                None => session_token(url, path)?,
+            };
+            let history = get(&format!("{}/api/wallet/{}/history", url, wallet_id), Some(&token))?;
+            if cli.json {
+                return print_json(&history);
+            }
+            for tx in history.as_array().cloned().unwrap_or_default() {
+                let counterparty = if tx["direction"] == "received" { &tx["sender_wallet_id"] } else { &tx["receiver_wallet_id"] };
+                println!(
+                    "block {:<6} {:<8} {:>12}  {}  {}",
+                    tx["block_index"],
+                    tx["direction"].as_str().unwrap_or_default(),
+                    tx["wallet_amount"],
+                    counterparty.as_str().unwrap_or_default(),
+                    tx["note"].as_str().unwrap_or_default()
+                );
+            }
+            Ok(())
+        }
+        Command::Send { to, amount, note, sign_only } => {
+            let wallet = unlock(path)?;
+            let utxos = get(&format!("{}/api/blockchain/utxos/{}", url, wallet.get_wallet_id()), None)?;
+            let coins: Vec<Coin> = utxos["utxos"]
+                .as_array()
+                .cloned()
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|utxo| utxo["spendable"] == true && utxo["pending_spend"] == false && utxo["asset"].is_null())
+                .filter_map(|utxo| serde_json::from_value(utxo).ok())
+                .collect();
+            let tx = build_payment(&wallet, &coins, to.clone(), *amount, note.clone(), chrono::Utc::now().timestamp())?;
+            if *sign_only {
+                println!("{}", hex::encode(tx.encode()));
+                Ok(())
+            } else {
+                broadcast(url, &tx)
+            }
+        }
+        Command::Broadcast { hex } => {
+            let bytes = hex::decode(hex.trim()).map_err(|_| "Transaction is not hex".to_string())?;
+            let tx = Transaction::decode(&bytes).map_err(|e| e.to_string())?;
+            broadcast(url, &tx)
+        }
+    }
+}
+
+fn get(url: &str, token: Option<&str>) -> Result<Value, String> {
+    let request = ureq::get(url);
+    let request = match token {
+        Some(token) => request.set("Authorization", &format!("Bearer {}", token)),
+        None => request,
+    };
+    response(request.call())
+}
+
+fn post(url: &str, body: Value) -> Result<Value, String> {
+    response(ureq::post(url).send_json(body))
+}
+
+fn response(result: Result<ureq::Response, ureq::Error>) -> Result<Value, String> {
+    match result {
+        Ok(response) => response.into_json().map_err(|e| e.to_string()),
+        Err(ureq::Error::Status(_, response)) => Err(response.into_string().unwrap_or_default().trim_matches('"').to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn broadcast(url: &str, tx: &Transaction) -> Result<(), String> {
+    let request = ureq::post(&format!("{}/api/blockchain/transactions/raw", url)).set("Content-Type", "application/octet-stream");
+    response(request.send_bytes(&tx.encode()))?;
+    println!("Sent {} to {} in transaction {}", tx.amount, tx.receiver_wallet_id, tx.id);
+    Ok(())
+}
+
+fn print_json(value: &Value) -> Result<(), String> {
+    println!("{}", serde_json::to_string_pretty(value).map_err(|e| e.to_string())?);
+    Ok(())
+}
+
+/// The key file password, from `WALX_WALLET_PASSWORD` for scripts or else the terminal.
+fn password(prompt: &str) -> Result<String, String> {
+    match env::var("WALX_WALLET_PASSWORD") {
+        Ok(password) => Ok(password),
+        Err(_) => rpassword::prompt_password(prompt).map_err(|e| e.to_string()),
+    }
+}
+
+fn save(path: &Path, wallet: &Wallet, force: bool) -> Result<(), String> {
+    if path.exists() && !force {
+        return Err(format!("{} already exists; pass --force to replace it", path.display()));
+    }
+    let password = password("New password: ")?;
+    if env::var("WALX_WALLET_PASSWORD").is_err() && rpassword::prompt_password("Repeat password: ").map_err(|e| e.to_string())? != password {
+        return Err("Passwords do not match".to_string());
+    }
+    if password.is_empty() {
+        return Err("The password must not be empty".to_string());
+    }
+    let keystore = Keystore::encrypt(wallet, &password).map_err(|e| e.to_string())?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    write_private(path, keystore.to_json().as_bytes()).map_err(|e| format!("{}: {}", path.display(), e))?;
+    println!("Wallet {} saved to {}", keystore.wallet_id, path.display());
+    Ok(())
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?.write_all(contents)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    fs::write(path, contents)
+}
+
+/// Tokens from `login` are kept beside the key file.
+fn session_path(path: &Path) -> PathBuf {
+    path.with_extension("session.json")
+}
+
+fn save_session(path: &Path, tokens: &Value) -> Result<(), String> {
+    let session = serde_json::json!({
+        "access_token": tokens["access_token"],
+        "refresh_token": tokens["refresh_token"],
+        "expires_at": chrono::Utc::now().timestamp() + tokens["expires_in"].as_i64().unwrap_or_default()
+    });
+    write_private(&session_path(path), session.to_string().as_bytes()).map_err(|e| e.to_string())
+}
+
+/// The saved access token, refreshed first if it has expired.
+fn session_token(url: &str, path: &Path) -> Result<String, String> {
+    let json = fs::read_to_string(session_path(path)).map_err(|_| "Not logged in: run `walx-wallet login` or pass --token".to_string())?;
+    let mut session: Value = serde_json::from_str(&json).map_err(|e| e.to_string())?;
+    if session["expires_at"].as_i64().unwrap_or_default() <= chrono::Utc::now().timestamp() {
+        let tokens = post(&format!("{}/api/auth/refresh", url), serde_json::json!({ "refresh_token": session["refresh_token"] }))
+            .map_err(|e| format!("{} (run `walx-wallet login` again)", e))?;
+        save_session(path, &tokens)?;
+        session = tokens;
+    }
+    session["access_token"].as_str().map(str::to_string).ok_or_else(|| "Saved session has no access token".to_string())
+}
+
+fn read_keystore(path: &Path) -> Result<Keystore, String> {
+    let json = fs::read_to_string(path).map_err(|e| format!("{}: {} (create a wallet with `walx-wallet create`)", path.display(), e))?;
+    Keystore::from_json(&json).map_err(|e| e.to_string())
+}
+
+fn unlock(path: &Path) -> Result<Wallet, String> {
+    let keystore = read_keystore(path)?;
+    keystore.decrypt(&password("Password: ")?).map_err(|e| e.to_string())
+}
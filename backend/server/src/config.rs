@@ -0,0 +1,54 @@
+//! Node settings read from the environment, shared by the server and its tools.
+
+use std::env;
+
+use blockchain::{ChainParams, Consensus, ProofOfAuthority, ProofOfWork, RuleSet, Wallet};
+
+/// Proof of work unless `CONSENSUS=poa`, in which case `POA_VALIDATORS` lists the
+/// validator public keys and `POA_SIGNING_KEY` optionally holds this node's key.
+pub fn consensus_from_env() -> Result<Box<dyn Consensus>, String> {
+    match env::var("CONSENSUS").unwrap_or_else(|_| "pow".to_string()).as_str() {
+        "pow" => Ok(Box::new(ProofOfWork::new(2))),
+        "poa" => {
+            let validators = env::var("POA_VALIDATORS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|key| key.trim().to_string())
+                .filter(|key| !key.is_empty())
+                .collect();
+            let signer = match env::var("POA_SIGNING_KEY") {
+                Ok(key) if !key.is_empty() => Some(Wallet::from_private_key(&key)?),
+                _ => None,
+            };
+            Ok(Box::new(ProofOfAuthority::new(validators, signer).map_err(|e| e.to_string())?))
+        }
+        other => Err(format!("unknown consensus {}", other)),
+    }
+}
+
+/// The genesis rules, with `MINT_AUTHORITY` as the wallet allowed to mint native
+/// coins. Every node and tool reading the same chain needs the same value.
+pub fn params_from_env() -> ChainParams {
+    let mint_authority = env::var("MINT_AUTHORITY").ok().filter(|id| !id.is_empty());
+    ChainParams::new(RuleSet { mint_authority, ..RuleSet::genesis() }).expect("the genesis rules activate at height 0")
+}
+
+/// The wallet, from `ORGANIZATION_WALLET_ID`, that any admin may send bulk payouts
+/// from. Without it an admin can only pay out of their own wallet.
+pub fn organization_wallet_from_env() -> Option<String> {
+    env::var("ORGANIZATION_WALLET_ID").ok().map(|id| id.trim().to_string()).filter(|id| !id.is_empty())
+}
+
+/// The mint authority's key from `MINT_SIGNING_KEY`, set only on the node that mints.
+pub fn mint_signer_from_env(params: &ChainParams) -> Result<Option<Wallet>, String> {
+    match env::var("MINT_SIGNING_KEY") {
+        Ok(key) if !key.is_empty() => {
+            let wallet = Wallet::from_private_key(&key)?;
+            if params.rules_at(0).mint_authority.as_deref() != Some(wallet.get_wallet_id().as_str()) {
+                return Err("MINT_SIGNING_KEY is not the key of MINT_AUTHORITY".to_string());
+            }
+            Ok(Some(wallet))
+        }
+        _ => Ok(None),
+    }
+}
@@ -2,12 +2,18 @@ use mongodb::{Client, Database, options::ClientOptions};
 use std::env;
 use std::error::Error;
 use std::sync::Mutex;
-use blockchain::Blockchain;
+use blockchain::{Blockchain, Wallet};
+use crate::session::SessionKeys;
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: Database,
     pub blockchain: std::sync::Arc<Mutex<Blockchain>>,
+    pub sessions: std::sync::Arc<SessionKeys>,
+    /// Key of the chain's mint authority, when this node mints.
+    pub minter: Option<std::sync::Arc<Wallet>>,
+    /// Wallet every admin may send bulk payouts from, besides their own.
+    pub organization_wallet: Option<String>,
 }
 
 pub async fn init_db() -> Result<Database, Box<dyn Error>> {
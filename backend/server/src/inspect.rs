@@ -0,0 +1,117 @@
+//! Read-only views of the chain state, shared by the API and the `walx-node` CLI
+//! so that both describe the chain the same way.
+
+use blockchain::{Block, Blockchain};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+
+/// The tip and the size of the node's working sets.
+pub fn tip(b: &Blockchain) -> Value {
+    let tip = b.get_latest_block();
+    json!({
+        "height": tip.index,
+        "hash": tip.hash,
+        "timestamp": tip.timestamp,
+        "version": tip.version,
+        "consensus": b.consensus.name(),
+        "pending_transactions": b.pending_transactions.len(),
+        "side_blocks": b.side_blocks.len(),
+        "utxos": b.utxos.len()
+    })
+}
+
+/// A block with whether it is on the main chain or a side branch.
+pub fn block(b: &Blockchain, block: &Block) -> Value {
+    let main_chain = b.chain.get(block.index as usize).is_some_and(|main| main.hash == block.hash);
+    json!({
+        "main_chain": main_chain,
+        "confirmations": if main_chain { b.chain.len() as u64 - block.index } else { 0 },
+        "block": block
+    })
+}
+
+/// A transaction with each of its outputs and where it was confirmed.
+pub fn transaction(b: &Blockchain, tx_id: &str) -> Option<Value> {
+    let tx = b.find_transaction(tx_id)?;
+    let block_index = b
+        .chain
+        .iter()
+        .find(|block| block.transactions.iter().any(|tx| tx.id == tx_id))
+        .map(|block| block.index);
+    let outputs: Vec<Value> = tx
+        .outputs
+        .iter()
+        .enumerate()
+        .map(|(index, output)| json!({
+            "index": index,
+            "receiver_wallet_id": output.receiver_wallet_id,
+            "amount": output.amount,
+            "memo": output.memo,
+            "change": output.receiver_wallet_id == tx.sender_wallet_id,
+            "spent": !b.utxos.contains_key(&(tx.id.clone(), index)),
+        }))
+        .collect();
+    Some(json!({
+        "id": tx.id,
+        "sender_wallet_id": tx.sender_wallet_id,
+        "receiver_wallet_id": tx.receiver_wallet_id,
+        "amount": tx.amount,
+        "note": tx.note,
+        "timestamp": tx.timestamp,
+        "batch": tx.is_batch(),
+        "block_index": block_index,
+        "confirmations": block_index.map(|i| b.chain.len() as u64 - i).unwrap_or(0),
+        "outputs": outputs
+    }))
+}
+
+/// Pending transactions in the order they would be mined.
+pub fn mempool(b: &Blockchain) -> Value {
+    let transactions: Vec<Value> = b
+        .pending_transactions
+        .iter()
+        .map(|tx| json!({
+            "id": tx.id,
+            "sender_wallet_id": tx.sender_wallet_id,
+            "receiver_wallet_id": tx.receiver_wallet_id,
+            "amount": tx.amount,
+            "timestamp": tx.timestamp,
+            "inputs": tx.inputs.len(),
+            "outputs": tx.outputs.len()
+        }))
+        .collect();
+    json!({ "count": transactions.len(), "transactions": transactions })
+}
+
+/// Unspent outputs paying `wallet_id`, oldest first, with whether they can be
+/// spent in the next block and whether a pending transaction already spends them.
+pub fn utxos(b: &Blockchain, wallet_id: &str) -> Value {
+    let next_height = b.chain.len() as u64;
+    let now = b.env.now();
+    let pending: HashSet<(&str, usize)> = b
+        .pending_transactions
+        .iter()
+        .flat_map(|tx| tx.inputs.iter().map(|input| (input.tx_id.as_str(), input.output_index)))
+        .collect();
+    let mut owned: Vec<(u64, &(String, usize), &blockchain::TxOutput)> = b
+        .utxos
+        .iter()
+        .filter(|(_, output)| output.receiver_wallet_id == wallet_id)
+        .map(|(key, output)| (b.utxo_heights.get(key).copied().unwrap_or_default(), key, output))
+        .collect();
+    owned.sort_by(|a, c| (a.0, a.1).cmp(&(c.0, c.1)));
+    let utxos: Vec<Value> = owned
+        .iter()
+        .map(|(height, (tx_id, index), output)| json!({
+            "tx_id": tx_id,
+            "output_index": index,
+            "amount": output.amount,
+            "asset": output.asset,
+            "height": height,
+            "spendable": output.is_unlocked(next_height, now, *height),
+            "pending_spend": pending.contains(&(tx_id.as_str(), *index))
+        }))
+        .collect();
+    let total: u64 = owned.iter().filter(|(_, _, output)| output.asset.is_none()).map(|(_, _, output)| output.amount).sum();
+    json!({ "wallet_id": wallet_id, "total": total, "utxos": utxos })
+}
@@ -4,3 +4,8 @@ pub mod api;
 pub mod zakat;
 pub mod logging;
 pub mod email;
+pub mod config;
+pub mod inspect;
+pub mod session;
+
+mod tests;
@@ -1,7 +1,6 @@
 // src/logging.rs
 use crate::db::AppState;
 use crate::models::LogEntry;
-use mongodb::bson::doc;
 use actix_web::web;
 use chrono::Utc;
 
@@ -18,7 +17,7 @@ pub async fn log_action(state: &web::Data<AppState>, action: &str, details: &str
         details: details.to_string(),
         status: status.to_string(),
         ip_address: ip,
-        block_hash: block_hash,
+        block_hash,
     };
     if let Err(e) = collection.insert_one(log, None).await {
         eprintln!("[Logging Error] Failed to insert log entry: {}", e);
@@ -1,9 +1,11 @@
-use actix_web::{web, App, HttpServer, middleware::Logger};
+use actix_web::{web, App, HttpServer, middleware::{from_fn, Logger}};
 use actix_cors::Cors;
 use dotenv::dotenv;
 use std::env;
 
-use server::{db, models, api, zakat, logging, email};
+use server::{db, api, zakat};
+use server::config::{consensus_from_env, mint_signer_from_env, organization_wallet_from_env, params_from_env};
+use server::session::{self, SessionKeys};
 use db::AppState;
 
 #[actix_web::main]
@@ -25,10 +27,27 @@ async fn main() -> std::io::Result<()> {
     }
 
     let db = db::init_db().await.expect("Failed to connect to MongoDB");
-    let blockchain = blockchain::Blockchain::new();
+    let params = params_from_env();
+    let minter = match mint_signer_from_env(&params) {
+        Ok(minter) => minter,
+        Err(e) => {
+            eprintln!("Invalid mint configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let blockchain = match consensus_from_env() {
+        Ok(consensus) => blockchain::Blockchain::with_params(consensus, blockchain::Environment::system(), params),
+        Err(e) => {
+            eprintln!("Invalid consensus configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
     let app_state = AppState { 
         db, 
-        blockchain: std::sync::Arc::new(std::sync::Mutex::new(blockchain)) 
+        blockchain: std::sync::Arc::new(std::sync::Mutex::new(blockchain)),
+        sessions: std::sync::Arc::new(SessionKeys::from_env()),
+        minter: minter.map(std::sync::Arc::new),
+        organization_wallet: organization_wallet_from_env(),
     };
 
     let port = env::var("PORT").unwrap_or_else(|_| "8080".to_string());
@@ -53,6 +72,7 @@ async fn main() -> std::io::Result<()> {
             .app_data(app_state_data.clone())
             .service(
                 web::scope("/api")
+                    .wrap(from_fn(session::authenticate))
                     .configure(api::config)
             )
     })
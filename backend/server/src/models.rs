@@ -1,19 +1,15 @@
 use serde::{Serialize, Deserialize};
 use mongodb::bson::oid::ObjectId;
+use blockchain::Wallet;
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
 pub enum UserRole {
     Admin,
+    #[default]
     User,
 }
 
-impl Default for UserRole {
-    fn default() -> Self {
-        UserRole::User
-    }
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct User {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
@@ -34,10 +30,73 @@ pub struct User {
     pub otp: Option<String>,
     #[serde(default)]
     pub otp_expiry: Option<i64>,
+    /// Ticket handed to whoever answered the login challenge; the OTP is only checked with it
+    #[serde(default)]
+    pub otp_ticket: Option<String>,
+    /// Outstanding login challenge, usable once before its expiry
+    #[serde(default)]
+    pub login_nonce: Option<String>,
+    #[serde(default)]
+    pub login_nonce_expiry: Option<i64>,
+    /// OTP confirming one sensitive admin action, the action it is for, and its expiry
+    #[serde(default)]
+    pub step_up_otp: Option<String>,
+    #[serde(default)]
+    pub step_up_action: Option<String>,
+    #[serde(default)]
+    pub step_up_expiry: Option<i64>,
     #[serde(default)]
     pub role: UserRole,
 }
 
+impl User {
+    /// How a custodial key is stored: the hex keypair, secret half first (64 bytes).
+    pub fn stored_key(wallet: &Wallet) -> String {
+        hex::encode(wallet.keypair.to_bytes())
+    }
+
+    /// The user's custodial wallet, from a key stored by [`User::stored_key`] or a bare
+    /// 32-byte secret. The key must belong to the user's public key.
+    pub fn custodial_wallet(&self) -> Result<Wallet, String> {
+        let key = self.encrypted_private_key.as_str();
+        let secret = key.get(..64).filter(|_| key.len() == 128).unwrap_or(key);
+        let wallet = Wallet::from_private_key(secret)?;
+        let public_key = wallet.get_public_key_hex();
+        if (key.len() == 128 && key[64..] != public_key) || (!self.public_key.is_empty() && self.public_key != public_key) {
+            return Err("Stored key does not match the wallet".to_string());
+        }
+        Ok(wallet)
+    }
+}
+
+/// What a user may see of their own record: no key material, OTPs or login challenges.
+#[derive(Serialize, Debug, Clone)]
+pub struct UserProfile {
+    pub full_name: String,
+    pub email: String,
+    pub cnic: String,
+    pub wallet_id: String,
+    pub public_key: String,
+    pub beneficiaries: Vec<String>,
+    pub created_at: i64,
+    pub role: UserRole,
+}
+
+impl From<User> for UserProfile {
+    fn from(user: User) -> Self {
+        UserProfile {
+            full_name: user.full_name,
+            email: user.email,
+            cnic: user.cnic,
+            wallet_id: user.wallet_id,
+            public_key: user.public_key,
+            beneficiaries: user.beneficiaries,
+            created_at: user.created_at,
+            role: user.role,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Log {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
@@ -62,6 +121,116 @@ pub struct LogEntry {
     pub block_hash: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MultisigWallet {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub name: String,
+    pub address: String,
+    pub threshold: usize,
+    pub public_keys: Vec<String>,
+    /// Wallet ids of the co-signers, in the order they were given
+    pub members: Vec<String>,
+    pub created_by: String,
+    pub created_at: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MultisigProposal {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub address: String,
+    pub receiver_wallet_id: String,
+    pub amount: u64,
+    #[serde(default)]
+    pub note: Option<String>,
+    /// Base64 PSBT holding the co-signer signatures collected so far
+    pub psbt: String,
+    #[serde(default)]
+    pub approvals: Vec<String>,
+    /// "pending", "executed" or "failed"
+    pub status: String,
+    #[serde(default)]
+    pub transaction_id: Option<String>,
+    pub proposed_by: String,
+    pub created_at: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Escrow {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub buyer_wallet_id: String,
+    pub seller_wallet_id: String,
+    pub amount: u64,
+    /// Hex SHA-256 of the secret the seller redeems with
+    pub hash_lock: String,
+    /// Block height from which the buyer can reclaim the funds
+    pub timeout: u64,
+    /// Transaction holding the HTLC output, and the output's index
+    pub funding_tx_id: String,
+    pub output_index: usize,
+    /// "funded", "claimed" or "refunded"
+    pub status: String,
+    #[serde(default)]
+    pub settlement_tx_id: Option<String>,
+    #[serde(default)]
+    pub note: Option<String>,
+    pub created_at: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Payout {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    /// Admin who uploaded the sheet
+    pub created_by: String,
+    /// Organization wallet the stipends are paid from
+    pub sender_wallet_id: String,
+    pub rows: Vec<PayoutRow>,
+    /// Sum of the valid rows
+    pub total: u64,
+    /// "previewed" or "executed"
+    pub status: String,
+    #[serde(default)]
+    pub transaction_ids: Vec<String>,
+    pub created_at: i64,
+    #[serde(default)]
+    pub executed_at: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PayoutRow {
+    /// 1-based line number in the uploaded CSV
+    pub line: usize,
+    /// Wallet id or email as written in the sheet
+    pub recipient: String,
+    #[serde(default)]
+    pub wallet_id: Option<String>,
+    pub amount: u64,
+    #[serde(default)]
+    pub memo: Option<String>,
+    /// "valid", "invalid", "paid" or "failed"
+    pub status: String,
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub transaction_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NotaryRecord {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    /// Wallet that paid for the anchoring transaction
+    pub wallet_id: String,
+    /// Hex SHA-256 of the document
+    pub document_hash: String,
+    pub transaction_id: String,
+    #[serde(default)]
+    pub note: Option<String>,
+    pub created_at: i64,
+}
 
 // Re-export blockchain types for DB usage if needed, or wrap them
 pub use blockchain::{Block, Transaction, TxOutput};
@@ -0,0 +1,201 @@
+//! Session tokens issued after OTP verification, and the middleware that checks them.
+//!
+//! Logging in takes a signature over a fresh challenge ([`login_message`]), then
+//! an OTP sent by email.
+//!
+//! A token is `base64url(claims JSON) "." base64url(HMAC-SHA256(secret, claims))`.
+//! Access tokens are short lived and sent as `Authorization: Bearer <token>`;
+//! refresh tokens only buy a new pair at `/auth/refresh`. Handlers that act for
+//! a wallet take a [`Caller`] and check it owns the wallet with [`Caller::owns`].
+
+use std::env;
+use std::future::{ready, Ready};
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::db::AppState;
+use crate::models::UserRole;
+
+/// Seconds an access token is valid.
+pub const ACCESS_TOKEN_TTL: i64 = 15 * 60;
+/// Seconds a refresh token is valid.
+pub const REFRESH_TOKEN_TTL: i64 = 7 * 24 * 60 * 60;
+/// Seconds a login challenge can be answered.
+pub const CHALLENGE_TTL: i64 = 120;
+
+/// What a wallet signs to answer a login challenge. The prefix keeps the
+/// signature from being valid for anything on the chain, where wallets sign
+/// bare transaction ids.
+pub fn login_message(wallet_id: &str, nonce: &str) -> String {
+    format!("Walx login {} {}", wallet_id, nonce)
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum TokenError {
+    #[error("malformed token")]
+    Malformed,
+    #[error("invalid token signature")]
+    BadSignature,
+    #[error("token expired")]
+    Expired,
+    #[error("wrong kind of token")]
+    WrongKind,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenKind {
+    Access,
+    Refresh,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Claims {
+    /// Wallet id of the user the token was issued to.
+    pub sub: String,
+    pub role: UserRole,
+    pub kind: TokenKind,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: &'static str,
+    /// Seconds until the access token expires.
+    pub expires_in: i64,
+}
+
+/// The key tokens are signed with.
+pub struct SessionKeys {
+    secret: Vec<u8>,
+}
+
+impl SessionKeys {
+    pub fn new(secret: &[u8]) -> Self {
+        SessionKeys { secret: secret.to_vec() }
+    }
+
+    /// `SESSION_SECRET`, or a random key when it is unset, in which case every
+    /// session ends when the server restarts.
+    pub fn from_env() -> Self {
+        match env::var("SESSION_SECRET") {
+            Ok(secret) if !secret.is_empty() => SessionKeys::new(secret.as_bytes()),
+            _ => {
+                log::warn!("SESSION_SECRET is not set; sessions will not survive a restart");
+                let mut secret = [0u8; 32];
+                rand::thread_rng().fill_bytes(&mut secret);
+                SessionKeys::new(&secret)
+            }
+        }
+    }
+
+    pub fn issue(&self, wallet_id: &str, role: UserRole, kind: TokenKind, now: i64) -> String {
+        let ttl = match kind {
+            TokenKind::Access => ACCESS_TOKEN_TTL,
+            TokenKind::Refresh => REFRESH_TOKEN_TTL,
+        };
+        let claims = Claims { sub: wallet_id.to_string(), role, kind, iat: now, exp: now + ttl };
+        let payload = BASE64.encode(serde_json::to_vec(&claims).expect("claims serialize"));
+        let signature = BASE64.encode(self.mac(payload.as_bytes()).finalize().into_bytes());
+        format!("{}.{}", payload, signature)
+    }
+
+    pub fn issue_pair(&self, wallet_id: &str, role: UserRole, now: i64) -> TokenPair {
+        TokenPair {
+            access_token: self.issue(wallet_id, role.clone(), TokenKind::Access, now),
+            refresh_token: self.issue(wallet_id, role, TokenKind::Refresh, now),
+            token_type: "Bearer",
+            expires_in: ACCESS_TOKEN_TTL,
+        }
+    }
+
+    pub fn verify(&self, token: &str, kind: TokenKind, now: i64) -> Result<Claims, TokenError> {
+        let (payload, signature) = token.split_once('.').ok_or(TokenError::Malformed)?;
+        let signature = BASE64.decode(signature).map_err(|_| TokenError::Malformed)?;
+        self.mac(payload.as_bytes()).verify_slice(&signature).map_err(|_| TokenError::BadSignature)?;
+        let claims: Claims = BASE64
+            .decode(payload)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or(TokenError::Malformed)?;
+        if claims.kind != kind {
+            return Err(TokenError::WrongKind);
+        }
+        if now >= claims.exp {
+            return Err(TokenError::Expired);
+        }
+        Ok(claims)
+    }
+
+    fn mac(&self, payload: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC takes keys of any length");
+        mac.update(payload);
+        mac
+    }
+}
+
+/// The user a request was authenticated as.
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub wallet_id: String,
+    pub role: UserRole,
+}
+
+impl Caller {
+    pub fn owns(&self, wallet_id: &str) -> bool {
+        self.wallet_id == wallet_id
+    }
+}
+
+impl FromRequest for Caller {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(req.extensions().get::<Caller>().cloned().ok_or_else(|| {
+            InternalError::from_response("missing session", HttpResponse::Unauthorized().json("Authentication required")).into()
+        }))
+    }
+}
+
+/// Checks the bearer token of a request, if any, and makes the [`Caller`] available
+/// to handlers. Requests without a token pass through; handlers that need one
+/// reject them by taking a `Caller`.
+pub async fn authenticate(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let token = req
+        .headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(str::to_string);
+    if let Some(token) = token {
+        let verified = match req.app_data::<web::Data<AppState>>() {
+            Some(data) => data.sessions.verify(&token, TokenKind::Access, chrono::Utc::now().timestamp()),
+            None => Err(TokenError::Malformed),
+        };
+        match verified {
+            Ok(claims) => {
+                req.extensions_mut().insert(Caller { wallet_id: claims.sub, role: claims.role });
+            }
+            Err(e) => {
+                let response = HttpResponse::Unauthorized().json(format!("Invalid session: {}", e));
+                return Ok(req.into_response(response).map_into_right_body());
+            }
+        }
+    }
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
@@ -0,0 +1,312 @@
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod tests {
+    use crate::api::payout::{may_pay_from, pay_in_batches};
+    use crate::models::{User, UserProfile, UserRole};
+    use crate::session::{Caller, SessionKeys, TokenError, TokenKind, ACCESS_TOKEN_TTL, REFRESH_TOKEN_TTL};
+
+    const NOW: i64 = 1_700_000_000;
+
+    #[test]
+    fn test_session_token_round_trip() {
+        let keys = SessionKeys::new(b"secret");
+        let pair = keys.issue_pair("alice", UserRole::Admin, NOW);
+        let claims = keys.verify(&pair.access_token, TokenKind::Access, NOW).unwrap();
+        assert_eq!((claims.sub.as_str(), claims.role, claims.kind), ("alice", UserRole::Admin, TokenKind::Access));
+        assert_eq!((claims.iat, claims.exp), (NOW, NOW + ACCESS_TOKEN_TTL));
+        let refresh = keys.verify(&pair.refresh_token, TokenKind::Refresh, NOW).unwrap();
+        assert_eq!(refresh.exp, NOW + REFRESH_TOKEN_TTL);
+    }
+
+    #[test]
+    fn test_session_token_signed_with_another_key_is_rejected() {
+        let token = SessionKeys::new(b"secret").issue("alice", UserRole::User, TokenKind::Access, NOW);
+        assert_eq!(SessionKeys::new(b"other").verify(&token, TokenKind::Access, NOW).unwrap_err(), TokenError::BadSignature);
+
+        // Claims swapped in from another token do not carry over its signature
+        let keys = SessionKeys::new(b"secret");
+        let admin = keys.issue("mallory", UserRole::Admin, TokenKind::Access, NOW);
+        let (forged_claims, _) = admin.split_once('.').unwrap();
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = format!("{}.{}", forged_claims, signature);
+        assert_eq!(keys.verify(&forged, TokenKind::Access, NOW).unwrap_err(), TokenError::BadSignature);
+    }
+
+    #[test]
+    fn test_session_token_of_the_wrong_kind_is_rejected() {
+        let keys = SessionKeys::new(b"secret");
+        let pair = keys.issue_pair("alice", UserRole::User, NOW);
+        assert_eq!(keys.verify(&pair.refresh_token, TokenKind::Access, NOW).unwrap_err(), TokenError::WrongKind);
+        assert_eq!(keys.verify(&pair.access_token, TokenKind::Refresh, NOW).unwrap_err(), TokenError::WrongKind);
+    }
+
+    #[test]
+    fn test_session_token_expires() {
+        let keys = SessionKeys::new(b"secret");
+        let token = keys.issue("alice", UserRole::User, TokenKind::Access, NOW);
+        assert!(keys.verify(&token, TokenKind::Access, NOW + ACCESS_TOKEN_TTL - 1).is_ok());
+        assert_eq!(keys.verify(&token, TokenKind::Access, NOW + ACCESS_TOKEN_TTL).unwrap_err(), TokenError::Expired);
+    }
+
+    #[test]
+    fn test_malformed_session_token_is_rejected() {
+        let keys = SessionKeys::new(b"secret");
+        for token in ["", "no-dot", "payload.!!!"] {
+            assert_eq!(keys.verify(token, TokenKind::Access, NOW).unwrap_err(), TokenError::Malformed, "{:?}", token);
+        }
+        // A token cut short loses its signature
+        let token = keys.issue("alice", UserRole::User, TokenKind::Access, NOW);
+        assert_eq!(keys.verify(&token[..token.len() - 3], TokenKind::Access, NOW).unwrap_err(), TokenError::BadSignature);
+    }
+
+    #[test]
+    fn test_caller_owns_only_its_wallet() {
+        let caller = Caller { wallet_id: "alice".to_string(), role: UserRole::User };
+        assert!(caller.owns("alice"));
+        assert!(!caller.owns("bob"));
+        assert!(!caller.owns("alice2"));
+        assert!(!caller.owns(""));
+    }
+
+    #[test]
+    fn test_profile_leaves_out_secrets() {
+        let user = User {
+            full_name: "Alice".to_string(),
+            wallet_id: "alice".to_string(),
+            encrypted_private_key: "11".repeat(32),
+            otp: Some("123456".to_string()),
+            otp_expiry: Some(NOW),
+            otp_ticket: Some("ticket".to_string()),
+            login_nonce: Some("nonce".to_string()),
+            login_nonce_expiry: Some(NOW),
+            step_up_otp: Some("654321".to_string()),
+            step_up_action: Some("mint".to_string()),
+            step_up_expiry: Some(NOW),
+            ..Default::default()
+        };
+        let profile = serde_json::to_value(UserProfile::from(user)).unwrap();
+        let fields = profile.as_object().unwrap();
+        assert_eq!(fields["wallet_id"], "alice");
+        for secret in ["encrypted_private_key", "otp", "otp_expiry", "otp_ticket", "login_nonce", "login_nonce_expiry", "step_up_otp", "step_up_action", "step_up_expiry"] {
+            assert!(!fields.contains_key(secret), "{}", secret);
+        }
+        let json = profile.to_string();
+        for value in ["1111", "123456", "ticket", "nonce", "654321"] {
+            assert!(!json.contains(value), "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_payout_is_sent_only_from_the_callers_or_the_organization_wallet() {
+        let caller = Caller { wallet_id: "admin".to_string(), role: UserRole::Admin };
+        assert!(may_pay_from(&caller, "admin", None));
+        assert!(!may_pay_from(&caller, "victim", None));
+        assert!(may_pay_from(&caller, "treasury", Some("treasury")));
+        assert!(!may_pay_from(&caller, "victim", Some("treasury")));
+    }
+
+    #[test]
+    fn test_payout_batches_spend_disjoint_coins_without_mining() {
+        use blockchain::{Blockchain, TxOutput, Wallet};
+        use std::collections::HashSet;
+
+        let mut chain = Blockchain::new();
+        let sender = Wallet::new();
+        let bob = Wallet::new().get_wallet_id();
+        for _ in 0..3 {
+            chain.mine_pending_transactions(&sender.get_wallet_id());
+        }
+        let height = chain.chain.len();
+
+        let batches = (0..3).map(|_| vec![TxOutput::new(1, bob.clone()), TxOutput::new(2, bob.clone())]).collect();
+        let results = pay_in_batches(&mut chain, &sender, batches, "Bulk payout");
+        assert!(results.iter().all(Result::is_ok), "{:?}", results);
+        assert_eq!(chain.chain.len(), height);
+        assert_eq!(chain.pending_transactions.len(), 3);
+        let inputs: HashSet<_> = chain.pending_transactions.iter().flat_map(|tx| tx.inputs.iter().map(|i| (i.tx_id.clone(), i.output_index))).collect();
+        assert_eq!(inputs.len(), 3);
+        chain.mine_pending_transactions("miner");
+        assert_eq!(chain.get_balance(&bob), 9);
+        assert_eq!(chain.get_balance(&sender.get_wallet_id()), 300 - 9);
+
+        // A batch that cannot be paid fails alone
+        let batches = vec![vec![TxOutput::new(1_000, bob.clone())], vec![TxOutput::new(1, bob.clone())]];
+        let results = pay_in_batches(&mut chain, &sender, batches, "Bulk payout");
+        assert!(results[0].as_ref().unwrap_err().contains("Insufficient"));
+        assert!(results[1].is_ok());
+
+        // Unconfirmed change is not spent: with a single coin only the first batch goes out
+        let carol = Wallet::new();
+        chain.mine_pending_transactions(&carol.get_wallet_id());
+        let batches = vec![vec![TxOutput::new(1, bob.clone())], vec![TxOutput::new(1, bob.clone())]];
+        let results = pay_in_batches(&mut chain, &carol, batches, "Bulk payout");
+        assert!(results[0].is_ok());
+        assert!(results[1].as_ref().unwrap_err().contains("Insufficient"));
+        assert_eq!(chain.chain.len(), height + 2);
+    }
+
+    async fn offline_state() -> actix_web::web::Data<crate::db::AppState> {
+        use std::sync::{Arc, Mutex};
+
+        // The client connects lazily; the handlers tested with it answer before any query
+        let client = mongodb::Client::with_uri_str("mongodb://127.0.0.1:1/?serverSelectionTimeoutMS=100").await.unwrap();
+        actix_web::web::Data::new(crate::db::AppState {
+            db: client.database("walx_test"),
+            blockchain: Arc::new(Mutex::new(blockchain::Blockchain::new())),
+            sessions: Arc::new(SessionKeys::new(b"secret")),
+            minter: None,
+            organization_wallet: None,
+        })
+    }
+
+    #[actix_web::test]
+    async fn test_signing_endpoints_refuse_other_peoples_wallets() {
+        use actix_web::middleware::from_fn;
+        use actix_web::{test, web, App};
+        use serde_json::json;
+
+        let state = offline_state().await;
+        let token = state.sessions.issue("mallory", UserRole::User, TokenKind::Access, chrono::Utc::now().timestamp());
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .service(web::scope("/api").wrap(from_fn(crate::session::authenticate)).configure(crate::api::config)),
+        )
+        .await;
+
+        let hash_lock = "00".repeat(32);
+        let requests = [
+            ("/api/escrow/create", json!({ "buyer_wallet_id": "victim", "seller_wallet_id": "mallory", "amount": 10, "hash_lock": hash_lock, "timeout_blocks": 5 })),
+            ("/api/multisig/create", json!({ "creator_wallet_id": "victim", "name": "m", "threshold": 1, "member_wallet_ids": ["victim", "mallory"] })),
+            ("/api/multisig/msaddress/propose", json!({ "proposer_wallet_id": "victim", "receiver_wallet_id": "mallory", "amount": 10 })),
+            ("/api/multisig/proposals/65f000000000000000000000/approve", json!({ "wallet_id": "victim" })),
+            ("/api/assets/issue", json!({ "issuer_wallet_id": "victim", "name": "Gold", "supply": 10 })),
+        ];
+        for (uri, body) in requests {
+            let req = test::TestRequest::post().uri(uri).insert_header(("Authorization", format!("Bearer {}", token))).set_json(body).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), 403, "{}", uri);
+        }
+        let req = test::TestRequest::post()
+            .uri(&format!("/api/notary/anchor?wallet_id=victim&sha256={}", hash_lock))
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 403);
+
+        // Without a session they are not reached at all
+        let req = test::TestRequest::post().uri("/api/assets/issue").set_json(json!({ "issuer_wallet_id": "victim", "name": "Gold", "supply": 10 })).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401);
+    }
+
+    #[actix_web::test]
+    async fn test_otp_is_only_tried_with_the_login_ticket() {
+        use actix_web::middleware::from_fn;
+        use actix_web::{test, web, App};
+        use serde_json::json;
+
+        let state = offline_state().await;
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .service(web::scope("/api").wrap(from_fn(crate::session::authenticate)).configure(crate::api::config)),
+        )
+        .await;
+
+        // Knowing the wallet id is not enough to make an attempt, and so to burn the OTP
+        let req = test::TestRequest::post().uri("/api/auth/verify-otp").set_json(json!({ "wallet_id": "victim", "otp": "123456" })).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_block_templates_are_handed_to_signed_in_miners() {
+        use actix_web::middleware::from_fn;
+        use actix_web::{test, web, App};
+
+        let state = offline_state().await;
+        let token = state.sessions.issue("miner", UserRole::User, TokenKind::Access, chrono::Utc::now().timestamp());
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .service(web::scope("/api").wrap(from_fn(crate::session::authenticate)).configure(crate::api::config)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/api/blockchain/template").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401);
+        assert!(state.blockchain.lock().unwrap().templates.is_empty());
+
+        let req = test::TestRequest::get().uri("/api/blockchain/template").insert_header(("Authorization", format!("Bearer {}", token))).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+        assert_eq!(state.blockchain.lock().unwrap().templates["miner"].len(), 1);
+    }
+
+    #[test]
+    fn test_escrow_is_settled_by_the_seller_or_the_buyer() {
+        use crate::api::escrow::settling_party;
+        use crate::models::Escrow;
+
+        let escrow = Escrow {
+            id: None,
+            buyer_wallet_id: "buyer".to_string(),
+            seller_wallet_id: "seller".to_string(),
+            amount: 10,
+            hash_lock: String::new(),
+            timeout: 5,
+            funding_tx_id: String::new(),
+            output_index: 0,
+            status: "funded".to_string(),
+            settlement_tx_id: None,
+            note: None,
+            created_at: 0,
+        };
+        assert_eq!(settling_party(&escrow, "claimed"), "seller");
+        assert_eq!(settling_party(&escrow, "refunded"), "buyer");
+    }
+
+    #[test]
+    fn test_approval_names_the_keys_it_adds() {
+        use crate::api::multisig::added_signers;
+        use blockchain::{Blockchain, MultisigPolicy, Wallet};
+
+        let (alice, bob, carol) = (Wallet::new(), Wallet::new(), Wallet::new());
+        let policy = MultisigPolicy::new(2, vec![alice.get_public_key_hex(), bob.get_public_key_hex(), carol.get_public_key_hex()]).unwrap();
+        let mut chain = Blockchain::new();
+        chain.mine_pending_transactions(&policy.address());
+        let mut proposal = chain.create_multisig_psbt(&policy, alice.get_wallet_id(), 10, None).unwrap();
+        proposal.sign(&alice);
+
+        let mut approval = proposal.clone();
+        approval.sign(&bob);
+        assert_eq!(added_signers(&proposal, &approval).into_iter().collect::<Vec<_>>(), vec![bob.get_public_key_hex()]);
+        // A PSBT that also carries Carol's signature names her key too, so Bob's approval is refused
+        approval.sign(&carol);
+        assert_eq!(added_signers(&proposal, &approval).len(), 2);
+        assert!(added_signers(&proposal, &proposal).is_empty());
+    }
+
+    #[test]
+    fn test_registered_key_loads_as_a_signing_wallet() {
+        use blockchain::transaction::verify_hex_signature;
+        use blockchain::Wallet;
+
+        // Stored as `register` stores it, loaded as `load_signer` and the send paths load it
+        let wallet = Wallet::new();
+        let user = User {
+            wallet_id: wallet.get_wallet_id(),
+            public_key: wallet.get_public_key_hex(),
+            encrypted_private_key: User::stored_key(&wallet),
+            ..Default::default()
+        };
+        assert_eq!(user.encrypted_private_key.len(), 128);
+        let signer = user.custodial_wallet().unwrap();
+        assert_eq!(signer.get_wallet_id(), user.wallet_id);
+        assert!(verify_hex_signature(&user.public_key, b"tx", &signer.sign_transaction("tx")));
+
+        // A bare secret works too; a key that is not the user's does not
+        let secret = User { encrypted_private_key: user.encrypted_private_key[..64].to_string(), ..user.clone() };
+        assert_eq!(secret.custodial_wallet().unwrap().get_wallet_id(), user.wallet_id);
+        let other = User { encrypted_private_key: User::stored_key(&Wallet::new()), ..user.clone() };
+        assert!(other.custodial_wallet().is_err());
+        let spliced = User { encrypted_private_key: format!("{}{}", &other.encrypted_private_key[..64], &user.encrypted_private_key[64..]), ..user };
+        assert!(spliced.custodial_wallet().is_err());
+    }
+}
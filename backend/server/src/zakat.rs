@@ -4,7 +4,6 @@ use actix_web::web;
 use std::time::Duration;
 use tokio::time;
 use futures::StreamExt;
-use mongodb::bson::doc;
 
 pub async fn start_zakat_scheduler(data: web::Data<AppState>) {
     let mut interval = time::interval(Duration::from_secs(30 * 24 * 60 * 60)); // 30 days
@@ -51,7 +50,7 @@ pub async fn start_zakat_scheduler(data: web::Data<AppState>) {
             };
             
             for (u, deduction) in deductions {
-                if let Ok(wallet) = blockchain::Wallet::from_private_key(&u.encrypted_private_key) {
+                if let Ok(wallet) = u.custodial_wallet() {
                     // Note: In a real app, we should re-check balance here to be safe
                     // But for now, we assume it hasn't changed drastically in milliseconds
                     if let Ok(tx) = chain.create_transaction(
@@ -69,8 +68,10 @@ pub async fn start_zakat_scheduler(data: web::Data<AppState>) {
             }
 
             if transactions_added {
-                chain.mine_pending_transactions("SYSTEM_MINER");
-                log::info!("Mined Zakat Block");
+                match chain.produce_block("SYSTEM_MINER") {
+                    Ok(()) => log::info!("Mined Zakat Block"),
+                    Err(e) => log::warn!("Zakat block not produced: {}", e),
+                }
             }
         }
     }
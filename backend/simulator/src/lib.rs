@@ -0,0 +1,242 @@
+//! Deterministic multi-node simulation for consensus testing.
+//!
+//! A [`Simulation`] runs several in-process [`Blockchain`] nodes that share one
+//! virtual clock and talk over a simulated [`Network`] that delays, drops,
+//! reorders and partitions messages. Every random choice, from wallet keys to
+//! message delays, comes from the seed in [`SimConfig`], so a run with the same
+//! seed and [`Scenario`] is repeated exactly, down to block hashes.
+//!
+//! Nothing here runs in real time: the clock jumps straight to the next message
+//! or scripted step, so a day of network activity runs in milliseconds.
+
+pub mod network;
+pub mod node;
+pub mod scenario;
+
+mod tests;
+
+use std::sync::Arc;
+
+use blockchain::{Blockchain, Clock, Environment, ManualClock, ProofOfWork, Wallet};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use thiserror::Error;
+
+pub use network::{Message, Network, NetworkConfig, NetworkStats};
+pub use node::{Node, Outbound};
+pub use scenario::{Scenario, Step};
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum SimError {
+    #[error("nodes disagree on the tip (height, hash): {0:?}")]
+    Diverged(Vec<(u64, String)>),
+    #[error("node {node} holds {actual} coins but {expected} were mined")]
+    SupplyMismatch { node: usize, expected: u64, actual: u64 },
+    #[error("node {0} holds an invalid chain")]
+    InvalidChain(usize),
+    #[error("no node {0}")]
+    UnknownNode(usize),
+    #[error("step at {time}s failed: {reason}")]
+    StepFailed { time: i64, reason: String },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SimConfig {
+    pub nodes: usize,
+    pub seed: u64,
+    /// Proof-of-work difficulty of every node.
+    pub difficulty: usize,
+    pub network: NetworkConfig,
+    /// Seconds between tip announcements.
+    pub sync_interval: i64,
+    /// Unix time the virtual clock starts at.
+    pub start_time: i64,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            nodes: 4,
+            seed: 0,
+            difficulty: 1,
+            network: NetworkConfig::default(),
+            sync_interval: 10,
+            start_time: 1_700_000_000,
+        }
+    }
+}
+
+pub struct Simulation {
+    pub nodes: Vec<Node>,
+    pub network: Network,
+    clock: Arc<ManualClock>,
+    rng: StdRng,
+    start_time: i64,
+    sync_interval: i64,
+    next_sync: i64,
+}
+
+impl Simulation {
+    pub fn new(config: SimConfig) -> Self {
+        let clock = Arc::new(ManualClock::new(config.start_time));
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        let nodes = (0..config.nodes)
+            .map(|id| {
+                let env = Environment::deterministic(clock.clone(), rng.gen());
+                let chain = Blockchain::with_environment(Box::new(ProofOfWork::new(config.difficulty)), env);
+                let mut secret = [0u8; 32];
+                rng.fill(&mut secret);
+                let wallet = Wallet::from_private_key(&hex::encode(secret)).expect("any 32 bytes are an ed25519 secret key");
+                Node::new(id, chain, wallet)
+            })
+            .collect();
+        Simulation {
+            nodes,
+            network: Network::new(config.network),
+            clock,
+            rng,
+            start_time: config.start_time,
+            sync_interval: config.sync_interval.max(1),
+            next_sync: config.sync_interval.max(1),
+        }
+    }
+
+    /// Seconds since the simulation started.
+    pub fn now(&self) -> i64 {
+        self.clock.now() - self.start_time
+    }
+
+    pub fn node(&self, id: usize) -> Result<&Node, SimError> {
+        self.nodes.get(id).ok_or(SimError::UnknownNode(id))
+    }
+
+    /// Runs the steps of `scenario` at their times, delivering messages in between.
+    /// Steps scheduled before the current time run immediately.
+    pub fn run(&mut self, scenario: &Scenario) -> Result<(), SimError> {
+        for (time, step) in scenario.steps() {
+            self.run_until(*time);
+            self.apply(step).map_err(|reason| SimError::StepFailed { time: *time, reason })?;
+        }
+        Ok(())
+    }
+
+    /// Keeps delivering messages until every node has the same tip and nothing
+    /// is in flight, for at most `timeout` seconds.
+    pub fn settle(&mut self, timeout: i64) -> Result<(), SimError> {
+        let deadline = self.now() + timeout;
+        while self.now() < deadline {
+            if self.network.is_idle() && self.check_converged().is_ok() {
+                return Ok(());
+            }
+            let next = self.network.next_delivery().map(|at| at - self.start_time).unwrap_or(deadline);
+            self.run_until(next.min(self.next_sync).min(deadline));
+        }
+        self.check_converged()
+    }
+
+    /// Delivers every message and tip announcement due up to `time`, then moves the clock there.
+    pub fn run_until(&mut self, time: i64) {
+        loop {
+            self.deliver_due();
+            let next = self.network.next_delivery().map(|at| at - self.start_time).unwrap_or(i64::MAX);
+            let next = next.min(self.next_sync);
+            if next > time {
+                break;
+            }
+            self.clock.set(self.start_time + next.max(self.now()));
+        }
+        if time > self.now() {
+            self.clock.set(self.start_time + time);
+        }
+    }
+
+    fn deliver_due(&mut self) {
+        if self.now() >= self.next_sync {
+            for id in 0..self.nodes.len() {
+                let announcement = self.nodes[id].announce_tip();
+                self.dispatch(id, announcement);
+            }
+            self.next_sync = self.now() + self.sync_interval;
+        }
+        while let Some(envelope) = self.network.deliver(self.clock.now()) {
+            for outbound in self.nodes[envelope.to].receive(envelope.from, envelope.message) {
+                self.dispatch(envelope.to, outbound);
+            }
+        }
+    }
+
+    fn dispatch(&mut self, from: usize, outbound: Outbound) {
+        let now = self.clock.now();
+        match outbound {
+            Outbound::To(to, message) => self.network.send(now, from, to, message, &mut self.rng),
+            Outbound::Broadcast(message) => {
+                for to in (0..self.nodes.len()).filter(|to| *to != from) {
+                    self.network.send(now, from, to, message.clone(), &mut self.rng);
+                }
+            }
+        }
+    }
+
+    /// Carries out one scripted step now.
+    pub fn apply(&mut self, step: &Step) -> Result<(), String> {
+        match step {
+            Step::Mine(node) => {
+                let outbound = self.node_mut(*node)?.mine()?;
+                self.dispatch(*node, outbound);
+            }
+            Step::Pay { from, to, amount } => {
+                let receiver = self.node_mut(*to)?.wallet_id();
+                let outbound = self.node_mut(*from)?.pay(receiver, *amount)?;
+                self.dispatch(*from, outbound);
+            }
+            Step::Partition(groups) => self.network.partition(groups),
+            Step::Heal => self.network.heal(),
+            Step::SetDropRate(rate) => self.network.config.drop_rate = *rate,
+            Step::SetLatency { min, max } => {
+                self.network.config.min_latency = *min;
+                self.network.config.max_latency = *max;
+            }
+        }
+        Ok(())
+    }
+
+    fn node_mut(&mut self, id: usize) -> Result<&mut Node, String> {
+        self.nodes.get_mut(id).ok_or_else(|| SimError::UnknownNode(id).to_string())
+    }
+
+    /// Height and hash of every node's tip.
+    pub fn tips(&self) -> Vec<(u64, String)> {
+        self.nodes.iter().map(|node| (node.height(), node.tip_hash().to_string())).collect()
+    }
+
+    pub fn check_converged(&self) -> Result<(), SimError> {
+        let tips = self.tips();
+        if tips.windows(2).all(|pair| pair[0] == pair[1]) {
+            Ok(())
+        } else {
+            Err(SimError::Diverged(tips))
+        }
+    }
+
+    /// Checks every node's chain links up and that its unspent coins add up to
+    /// exactly the rewards of the blocks on it: no coin created or lost by a reorg.
+    pub fn check_conservation(&self) -> Result<(), SimError> {
+        for node in &self.nodes {
+            if !node.chain.is_chain_valid() {
+                return Err(SimError::InvalidChain(node.id));
+            }
+            let expected: u64 = (1..=node.height()).map(|height| node.chain.params.rules_at(height).mining_reward).sum();
+            let actual: u64 = node.chain.utxos.values().filter(|output| output.asset.is_none()).map(|output| output.amount).sum();
+            if actual != expected {
+                return Err(SimError::SupplyMismatch { node: node.id, expected, actual });
+            }
+        }
+        Ok(())
+    }
+
+    /// Convergence and conservation together, for the end of a scenario.
+    pub fn check_invariants(&self) -> Result<(), SimError> {
+        self.check_converged()?;
+        self.check_conservation()
+    }
+}
@@ -0,0 +1,145 @@
+//! The simulated network: point-to-point messages with a random delay that may
+//! be dropped, or cut off by a partition. Messages sent at the same time arrive
+//! in an order set by their delays, so they can overtake each other.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+
+use rand::rngs::StdRng;
+use rand::Rng;
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    /// A block in its wire encoding, as produced by [`blockchain::Encode::encode`].
+    Block(Vec<u8>),
+    /// A transaction in its wire encoding.
+    Transaction(Vec<u8>),
+    /// Asks for a block by hash, to fill in a missing parent.
+    GetBlock(String),
+    /// Periodic announcement of the sender's tip, so nodes that missed blocks catch up.
+    Tip { hash: String, height: u64 },
+}
+
+/// Delay and loss of the links between nodes, in seconds of virtual time.
+#[derive(Debug, Clone, Copy)]
+pub struct NetworkConfig {
+    pub min_latency: i64,
+    pub max_latency: i64,
+    /// Chance of a message being lost, from 0.0 to 1.0.
+    pub drop_rate: f64,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig { min_latency: 1, max_latency: 3, drop_rate: 0.0 }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    pub deliver_at: i64,
+    pub from: usize,
+    pub to: usize,
+    pub message: Message,
+    seq: u64,
+}
+
+// Envelopes are ordered by delivery time, then by send order.
+impl PartialEq for Envelope {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Envelope {}
+
+impl PartialOrd for Envelope {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Envelope {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.deliver_at, self.seq).cmp(&(other.deliver_at, other.seq))
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkStats {
+    pub sent: u64,
+    pub delivered: u64,
+    pub dropped: u64,
+}
+
+pub struct Network {
+    pub config: NetworkConfig,
+    in_flight: BinaryHeap<Reverse<Envelope>>,
+    /// Node -> partition group while partitioned. Nodes talk only within their group.
+    groups: Option<HashMap<usize, usize>>,
+    next_seq: u64,
+    stats: NetworkStats,
+}
+
+impl Network {
+    pub fn new(config: NetworkConfig) -> Self {
+        Network { config, in_flight: BinaryHeap::new(), groups: None, next_seq: 0, stats: NetworkStats::default() }
+    }
+
+    /// Queues a message, unless it is lost or the nodes are partitioned.
+    pub fn send(&mut self, now: i64, from: usize, to: usize, message: Message, rng: &mut StdRng) {
+        self.stats.sent += 1;
+        if !self.connected(from, to) || rng.gen_bool(self.config.drop_rate.clamp(0.0, 1.0)) {
+            self.stats.dropped += 1;
+            return;
+        }
+        let latency = rng.gen_range(self.config.min_latency, self.config.max_latency.max(self.config.min_latency) + 1);
+        self.in_flight.push(Reverse(Envelope { deliver_at: now + latency, from, to, message, seq: self.next_seq }));
+        self.next_seq += 1;
+    }
+
+    /// The next message due by `now`. A message whose link was partitioned while
+    /// it was in flight is lost.
+    pub fn deliver(&mut self, now: i64) -> Option<Envelope> {
+        while self.in_flight.peek().is_some_and(|Reverse(envelope)| envelope.deliver_at <= now) {
+            let Reverse(envelope) = self.in_flight.pop()?;
+            if self.connected(envelope.from, envelope.to) {
+                self.stats.delivered += 1;
+                return Some(envelope);
+            }
+            self.stats.dropped += 1;
+        }
+        None
+    }
+
+    /// When the next message is due.
+    pub fn next_delivery(&self) -> Option<i64> {
+        self.in_flight.peek().map(|Reverse(envelope)| envelope.deliver_at)
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.in_flight.is_empty()
+    }
+
+    /// Splits the nodes into `groups` that cannot reach each other. A node in no
+    /// group is cut off from everyone.
+    pub fn partition(&mut self, groups: &[Vec<usize>]) {
+        let groups = groups.iter().enumerate().flat_map(|(group, nodes)| nodes.iter().map(move |node| (*node, group)));
+        self.groups = Some(groups.collect());
+    }
+
+    pub fn heal(&mut self) {
+        self.groups = None;
+    }
+
+    pub fn connected(&self, a: usize, b: usize) -> bool {
+        match &self.groups {
+            Some(groups) => groups.get(&a).is_some() && groups.get(&a) == groups.get(&b),
+            None => true,
+        }
+    }
+
+    pub fn stats(&self) -> NetworkStats {
+        self.stats
+    }
+}
@@ -0,0 +1,118 @@
+//! A simulated full node: a [`Blockchain`] and a mining wallet, reacting to
+//! messages from its peers.
+
+use blockchain::{Block, BlockStatus, Blockchain, Decode, Encode, Transaction, Wallet};
+
+use crate::network::Message;
+
+/// Where a node wants a message to go.
+#[derive(Debug, Clone)]
+pub enum Outbound {
+    To(usize, Message),
+    /// To every other node.
+    Broadcast(Message),
+}
+
+pub struct Node {
+    pub id: usize,
+    pub chain: Blockchain,
+    pub wallet: Wallet,
+}
+
+impl Node {
+    pub fn new(id: usize, chain: Blockchain, wallet: Wallet) -> Self {
+        Node { id, chain, wallet }
+    }
+
+    pub fn wallet_id(&self) -> String {
+        self.wallet.get_wallet_id()
+    }
+
+    pub fn tip_hash(&self) -> &str {
+        &self.chain.get_latest_block().hash
+    }
+
+    pub fn height(&self) -> u64 {
+        self.chain.get_latest_block().index
+    }
+
+    /// Mines a block on the node's tip and announces it.
+    pub fn mine(&mut self) -> Result<Outbound, String> {
+        self.chain.produce_block(&self.wallet.get_wallet_id())?;
+        Ok(Outbound::Broadcast(Message::Block(self.chain.get_latest_block().encode())))
+    }
+
+    /// Pays `amount` from the node's wallet into its mempool and announces the transaction.
+    pub fn pay(&mut self, receiver_id: String, amount: u64) -> Result<Outbound, String> {
+        let tx = self.chain.create_transaction(&self.wallet, receiver_id, amount, None)?;
+        if !self.chain.add_transaction(tx.clone()) {
+            return Err(format!("Transaction {} rejected by its own node", tx.id));
+        }
+        Ok(Outbound::Broadcast(Message::Transaction(tx.encode())))
+    }
+
+    pub fn announce_tip(&self) -> Outbound {
+        Outbound::Broadcast(Message::Tip { hash: self.tip_hash().to_string(), height: self.height() })
+    }
+
+    /// Handles a message from `from`. New blocks and transactions are relayed as
+    /// received; missing parents are asked for from whoever sent the child. Bytes
+    /// that do not decode are dropped.
+    pub fn receive(&mut self, from: usize, message: Message) -> Vec<Outbound> {
+        match message {
+            Message::Block(bytes) => {
+                let block = match Block::decode(&bytes) {
+                    Ok(block) => block,
+                    Err(e) => {
+                        log::debug!("node {}: undecodable block from {}: {}", self.id, from, e);
+                        return Vec::new();
+                    }
+                };
+                let (hash, index, previous_hash) = (block.hash.clone(), block.index, block.previous_hash.clone());
+                match self.chain.submit_block(block) {
+                    Ok(BlockStatus::Duplicate) => Vec::new(),
+                    Ok(BlockStatus::Orphan) => vec![Outbound::To(from, Message::GetBlock(previous_hash))],
+                    Ok(status) => {
+                        log::debug!("node {}: block {} at height {}: {:?}", self.id, hash, index, status);
+                        vec![Outbound::Broadcast(Message::Block(bytes))]
+                    }
+                    Err(e) => {
+                        log::debug!("node {}: block {} rejected: {}", self.id, hash, e);
+                        Vec::new()
+                    }
+                }
+            }
+            Message::Transaction(bytes) => {
+                let tx = match Transaction::decode(&bytes) {
+                    Ok(tx) => tx,
+                    Err(e) => {
+                        log::debug!("node {}: undecodable transaction from {}: {}", self.id, from, e);
+                        return Vec::new();
+                    }
+                };
+                if self.chain.pending_transactions.iter().any(|pending| pending.id == tx.id) || !self.chain.add_transaction(tx) {
+                    return Vec::new();
+                }
+                vec![Outbound::Broadcast(Message::Transaction(bytes))]
+            }
+            Message::GetBlock(hash) => match self.chain.block_by_hash(&hash) {
+                Some(block) => vec![Outbound::To(from, Message::Block(block.encode()))],
+                None => Vec::new(),
+            },
+            Message::Tip { hash, height } => {
+                if height <= self.height() {
+                    return Vec::new();
+                }
+                // Ask for the first block of the announced branch that is missing here
+                let mut wanted = hash;
+                while let Some(block) = self.chain.side_blocks.get(&wanted) {
+                    wanted = block.previous_hash.clone();
+                }
+                match self.chain.block_by_hash(&wanted) {
+                    Some(_) => Vec::new(),
+                    None => vec![Outbound::To(from, Message::GetBlock(wanted))],
+                }
+            }
+        }
+    }
+}
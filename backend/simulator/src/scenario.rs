@@ -0,0 +1,53 @@
+//! Scripts of timed events to run a simulation through.
+
+/// One scripted event.
+#[derive(Debug, Clone)]
+pub enum Step {
+    /// The node mines a block on its tip.
+    Mine(usize),
+    /// The node pays `amount` to the wallet of node `to`.
+    Pay { from: usize, to: usize, amount: u64 },
+    /// Splits the network into groups of nodes that cannot reach each other.
+    Partition(Vec<Vec<usize>>),
+    Heal,
+    SetDropRate(f64),
+    SetLatency { min: i64, max: i64 },
+}
+
+/// Steps keyed by the virtual time, in seconds from the start, they happen at.
+/// Steps at the same time run in the order they were added.
+#[derive(Debug, Clone, Default)]
+pub struct Scenario {
+    steps: Vec<(i64, Step)>,
+}
+
+impl Scenario {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn at(mut self, time: i64, step: Step) -> Self {
+        let position = self.steps.partition_point(|(at, _)| *at <= time);
+        self.steps.insert(position, (time, step));
+        self
+    }
+
+    /// `node` mines every `interval` seconds from `start` until before `end`.
+    pub fn mine_every(mut self, node: usize, start: i64, end: i64, interval: i64) -> Self {
+        let mut time = start;
+        while time < end {
+            self = self.at(time, Step::Mine(node));
+            time += interval.max(1);
+        }
+        self
+    }
+
+    pub fn steps(&self) -> &[(i64, Step)] {
+        &self.steps
+    }
+
+    /// Time of the last step.
+    pub fn duration(&self) -> i64 {
+        self.steps.last().map(|(time, _)| *time).unwrap_or_default()
+    }
+}
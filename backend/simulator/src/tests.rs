@@ -0,0 +1,93 @@
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod tests {
+    #[test]
+    fn test_partition_heals_onto_the_longest_branch() {
+        use crate::{Scenario, SimConfig, Simulation, Step};
+
+        let mut sim = Simulation::new(SimConfig { nodes: 4, seed: 7, ..SimConfig::default() });
+        let scenario = Scenario::new()
+            .at(0, Step::Mine(0))
+            .at(10, Step::Partition(vec![vec![0, 1], vec![2, 3]]))
+            // Only the minority side sees the payment and confirms it
+            .at(11, Step::Pay { from: 0, to: 1, amount: 30 })
+            .at(15, Step::Mine(1))
+            .at(16, Step::Mine(2))
+            .at(20, Step::Mine(3))
+            .at(24, Step::Mine(2))
+            .at(30, Step::Heal);
+        sim.run(&scenario).unwrap();
+        sim.settle(120).unwrap();
+        sim.check_invariants().unwrap();
+        assert_eq!(sim.node(0).unwrap().height(), 4);
+
+        // The payment fell out with the losing branch and waits in the mempool again
+        let receiver = sim.node(1).unwrap().wallet_id();
+        assert!(sim.nodes.iter().all(|node| node.chain.get_balance(&receiver) == 0));
+        assert_eq!(sim.node(0).unwrap().chain.pending_transactions.len(), 1);
+
+        let now = sim.now();
+        sim.run(&Scenario::new().at(now + 1, Step::Mine(0))).unwrap();
+        sim.settle(60).unwrap();
+        sim.check_invariants().unwrap();
+        assert!(sim.nodes.iter().all(|node| node.chain.get_balance(&receiver) == 30));
+    }
+
+    #[test]
+    fn test_blocks_travel_as_their_encoding() {
+        use crate::{Message, Outbound, SimConfig, Simulation};
+
+        let mut sim = Simulation::new(SimConfig { nodes: 2, ..SimConfig::default() });
+        let bytes = match sim.nodes[0].mine().unwrap() {
+            Outbound::Broadcast(Message::Block(bytes)) => bytes,
+            other => panic!("unexpected announcement {:?}", other),
+        };
+        assert!(sim.nodes[1].receive(0, Message::Block(bytes[..bytes.len() - 1].to_vec())).is_empty());
+        assert_eq!(sim.nodes[1].height(), 0);
+
+        let relayed = sim.nodes[1].receive(0, Message::Block(bytes.clone()));
+        assert!(matches!(&relayed[..], [Outbound::Broadcast(Message::Block(relay))] if *relay == bytes));
+        assert_eq!(sim.nodes[1].tip_hash(), sim.nodes[0].tip_hash());
+    }
+
+    #[test]
+    fn test_lossy_reordering_network_converges() {
+        use crate::{NetworkConfig, Scenario, SimConfig, Simulation, Step};
+
+        let network = NetworkConfig { min_latency: 1, max_latency: 8, drop_rate: 0.25 };
+        let mut sim = Simulation::new(SimConfig { nodes: 5, seed: 42, network, ..SimConfig::default() });
+        let mut scenario = Scenario::new();
+        for node in 0..5 {
+            scenario = scenario.mine_every(node, node as i64 * 3, 200, 15);
+        }
+        scenario = scenario
+            .at(60, Step::Pay { from: 0, to: 4, amount: 10 })
+            .at(90, Step::Pay { from: 1, to: 3, amount: 25 })
+            // A last block after a quiet spell breaks any tie left between branches
+            .at(300, Step::Mine(0));
+        sim.run(&scenario).unwrap();
+        sim.settle(600).unwrap();
+        sim.check_invariants().unwrap();
+        assert!(sim.network.stats().dropped > 0);
+    }
+
+    #[test]
+    fn test_same_seed_replays_the_same_run() {
+        use crate::{NetworkConfig, Scenario, SimConfig, Simulation, Step};
+
+        let run = |seed| {
+            let network = NetworkConfig { min_latency: 1, max_latency: 5, drop_rate: 0.1 };
+            let mut sim = Simulation::new(SimConfig { nodes: 3, seed, network, ..SimConfig::default() });
+            let scenario = Scenario::new()
+                .mine_every(0, 0, 60, 7)
+                .mine_every(1, 2, 60, 9)
+                .at(30, Step::Pay { from: 0, to: 2, amount: 5 })
+                .at(100, Step::Mine(2));
+            sim.run(&scenario).unwrap();
+            sim.settle(300).unwrap();
+            (sim.tips(), sim.network.stats())
+        };
+        assert_eq!(run(3), run(3));
+        assert_ne!(run(3).0, run(4).0);
+    }
+}
@@ -8,4 +8,42 @@ const api = axios.create({
   },
 });
 
+export const clearSession = () => {
+  localStorage.removeItem('wallet_id');
+  localStorage.removeItem('access_token');
+  localStorage.removeItem('refresh_token');
+};
+
+// Send the session token with every request
+api.interceptors.request.use((config) => {
+  const token = localStorage.getItem('access_token');
+  if (token) {
+    config.headers.Authorization = `Bearer ${token}`;
+  }
+  return config;
+});
+
+// When the access token has expired, trade the refresh token for a new pair and retry once
+api.interceptors.response.use(
+  (response) => response,
+  async (error) => {
+    const original = error.config;
+    const refreshToken = localStorage.getItem('refresh_token');
+    if (error.response?.status !== 401 || !refreshToken || original._retried || original.url === '/auth/refresh') {
+      return Promise.reject(error);
+    }
+    original._retried = true;
+    try {
+      const res = await api.post('/auth/refresh', { refresh_token: refreshToken });
+      localStorage.setItem('access_token', res.data.access_token);
+      localStorage.setItem('refresh_token', res.data.refresh_token);
+      return api(original);
+    } catch (refreshError) {
+      clearSession();
+      window.location.assign('/login');
+      return Promise.reject(refreshError);
+    }
+  },
+);
+
 export default api;
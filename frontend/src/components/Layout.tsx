@@ -1,6 +1,7 @@
 // src/components/Layout.tsx
 import React from 'react';
 import { Outlet, Link, useLocation, useNavigate } from 'react-router-dom';
+import { clearSession } from '../api';
 import { LayoutDashboard, Send, History, Box, FileText, LogOut, User, BarChart, Shield, Menu, X } from 'lucide-react';
 
 const Layout: React.FC = () => {
@@ -9,7 +10,7 @@ const Layout: React.FC = () => {
     const [sidebarOpen, setSidebarOpen] = React.useState(false);
 
     const handleLogout = () => {
-        localStorage.removeItem('wallet_id');
+        clearSession();
         localStorage.removeItem('private_key');
         navigate('/login');
     };
@@ -0,0 +1,28 @@
+// Ed25519 signing in the browser, so private keys never leave it
+
+// PKCS#8 wrapping of a raw 32-byte Ed25519 seed (RFC 8410)
+const PKCS8_PREFIX = [0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20];
+
+const fromHex = (hex: string): Uint8Array => {
+  const clean = hex.trim();
+  if (!/^([0-9a-fA-F]{2})*$/.test(clean)) {
+    throw new Error('Private key must be hex');
+  }
+  return new Uint8Array(clean.match(/../g)?.map((byte) => parseInt(byte, 16)) ?? []);
+};
+
+const toHex = (bytes: ArrayBuffer): string =>
+  Array.from(new Uint8Array(bytes), (byte) => byte.toString(16).padStart(2, '0')).join('');
+
+// Signs `message` with a hex private key: the 32-byte seed, or the 64-byte
+// seed-and-public-key form the server hands out at registration
+export const signMessage = async (privateKeyHex: string, message: string): Promise<string> => {
+  const key = fromHex(privateKeyHex);
+  if (key.length !== 32 && key.length !== 64) {
+    throw new Error('Private key must be 32 or 64 bytes');
+  }
+  const pkcs8 = new Uint8Array([...PKCS8_PREFIX, ...key.slice(0, 32)]);
+  const signingKey = await crypto.subtle.importKey('pkcs8', pkcs8, { name: 'Ed25519' }, false, ['sign']);
+  const signature = await crypto.subtle.sign({ name: 'Ed25519' }, signingKey, new TextEncoder().encode(message));
+  return toHex(signature);
+};
@@ -248,7 +248,7 @@ const Dashboard: React.FC = () => {
                                 <div className="text-right">
                                     <p className={`text-sm font-semibold ${tx.sender_wallet_id === walletId ? 'text-white' : 'text-primary-400'
                                         }`}>
-                                        {tx.sender_wallet_id === walletId ? '-' : '+'}{tx.amount} COINS
+                                        {tx.sender_wallet_id === walletId ? '-' : '+'}{tx.wallet_amount ?? tx.amount} COINS
                                     </p>
                                 </div>
                             </div>
@@ -3,6 +3,12 @@ import React, { useEffect, useState } from 'react';
 import api from '../api';
 import { ArrowUpRight, ArrowDownLeft, Calendar } from 'lucide-react';
 
+interface TxOutput {
+    receiver_wallet_id: string;
+    amount: number;
+    memo?: string;
+}
+
 interface Transaction {
     id: string;
     sender_wallet_id: string;
@@ -10,6 +16,10 @@ interface Transaction {
     amount: number;
     timestamp: number;
     note?: string;
+    decrypted_note?: string;
+    outputs: TxOutput[];
+    direction: 'sent' | 'received' | 'self';
+    wallet_amount: number;
 }
 
 const History: React.FC = () => {
@@ -25,6 +35,7 @@ const History: React.FC = () => {
 
     const fetchHistory = async (id: string) => {
         try {
+            // The session identifies us as the owner, so private notes come back decrypted
             const res = await api.get(`/wallet/${id}/history`);
             setTransactions(res.data.reverse()); // Show newest first
         } catch (err) {
@@ -65,7 +76,9 @@ const History: React.FC = () => {
                             </thead>
                             <tbody className="divide-y divide-white/5">
                                 {transactions.map((tx) => {
-                                    const isReceived = tx.receiver_wallet_id === walletId;
+                                    const isReceived = tx.direction === 'received';
+                                    const memo = tx.outputs.find((o) => o.receiver_wallet_id === walletId)?.memo;
+                                    const recipients = tx.outputs.filter((o) => o.receiver_wallet_id !== tx.sender_wallet_id).length;
                                     return (
                                         <tr key={tx.id} className="hover:bg-white/5 transition-colors">
                                             <td className="p-4">
@@ -75,7 +88,7 @@ const History: React.FC = () => {
                                                 </div>
                                             </td>
                                             <td className="p-4 font-bold text-white">
-                                                {isReceived ? '+' : '-'}{tx.amount}
+                                                {isReceived ? '+' : '-'}{tx.wallet_amount}
                                             </td>
                                             <td className="p-4">
                                                 <div className="flex flex-col">
@@ -83,7 +96,7 @@ const History: React.FC = () => {
                                                         {isReceived ? 'From' : 'To'}
                                                     </span>
                                                     <code className="text-xs text-primary-300 font-mono bg-black/20 px-2 py-1 rounded w-fit max-w-[200px] truncate">
-                                                        {isReceived ? tx.sender_wallet_id : tx.receiver_wallet_id}
+                                                        {isReceived ? tx.sender_wallet_id : tx.receiver_wallet_id === 'BATCH' ? `${recipients} recipients` : tx.receiver_wallet_id}
                                                     </code>
                                                 </div>
                                             </td>
@@ -91,7 +104,7 @@ const History: React.FC = () => {
                                                 {new Date(tx.timestamp * 1000).toLocaleString()}
                                             </td>
                                             <td className="p-4 text-gray-400 text-sm italic">
-                                                {tx.note || '-'}
+                                                {(isReceived && memo) || tx.decrypted_note || tx.note || '-'}
                                             </td>
                                         </tr>
                                     );
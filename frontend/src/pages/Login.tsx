@@ -2,12 +2,14 @@
 import React, { useState } from 'react';
 import { useNavigate, Link } from 'react-router-dom';
 import api from '../api';
+import { signMessage } from '../crypto';
 import { ArrowRight } from 'lucide-react';
 
 const Login: React.FC = () => {
     const [walletId, setWalletId] = useState('');
     const [privateKey, setPrivateKey] = useState('');
     const [otp, setOtp] = useState('');
+    const [ticket, setTicket] = useState('');
     const [step, setStep] = useState<'credentials' | 'otp'>('credentials');
     const [error, setError] = useState('');
     const [loading, setLoading] = useState(false);
@@ -20,21 +22,26 @@ const Login: React.FC = () => {
 
         try {
             if (step === 'credentials') {
-                // Step 1: Send credentials to get OTP
-                await api.post('/auth/login', { wallet_id: walletId, private_key: privateKey });
+                // Step 1: Sign the server's challenge locally, then get an OTP
+                const challenge = await api.post('/auth/challenge', { wallet_id: walletId });
+                const signature = await signMessage(privateKey, challenge.data.message);
+                const login = await api.post('/auth/login', { wallet_id: walletId, nonce: challenge.data.nonce, signature });
+                setTicket(login.data.ticket);
                 setStep('otp');
             } else {
                 // Step 2: Verify OTP
-                await api.post('/auth/verify-otp', { wallet_id: walletId, otp });
+                const res = await api.post('/auth/verify-otp', { wallet_id: walletId, ticket, otp });
 
                 // If success
                 localStorage.setItem('wallet_id', walletId);
+                localStorage.setItem('access_token', res.data.access_token);
+                localStorage.setItem('refresh_token', res.data.refresh_token);
                 localStorage.setItem('private_key', privateKey);
                 navigate('/');
             }
         } catch (err: any) {
             console.error('Login failed', err);
-            setError(err.response?.data || 'Login failed. Please check your credentials.');
+            setError(err.response?.data || err.message || 'Login failed. Please check your credentials.');
         } finally {
             setLoading(false);
         }
@@ -80,6 +87,9 @@ const Login: React.FC = () => {
                                     placeholder="Enter your private key"
                                     required
                                 />
+                                <p className="text-xs text-gray-400">
+                                    Your key signs a login challenge in this browser and is never sent to the server
+                                </p>
                             </div>
                         </>
                     ) : (
@@ -96,7 +106,7 @@ const Login: React.FC = () => {
                                 autoFocus
                             />
                             <p className="text-xs text-gray-400 text-center">
-                                Check your server console for the OTP code (Mock Mode)
+                                Enter the code we emailed you
                             </p>
                         </div>
                     )}